@@ -1,5 +1,14 @@
+use std::{env, path::PathBuf};
+
 fn main() {
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+
+    // embed_migrations! reads the directory at compile time, so a new migration alone has to
+    // rebuild the crate.
+    println!("cargo:rerun-if-changed=migrations");
+
     tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("api_descriptor.bin"))
         .compile(
             &[
                 "proto/api/agents.proto",
@@ -9,6 +18,9 @@ fn main() {
                 "proto/api/users.proto",
                 "proto/api/permissions.proto",
                 "proto/api/permission_membership.proto",
+                "proto/api/mesh.proto",
+                "proto/grpc/health/v1/health.proto",
+                "proto/grpc/reflection/v1alpha/reflection.proto",
             ],
             &["proto"],
         )
@@ -0,0 +1,13 @@
+DROP INDEX IF EXISTS agents_updated_at_idx;
+DROP INDEX IF EXISTS routers_updated_at_idx;
+DROP INDEX IF EXISTS tunnels_updated_at_idx;
+
+DROP TRIGGER IF EXISTS set_updated_at ON users;
+DROP TRIGGER IF EXISTS set_updated_at ON agents;
+DROP TRIGGER IF EXISTS set_updated_at ON routers;
+DROP TRIGGER IF EXISTS set_updated_at ON tunnels;
+
+ALTER TABLE users DROP COLUMN created_at, DROP COLUMN updated_at;
+ALTER TABLE agents DROP COLUMN created_at, DROP COLUMN updated_at;
+ALTER TABLE routers DROP COLUMN created_at, DROP COLUMN updated_at;
+ALTER TABLE tunnels DROP COLUMN created_at, DROP COLUMN updated_at;
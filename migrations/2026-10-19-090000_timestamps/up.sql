@@ -0,0 +1,24 @@
+ALTER TABLE users
+    ADD COLUMN created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    ADD COLUMN updated_at TIMESTAMP NOT NULL DEFAULT NOW();
+
+ALTER TABLE agents
+    ADD COLUMN created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    ADD COLUMN updated_at TIMESTAMP NOT NULL DEFAULT NOW();
+
+ALTER TABLE routers
+    ADD COLUMN created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    ADD COLUMN updated_at TIMESTAMP NOT NULL DEFAULT NOW();
+
+ALTER TABLE tunnels
+    ADD COLUMN created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    ADD COLUMN updated_at TIMESTAMP NOT NULL DEFAULT NOW();
+
+SELECT diesel_manage_updated_at('users');
+SELECT diesel_manage_updated_at('agents');
+SELECT diesel_manage_updated_at('routers');
+SELECT diesel_manage_updated_at('tunnels');
+
+CREATE INDEX agents_updated_at_idx ON agents (updated_at);
+CREATE INDEX routers_updated_at_idx ON routers (updated_at);
+CREATE INDEX tunnels_updated_at_idx ON tunnels (updated_at);
@@ -0,0 +1,5 @@
+DROP TRIGGER IF EXISTS notify_mesh_change ON agents;
+DROP TRIGGER IF EXISTS notify_mesh_change ON routers;
+DROP TRIGGER IF EXISTS notify_mesh_change ON tunnels;
+
+DROP FUNCTION IF EXISTS notify_mesh_change();
@@ -0,0 +1,14 @@
+-- Tell listeners (Mesh.Watch) which table changed, once per statement.
+CREATE OR REPLACE FUNCTION notify_mesh_change() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('mesh_changes', TG_TABLE_NAME);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER notify_mesh_change AFTER INSERT OR UPDATE OR DELETE ON agents
+    FOR EACH STATEMENT EXECUTE PROCEDURE notify_mesh_change();
+CREATE TRIGGER notify_mesh_change AFTER INSERT OR UPDATE OR DELETE ON routers
+    FOR EACH STATEMENT EXECUTE PROCEDURE notify_mesh_change();
+CREATE TRIGGER notify_mesh_change AFTER INSERT OR UPDATE OR DELETE ON tunnels
+    FOR EACH STATEMENT EXECUTE PROCEDURE notify_mesh_change();
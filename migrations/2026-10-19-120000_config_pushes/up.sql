@@ -0,0 +1,20 @@
+-- One row per push attempt an agent reports, successful or not.
+CREATE TABLE config_pushes
+(
+    id           SERIAL PRIMARY KEY,
+    router       INTEGER   NOT NULL REFERENCES routers (id) ON DELETE CASCADE,
+    -- Router.Render's config_hash of the configuration that was pushed.
+    config_hash  VARCHAR   NOT NULL,
+    driver       VARCHAR   NOT NULL,
+    started_at   TIMESTAMP NOT NULL,
+    finished_at  TIMESTAMP NOT NULL,
+    success      BOOLEAN   NOT NULL,
+    error_output TEXT      NOT NULL DEFAULT '',
+    reported_at  TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX config_pushes_router_idx ON config_pushes (router, finished_at DESC);
+
+-- Push results show up on the dashboard straight away.
+CREATE TRIGGER notify_mesh_change AFTER INSERT OR DELETE ON config_pushes
+    FOR EACH STATEMENT EXECUTE PROCEDURE notify_mesh_change();
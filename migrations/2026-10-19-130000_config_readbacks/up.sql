@@ -0,0 +1,8 @@
+-- The last running config an agent read back from each router, managed interfaces only.
+CREATE TABLE config_readbacks
+(
+    router      INTEGER PRIMARY KEY REFERENCES routers (id) ON DELETE CASCADE,
+    config      TEXT      NOT NULL,
+    read_at     TIMESTAMP NOT NULL,
+    reported_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
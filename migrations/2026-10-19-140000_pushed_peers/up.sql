@@ -0,0 +1,10 @@
+-- The peers each router has a tunnel interface for, as of its last successful push of the current config. Peers
+-- that are no longer linked get their interface removed by the next config.
+CREATE TABLE pushed_peers
+(
+    router    INTEGER   NOT NULL REFERENCES routers (id) ON DELETE CASCADE,
+    -- Not a foreign key: the peer's tunnel is usually gone by the time this matters.
+    peer      INTEGER   NOT NULL,
+    pushed_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (router, peer)
+);
@@ -0,0 +1,2 @@
+ALTER TABLE tunnels
+    DROP COLUMN ip_reported_at;
@@ -0,0 +1,3 @@
+-- When the agent of a dynamic endpoint reported its current address; NULL until it does.
+ALTER TABLE tunnels
+    ADD COLUMN ip_reported_at TIMESTAMP;
@@ -0,0 +1,4 @@
+DROP TABLE address_resolutions;
+
+ALTER TABLE tunnels
+    DROP COLUMN ip_resolved_at;
@@ -0,0 +1,16 @@
+-- When the server last resolved a dynamic endpoint's hostname to its current address.
+ALTER TABLE tunnels
+    ADD COLUMN ip_resolved_at TIMESTAMP;
+
+-- Every change in what a dynamic endpoint's hostname resolves to, or in the error resolving it.
+CREATE TABLE address_resolutions
+(
+    id          SERIAL PRIMARY KEY,
+    tunnel      INTEGER   NOT NULL REFERENCES tunnels (id) ON DELETE CASCADE,
+    address     VARCHAR,
+    error       TEXT      NOT NULL DEFAULT '',
+    ttl_secs    INTEGER   NOT NULL,
+    resolved_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX address_resolutions_tunnel_idx ON address_resolutions (tunnel, id);
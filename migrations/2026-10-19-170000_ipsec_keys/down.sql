@@ -0,0 +1 @@
+DROP TABLE ipsec_keys;
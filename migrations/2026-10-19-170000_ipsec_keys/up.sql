@@ -0,0 +1,13 @@
+-- The pre-shared key of each IPSec peering, between endpoints tunnel_a and tunnel_b. The key is
+-- encrypted with render.psk_secret: a 12 byte nonce followed by the AES-256-GCM ciphertext.
+CREATE TABLE ipsec_keys
+(
+    tunnel_a   INTEGER   NOT NULL REFERENCES tunnels (id) ON DELETE CASCADE,
+    tunnel_b   INTEGER   NOT NULL REFERENCES tunnels (id) ON DELETE CASCADE,
+    ciphertext BYTEA     NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (tunnel_a, tunnel_b),
+    CHECK (tunnel_a < tunnel_b)
+);
+
+CREATE INDEX ipsec_keys_tunnel_b_idx ON ipsec_keys (tunnel_b);
@@ -0,0 +1,5 @@
+DROP TABLE wireguard_endpoints;
+
+DELETE FROM tunnels WHERE tunnel_type = 'WireGuard';
+ALTER TABLE tunnels DROP CONSTRAINT "tunnel_type can only be GRE, IPSec or WireGuard";
+ALTER TABLE tunnels ADD CONSTRAINT "tunnel_type can only be GRE or IPSec" CHECK (tunnel_type IN ('GRE', 'IPSec'));
@@ -0,0 +1,15 @@
+ALTER TABLE tunnels DROP CONSTRAINT "tunnel_type can only be GRE or IPSec";
+ALTER TABLE tunnels ADD CONSTRAINT "tunnel_type can only be GRE, IPSec or WireGuard"
+    CHECK (tunnel_type IN ('GRE', 'IPSec', 'WireGuard'));
+
+-- The WireGuard identity of each WireGuard endpoint: its keypair, the private key encrypted with
+-- render.psk_secret like the IPSec pre-shared keys, its address inside the tunnels and its port.
+CREATE TABLE wireguard_endpoints
+(
+    tunnel             INTEGER PRIMARY KEY REFERENCES tunnels (id) ON DELETE CASCADE,
+    public_key         VARCHAR   NOT NULL,
+    private_ciphertext BYTEA     NOT NULL,
+    address            VARCHAR   NOT NULL UNIQUE,
+    listen_port        INTEGER   NOT NULL,
+    created_at         TIMESTAMP NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,11 @@
+DROP TABLE agent_drivers;
+
+UPDATE routers SET router_type = NULL WHERE router_type NOT IN ('Cisco', 'PyDECNet');
+UPDATE routers SET conn_type = NULL WHERE conn_type NOT IN ('SNMP', 'SSH');
+ALTER TABLE routers DROP CONSTRAINT "router_type must be in router_types";
+ALTER TABLE routers DROP CONSTRAINT "conn_type must be in conn_types";
+ALTER TABLE routers ADD CONSTRAINT "router_type can only be Cisco or PyDECNet" CHECK (router_type IN ('Cisco', 'PyDECNet'));
+ALTER TABLE routers ADD CONSTRAINT "conn_type can only be SNMP or SSH" CHECK (conn_type IN ('SNMP', 'SSH'));
+
+DROP TABLE conn_types;
+DROP TABLE router_types;
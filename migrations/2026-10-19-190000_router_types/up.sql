@@ -0,0 +1,34 @@
+-- Router and connection types are rows rather than CHECK constraints, so a new one is an INSERT.
+-- The server adds the ones it renders for at startup.
+CREATE TABLE router_types
+(
+    name        VARCHAR PRIMARY KEY,
+    description VARCHAR   NOT NULL DEFAULT '',
+    created_at  TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE conn_types
+(
+    name       VARCHAR PRIMARY KEY,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+INSERT INTO router_types (name, description) VALUES ('Cisco', 'Cisco IOS'), ('PyDECNet', 'PyDECnet on Linux');
+INSERT INTO conn_types (name) VALUES ('SNMP'), ('SSH');
+
+ALTER TABLE routers DROP CONSTRAINT "router_type can only be Cisco or PyDECNet";
+ALTER TABLE routers DROP CONSTRAINT "conn_type can only be SNMP or SSH";
+ALTER TABLE routers ADD CONSTRAINT "router_type must be in router_types"
+    FOREIGN KEY (router_type) REFERENCES router_types (name) ON UPDATE CASCADE;
+ALTER TABLE routers ADD CONSTRAINT "conn_type must be in conn_types"
+    FOREIGN KEY (conn_type) REFERENCES conn_types (name) ON UPDATE CASCADE;
+
+-- The drivers each agent advertised in its last heartbeat. Agents may know router types the
+-- server does not, so these are not foreign keys.
+CREATE TABLE agent_drivers
+(
+    agent       INTEGER NOT NULL REFERENCES agents (id) ON DELETE CASCADE,
+    router_type VARCHAR NOT NULL,
+    conn_type   VARCHAR NOT NULL,
+    PRIMARY KEY (agent, router_type, conn_type)
+);
@@ -0,0 +1,2 @@
+DELETE FROM permission_membership WHERE permission IN (SELECT id FROM permissions WHERE name = 'admin');
+DELETE FROM permissions WHERE name = 'admin';
@@ -0,0 +1,5 @@
+-- Members of this permission manage users, permissions and the whole mesh; everyone else only
+-- their own agents. `server grant-admin EMAIL` adds the first one.
+INSERT INTO permissions (name, description)
+SELECT 'admin', 'Manages users, permissions and every agent, router and tunnel'
+WHERE NOT EXISTS (SELECT 1 FROM permissions WHERE name = 'admin');
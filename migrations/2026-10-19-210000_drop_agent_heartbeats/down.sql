@@ -0,0 +1,8 @@
+CREATE TABLE agent_heartbeats
+(
+    agent       INTEGER PRIMARY KEY REFERENCES agents (id) ON DELETE CASCADE,
+    last_seen   TIMESTAMP NOT NULL DEFAULT NOW(),
+    version     VARCHAR   NOT NULL DEFAULT '',
+    hostname    VARCHAR   NOT NULL DEFAULT '',
+    uptime_secs BIGINT    NOT NULL DEFAULT 0
+);
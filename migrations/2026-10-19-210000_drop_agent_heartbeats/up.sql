@@ -0,0 +1,2 @@
+-- Agents advertise their drivers with Agent.SetDrivers; nothing reads heartbeats any more.
+DROP TABLE agent_heartbeats;
@@ -0,0 +1 @@
+ALTER SEQUENCE tunnels_id_seq MINVALUE 1 START WITH 1;
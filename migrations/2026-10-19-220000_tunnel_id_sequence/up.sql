@@ -0,0 +1,4 @@
+-- Tunnel ids double as interface numbers and must be at least 50, but the sequence started at 1,
+-- so Tunnel.Add failed the check on every new database.
+SELECT setval('tunnels_id_seq', GREATEST(50, (SELECT COALESCE(MAX(id), 0) + 1 FROM tunnels)), false);
+ALTER SEQUENCE tunnels_id_seq MINVALUE 50 START WITH 50;
@@ -0,0 +1,46 @@
+-- Every timestamp is read as an instant (SystemTime), and was written as UTC; say so, rather than
+-- rely on the session time zone being UTC.
+ALTER TABLE address_resolutions
+    ALTER COLUMN resolved_at TYPE TIMESTAMPTZ USING resolved_at AT TIME ZONE 'UTC';
+
+ALTER TABLE agents
+    ALTER COLUMN created_at TYPE TIMESTAMPTZ USING created_at AT TIME ZONE 'UTC',
+    ALTER COLUMN updated_at TYPE TIMESTAMPTZ USING updated_at AT TIME ZONE 'UTC';
+
+ALTER TABLE config_pushes
+    ALTER COLUMN started_at TYPE TIMESTAMPTZ USING started_at AT TIME ZONE 'UTC',
+    ALTER COLUMN finished_at TYPE TIMESTAMPTZ USING finished_at AT TIME ZONE 'UTC',
+    ALTER COLUMN reported_at TYPE TIMESTAMPTZ USING reported_at AT TIME ZONE 'UTC';
+
+ALTER TABLE config_readbacks
+    ALTER COLUMN read_at TYPE TIMESTAMPTZ USING read_at AT TIME ZONE 'UTC',
+    ALTER COLUMN reported_at TYPE TIMESTAMPTZ USING reported_at AT TIME ZONE 'UTC';
+
+ALTER TABLE conn_types
+    ALTER COLUMN created_at TYPE TIMESTAMPTZ USING created_at AT TIME ZONE 'UTC';
+
+ALTER TABLE ipsec_keys
+    ALTER COLUMN created_at TYPE TIMESTAMPTZ USING created_at AT TIME ZONE 'UTC';
+
+ALTER TABLE pushed_peers
+    ALTER COLUMN pushed_at TYPE TIMESTAMPTZ USING pushed_at AT TIME ZONE 'UTC';
+
+ALTER TABLE router_types
+    ALTER COLUMN created_at TYPE TIMESTAMPTZ USING created_at AT TIME ZONE 'UTC';
+
+ALTER TABLE routers
+    ALTER COLUMN created_at TYPE TIMESTAMPTZ USING created_at AT TIME ZONE 'UTC',
+    ALTER COLUMN updated_at TYPE TIMESTAMPTZ USING updated_at AT TIME ZONE 'UTC';
+
+ALTER TABLE tunnels
+    ALTER COLUMN created_at TYPE TIMESTAMPTZ USING created_at AT TIME ZONE 'UTC',
+    ALTER COLUMN updated_at TYPE TIMESTAMPTZ USING updated_at AT TIME ZONE 'UTC',
+    ALTER COLUMN ip_reported_at TYPE TIMESTAMPTZ USING ip_reported_at AT TIME ZONE 'UTC',
+    ALTER COLUMN ip_resolved_at TYPE TIMESTAMPTZ USING ip_resolved_at AT TIME ZONE 'UTC';
+
+ALTER TABLE users
+    ALTER COLUMN created_at TYPE TIMESTAMPTZ USING created_at AT TIME ZONE 'UTC',
+    ALTER COLUMN updated_at TYPE TIMESTAMPTZ USING updated_at AT TIME ZONE 'UTC';
+
+ALTER TABLE wireguard_endpoints
+    ALTER COLUMN created_at TYPE TIMESTAMPTZ USING created_at AT TIME ZONE 'UTC';
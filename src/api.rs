@@ -1,2 +1,5 @@
 #![allow(clippy::derive_partial_eq_without_eq)]
 tonic::include_proto!("api");
+
+/// Descriptors of every message and service above, for the HTTP gateway to transcode with.
+pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("api_descriptor");
@@ -0,0 +1,134 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ring::hmac;
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+/// The user a signed token was issued to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UserId(pub i32);
+
+/// Who sent a request, added to the extensions of every request the interceptor lets through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Caller {
+    /// Tokens are not signed, so callers cannot be told apart and none is anyone in particular.
+    Anyone,
+    /// Auth is disabled, so each caller may do anything.
+    Developer,
+    User(UserId),
+}
+
+/// Issues and checks the tokens clients send in the authorization header.
+///
+/// With a secret, tokens are `<user id>.<expiry>.<signature>`, signed with HMAC-SHA256, and only
+/// unexpired tokens with a valid signature are accepted. Without one, tokens are random and any
+/// token is accepted, but says nothing about its holder: only with auth disabled may they do
+/// anything.
+pub struct Tokens {
+    key: Option<hmac::Key>,
+    ttl: Duration,
+    disabled: bool,
+}
+
+impl std::fmt::Debug for Tokens {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Tokens")
+            .field("signed", &self.key.is_some())
+            .field("ttl", &self.ttl)
+            .field("disabled", &self.disabled)
+            .finish()
+    }
+}
+
+impl Tokens {
+    pub fn new(secret: Option<&[u8]>, ttl: Duration) -> Tokens {
+        Tokens {
+            key: secret.map(|secret| hmac::Key::new(hmac::HMAC_SHA256, secret)),
+            ttl,
+            disabled: false,
+        }
+    }
+
+    /// Unsigned tokens whose holders may do anything, for development (`auth.disabled`).
+    pub fn disabled(ttl: Duration) -> Tokens {
+        Tokens {
+            key: None,
+            ttl,
+            disabled: true,
+        }
+    }
+
+    /// Who holds a token, once `verify` found no user in it.
+    fn unsigned_caller(&self) -> Caller {
+        match self.disabled {
+            true => Caller::Developer,
+            false => Caller::Anyone,
+        }
+    }
+
+    pub fn issue(&self, user_id: i32) -> String {
+        let key = match &self.key {
+            Some(key) => key,
+            None => return base64::encode_config(rand::random::<[u8; 32]>(), base64::URL_SAFE_NO_PAD),
+        };
+
+        let expires = (SystemTime::now() + self.ttl).duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let claims = format!("{}.{}", user_id, expires);
+        let signature = hmac::sign(key, claims.as_bytes());
+        format!("{}.{}", claims, base64::encode_config(signature, base64::URL_SAFE_NO_PAD))
+    }
+
+    /// The user `token` was issued to, `None` if tokens are not signed.
+    pub fn verify(&self, token: &str) -> Result<Option<UserId>, Status> {
+        let key = match &self.key {
+            Some(key) => key,
+            None => return Ok(None),
+        };
+        let invalid = || Status::unauthenticated("Invalid token");
+
+        let (claims, signature) = token.rsplit_once('.').ok_or_else(invalid)?;
+        let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD).map_err(|_| invalid())?;
+        hmac::verify(key, claims.as_bytes(), &signature).map_err(|_| invalid())?;
+
+        let (user_id, expires) = claims.split_once('.').ok_or_else(invalid)?;
+        let expires: u64 = expires.parse().map_err(|_| invalid())?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        if expires <= now {
+            return Err(Status::unauthenticated("Token expired"));
+        }
+
+        Ok(Some(UserId(user_id.parse().map_err(|_| invalid())?)))
+    }
+}
+
+/// Refuses requests without a valid token in the authorization header.
+#[derive(Debug, Clone)]
+pub struct AuthInterceptor {
+    tokens: Arc<Tokens>,
+}
+
+impl AuthInterceptor {
+    pub fn new(tokens: Arc<Tokens>) -> AuthInterceptor {
+        AuthInterceptor { tokens }
+    }
+}
+
+impl Interceptor for AuthInterceptor {
+    fn call(&mut self, mut req: Request<()>) -> Result<Request<()>, Status> {
+        let token = match req.metadata().get("authorization") {
+            Some(token) => token
+                .to_str()
+                .map_err(|_| Status::unauthenticated("Invalid token"))?,
+            None => return Err(Status::unauthenticated("Token not found")),
+        };
+
+        let caller = match self.tokens.verify(token)? {
+            Some(user) => Caller::User(user),
+            None => self.tokens.unsigned_caller(),
+        };
+        req.extensions_mut().insert(caller);
+
+        Ok(req)
+    }
+}
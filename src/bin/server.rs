@@ -1,5 +1,8 @@
 use std::{
     env,
+    fs,
+    process,
+    sync::Arc,
     time::Duration,
 };
 
@@ -8,79 +11,309 @@ use diesel::r2d2::ConnectionManager;
 use diesel::r2d2::Pool;
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 use dotenvy::dotenv;
-use tonic::{Request, Status};
-use tonic::transport::Server;
+use tokio::time::Instant;
+use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
+use tracing::{error, info, warn};
 
-use tunnel_manager::api::*;
-use tunnel_manager::handlers::*;
+use tunnel_manager::api::FILE_DESCRIPTOR_SET;
+use tunnel_manager::auth::Tokens;
+use tunnel_manager::config::{self, Config, Settings, TlsConfig};
+use tunnel_manager::gateway;
+use tunnel_manager::grpc_web::{Cors, GrpcWebLayer};
+use tunnel_manager::handlers::reflection::ReflectionService;
+use tunnel_manager::server;
+use tunnel_manager::shutdown::{self, Shutdown};
+use tunnel_manager::legacy::LegacyData;
+use tunnel_manager::metrics::{Exporter, Metrics, MetricsLayer};
+use tunnel_manager::resolver::{DnsResolver, Resolver};
+use tunnel_manager::storage::access;
+use tunnel_manager::storage::changes::ChangeListener;
+use tunnel_manager::storage::drivers::RouterType;
+use tunnel_manager::storage::mesh::Mesh;
 
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
 
+const USAGE: &str = "usage: server [--config FILE] [--SETTING VALUE ...] [import-legacy ... | grant-admin EMAIL]
+
+Settings are read from the TOML file given with --config (or TUNNEL_MANAGER_CONFIG), then from
+the environment, then from the command line. Every setting can be given as";
+
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tracing_subscriber::fmt().try_init().unwrap();
+async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     dotenv().ok();
 
-    let db_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let db_max_conn_str = env::var("DB_MAX_CONNECTION").unwrap_or_else(|_| "5".to_string());
-    let db_max_conn = db_max_conn_str.parse::<u32>().unwrap();
-    let grpc_host = env::var("GRPC_HOST").unwrap_or_else(|_| "[::1]".to_string());
-    let grpc_port = env::var("GRPC_PORT").unwrap_or_else(|_| "50051".to_string());
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (config, args) = match config::load::<Config>(&args, |var| env::var(var).ok()) {
+        Ok((_, args)) if args.first().map(|a| a.as_str()) == Some("--help") => {
+            println!("{}", usage());
+            return Ok(());
+        }
+        Ok(loaded) => loaded,
+        Err(err) => {
+            eprintln!("{}", err);
+            eprintln!("Run server --help for the list of settings.");
+            process::exit(2);
+        }
+    };
 
-    let manager = ConnectionManager::<PgConnection>::new(db_url);
+    config.log.init()?;
 
-    // Create a connection pool
-    let pool = Pool::builder()
-        .test_on_check_out(true)
-        .max_size(db_max_conn)
-        .build(manager)
-        .expect("Could not build connection pool");
+    let metrics = Metrics::new();
+    let pool = match config.database.pool(metrics.pool_events()) {
+        Ok(pool) => pool,
+        Err(err) => {
+            eprintln!("Could not connect to the database: {}", err);
+            process::exit(1);
+        }
+    };
 
     {
         // Run database migrations
-        let conn = &mut pool.get().unwrap();
-        conn.run_pending_migrations(MIGRATIONS).unwrap();
-    }
-
-    let addr = format!("{}:{}", grpc_host, grpc_port).parse()?;
-
-    // let auth = login::AuthService::new(pool.clone());
-    let agent = agents::AgentService::new(pool.clone());
-    let router = routers::RouterService::new(pool.clone());
-    let tunnel = tunnels::TunnelService::new(pool.clone());
-    let user = users::UserService::new(pool.clone());
-    let permission = permissions::PermissionService::new(pool.clone());
-
-    let layer = tower::ServiceBuilder::new()
-        .timeout(Duration::from_secs(30))
-        .layer(tonic::service::interceptor(auth_interceptor))
-        .into_inner();
-
-    println!("Running on port {}", grpc_port);
-
-    Server::builder()
-        .layer(layer)
-        // .add_service(login_server::LoginServer::new(auth))
-        .add_service(agent_server::AgentServer::new(agent))
-        // .add_service(auth_server::AuthServer::new(auth))
-        .add_service(router_server::RouterServer::new(router))
-        .add_service(tunnel_server::TunnelServer::new(tunnel))
-        .add_service(user_server::UserServer::new(user))
-        .add_service(permission_server::PermissionServer::new(permission))
-        .serve(addr)
-        .await?;
+        let conn = &mut pool.get()?;
+        conn.run_pending_migrations(MIGRATIONS)?;
+        RouterType::register(conn)?;
+    }
+
+    if args.first().map(|a| a.as_str()) == Some("import-legacy") {
+        return import_legacy(&pool, &args[1..]).await;
+    }
+
+    if args.first().map(|a| a.as_str()) == Some("grant-admin") {
+        return grant_admin(&pool, &args[1..]);
+    }
+
+    let addr = config.listen.grpc_addr()?;
+    let http_addr = config.listen.http_addr()?;
+
+    let listener = ChangeListener::spawn(config.database.url.clone(), &config.notifications);
+    let ttl = Duration::from_secs(config.auth.token_ttl_secs);
+    let tokens = Arc::new(match config.auth.disabled {
+        true => {
+            warn!(message = "auth.disabled is set, any token is accepted with admin rights");
+            Tokens::disabled(ttl)
+        }
+        false => Tokens::new(config.auth.secret()?.as_deref(), ttl),
+    });
+    let reflection = ReflectionService::new(FILE_DESCRIPTOR_SET, server::SERVICES)?;
+    let shutdown = Shutdown::new();
+
+    let mut builder = Server::builder().accept_http1(true);
+    if let Some(tls) = tls_config(&config.tls)? {
+        builder = builder.tls_config(tls)?;
+    }
+
+    let builder = builder.layer(
+        tower::ServiceBuilder::new()
+            .layer(GrpcWebLayer::new(Cors::new(&config.listen.cors_allowed_origins.join(","))))
+            .layer(MetricsLayer::new(metrics.clone()))
+            .timeout(Duration::from_secs(config.listen.request_timeout_secs))
+            .into_inner(),
+    );
+
+    // Built twice: once to serve gRPC, once as the in-process backend of the HTTP gateway.
+    let services = || {
+        server::services(
+            &mut builder.clone(),
+            &pool,
+            &listener,
+            &reflection,
+            &tokens,
+            &config,
+            &metrics,
+            &shutdown,
+        )
+    };
+
+    let grpc = services().into_service();
+    let exporter = Exporter::new(metrics.clone(), pool.clone());
+    let http_tls = gateway::tls::server_config(&config.tls)?;
+    let gateway_shutdown = shutdown.clone();
+    let gateway = tokio::spawn(async move {
+        if let Err(err) = gateway::serve(http_addr, http_tls, grpc, exporter, gateway_shutdown).await {
+            eprintln!("HTTP gateway stopped: {}", err);
+        }
+    });
+
+    if config.resolver.enabled {
+        let timeout = Duration::from_secs(config.resolver.timeout_secs);
+        let dns = match config.resolver.nameservers()?.as_slice() {
+            [] => DnsResolver::system(timeout),
+            nameservers => DnsResolver::new(nameservers.to_vec(), timeout),
+        };
+        Resolver::new(pool.clone(), Arc::new(dns), &config.resolver).spawn(shutdown.clone());
+    }
+
+    let grpc_shutdown = shutdown.clone();
+    let mut grpc = tokio::spawn(services().serve_with_shutdown(addr, async move { grpc_shutdown.triggered().await }));
+
+    println!("Running on {}, HTTP gateway on {}", addr, http_addr);
+
+    tokio::select! {
+        // Only ends by itself when it could not start.
+        result = &mut grpc => return Ok(result??),
+        signal = shutdown::signal() => info!(message = "Shutting down", signal = signal?),
+    }
+
+    // Stops both ports accepting, ends the Watch streams with UNAVAILABLE so agents reconnect to
+    // another server, and has health checks report NOT_SERVING.
+    shutdown.trigger();
+
+    let deadline = Instant::now() + Duration::from_secs(config.listen.shutdown_timeout_secs);
+    let drained = tokio::time::timeout_at(deadline, async {
+        if let Ok(Err(err)) = grpc.await {
+            error!(message = "gRPC server stopped", %err);
+        }
+        let _ = gateway.await;
+    });
+    if drained.await.is_err() {
+        warn!(message = "Requests still in flight at the shutdown deadline, dropping them");
+    }
+
+    // Handlers can leave blocking database work behind them; transactions are rolled back if
+    // their connection is closed mid-way, so give them until the deadline to commit.
+    loop {
+        let state = pool.state();
+        let busy = state.connections - state.idle_connections;
+        if busy == 0 {
+            break;
+        }
+        if Instant::now() >= deadline {
+            warn!(message = "Database connections still in use at the shutdown deadline", busy);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    tokio::task::spawn_blocking(move || listener.stop()).await?;
+    info!(message = "Stopped");
+
+    Ok(())
+}
+
+fn usage() -> String {
+    let mut usage = USAGE.to_string();
+    usage.push_str(" a flag or an environment variable:\n");
+    for key in Config::KEYS {
+        usage.push_str(&format!("\n  {:<40} {}", config::flag(key), config::env_var::<Config>(key)));
+    }
+    usage
+}
+
+/// The TLS settings for the gRPC port, if it is to use TLS.
+fn tls_config(tls: &TlsConfig) -> Result<Option<ServerTlsConfig>, Box<dyn std::error::Error + Send + Sync>> {
+    let (cert, key) = match (&tls.cert, &tls.key) {
+        (Some(cert), Some(key)) => (fs::read(cert)?, fs::read(key)?),
+        _ => return Ok(None),
+    };
+
+    let mut config = ServerTlsConfig::new().identity(Identity::from_pem(cert, key));
+    if let Some(client_ca) = &tls.client_ca {
+        config = config.client_ca_root(Certificate::from_pem(fs::read(client_ca)?));
+    }
+
+    Ok(Some(config))
+}
+
+/// Gives an existing user the admin permission.
+fn grant_admin(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    args: &[String],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let email = match args {
+        [email] => email,
+        _ => return Err("usage: server grant-admin EMAIL".into()),
+    };
+
+    match access::grant_admin(&mut *pool.get()?, email) {
+        Ok(true) => println!("{} is now an admin.", email),
+        Ok(false) => println!("{} already is an admin.", email),
+        Err(diesel::result::Error::NotFound) => return Err(format!("no user with email {}", email).into()),
+        Err(err) => return Err(err.into()),
+    }
 
     Ok(())
 }
 
-fn auth_interceptor(req: Request<()>) -> Result<Request<()>, Status> {
-    let token = match req.metadata().get("authorization") {
-        Some(token) => token.to_str(),
-        None => return Err(Status::unauthenticated("Token not found"))
+const IMPORT_USAGE: &str = "usage: server import-legacy (--database URL | --dump FILE) [options]
+
+options:
+  --routers-table NAME   legacy routers table (default routers)
+  --tunnels-table NAME   legacy tunnels table (default tunnels)
+  --output FILE          write the mapped mesh as TOML instead of importing it
+  --dry-run              show what the import would change without changing anything";
+
+/// Copies the routers and tunnels of the old Python tunnel manager into this database.
+async fn import_legacy(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    args: &[String],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut database = None;
+    let mut dump = None;
+    let mut output = None;
+    let mut routers_table = "routers".to_string();
+    let mut tunnels_table = "tunnels".to_string();
+    let mut dry_run = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().cloned().ok_or(IMPORT_USAGE);
+        match arg.as_str() {
+            "--database" => database = Some(value()?),
+            "--dump" => dump = Some(value()?),
+            "--output" => output = Some(value()?),
+            "--routers-table" => routers_table = value()?,
+            "--tunnels-table" => tunnels_table = value()?,
+            "--dry-run" => dry_run = true,
+            _ => return Err(IMPORT_USAGE.into()),
+        }
+    }
+
+    let data = match (database, dump) {
+        (Some(url), None) => {
+            let conn = &mut PgConnection::establish(&url)?;
+            LegacyData::from_connection(conn, &routers_table, &tunnels_table)?
+        }
+        (None, Some(file)) => {
+            let routers_table = routers_table.rsplit('.').next().unwrap_or_default().to_lowercase();
+            let tunnels_table = tunnels_table.rsplit('.').next().unwrap_or_default().to_lowercase();
+            LegacyData::from_dump(&fs::read_to_string(file)?, &routers_table, &tunnels_table)?
+        }
+        _ => return Err(IMPORT_USAGE.into()),
     };
 
-    println!("token: {}", token.unwrap());
+    let import = data.map();
+
+    println!(
+        "Read {} routers and {} tunnels, mapped them onto {} placeholder agents.",
+        data.routers.len(),
+        data.tunnels.len(),
+        import.document.agents.len()
+    );
+
+    for line in &import.unmapped {
+        println!("not mapped: {}", line);
+    }
+
+    if let Some(file) = output {
+        fs::write(&file, import.document.to_toml())?;
+        println!("Wrote {}; review it, then run tmctl mesh apply.", file);
+        return Ok(());
+    }
+
+    let (plan, skipped) = Mesh::import_legacy(pool, &import, dry_run).await?;
 
-    // do some validation with token here ...
-    Ok(req)
-}
\ No newline at end of file
+    for line in &skipped {
+        println!("skipped: {}", line);
+    }
+
+    for change in &plan.changes {
+        println!("{} {} {}", change.action, change.kind, change.key);
+    }
+
+    match plan.applied {
+        true => println!("Imported {} changes.", plan.changes.len()),
+        false => println!("Dry run: {} changes would be made.", plan.changes.len()),
+    }
+
+    Ok(())
+}
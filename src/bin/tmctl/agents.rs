@@ -0,0 +1,195 @@
+use tunnel_manager::api::agent_client::AgentClient;
+use tunnel_manager::api::agent_request::IdUuidOrOwner;
+use tunnel_manager::api::{AgentData, AgentListRequest, AgentRequest, AgentTree};
+
+use crate::args::{id_or, Args};
+use crate::config::Context;
+use crate::output::{self, Format, Record, Value};
+use crate::{list_options, print_next_page, Error};
+
+pub const USAGE: &str = "\
+  agents list [--owner ID] [--description-contains TEXT] [LIST OPTIONS]
+  agents get ID|UUID | --owner ID
+  agents tree ID|UUID | --owner ID     the agent with its routers and their tunnels
+  agents register UUID --owner ID [--description TEXT]
+  agents update ID [--uuid UUID] [--owner ID] [--description TEXT]
+  agents unregister ID|UUID";
+
+impl From<&AgentData> for Record {
+    fn from(a: &AgentData) -> Record {
+        Record::new()
+            .field("id", &a.id)
+            .field("uuid", &a.uuid)
+            .field("owner", a.owner)
+            .field("description", &a.description)
+            .field("created_at", &a.created_at)
+            .field("updated_at", &a.updated_at)
+            .field("drivers", drivers(a))
+    }
+}
+
+/// The drivers the agent advertised, as `TYPE/CONN` pairs.
+fn drivers(a: &AgentData) -> String {
+    a.drivers
+        .iter()
+        .map(|d| format!("{}/{}", d.router_type, d.conn_type))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl From<&AgentTree> for Record {
+    fn from(t: &AgentTree) -> Record {
+        let mut record = t.agent.as_ref().map(Record::from).unwrap_or_else(Record::new);
+        record.0.push(("routers", Value::List(t.routers.iter().map(Record::from).collect())));
+        record
+    }
+}
+
+pub async fn run(ctx: &Context, command: &str, mut args: Args) -> Result<(), Error> {
+    let mut client = AgentClient::new(ctx.channel().await?);
+
+    match command {
+        "list" => {
+            let options = list_options(&mut args)?;
+            let mut request = AgentListRequest {
+                updated_since: options.updated_since,
+                page_size: options.page_size,
+                page_token: options.page_token,
+                order_by: options.order_by,
+                owner: args.parse("--owner")?,
+                description_contains: args.value("--description-contains")?,
+            };
+            args.finish()?;
+
+            let mut agents = Vec::new();
+            loop {
+                let response = client.list(request.clone()).await?.into_inner();
+                agents.extend(response.agents.iter().map(Record::from));
+                request.page_token = response.next_page_token;
+
+                if !options.all || request.page_token.is_empty() {
+                    break;
+                }
+            }
+
+            output::print_list(ctx.output, &agents);
+            print_next_page(&request.page_token);
+        }
+        "get" => {
+            let request = agent_request(&mut args)?;
+            args.finish()?;
+
+            let response = client.get(request).await?.into_inner();
+            let agents: Vec<Record> = response.agents.iter().map(Record::from).collect();
+            output::print_list(ctx.output, &agents);
+        }
+        "tree" => {
+            let request = agent_request(&mut args)?;
+            args.finish()?;
+
+            let response = client.get_tree(request).await?.into_inner();
+            match ctx.output {
+                Format::Table => print_trees(&response.agents),
+                format => output::print_list(format, &response.agents.iter().map(Record::from).collect::<Vec<_>>()),
+            }
+        }
+        "register" => {
+            let owner = args.parse("--owner")?.ok_or("--owner is required")?;
+            let description = args.value("--description")?;
+            let uuid = args.positional("agent uuid")?;
+            args.finish()?;
+
+            let agent = client
+                .register(AgentData {
+                    uuid,
+                    owner,
+                    description,
+                    ..Default::default()
+                })
+                .await?
+                .into_inner();
+            output::print_one(ctx.output, &(&agent).into());
+        }
+        "update" => {
+            let uuid = args.value("--uuid")?.unwrap_or_default();
+            let owner = args.parse("--owner")?.unwrap_or_default();
+            let description = args.value("--description")?;
+            let id = args.positional("agent id")?.parse().map_err(|_| "agent id must be a number")?;
+            args.finish()?;
+
+            let agent = client
+                .update(AgentData {
+                    id: Some(id),
+                    uuid,
+                    owner,
+                    description,
+                    ..Default::default()
+                })
+                .await?
+                .into_inner();
+            output::print_one(ctx.output, &(&agent).into());
+        }
+        "unregister" => {
+            let value = args.positional("agent id or uuid")?;
+            args.finish()?;
+
+            client
+                .unregister(AgentRequest {
+                    id_uuid_or_owner: Some(id_or(value.clone(), IdUuidOrOwner::Id, IdUuidOrOwner::Uuid)),
+                })
+                .await?;
+            eprintln!("Unregistered agent {}.", value);
+        }
+        _ => return Err(crate::usage()),
+    }
+
+    Ok(())
+}
+
+/// Agents can be looked up by id, uuid or, with `--owner`, everything a user owns.
+fn agent_request(args: &mut Args) -> Result<AgentRequest, Error> {
+    let id_uuid_or_owner = match args.parse("--owner")? {
+        Some(owner) => IdUuidOrOwner::Owner(owner),
+        None => id_or(args.positional("agent id or uuid")?, IdUuidOrOwner::Id, IdUuidOrOwner::Uuid),
+    };
+
+    Ok(AgentRequest {
+        id_uuid_or_owner: Some(id_uuid_or_owner),
+    })
+}
+
+fn print_trees(trees: &[AgentTree]) {
+    if trees.is_empty() {
+        println!("No results.");
+    }
+
+    for tree in trees {
+        if let Some(agent) = &tree.agent {
+            println!(
+                "agent {} {} (owner {}) {}",
+                agent.id.unwrap_or_default(),
+                agent.uuid,
+                agent.owner,
+                agent.description.as_deref().unwrap_or_default()
+            );
+        }
+
+        for router in &tree.routers {
+            if let Some(r) = &router.router {
+                println!(
+                    "  router {} {} via {}",
+                    r.id.unwrap_or_default(),
+                    r.router_type.as_deref().unwrap_or("-"),
+                    r.conn_type.as_deref().unwrap_or("-")
+                );
+            }
+
+            for t in &router.tunnels {
+                println!(
+                    "    tunnel {} {} {} -> {} ({}, {}, cost {})",
+                    t.id, t.hostname, t.source, t.ip, t.tunnel_type, t.topology_type, t.cost
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use std::str::FromStr;
+
+use crate::Error;
+
+/// Command line arguments left after the command words. Flags are taken out by name, whatever
+/// their position, and whatever is left over afterwards is an error.
+pub struct Args {
+    args: Vec<String>,
+}
+
+impl Args {
+    pub fn new(args: Vec<String>) -> Args {
+        Args { args }
+    }
+
+    /// Removes `--name` and reports whether it was there.
+    pub fn flag(&mut self, name: &str) -> bool {
+        match self.args.iter().position(|a| a == name) {
+            Some(pos) => {
+                self.args.remove(pos);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes `--name VALUE` or `--name=VALUE` and returns the value.
+    pub fn value(&mut self, name: &str) -> Result<Option<String>, Error> {
+        let prefix = format!("{}=", name);
+
+        if let Some(pos) = self.args.iter().position(|a| a.starts_with(&prefix)) {
+            return Ok(Some(self.args.remove(pos)[prefix.len()..].to_string()));
+        }
+
+        match self.args.iter().position(|a| a == name) {
+            Some(pos) if pos + 1 < self.args.len() => {
+                self.args.remove(pos);
+                Ok(Some(self.args.remove(pos)))
+            }
+            Some(_) => Err(format!("{} needs a value", name).into()),
+            None => Ok(None),
+        }
+    }
+
+    /// Like `value`, parsed into `T`.
+    pub fn parse<T: FromStr>(&mut self, name: &str) -> Result<Option<T>, Error> {
+        self.value(name)?
+            .map(|v| v.parse().map_err(|_| format!("invalid value for {}: {}", name, v).into()))
+            .transpose()
+    }
+
+    /// Like `value`, for flags that must be given.
+    pub fn required(&mut self, name: &str) -> Result<String, Error> {
+        self.value(name)?
+            .ok_or_else(|| format!("{} is required", name).into())
+    }
+
+    /// Takes the next positional argument, described as `what` in the error if it is missing.
+    pub fn positional(&mut self, what: &str) -> Result<String, Error> {
+        match self.args.iter().position(|a| !a.starts_with("--")) {
+            Some(pos) => Ok(self.args.remove(pos)),
+            None => Err(format!("missing {}", what).into()),
+        }
+    }
+
+    pub fn optional_positional(&mut self) -> Option<String> {
+        self.args
+            .iter()
+            .position(|a| !a.starts_with("--"))
+            .map(|pos| self.args.remove(pos))
+    }
+
+    /// Fails on anything that was not consumed.
+    pub fn finish(self) -> Result<(), Error> {
+        match self.args.first() {
+            Some(arg) => Err(format!("unexpected argument: {}", arg).into()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Parses an id given as a number, falling back to `other` for anything else (an email, a UUID,
+/// a permission name).
+pub fn id_or<T>(value: String, id: fn(i32) -> T, other: fn(String) -> T) -> T {
+    match value.parse() {
+        Ok(n) => id(n),
+        Err(_) => other(value),
+    }
+}
@@ -0,0 +1,146 @@
+use std::path::PathBuf;
+use std::{env, fs};
+
+use serde::Deserialize;
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::service::interceptor::InterceptedService;
+use tonic::service::Interceptor;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig};
+use tonic::{Request, Status};
+
+use crate::output::Format;
+use crate::Error;
+
+pub const DEFAULT_SERVER: &str = "http://[::1]:50051";
+
+/// What `tmctl login` leaves behind in `~/.config/tmctl/credentials.toml`.
+#[derive(Deserialize, Default)]
+pub struct Credentials {
+    pub server: String,
+    pub token: String,
+}
+
+impl Credentials {
+    pub fn path() -> Option<PathBuf> {
+        let base = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+        Some(base.join("tmctl").join("credentials.toml"))
+    }
+
+    pub fn load() -> Option<Credentials> {
+        let contents = fs::read_to_string(Credentials::path()?).ok()?;
+        toml::from_str(&contents).ok()
+    }
+
+    /// Writes the credentials, readable only by the current user.
+    pub fn save(&self) -> Result<PathBuf, Error> {
+        let path = Credentials::path().ok_or("cannot find a config directory, set HOME")?;
+        fs::create_dir_all(path.parent().unwrap())?;
+
+        let contents = format!(
+            "server = {}\ntoken = {}\n",
+            toml_str(&self.server),
+            toml_str(&self.token)
+        );
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        std::io::Write::write_all(&mut options.open(&path)?, contents.as_bytes())?;
+
+        Ok(path)
+    }
+
+    pub fn remove() -> Result<bool, Error> {
+        match Credentials::path() {
+            Some(path) if path.exists() => {
+                fs::remove_file(path)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+fn toml_str(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Settings shared by every command.
+pub struct Context {
+    pub server: String,
+    pub token: String,
+    pub output: Format,
+    /// The CA that signed the certificate of an https:// server.
+    pub ca_cert: Option<PathBuf>,
+    /// The name the certificate is checked against, when it is not the one in the server address.
+    pub tls_domain: Option<String>,
+}
+
+impl Context {
+    /// The server comes from `--server`, then `TUNNEL_MANAGER_SERVER`, then the last login. The
+    /// cached token is only sent to the server it was issued by. `--ca-cert` and `--tls-domain`
+    /// fall back to `TUNNEL_MANAGER_CA_CERT` and `TUNNEL_MANAGER_TLS_DOMAIN`.
+    pub fn new(server: Option<String>, ca_cert: Option<PathBuf>, tls_domain: Option<String>, output: Format) -> Context {
+        let credentials = Credentials::load().unwrap_or_default();
+
+        let server = server
+            .or_else(|| env::var("TUNNEL_MANAGER_SERVER").ok())
+            .or_else(|| Some(credentials.server.clone()).filter(|s| !s.is_empty()))
+            .unwrap_or_else(|| DEFAULT_SERVER.to_string());
+
+        let token = env::var("TUNNEL_MANAGER_TOKEN").unwrap_or_else(|_| match credentials.server == server {
+            true => credentials.token,
+            false => String::new(),
+        });
+
+        Context {
+            server,
+            token,
+            output,
+            ca_cert: ca_cert.or_else(|| env::var_os("TUNNEL_MANAGER_CA_CERT").map(PathBuf::from)),
+            tls_domain: tls_domain.or_else(|| env::var("TUNNEL_MANAGER_TLS_DOMAIN").ok()),
+        }
+    }
+
+    pub async fn channel(&self) -> Result<InterceptedService<Channel, AuthInterceptor>, Error> {
+        let mut endpoint = Channel::from_shared(self.server.clone())?;
+
+        if self.server.starts_with("https://") {
+            // tmctl carries no list of public CAs, so the server's has to be given.
+            let ca_cert = self
+                .ca_cert
+                .as_ref()
+                .ok_or("an https:// server needs --ca-cert or TUNNEL_MANAGER_CA_CERT")?;
+            let pem = fs::read(ca_cert).map_err(|err| format!("cannot read {}: {}", ca_cert.display(), err))?;
+
+            let mut tls = ClientTlsConfig::new().ca_certificate(Certificate::from_pem(pem));
+            if let Some(domain) = &self.tls_domain {
+                tls = tls.domain_name(domain);
+            }
+            endpoint = endpoint.tls_config(tls)?;
+        }
+
+        let channel = endpoint.connect().await?;
+        let token = self.token.parse()?;
+
+        Ok(InterceptedService::new(channel, AuthInterceptor { token }))
+    }
+}
+
+/// Attaches the token to every request.
+#[derive(Clone)]
+pub struct AuthInterceptor {
+    token: MetadataValue<Ascii>,
+}
+
+impl Interceptor for AuthInterceptor {
+    fn call(&mut self, mut req: Request<()>) -> Result<Request<()>, Status> {
+        req.metadata_mut().insert("authorization", self.token.clone());
+        Ok(req)
+    }
+}
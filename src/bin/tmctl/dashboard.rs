@@ -0,0 +1,377 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::process::Command;
+use std::{env, fs};
+
+use serde::Deserialize;
+
+use tunnel_manager::api::mesh_client::MeshClient;
+use tunnel_manager::api::router_client::RouterClient;
+use tunnel_manager::api::router_request::IdOrAgent;
+use tunnel_manager::api::tunnel_client::TunnelClient;
+use tunnel_manager::api::{MeshStatus, RouterPushStatus, RouterRequest, TunnelResponse, TunnelUpdateRequest};
+
+use crate::args::Args;
+use crate::config::Context;
+use crate::output::format_timestamp;
+use crate::terminal::{self, Key, Keys, RawMode};
+use crate::Error;
+
+pub const USAGE: &str = "\
+  dashboard                            live view of agents, routers and tunnels";
+
+const HELP: &str = "up/down move  p re-push router  e edit tunnel  q quit";
+
+enum Row {
+    Agent(String),
+    Router(i32, String),
+    Tunnel(Box<TunnelResponse>, String),
+}
+
+impl Row {
+    fn text(&self) -> &str {
+        match self {
+            Row::Agent(text) | Row::Router(_, text) | Row::Tunnel(_, text) => text,
+        }
+    }
+}
+
+struct Dashboard {
+    server: String,
+    status: Option<MeshStatus>,
+    rows: Vec<Row>,
+    selected: usize,
+    offset: usize,
+    message: String,
+}
+
+pub async fn run(ctx: &Context, args: Args) -> Result<(), Error> {
+    args.finish()?;
+
+    let channel = ctx.channel().await?;
+    let mut mesh = MeshClient::new(channel.clone());
+    let mut routers = RouterClient::new(channel.clone());
+    let mut tunnels = TunnelClient::new(channel);
+
+    let mut updates = mesh.watch(()).await?.into_inner();
+    let mut keys = Keys::spawn();
+    let mut raw = Some(RawMode::enable()?);
+
+    let mut dashboard = Dashboard {
+        server: ctx.server.clone(),
+        status: None,
+        rows: Vec::new(),
+        selected: 0,
+        offset: 0,
+        message: "Waiting for the first update...".to_string(),
+    };
+
+    loop {
+        dashboard.render()?;
+
+        tokio::select! {
+            update = updates.message() => match update {
+                Ok(Some(status)) => dashboard.update(status),
+                Ok(None) => return Err("the server closed the watch stream".into()),
+                Err(status) => return Err(status.into()),
+            },
+            key = keys.next() => match key {
+                None | Some(Key::Quit) | Some(Key::Char('q')) => return Ok(()),
+                Some(Key::Up) | Some(Key::Char('k')) => dashboard.move_by(-1),
+                Some(Key::Down) | Some(Key::Char('j')) => dashboard.move_by(1),
+                Some(Key::PageUp) => dashboard.move_by(-10),
+                Some(Key::PageDown) => dashboard.move_by(10),
+                Some(Key::Home) => dashboard.move_by(isize::MIN / 2),
+                Some(Key::End) => dashboard.move_by(isize::MAX / 2),
+                Some(Key::Char('p')) => {
+                    dashboard.message = match dashboard.selected_router() {
+                        Some(router_id) => match routers
+                            .repush(RouterRequest {
+                                id_or_agent: Some(IdOrAgent::Id(router_id)),
+                                ..Default::default()
+                            })
+                            .await
+                        {
+                            Ok(_) => format!("Router {} queued for re-push.", router_id),
+                            Err(status) => format!("Re-push failed: {}", status.message()),
+                        },
+                        None => "Select a router or tunnel to re-push.".to_string(),
+                    };
+                }
+                Some(Key::Char('e')) => {
+                    let Some(tunnel) = dashboard.selected_tunnel() else {
+                        dashboard.message = "Select a tunnel to edit.".to_string();
+                        continue;
+                    };
+
+                    keys.pause();
+                    drop(raw.take());
+                    let edited = edit_tunnel(&tunnel);
+                    raw = Some(RawMode::enable()?);
+                    keys.resume();
+
+                    dashboard.message = match edited {
+                        Ok(Some(update)) => match tunnels.update(update).await {
+                            Ok(_) => format!("Tunnel {} updated.", tunnel.id),
+                            Err(status) => format!("Update failed: {}", status.message()),
+                        },
+                        Ok(None) => format!("Tunnel {} unchanged.", tunnel.id),
+                        Err(err) => format!("Edit failed: {}", err),
+                    };
+                }
+                Some(_) => {}
+            },
+        }
+    }
+}
+
+impl Dashboard {
+    fn update(&mut self, status: MeshStatus) {
+        let selected_key = self.rows.get(self.selected).map(|r| r.text().to_string());
+
+        self.rows = rows(&status);
+        self.status = Some(status);
+        self.message.clear();
+
+        // Stay on the same row if it still exists.
+        if let Some(key) = selected_key {
+            if let Some(pos) = self.rows.iter().position(|r| r.text() == key) {
+                self.selected = pos;
+            }
+        }
+        self.move_by(0);
+    }
+
+    fn move_by(&mut self, delta: isize) {
+        let last = self.rows.len().saturating_sub(1) as isize;
+        self.selected = (self.selected as isize + delta).clamp(0, last) as usize;
+    }
+
+    /// The selected router, or the router of the selected tunnel.
+    fn selected_router(&self) -> Option<i32> {
+        match self.rows.get(self.selected)? {
+            Row::Router(id, _) => Some(*id),
+            Row::Tunnel(tunnel, _) => Some(tunnel.router),
+            Row::Agent(_) => None,
+        }
+    }
+
+    fn selected_tunnel(&self) -> Option<TunnelResponse> {
+        match self.rows.get(self.selected)? {
+            Row::Tunnel(tunnel, _) => Some(*tunnel.clone()),
+            _ => None,
+        }
+    }
+
+    fn render(&mut self) -> io::Result<()> {
+        let (height, width) = terminal::size();
+        let body = height.saturating_sub(3).max(1);
+
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if self.selected >= self.offset + body {
+            self.offset = self.selected + 1 - body;
+        }
+
+        let mut out = String::from("\x1b[H\x1b[2J");
+
+        let header = match &self.status {
+            Some(status) => {
+                let routers: usize = status.agents.iter().filter_map(|a| a.tree.as_ref()).map(|t| t.routers.len()).sum();
+                let tunnels: usize = status
+                    .agents
+                    .iter()
+                    .filter_map(|a| a.tree.as_ref())
+                    .flat_map(|t| &t.routers)
+                    .map(|r| r.tunnels.len())
+                    .sum();
+                format!(
+                    "Tunnel Manager  {}  {} agents, {} routers, {} tunnels  updated {}",
+                    self.server,
+                    status.agents.len(),
+                    routers,
+                    tunnels,
+                    status.generated_at.as_ref().map(format_timestamp).unwrap_or_default()
+                )
+            }
+            None => format!("Tunnel Manager  {}", self.server),
+        };
+        line(&mut out, &format!("\x1b[1m{}\x1b[0m", clip(&header, width)));
+        line(&mut out, "");
+
+        for (i, row) in self.rows.iter().enumerate().skip(self.offset).take(body) {
+            let text = clip(row.text(), width);
+            match i == self.selected {
+                true => line(&mut out, &format!("\x1b[7m{:<width$}\x1b[0m", text, width = width)),
+                false => line(&mut out, &text),
+            }
+        }
+
+        let footer = match self.message.is_empty() {
+            true => HELP.to_string(),
+            false => format!("{}  |  {}", self.message, HELP),
+        };
+        out.push_str(&format!("\x1b[{};1H\x1b[2m{}\x1b[0m", height, clip(&footer, width)));
+
+        let mut stdout = io::stdout();
+        stdout.write_all(out.as_bytes())?;
+        stdout.flush()
+    }
+}
+
+/// Flattens the status into one line per agent, router and tunnel.
+fn rows(status: &MeshStatus) -> Vec<Row> {
+    let mut rows = Vec::new();
+
+    for agent_status in &status.agents {
+        let Some(tree) = &agent_status.tree else { continue };
+        let pushes: HashMap<i32, &RouterPushStatus> = agent_status.pushes.iter().map(|p| (p.router, p)).collect();
+
+        if let Some(agent) = &tree.agent {
+            let seen = agent_status
+                .last_seen
+                .as_ref()
+                .map(|t| format!(", last seen {}", format_timestamp(t)))
+                .unwrap_or_default();
+            rows.push(Row::Agent(format!(
+                "agent {} {}  [{}{}]  owner {}  {}",
+                agent.id.unwrap_or_default(),
+                agent.uuid,
+                agent_status.state,
+                seen,
+                agent.owner,
+                agent.description.as_deref().unwrap_or_default()
+            )));
+        }
+
+        for router_tree in &tree.routers {
+            let Some(router) = &router_tree.router else { continue };
+            let router_id = router.id.unwrap_or_default();
+            let push = match pushes.get(&router_id) {
+                Some(p) if p.success => format!(
+                    "pushed {}",
+                    p.finished_at.as_ref().map(format_timestamp).unwrap_or_default()
+                ),
+                Some(p) => format!("push failed: {}", p.error),
+                None => "no push reported".to_string(),
+            };
+
+            rows.push(Row::Router(
+                router_id,
+                format!(
+                    "  router {}  {} via {}  {}",
+                    router_id,
+                    router.router_type.as_deref().unwrap_or("-"),
+                    router.conn_type.as_deref().unwrap_or("-"),
+                    push
+                ),
+            ));
+
+            for t in &router_tree.tunnels {
+                let text = format!(
+                    "    tunnel {}  {}  {} -> {}{}  {} {} cost {}",
+                    t.id,
+                    t.hostname,
+                    t.source,
+                    t.ip,
+                    if t.dynamic_ip { " (dynamic)" } else { "" },
+                    t.tunnel_type,
+                    t.topology_type,
+                    t.cost
+                );
+                rows.push(Row::Tunnel(Box::new(t.clone()), text));
+            }
+        }
+    }
+
+    rows
+}
+
+fn line(out: &mut String, text: &str) {
+    out.push_str(text);
+    out.push_str("\r\n");
+}
+
+fn clip(text: &str, width: usize) -> String {
+    text.chars().take(width).collect()
+}
+
+/// The editable fields of a tunnel, as written to and read back from the editor.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TunnelEdit {
+    router: i32,
+    hostname: String,
+    ip: String,
+    dynamic_ip: bool,
+    ip_class: i32,
+    source: String,
+    cost: i32,
+    tunnel_type: String,
+    topology_type: String,
+    description: String,
+}
+
+/// Opens the tunnel in `$EDITOR` and returns the update for whatever was changed.
+fn edit_tunnel(tunnel: &TunnelResponse) -> Result<Option<TunnelUpdateRequest>, Error> {
+    let path = env::temp_dir().join(format!("tmctl-tunnel-{}-{}.toml", tunnel.id, std::process::id()));
+    let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+
+    fs::write(
+        &path,
+        format!(
+            "# Tunnel {}. Save and quit to apply, or quit without saving to cancel.\n\
+             router = {}\nhostname = {}\nip = {}\ndynamic_ip = {}\nip_class = {}\nsource = {}\n\
+             cost = {}\ntunnel_type = {}\ntopology_type = {}\ndescription = {}\n",
+            tunnel.id,
+            tunnel.router,
+            quote(&tunnel.hostname),
+            quote(&tunnel.ip),
+            tunnel.dynamic_ip,
+            tunnel.ip_class,
+            quote(&tunnel.source),
+            tunnel.cost,
+            quote(&tunnel.tunnel_type),
+            quote(&tunnel.topology_type),
+            quote(&tunnel.description),
+        ),
+    )?;
+
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let status = Command::new("sh").arg("-c").arg(format!("{} \"$1\"", editor)).arg("sh").arg(&path).status();
+    let contents = fs::read_to_string(&path);
+    fs::remove_file(&path).ok();
+
+    if !status?.success() {
+        return Err("the editor exited with an error".into());
+    }
+
+    let edit: TunnelEdit = toml::from_str(&contents?).map_err(|e| e.to_string())?;
+    let changed = |old: &String, new: String| Some(new).filter(|new| new != old);
+    let changed_int = |old: i32, new: i32| Some(new).filter(|new| *new != old);
+
+    let update = TunnelUpdateRequest {
+        id: tunnel.id,
+        version: None,
+        router: changed_int(tunnel.router, edit.router),
+        ip: changed(&tunnel.ip, edit.ip),
+        dynamic_ip: Some(edit.dynamic_ip).filter(|d| *d != tunnel.dynamic_ip),
+        ip_class: changed_int(tunnel.ip_class, edit.ip_class),
+        hostname: changed(&tunnel.hostname, edit.hostname),
+        description: changed(&tunnel.description, edit.description),
+        source: changed(&tunnel.source, edit.source),
+        cost: changed_int(tunnel.cost, edit.cost),
+        tunnel_type: changed(&tunnel.tunnel_type, edit.tunnel_type),
+        topology_type: changed(&tunnel.topology_type, edit.topology_type),
+        dry_run: false,
+    };
+
+    let unchanged = TunnelUpdateRequest {
+        id: tunnel.id,
+        ..Default::default()
+    };
+
+    Ok(Some(update).filter(|u| *u != unchanged))
+}
@@ -0,0 +1,254 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::process;
+use std::env;
+use std::path::PathBuf;
+
+use prost_types::Timestamp;
+use tonic::Status;
+
+use tunnel_manager::api::auth_client::AuthClient;
+use tunnel_manager::api::{ConfigDiff, LoginRequest};
+
+use crate::args::Args;
+use crate::config::{Context, Credentials};
+use crate::output::Format;
+
+mod agents;
+mod args;
+mod config;
+mod dashboard;
+mod memberships;
+mod mesh;
+mod output;
+mod permissions;
+mod routers;
+mod terminal;
+mod tunnels;
+mod users;
+
+pub type Error = Box<dyn std::error::Error>;
+
+const GLOBAL_USAGE: &str = "\
+usage: tmctl [--server URL] [--ca-cert PATH] [--tls-domain NAME] [--output table|json|yaml] <command>
+
+commands:
+  login [EMAIL] [--token TOKEN]        log in and cache the token for this server
+  logout                               forget the cached token";
+
+const LIST_USAGE: &str = "\
+list options:
+  --page-size N  --page-token TOKEN  --order-by \"FIELD [asc|desc]\"  --updated-since TIME  --all
+
+environment:
+  TUNNEL_MANAGER_SERVER     server address (default http://[::1]:50051, or the last login)
+  TUNNEL_MANAGER_CA_CERT    PEM file of the CA that signed an https:// server's certificate
+  TUNNEL_MANAGER_TLS_DOMAIN name to check the server's certificate against, if not the address's
+  TUNNEL_MANAGER_TOKEN      token sent in the authorization header, instead of the cached one
+  TUNNEL_MANAGER_PASSWORD   password for login and add commands, instead of prompting";
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    if let Err(err) = run(args).await {
+        match err.downcast_ref::<Status>() {
+            Some(status) => eprintln!("tmctl: {:?}: {}", status.code(), status.message()),
+            None => eprintln!("tmctl: {}", err),
+        }
+        process::exit(1);
+    }
+}
+
+async fn run(args: Vec<String>) -> Result<(), Error> {
+    let mut args = Args::new(args);
+    let server = args.value("--server")?;
+    let ca_cert = args.value("--ca-cert")?.map(PathBuf::from);
+    let tls_domain = args.value("--tls-domain")?;
+    let output: Format = args.parse("--output")?.unwrap_or(Format::Table);
+
+    if args.flag("--help") || args.flag("-h") {
+        println!("{}", usage());
+        return Ok(());
+    }
+
+    let ctx = Context::new(server, ca_cert, tls_domain, output);
+    let service = args.optional_positional().ok_or_else(usage)?;
+
+    match service.as_str() {
+        "login" => return login(&ctx, args).await,
+        "logout" => {
+            args.finish()?;
+            match Credentials::remove()? {
+                true => eprintln!("Logged out."),
+                false => eprintln!("Not logged in."),
+            }
+            return Ok(());
+        }
+        "dashboard" => return dashboard::run(&ctx, args).await,
+        _ => {}
+    }
+
+    let command = args.optional_positional().ok_or_else(usage)?;
+
+    match service.as_str() {
+        "users" => users::run(&ctx, &command, args).await,
+        "permissions" => permissions::run(&ctx, &command, args).await,
+        "memberships" => memberships::run(&ctx, &command, args).await,
+        "agents" => agents::run(&ctx, &command, args).await,
+        "routers" => routers::run(&ctx, &command, args).await,
+        "tunnels" => tunnels::run(&ctx, &command, args).await,
+        "mesh" => mesh::run(&ctx, &command, args).await,
+        _ => Err(usage()),
+    }
+}
+
+pub fn usage() -> Error {
+    let commands: Vec<String> = [
+        users::USAGE,
+        permissions::USAGE,
+        memberships::USAGE,
+        agents::USAGE,
+        routers::USAGE,
+        tunnels::USAGE,
+        mesh::USAGE,
+        dashboard::USAGE,
+    ]
+    .iter()
+    .map(|usage| format!("  {}", usage))
+    .collect();
+
+    format!(
+        "{}\n{}\n\n{}\n\n{}\n\n{}",
+        GLOBAL_USAGE,
+        commands.join("\n"),
+        routers::OPTIONS_USAGE,
+        tunnels::OPTIONS_USAGE,
+        LIST_USAGE
+    )
+    .into()
+}
+
+/// Logs in with an email and password, or stores a token handed out some other way, and caches
+/// it for the server in use.
+async fn login(ctx: &Context, mut args: Args) -> Result<(), Error> {
+    let token = args.value("--token")?;
+    let email = args.optional_positional();
+    args.finish()?;
+
+    let token = match (token, email) {
+        (Some(token), None) => token,
+        (None, Some(email)) => {
+            let password = read_password("Password: ")?;
+            let channel = Context {
+                server: ctx.server.clone(),
+                token: String::new(),
+                output: ctx.output,
+                ca_cert: ctx.ca_cert.clone(),
+                tls_domain: ctx.tls_domain.clone(),
+            }
+            .channel()
+            .await?;
+
+            let response = AuthClient::new(channel)
+                .login(LoginRequest { email, password })
+                .await?
+                .into_inner();
+
+            if response.token.is_empty() {
+                return Err("the server accepted the login but returned no token".into());
+            }
+            response.token
+        }
+        _ => return Err("usage: tmctl login EMAIL | tmctl login --token TOKEN".into()),
+    };
+
+    let path = Credentials {
+        server: ctx.server.clone(),
+        token,
+    }
+    .save()?;
+
+    eprintln!("Logged in to {}, token cached in {}.", ctx.server, path.display());
+
+    Ok(())
+}
+
+/// Paging, ordering and `--updated-since` flags shared by every list command.
+pub struct ListOptions {
+    pub updated_since: Option<Timestamp>,
+    pub page_size: i32,
+    pub page_token: String,
+    pub order_by: String,
+    pub all: bool,
+}
+
+pub fn list_options(args: &mut Args) -> Result<ListOptions, Error> {
+    Ok(ListOptions {
+        updated_since: args
+            .value("--updated-since")?
+            .map(|s| output::parse_timestamp(&s))
+            .transpose()?,
+        page_size: args.parse("--page-size")?.unwrap_or_default(),
+        page_token: args.value("--page-token")?.unwrap_or_default(),
+        order_by: args.value("--order-by")?.unwrap_or_default(),
+        all: args.flag("--all"),
+    })
+}
+
+/// Tells the user there are more pages, on stderr so piped output stays parseable.
+pub fn print_next_page(token: &str) {
+    if !token.is_empty() {
+        eprintln!("More results available: --page-token {} (or --all)", token);
+    }
+}
+
+/// Prints what a `--dry-run` change would do to router configs, as one unified diff.
+pub fn print_config_diffs(diffs: &[ConfigDiff]) {
+    if diffs.is_empty() {
+        println!("No router configuration would change.");
+        return;
+    }
+
+    for diff in diffs {
+        print!("{}", diff.diff);
+        if !diff.render_error.is_empty() {
+            println!("# router {} could no longer be rendered: {}", diff.router, diff.render_error);
+        }
+    }
+}
+
+/// Reads a password from `TUNNEL_MANAGER_PASSWORD`, or prompts for it without echoing.
+pub fn read_password(prompt: &str) -> Result<String, Error> {
+    if let Ok(password) = env::var("TUNNEL_MANAGER_PASSWORD") {
+        return Ok(password);
+    }
+
+    eprint!("{}", prompt);
+    io::stderr().flush()?;
+
+    let no_echo = match io::stdin().is_terminal() {
+        true => Some(terminal::NoEcho::enable()?),
+        false => None,
+    };
+
+    let mut password = String::new();
+    let result = io::stdin().lock().read_line(&mut password);
+
+    if no_echo.is_some() {
+        drop(no_echo);
+        eprintln!();
+    }
+
+    result?;
+    Ok(password.trim_end_matches(['\r', '\n']).to_string())
+}
+
+pub fn confirm(question: &str) -> io::Result<bool> {
+    print!("\n{} [y/N] ", question);
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
@@ -0,0 +1,103 @@
+use tunnel_manager::api::permission_membership_client::PermissionMembershipClient;
+use tunnel_manager::api::permission_membership_request::IdPermissionOrUserid;
+use tunnel_manager::api::{PermissionMembershipData, PermissionMembershipRequest};
+
+use crate::args::Args;
+use crate::config::Context;
+use crate::output::{self, Record};
+use crate::Error;
+
+pub const USAGE: &str = "\
+  memberships list [--permission ID | --user ID]
+  memberships add --permission ID --user ID
+  memberships update ID [--permission ID] [--user ID]
+  memberships delete ID";
+
+impl From<&PermissionMembershipData> for Record {
+    fn from(m: &PermissionMembershipData) -> Record {
+        Record::new()
+            .field("id", &m.id)
+            .field("permission", m.permission)
+            .field("user", m.user_id)
+    }
+}
+
+pub async fn run(ctx: &Context, command: &str, mut args: Args) -> Result<(), Error> {
+    let mut client = PermissionMembershipClient::new(ctx.channel().await?);
+
+    match command {
+        "list" => {
+            let permission = args.parse("--permission")?;
+            let user = args.parse("--user")?;
+            args.finish()?;
+
+            let response = match (permission, user) {
+                (None, None) => client.list(()).await?,
+                (Some(permission), None) => {
+                    client
+                        .get_permission_members(membership_request(IdPermissionOrUserid::Permission(permission)))
+                        .await?
+                }
+                (None, Some(user)) => {
+                    client
+                        .get_user_permissions(membership_request(IdPermissionOrUserid::UserId(user)))
+                        .await?
+                }
+                (Some(_), Some(_)) => return Err("use either --permission or --user".into()),
+            };
+
+            let memberships: Vec<Record> = response.into_inner().memberships.iter().map(Record::from).collect();
+            output::print_list(ctx.output, &memberships);
+        }
+        "add" => {
+            let permission = args.parse("--permission")?.ok_or("--permission is required")?;
+            let user_id = args.parse("--user")?.ok_or("--user is required")?;
+            args.finish()?;
+
+            let membership = client
+                .add(PermissionMembershipData {
+                    id: None,
+                    permission,
+                    user_id,
+                })
+                .await?
+                .into_inner();
+            output::print_one(ctx.output, &(&membership).into());
+        }
+        "update" => {
+            let permission = args.parse("--permission")?.unwrap_or_default();
+            let user_id = args.parse("--user")?.unwrap_or_default();
+            let id = args.positional("membership id")?.parse().map_err(|_| "membership id must be a number")?;
+            args.finish()?;
+
+            let membership = client
+                .update(PermissionMembershipData {
+                    id: Some(id),
+                    permission,
+                    user_id,
+                })
+                .await?
+                .into_inner();
+            output::print_one(ctx.output, &(&membership).into());
+        }
+        "delete" => {
+            let id = args.positional("membership id")?.parse().map_err(|_| "membership id must be a number")?;
+            args.finish()?;
+
+            let membership = client
+                .delete(membership_request(IdPermissionOrUserid::Id(id)))
+                .await?
+                .into_inner();
+            output::print_one(ctx.output, &(&membership).into());
+        }
+        _ => return Err(crate::usage()),
+    }
+
+    Ok(())
+}
+
+fn membership_request(id: IdPermissionOrUserid) -> PermissionMembershipRequest {
+    PermissionMembershipRequest {
+        id_permission_or_userid: Some(id),
+    }
+}
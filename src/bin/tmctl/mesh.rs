@@ -0,0 +1,121 @@
+use std::fs;
+
+use tunnel_manager::api::mesh_client::MeshClient;
+use tunnel_manager::api::{MeshApplyRequest, MeshPlan};
+
+use crate::args::Args;
+use crate::config::Context;
+use crate::{confirm, Error};
+
+pub const USAGE: &str = "\
+  mesh export [FILE]                   write the current mesh as TOML to FILE or stdout
+  mesh plan FILE                       show what applying FILE would change
+  mesh apply FILE [--auto-approve]     show the plan for FILE, then apply it";
+
+pub async fn run(ctx: &Context, command: &str, mut args: Args) -> Result<(), Error> {
+    let mut client = MeshClient::new(ctx.channel().await?);
+
+    match command {
+        "export" => {
+            let file = args.optional_positional();
+            args.finish()?;
+
+            let document = client.export(()).await?.into_inner().document;
+            match file {
+                Some(file) => fs::write(file, document)?,
+                None => print!("{}", document),
+            }
+        }
+        "plan" => {
+            let file = args.positional("mesh file")?;
+            args.finish()?;
+
+            let plan = client
+                .apply(MeshApplyRequest {
+                    document: fs::read_to_string(file)?,
+                    dry_run: true,
+                    ..Default::default()
+                })
+                .await?
+                .into_inner();
+
+            print_plan(&plan);
+        }
+        "apply" => {
+            let auto_approve = args.flag("--auto-approve");
+            let file = args.positional("mesh file")?;
+            args.finish()?;
+
+            let document = fs::read_to_string(file)?;
+            let plan = client
+                .apply(MeshApplyRequest {
+                    document: document.clone(),
+                    dry_run: true,
+                    ..Default::default()
+                })
+                .await?
+                .into_inner();
+
+            print_plan(&plan);
+
+            if plan.changes.is_empty() {
+                return Ok(());
+            }
+
+            if !auto_approve && !confirm("Apply these changes?")? {
+                println!("Apply cancelled.");
+                return Ok(());
+            }
+
+            let applied = client
+                .apply(MeshApplyRequest {
+                    document,
+                    dry_run: false,
+                    expected_plan_hash: plan.plan_hash,
+                })
+                .await?
+                .into_inner();
+
+            println!("Applied {} changes.", applied.changes.len());
+        }
+        _ => return Err(crate::usage()),
+    }
+
+    Ok(())
+}
+
+fn print_plan(plan: &MeshPlan) {
+    if plan.changes.is_empty() {
+        println!("No changes. The mesh matches the document.");
+        return;
+    }
+
+    let (mut adds, mut updates, mut deletes) = (0, 0, 0);
+
+    for change in &plan.changes {
+        let symbol = match change.action.as_str() {
+            "add" => {
+                adds += 1;
+                "+"
+            }
+            "delete" => {
+                deletes += 1;
+                "-"
+            }
+            _ => {
+                updates += 1;
+                "~"
+            }
+        };
+
+        println!("{} {} {}", symbol, change.kind, change.key);
+        for detail in &change.details {
+            println!("    {}", detail);
+        }
+    }
+
+    println!(
+        "\nPlan: {} to add, {} to change, {} to delete.",
+        adds, updates, deletes
+    );
+}
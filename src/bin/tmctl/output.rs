@@ -0,0 +1,358 @@
+use std::fmt::Write;
+use std::str::FromStr;
+
+use prost_types::Timestamp;
+
+/// How results are printed: aligned columns for people, JSON or YAML for scripts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Table,
+    Json,
+    Yaml,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "table" => Ok(Format::Table),
+            "json" => Ok(Format::Json),
+            "yaml" => Ok(Format::Yaml),
+            _ => Err(format!("unknown output format {}, use table, json or yaml", s)),
+        }
+    }
+}
+
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Str(String),
+    List(Vec<Record>),
+}
+
+impl From<i32> for Value {
+    fn from(v: i32) -> Value {
+        Value::Int(v as i64)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(v: bool) -> Value {
+        Value::Bool(v)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(v: &str) -> Value {
+        Value::Str(v.to_string())
+    }
+}
+
+impl From<&String> for Value {
+    fn from(v: &String) -> Value {
+        Value::Str(v.clone())
+    }
+}
+
+impl<T: Into<Value> + Clone> From<&Option<T>> for Value {
+    fn from(v: &Option<T>) -> Value {
+        match v {
+            Some(v) => v.clone().into(),
+            None => Value::Null,
+        }
+    }
+}
+
+impl From<Option<&str>> for Value {
+    fn from(v: Option<&str>) -> Value {
+        v.map(Value::from).unwrap_or(Value::Null)
+    }
+}
+
+impl From<String> for Value {
+    fn from(v: String) -> Value {
+        Value::Str(v)
+    }
+}
+
+impl From<&Option<Timestamp>> for Value {
+    fn from(v: &Option<Timestamp>) -> Value {
+        match v {
+            Some(t) => Value::Str(format_timestamp(t)),
+            None => Value::Null,
+        }
+    }
+}
+
+/// One result, as ordered field/value pairs.
+pub struct Record(pub Vec<(&'static str, Value)>);
+
+impl Record {
+    pub fn new() -> Record {
+        Record(Vec::new())
+    }
+
+    pub fn field(mut self, name: &'static str, value: impl Into<Value>) -> Record {
+        self.0.push((name, value.into()));
+        self
+    }
+}
+
+pub fn print_one(format: Format, record: &Record) {
+    let mut out = String::new();
+
+    match format {
+        Format::Table => write_table(&mut out, std::slice::from_ref(record)),
+        Format::Json => {
+            write_json_record(&mut out, record);
+            out.push('\n');
+        }
+        Format::Yaml => write_yaml_record(&mut out, record, 0, false),
+    }
+
+    print!("{}", out);
+}
+
+pub fn print_list(format: Format, records: &[Record]) {
+    let mut out = String::new();
+
+    match format {
+        Format::Table => write_table(&mut out, records),
+        Format::Json => {
+            write_json_list(&mut out, records);
+            out.push('\n');
+        }
+        Format::Yaml if records.is_empty() => out.push_str("[]\n"),
+        Format::Yaml => write_yaml_list(&mut out, records, 0),
+    }
+
+    print!("{}", out);
+}
+
+fn write_table(out: &mut String, records: &[Record]) {
+    let Some(first) = records.first() else {
+        out.push_str("No results.\n");
+        return;
+    };
+
+    let headers: Vec<&str> = first
+        .0
+        .iter()
+        .filter(|(_, v)| !matches!(v, Value::List(_)))
+        .map(|(k, _)| *k)
+        .collect();
+    let rows: Vec<Vec<String>> = records
+        .iter()
+        .map(|r| {
+            r.0.iter()
+                .filter(|(_, v)| !matches!(v, Value::List(_)))
+                .map(|(_, v)| table_cell(v))
+                .collect()
+        })
+        .collect();
+
+    let widths: Vec<usize> = headers
+        .iter()
+        .enumerate()
+        .map(|(i, h)| {
+            rows.iter()
+                .map(|r| r[i].chars().count())
+                .chain(std::iter::once(h.len()))
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+
+    let mut write_row = |cells: Vec<String>| {
+        let line = cells
+            .iter()
+            .zip(&widths)
+            .map(|(c, w)| format!("{:<width$}", c, width = w))
+            .collect::<Vec<_>>()
+            .join("  ");
+        out.push_str(line.trim_end());
+        out.push('\n');
+    };
+
+    write_row(headers.iter().map(|h| h.to_uppercase()).collect());
+    for row in rows {
+        write_row(row);
+    }
+}
+
+fn table_cell(value: &Value) -> String {
+    match value {
+        Value::Null => "-".to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Int(i) => i.to_string(),
+        Value::Str(s) => s.clone(),
+        Value::List(l) => format!("[{}]", l.len()),
+    }
+}
+
+fn write_json_list(out: &mut String, records: &[Record]) {
+    out.push('[');
+    for (i, record) in records.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        write_json_record(out, record);
+    }
+    out.push(']');
+}
+
+fn write_json_record(out: &mut String, record: &Record) {
+    out.push('{');
+    for (i, (key, value)) in record.0.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        write_json_str(out, key);
+        out.push(':');
+        match value {
+            Value::Null => out.push_str("null"),
+            Value::Bool(b) => write!(out, "{}", b).unwrap(),
+            Value::Int(n) => write!(out, "{}", n).unwrap(),
+            Value::Str(s) => write_json_str(out, s),
+            Value::List(l) => write_json_list(out, l),
+        }
+    }
+    out.push('}');
+}
+
+/// Writes a double-quoted, escaped string. The result is valid in both JSON and YAML.
+fn write_json_str(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn write_yaml_list(out: &mut String, records: &[Record], indent: usize) {
+    for record in records {
+        write_yaml_record(out, record, indent, true);
+    }
+}
+
+/// Writes `record` as a YAML mapping at `indent`, as a sequence item if `item` is set.
+fn write_yaml_record(out: &mut String, record: &Record, indent: usize, item: bool) {
+    for (i, (key, value)) in record.0.iter().enumerate() {
+        out.push_str(&" ".repeat(indent));
+        if item {
+            out.push_str(if i == 0 { "- " } else { "  " });
+        }
+        write!(out, "{}:", key).unwrap();
+
+        let nested = indent + if item { 2 } else { 0 };
+        match value {
+            Value::Null => out.push_str(" null\n"),
+            Value::Bool(b) => writeln!(out, " {}", b).unwrap(),
+            Value::Int(n) => writeln!(out, " {}", n).unwrap(),
+            Value::Str(s) => {
+                out.push(' ');
+                write_json_str(out, s);
+                out.push('\n');
+            }
+            Value::List(l) if l.is_empty() => out.push_str(" []\n"),
+            Value::List(l) => {
+                out.push('\n');
+                write_yaml_list(out, l, nested + 2);
+            }
+        }
+    }
+}
+
+/// Formats a timestamp as RFC 3339 in UTC.
+pub fn format_timestamp(t: &Timestamp) -> String {
+    let days = t.seconds.div_euclid(86400);
+    let secs = t.seconds.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// Parses `2024-05-01`, `2024-05-01T12:00:00Z`, `2024-05-01T12:00:00+02:00` or unix seconds.
+pub fn parse_timestamp(s: &str) -> Result<Timestamp, String> {
+    let invalid = || format!("invalid time {}, use RFC 3339 or unix seconds", s);
+
+    if let Ok(seconds) = s.parse::<i64>() {
+        return Ok(Timestamp { seconds, nanos: 0 });
+    }
+
+    let (date, time) = s.split_once(['T', ' ']).unwrap_or((s, "00:00:00Z"));
+    let date: Vec<i64> = date.split('-').map(|p| p.parse()).collect::<Result<_, _>>().map_err(|_| invalid())?;
+    if date.len() != 3 {
+        return Err(invalid());
+    }
+
+    let (clock, offset) = match time.find(['Z', 'z', '+', '-']) {
+        Some(pos) => time.split_at(pos),
+        None => (time, "Z"),
+    };
+    let clock: Vec<i64> = clock
+        .split(':')
+        .map(|p| p.split('.').next().unwrap_or(p).parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| invalid())?;
+    if clock.len() != 3 {
+        return Err(invalid());
+    }
+
+    let offset = match offset {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = if offset.starts_with('-') { -1 } else { 1 };
+            let (h, m) = offset[1..].split_once(':').ok_or_else(invalid)?;
+            sign * (h.parse::<i64>().map_err(|_| invalid())? * 3600 + m.parse::<i64>().map_err(|_| invalid())? * 60)
+        }
+    };
+
+    Ok(Timestamp {
+        seconds: days_from_civil(date[0], date[1], date[2]) * 86400 + clock[0] * 3600 + clock[1] * 60 + clock[2]
+            - offset,
+        nanos: 0,
+    })
+}
+
+// Conversions between days since 1970-01-01 and proleptic Gregorian dates, after
+// http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
@@ -0,0 +1,113 @@
+use tunnel_manager::api::permission_client::PermissionClient;
+use tunnel_manager::api::permission_request::IdOrName;
+use tunnel_manager::api::{PermissionData, PermissionListRequest, PermissionRequest};
+
+use crate::args::{id_or, Args};
+use crate::config::Context;
+use crate::output::{self, Record};
+use crate::{list_options, print_next_page, Error};
+
+pub const USAGE: &str = "\
+  permissions list [--name-contains TEXT] [LIST OPTIONS]
+  permissions get ID|NAME
+  permissions add NAME [--description TEXT]
+  permissions update ID [--name NAME] [--description TEXT]
+  permissions delete ID|NAME";
+
+impl From<&PermissionData> for Record {
+    fn from(p: &PermissionData) -> Record {
+        Record::new()
+            .field("id", &p.id)
+            .field("name", &p.name)
+            .field("description", &p.description)
+    }
+}
+
+pub async fn run(ctx: &Context, command: &str, mut args: Args) -> Result<(), Error> {
+    let mut client = PermissionClient::new(ctx.channel().await?);
+
+    match command {
+        "list" => {
+            let options = list_options(&mut args)?;
+            if options.updated_since.is_some() {
+                return Err("permissions have no timestamps, --updated-since is not supported".into());
+            }
+
+            let mut request = PermissionListRequest {
+                page_size: options.page_size,
+                page_token: options.page_token,
+                order_by: options.order_by,
+                name_contains: args.value("--name-contains")?,
+            };
+            args.finish()?;
+
+            let mut permissions = Vec::new();
+            loop {
+                let response = client.list(request.clone()).await?.into_inner();
+                permissions.extend(response.permissions.iter().map(Record::from));
+                request.page_token = response.next_page_token;
+
+                if !options.all || request.page_token.is_empty() {
+                    break;
+                }
+            }
+
+            output::print_list(ctx.output, &permissions);
+            print_next_page(&request.page_token);
+        }
+        "get" => {
+            let request = permission_request(args.positional("permission id or name")?);
+            args.finish()?;
+
+            let permission = client.get(request).await?.into_inner();
+            output::print_one(ctx.output, &(&permission).into());
+        }
+        "add" => {
+            let description = args.value("--description")?.unwrap_or_default();
+            let name = args.positional("permission name")?;
+            args.finish()?;
+
+            let permission = client
+                .add(PermissionData {
+                    id: None,
+                    name,
+                    description,
+                })
+                .await?
+                .into_inner();
+            output::print_one(ctx.output, &(&permission).into());
+        }
+        "update" => {
+            let name = args.value("--name")?.unwrap_or_default();
+            let description = args.value("--description")?.unwrap_or_default();
+            let id = args.positional("permission id")?.parse().map_err(|_| "permission id must be a number")?;
+            args.finish()?;
+
+            let permission = client
+                .update(PermissionData {
+                    id: Some(id),
+                    name,
+                    description,
+                })
+                .await?
+                .into_inner();
+            output::print_one(ctx.output, &(&permission).into());
+        }
+        "delete" => {
+            let request = permission_request(args.positional("permission id or name")?);
+            args.finish()?;
+
+            let permission = client.delete(request).await?.into_inner();
+            output::print_one(ctx.output, &(&permission).into());
+        }
+        _ => return Err(crate::usage()),
+    }
+
+    Ok(())
+}
+
+fn permission_request(value: String) -> PermissionRequest {
+    PermissionRequest {
+        id_or_name: Some(id_or(value, IdOrName::Id, IdOrName::Name)),
+    }
+}
@@ -0,0 +1,347 @@
+use tunnel_manager::api::router_client::RouterClient;
+use tunnel_manager::api::router_request::IdOrAgent;
+use tunnel_manager::api::{
+    DriftReport, InterfaceDrift, PushHistoryRequest, PushRecord, RouterAddRequest, RouterListRequest, RouterRequest, RouterResponse, RouterSync,
+    RouterTree, RouterType, RouterUpdateRequest,
+};
+
+use crate::args::Args;
+use crate::config::Context;
+use crate::output::{self, Record, Value};
+use crate::{list_options, print_config_diffs, print_next_page, read_password, Error};
+
+pub const USAGE: &str = "\
+  routers list [--agent ID] [--router-type TYPE] [--conn-type TYPE] [LIST OPTIONS]
+  routers get ID | --agent ID
+  routers add --agent ID [ROUTER OPTIONS]
+  routers update ID [--agent ID] [ROUTER OPTIONS]
+  routers delete ID [--dry-run]
+  routers repush ID                    have the agent push the router's config again
+  routers config ID                    print the configuration rendered for the router
+  routers pushes ID [LIST OPTIONS]     the pushes agents reported for the router, newest first
+  routers sync-status [ID | --agent ID]
+                                       whether routers run the config they are meant to
+  routers drift [ID | --agent ID]      how the configs read back from routers differ from theirs
+  routers types                        the router types and connection types routers can have";
+
+pub const OPTIONS_USAGE: &str = "\
+router options:
+  --router-type TYPE  --conn-type TYPE (see routers types)  --snmp-community TEXT
+  --ssh-username NAME  --ssh-password (prompts)
+  --dry-run                            only show how router configs would change";
+
+impl From<&RouterResponse> for Record {
+    fn from(r: &RouterResponse) -> Record {
+        Record::new()
+            .field("id", &r.id)
+            .field("agent", &r.agent)
+            .field("router_type", &r.router_type)
+            .field("conn_type", &r.conn_type)
+            .field("ssh_username", &r.ssh_username)
+            .field("created_at", &r.created_at)
+            .field("updated_at", &r.updated_at)
+    }
+}
+
+impl From<&RouterTree> for Record {
+    fn from(t: &RouterTree) -> Record {
+        let mut record = t.router.as_ref().map(Record::from).unwrap_or_else(Record::new);
+        record.0.push(("tunnels", Value::List(t.tunnels.iter().map(Record::from).collect())));
+        record
+    }
+}
+
+impl From<&PushRecord> for Record {
+    fn from(p: &PushRecord) -> Record {
+        Record::new()
+            .field("id", p.id)
+            .field("router", p.router)
+            .field("config_hash", &p.config_hash)
+            .field("driver", &p.driver)
+            .field("started_at", &p.started_at)
+            .field("finished_at", &p.finished_at)
+            .field("success", p.success)
+            .field("error_output", &p.error_output)
+    }
+}
+
+impl From<&RouterSync> for Record {
+    fn from(s: &RouterSync) -> Record {
+        Record::new()
+            .field("router", s.router)
+            .field("agent", s.agent)
+            .field("behind", s.behind)
+            .field("desired_hash", &s.desired_hash)
+            .field("pushed_hash", &s.pushed_hash)
+            .field("last_push", s.last_push.as_ref().map(|p| if p.success { "ok" } else { "failed" }))
+            .field("last_push_at", &s.last_push.as_ref().and_then(|p| p.finished_at.clone()))
+            .field("render_error", &s.render_error)
+    }
+}
+
+impl From<&InterfaceDrift> for Record {
+    fn from(d: &InterfaceDrift) -> Record {
+        Record::new()
+            .field("interface", &d.interface)
+            .field("kind", &d.kind)
+            .field("desired", &d.desired)
+            .field("running", &d.running)
+    }
+}
+
+impl From<&DriftReport> for Record {
+    fn from(d: &DriftReport) -> Record {
+        let mut record = Record::new()
+            .field("router", d.router)
+            .field("agent", d.agent)
+            .field("drifted", d.drifted)
+            .field("read_at", &d.read_at)
+            .field("render_error", &d.render_error);
+        record.0.push(("interfaces", Value::List(d.interfaces.iter().map(Record::from).collect())));
+        record
+    }
+}
+
+impl From<&RouterType> for Record {
+    fn from(t: &RouterType) -> Record {
+        Record::new()
+            .field("name", &t.name)
+            .field("description", &t.description)
+            .field("renderable", t.renderable)
+            .field("conn_types", t.conn_types.join(", "))
+    }
+}
+
+pub async fn run(ctx: &Context, command: &str, mut args: Args) -> Result<(), Error> {
+    let mut client = RouterClient::new(ctx.channel().await?);
+
+    match command {
+        "list" => {
+            let options = list_options(&mut args)?;
+            let mut request = RouterListRequest {
+                updated_since: options.updated_since,
+                page_size: options.page_size,
+                page_token: options.page_token,
+                order_by: options.order_by,
+                agent: args.parse("--agent")?,
+                router_type: args.value("--router-type")?,
+                conn_type: args.value("--conn-type")?,
+            };
+            args.finish()?;
+
+            let mut routers = Vec::new();
+            loop {
+                let response = client.list(request.clone()).await?.into_inner();
+                routers.extend(response.routers.iter().map(Record::from));
+                request.page_token = response.next_page_token;
+
+                if !options.all || request.page_token.is_empty() {
+                    break;
+                }
+            }
+
+            output::print_list(ctx.output, &routers);
+            print_next_page(&request.page_token);
+        }
+        "get" => {
+            let id_or_agent = match args.parse("--agent")? {
+                Some(agent) => IdOrAgent::Agent(agent),
+                None => IdOrAgent::Id(router_id(&mut args)?),
+            };
+            args.finish()?;
+
+            let response = client
+                .get(RouterRequest {
+                    id_or_agent: Some(id_or_agent),
+                    ..Default::default()
+                })
+                .await?
+                .into_inner();
+            let routers: Vec<Record> = response.routers.iter().map(Record::from).collect();
+            output::print_list(ctx.output, &routers);
+        }
+        "add" => {
+            let agent = args.parse("--agent")?.ok_or("--agent is required")?;
+            let options = router_options(&mut args)?;
+            args.finish()?;
+
+            let router = client
+                .add(RouterAddRequest {
+                    agent,
+                    snmp_community: options.snmp_community,
+                    ssh_username: options.ssh_username,
+                    ssh_password: options.ssh_password,
+                    conn_type: options.conn_type,
+                    router_type: options.router_type,
+                    dry_run: options.dry_run,
+                })
+                .await?
+                .into_inner();
+            print_router(ctx, &router, options.dry_run);
+        }
+        "update" => {
+            let agent = args.parse("--agent")?;
+            let options = router_options(&mut args)?;
+            let id = router_id(&mut args)?;
+            args.finish()?;
+
+            let dry_run = options.dry_run;
+            let router = client
+                .update(RouterUpdateRequest {
+                    id,
+                    agent,
+                    ..options
+                })
+                .await?
+                .into_inner();
+            print_router(ctx, &router, dry_run);
+        }
+        "delete" => {
+            let dry_run = args.flag("--dry-run");
+            let id = router_id(&mut args)?;
+            args.finish()?;
+
+            let router = client
+                .delete(RouterRequest {
+                    id_or_agent: Some(IdOrAgent::Id(id)),
+                    dry_run,
+                })
+                .await?
+                .into_inner();
+            print_router(ctx, &router, dry_run);
+        }
+        "repush" => {
+            let id = router_id(&mut args)?;
+            args.finish()?;
+
+            let router = client
+                .repush(RouterRequest {
+                    id_or_agent: Some(IdOrAgent::Id(id)),
+                    ..Default::default()
+                })
+                .await?
+                .into_inner();
+            output::print_one(ctx.output, &(&router).into());
+        }
+        "config" => {
+            let id = router_id(&mut args)?;
+            args.finish()?;
+
+            let rendered = client
+                .render(RouterRequest {
+                    id_or_agent: Some(IdOrAgent::Id(id)),
+                    ..Default::default()
+                })
+                .await?
+                .into_inner();
+            print!("{}", rendered.config);
+        }
+        "pushes" => {
+            let options = list_options(&mut args)?;
+            if options.updated_since.is_some() {
+                return Err("pushes are never updated, --updated-since is not supported".into());
+            }
+
+            let mut request = PushHistoryRequest {
+                router: router_id(&mut args)?,
+                page_size: options.page_size,
+                page_token: options.page_token,
+                order_by: options.order_by,
+            };
+            args.finish()?;
+
+            let mut pushes = Vec::new();
+            loop {
+                let response = client.push_history(request.clone()).await?.into_inner();
+                pushes.extend(response.pushes.iter().map(Record::from));
+                request.page_token = response.next_page_token;
+
+                if !options.all || request.page_token.is_empty() {
+                    break;
+                }
+            }
+
+            output::print_list(ctx.output, &pushes);
+            print_next_page(&request.page_token);
+        }
+        "sync-status" => {
+            let id_or_agent = optional_id_or_agent(&mut args)?;
+            args.finish()?;
+
+            let response = client
+                .sync_status(RouterRequest {
+                    id_or_agent,
+                    ..Default::default()
+                })
+                .await?
+                .into_inner();
+            let routers: Vec<Record> = response.routers.iter().map(Record::from).collect();
+            output::print_list(ctx.output, &routers);
+        }
+        "drift" => {
+            let id_or_agent = optional_id_or_agent(&mut args)?;
+            args.finish()?;
+
+            let response = client
+                .get_drift(RouterRequest {
+                    id_or_agent,
+                    ..Default::default()
+                })
+                .await?
+                .into_inner();
+            let routers: Vec<Record> = response.routers.iter().map(Record::from).collect();
+            output::print_list(ctx.output, &routers);
+        }
+        "types" => {
+            args.finish()?;
+
+            let response = client.list_types(()).await?.into_inner();
+            let types: Vec<Record> = response.router_types.iter().map(Record::from).collect();
+            output::print_list(ctx.output, &types);
+            eprintln!("connection types: {}", response.conn_types.join(", "));
+        }
+        _ => return Err(crate::usage()),
+    }
+
+    Ok(())
+}
+
+/// The router a change returned, or for dry runs what it would do to router configs.
+fn print_router(ctx: &Context, router: &RouterResponse, dry_run: bool) {
+    match dry_run {
+        true => print_config_diffs(&router.config_diffs),
+        false => output::print_one(ctx.output, &router.into()),
+    }
+}
+
+fn router_id(args: &mut Args) -> Result<i32, Error> {
+    Ok(args.positional("router id")?.parse().map_err(|_| "router id must be a number")?)
+}
+
+/// `ID`, `--agent ID` or neither, for commands that cover every router by default.
+fn optional_id_or_agent(args: &mut Args) -> Result<Option<IdOrAgent>, Error> {
+    if let Some(agent) = args.parse("--agent")? {
+        return Ok(Some(IdOrAgent::Agent(agent)));
+    }
+
+    match args.optional_positional() {
+        Some(id) => Ok(Some(IdOrAgent::Id(id.parse().map_err(|_| "router id must be a number")?))),
+        None => Ok(None),
+    }
+}
+
+/// The settings shared by add and update, collected into an update request.
+fn router_options(args: &mut Args) -> Result<RouterUpdateRequest, Error> {
+    Ok(RouterUpdateRequest {
+        router_type: args.value("--router-type")?,
+        conn_type: args.value("--conn-type")?,
+        snmp_community: args.value("--snmp-community")?,
+        ssh_username: args.value("--ssh-username")?,
+        ssh_password: match args.flag("--ssh-password") {
+            true => Some(read_password("SSH password: ")?),
+            false => None,
+        },
+        dry_run: args.flag("--dry-run"),
+        ..Default::default()
+    })
+}
@@ -0,0 +1,199 @@
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{mem, thread};
+
+use tokio::sync::mpsc;
+
+/// Puts the terminal into raw mode on the alternate screen, and restores it when dropped.
+pub struct RawMode {
+    original: libc::termios,
+}
+
+impl RawMode {
+    pub fn enable() -> io::Result<RawMode> {
+        // SAFETY: cfmakeraw only writes into the termios struct we pass in.
+        let original = change_termios(|termios| unsafe { libc::cfmakeraw(termios) })?;
+
+        let mut stdout = io::stdout();
+        stdout.write_all(b"\x1b[?1049h\x1b[?25l")?;
+        stdout.flush()?;
+
+        Ok(RawMode { original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        stdout.write_all(b"\x1b[?25h\x1b[?1049l").ok();
+        stdout.flush().ok();
+
+        restore_termios(&self.original);
+    }
+}
+
+/// Stops the terminal echoing what is typed, for reading a password, and restores it when dropped.
+pub struct NoEcho {
+    original: libc::termios,
+}
+
+impl NoEcho {
+    pub fn enable() -> io::Result<NoEcho> {
+        let original = change_termios(|termios| termios.c_lflag &= !libc::ECHO)?;
+        Ok(NoEcho { original })
+    }
+}
+
+impl Drop for NoEcho {
+    fn drop(&mut self) {
+        restore_termios(&self.original);
+    }
+}
+
+/// Applies `change` to the settings of the terminal on stdin, returning the ones it replaced.
+fn change_termios(change: impl FnOnce(&mut libc::termios)) -> io::Result<libc::termios> {
+    // SAFETY: tcgetattr/tcsetattr only read and write the termios struct we pass in.
+    unsafe {
+        let mut termios: libc::termios = mem::zeroed();
+        if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let original = termios;
+        change(&mut termios);
+        if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(original)
+    }
+}
+
+fn restore_termios(original: &libc::termios) {
+    // SAFETY: restores settings read by `change_termios`.
+    unsafe {
+        libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, original);
+    }
+}
+
+/// Rows and columns of the terminal, or 24x80 if it cannot be told.
+pub fn size() -> (usize, usize) {
+    // SAFETY: TIOCGWINSZ only writes into the winsize struct we pass in.
+    unsafe {
+        let mut size: libc::winsize = mem::zeroed();
+        if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) == 0 && size.ws_row > 0 {
+            return (size.ws_row as usize, size.ws_col as usize);
+        }
+    }
+
+    (24, 80)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Key {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Char(char),
+    Quit,
+}
+
+/// Reads keys on a background thread. The thread polls with a timeout rather than blocking in
+/// `read`, so it can be paused while another program (an editor) owns the terminal.
+pub struct Keys {
+    rx: mpsc::UnboundedReceiver<Key>,
+    paused: Arc<AtomicBool>,
+    reading: Arc<AtomicBool>,
+}
+
+impl Keys {
+    pub fn spawn() -> Keys {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let paused = Arc::new(AtomicBool::new(false));
+        let reading = Arc::new(AtomicBool::new(false));
+
+        let (thread_paused, thread_reading) = (paused.clone(), reading.clone());
+        thread::spawn(move || {
+            let mut buf = [0u8; 32];
+
+            while !tx.is_closed() {
+                // Announce the read before checking for a pause, so `pause` either sees us
+                // reading and waits, or we see the pause and back off.
+                thread_reading.store(true, Ordering::SeqCst);
+                if thread_paused.load(Ordering::SeqCst) {
+                    thread_reading.store(false, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(20));
+                    continue;
+                }
+
+                let mut fd = libc::pollfd {
+                    fd: libc::STDIN_FILENO,
+                    events: libc::POLLIN,
+                    revents: 0,
+                };
+
+                // SAFETY: poll and read only touch the pollfd and buffer we own.
+                let n = unsafe {
+                    match libc::poll(&mut fd, 1, 100) {
+                        1 => libc::read(libc::STDIN_FILENO, buf.as_mut_ptr().cast(), buf.len()),
+                        _ => 0,
+                    }
+                };
+                thread_reading.store(false, Ordering::SeqCst);
+
+                for key in parse_keys(&buf[..n.max(0) as usize]) {
+                    if tx.send(key).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        Keys { rx, paused, reading }
+    }
+
+    pub async fn next(&mut self) -> Option<Key> {
+        self.rx.recv().await
+    }
+
+    /// Stops reading from the terminal, waiting for a read in progress to finish.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+        while self.reading.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+    }
+}
+
+fn parse_keys(bytes: &[u8]) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let (key, len) = match &bytes[i..] {
+            [0x1b, b'[', b'A', ..] => (Some(Key::Up), 3),
+            [0x1b, b'[', b'B', ..] => (Some(Key::Down), 3),
+            [0x1b, b'[', b'H', ..] => (Some(Key::Home), 3),
+            [0x1b, b'[', b'F', ..] => (Some(Key::End), 3),
+            [0x1b, b'[', b'5', b'~', ..] => (Some(Key::PageUp), 4),
+            [0x1b, b'[', b'6', b'~', ..] => (Some(Key::PageDown), 4),
+            [0x1b, b'[', ..] => (None, 3),
+            [3, ..] => (Some(Key::Quit), 1),
+            [b, ..] if b.is_ascii() && !b.is_ascii_control() => (Some(Key::Char(*b as char)), 1),
+            _ => (None, 1),
+        };
+
+        keys.extend(key);
+        i += len;
+    }
+
+    keys
+}
@@ -0,0 +1,262 @@
+use tunnel_manager::api::tunnel_client::TunnelClient;
+use tunnel_manager::api::tunnel_request::IdOrRouter;
+use tunnel_manager::api::{AddressReport, AddressResolution, ResolutionHistoryRequest, TunnelAddRequest, TunnelListRequest, TunnelRequest, TunnelResponse, TunnelUpdateRequest};
+
+use crate::args::Args;
+use crate::config::Context;
+use crate::output::{self, Record};
+use crate::{list_options, print_config_diffs, print_next_page, Error};
+
+pub const USAGE: &str = "\
+  tunnels list [--router ID] [--agent ID] [--topology-type TYPE] [--ip-class 4|6]
+               [--tunnel-type TYPE] [--hostname-contains TEXT] [LIST OPTIONS]
+  tunnels get ID | --router ID
+  tunnels add --router ID --ip IP --hostname NAME --source IP --description TEXT [TUNNEL OPTIONS]
+  tunnels update ID [--router ID] [--ip IP] [--hostname NAME] [--source IP] [--description TEXT]
+                 [TUNNEL OPTIONS]
+  tunnels delete ID [--dry-run]
+  tunnels report-address ID [IP]        without IP, the address the server sees
+  tunnels resolutions ID [LIST OPTIONS]
+  tunnels rotate-keys ID | --router ID  new IPSec pre-shared keys for the tunnel's peerings";
+
+pub const OPTIONS_USAGE: &str = "\
+tunnel options:
+  --version N  --dynamic-ip true|false  --ip-class 4|6  --cost N  --tunnel-type GRE|IPSec|WireGuard
+  --topology-type mesh|hub|spoke
+  --dry-run                            only show how router configs would change";
+
+impl From<&TunnelResponse> for Record {
+    fn from(t: &TunnelResponse) -> Record {
+        Record::new()
+            .field("id", t.id)
+            .field("router", t.router)
+            .field("version", t.version)
+            .field("hostname", &t.hostname)
+            .field("ip", &t.ip)
+            .field("dynamic_ip", t.dynamic_ip)
+            .field("ip_class", t.ip_class)
+            .field("source", &t.source)
+            .field("cost", t.cost)
+            .field("tunnel_type", &t.tunnel_type)
+            .field("topology_type", &t.topology_type)
+            .field("description", &t.description)
+            .field("created_at", &t.created_at)
+            .field("updated_at", &t.updated_at)
+            .field("ip_reported_at", &t.ip_reported_at)
+            .field("ip_resolved_at", &t.ip_resolved_at)
+    }
+}
+
+impl From<&AddressResolution> for Record {
+    fn from(r: &AddressResolution) -> Record {
+        Record::new()
+            .field("id", r.id)
+            .field("tunnel", r.tunnel)
+            .field("ip", &r.ip)
+            .field("error", &r.error)
+            .field("ttl_secs", r.ttl_secs)
+            .field("resolved_at", &r.resolved_at)
+    }
+}
+
+pub async fn run(ctx: &Context, command: &str, mut args: Args) -> Result<(), Error> {
+    let mut client = TunnelClient::new(ctx.channel().await?);
+
+    match command {
+        "list" => {
+            let options = list_options(&mut args)?;
+            let mut request = TunnelListRequest {
+                updated_since: options.updated_since,
+                page_size: options.page_size,
+                page_token: options.page_token,
+                order_by: options.order_by,
+                router: args.parse("--router")?,
+                agent: args.parse("--agent")?,
+                topology_type: args.value("--topology-type")?,
+                ip_class: args.parse("--ip-class")?,
+                hostname_contains: args.value("--hostname-contains")?,
+                tunnel_type: args.value("--tunnel-type")?,
+            };
+            args.finish()?;
+
+            let mut tunnels = Vec::new();
+            loop {
+                let response = client.list(request.clone()).await?.into_inner();
+                tunnels.extend(response.tunnels.iter().map(Record::from));
+                request.page_token = response.next_page_token;
+
+                if !options.all || request.page_token.is_empty() {
+                    break;
+                }
+            }
+
+            output::print_list(ctx.output, &tunnels);
+            print_next_page(&request.page_token);
+        }
+        "get" => {
+            let id_or_router = match args.parse("--router")? {
+                Some(router) => IdOrRouter::Router(router),
+                None => IdOrRouter::Id(tunnel_id(&mut args)?),
+            };
+            args.finish()?;
+
+            let response = client
+                .get(TunnelRequest {
+                    id_or_router: Some(id_or_router),
+                    ..Default::default()
+                })
+                .await?
+                .into_inner();
+            let tunnels: Vec<Record> = response.tunnels.iter().map(Record::from).collect();
+            output::print_list(ctx.output, &tunnels);
+        }
+        "add" => {
+            let options = tunnel_options(&mut args)?;
+            let request = TunnelAddRequest {
+                router: options.router.ok_or("--router is required")?,
+                ip: options.ip.ok_or("--ip is required")?,
+                hostname: options.hostname.ok_or("--hostname is required")?,
+                description: options.description.ok_or("--description is required")?,
+                source: options.source.ok_or("--source is required")?,
+                version: options.version,
+                dynamic_ip: options.dynamic_ip,
+                ip_class: options.ip_class,
+                cost: options.cost,
+                tunnel_type: options.tunnel_type,
+                topology_type: options.topology_type,
+                dry_run: options.dry_run,
+            };
+            args.finish()?;
+
+            let tunnel = client.add(request).await?.into_inner();
+            print_tunnel(ctx, &tunnel, options.dry_run);
+        }
+        "update" => {
+            let options = tunnel_options(&mut args)?;
+            let id = tunnel_id(&mut args)?;
+            args.finish()?;
+
+            let dry_run = options.dry_run;
+            let tunnel = client
+                .update(TunnelUpdateRequest { id, ..options })
+                .await?
+                .into_inner();
+            print_tunnel(ctx, &tunnel, dry_run);
+        }
+        "delete" => {
+            let dry_run = args.flag("--dry-run");
+            let id = tunnel_id(&mut args)?;
+            args.finish()?;
+
+            let tunnel = client
+                .delete(TunnelRequest {
+                    id_or_router: Some(IdOrRouter::Id(id)),
+                    dry_run,
+                })
+                .await?
+                .into_inner();
+            print_tunnel(ctx, &tunnel, dry_run);
+        }
+        "report-address" => {
+            let tunnel = tunnel_id(&mut args)?;
+            let ip = args.optional_positional().unwrap_or_default();
+            args.finish()?;
+
+            let response = client
+                .report_address(AddressReport { tunnel, ip })
+                .await?
+                .into_inner();
+            let peers: Vec<String> = response.peers.iter().map(i32::to_string).collect();
+            let record = response
+                .tunnel
+                .as_ref()
+                .map(Record::from)
+                .unwrap_or_else(Record::new)
+                .field("changed", response.changed)
+                .field("peer_routers", peers.join(","));
+            output::print_one(ctx.output, &record);
+        }
+        "resolutions" => {
+            let options = list_options(&mut args)?;
+            if options.updated_since.is_some() {
+                return Err("resolutions are never updated, --updated-since is not supported".into());
+            }
+
+            let mut request = ResolutionHistoryRequest {
+                tunnel: tunnel_id(&mut args)?,
+                page_size: options.page_size,
+                page_token: options.page_token,
+                order_by: options.order_by,
+            };
+            args.finish()?;
+
+            let mut resolutions = Vec::new();
+            loop {
+                let response = client.resolution_history(request.clone()).await?.into_inner();
+                resolutions.extend(response.resolutions.iter().map(Record::from));
+                request.page_token = response.next_page_token;
+
+                if !options.all || request.page_token.is_empty() {
+                    break;
+                }
+            }
+
+            output::print_list(ctx.output, &resolutions);
+            print_next_page(&request.page_token);
+        }
+        "rotate-keys" => {
+            let id_or_router = match args.parse("--router")? {
+                Some(router) => IdOrRouter::Router(router),
+                None => IdOrRouter::Id(tunnel_id(&mut args)?),
+            };
+            args.finish()?;
+
+            let response = client
+                .rotate_keys(TunnelRequest {
+                    id_or_router: Some(id_or_router),
+                    ..Default::default()
+                })
+                .await?
+                .into_inner();
+            let routers: Vec<String> = response.routers.iter().map(i32::to_string).collect();
+            let record = Record::new()
+                .field("rotated", response.rotated)
+                .field("routers", routers.join(","));
+            output::print_one(ctx.output, &record);
+        }
+        _ => return Err(crate::usage()),
+    }
+
+    Ok(())
+}
+
+/// The tunnel a change returned, or for dry runs what it would do to router configs.
+fn print_tunnel(ctx: &Context, tunnel: &TunnelResponse, dry_run: bool) {
+    match dry_run {
+        true => print_config_diffs(&tunnel.config_diffs),
+        false => output::print_one(ctx.output, &tunnel.into()),
+    }
+}
+
+fn tunnel_id(args: &mut Args) -> Result<i32, Error> {
+    Ok(args.positional("tunnel id")?.parse().map_err(|_| "tunnel id must be a number")?)
+}
+
+/// Every tunnel setting, collected into an update request that add picks the required ones from.
+fn tunnel_options(args: &mut Args) -> Result<TunnelUpdateRequest, Error> {
+    Ok(TunnelUpdateRequest {
+        id: 0,
+        version: args.parse("--version")?,
+        router: args.parse("--router")?,
+        ip: args.value("--ip")?,
+        dynamic_ip: args.parse("--dynamic-ip")?,
+        ip_class: args.parse("--ip-class")?,
+        hostname: args.value("--hostname")?,
+        description: args.value("--description")?,
+        source: args.value("--source")?,
+        cost: args.parse("--cost")?,
+        tunnel_type: args.value("--tunnel-type")?,
+        topology_type: args.value("--topology-type")?,
+        dry_run: args.flag("--dry-run"),
+    })
+}
@@ -0,0 +1,109 @@
+use tunnel_manager::api::user_client::UserClient;
+use tunnel_manager::api::user_request::IdOrEmail;
+use tunnel_manager::api::{UserAddRequest, UserListRequest, UserRequest, UserResponse, UserUpdateRequest};
+
+use crate::args::{id_or, Args};
+use crate::config::Context;
+use crate::output::{self, Record};
+use crate::{list_options, print_next_page, read_password, Error};
+
+pub const USAGE: &str = "\
+  users list [--email-contains TEXT] [LIST OPTIONS]
+  users get ID|EMAIL
+  users add EMAIL                      prompts for the password
+  users update ID [--email EMAIL] [--password]
+  users delete ID|EMAIL";
+
+impl From<&UserResponse> for Record {
+    fn from(u: &UserResponse) -> Record {
+        Record::new()
+            .field("id", u.id)
+            .field("email", &u.email)
+            .field("created_at", &u.created_at)
+            .field("updated_at", &u.updated_at)
+    }
+}
+
+pub async fn run(ctx: &Context, command: &str, mut args: Args) -> Result<(), Error> {
+    let mut client = UserClient::new(ctx.channel().await?);
+
+    match command {
+        "list" => {
+            let options = list_options(&mut args)?;
+            let mut request = UserListRequest {
+                updated_since: options.updated_since,
+                page_size: options.page_size,
+                page_token: options.page_token,
+                order_by: options.order_by,
+                email_contains: args.value("--email-contains")?,
+            };
+            args.finish()?;
+
+            let mut users = Vec::new();
+            loop {
+                let response = client.list(request.clone()).await?.into_inner();
+                users.extend(response.users.iter().map(Record::from));
+                request.page_token = response.next_page_token;
+
+                if !options.all || request.page_token.is_empty() {
+                    break;
+                }
+            }
+
+            output::print_list(ctx.output, &users);
+            print_next_page(&request.page_token);
+        }
+        "get" => {
+            let request = user_request(args.positional("user id or email")?);
+            args.finish()?;
+
+            let user = client.get(request).await?.into_inner();
+            output::print_one(ctx.output, &(&user).into());
+        }
+        "add" => {
+            let email = args.positional("email")?;
+            args.finish()?;
+
+            let user = client
+                .add(UserAddRequest {
+                    email,
+                    password: read_password("Password: ")?,
+                })
+                .await?
+                .into_inner();
+            output::print_one(ctx.output, &(&user).into());
+        }
+        "update" => {
+            let email = args.value("--email")?;
+            let change_password = args.flag("--password");
+            let id = args.positional("user id")?.parse().map_err(|_| "user id must be a number")?;
+            let password = match change_password {
+                true => Some(read_password("New password: ")?),
+                false => None,
+            };
+            args.finish()?;
+
+            let user = client
+                .update(UserUpdateRequest { id, email, password })
+                .await?
+                .into_inner();
+            output::print_one(ctx.output, &(&user).into());
+        }
+        "delete" => {
+            let request = user_request(args.positional("user id or email")?);
+            args.finish()?;
+
+            let user = client.delete(request).await?.into_inner();
+            output::print_one(ctx.output, &(&user).into());
+        }
+        _ => return Err(crate::usage()),
+    }
+
+    Ok(())
+}
+
+fn user_request(value: String) -> UserRequest {
+    UserRequest {
+        id_or_email: Some(id_or(value, IdOrEmail::Id, IdOrEmail::Email)),
+    }
+}
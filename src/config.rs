@@ -0,0 +1,799 @@
+//! Typed server configuration, layered from lowest to highest precedence: built-in defaults, a
+//! TOML file, environment variables, then command line flags.
+//!
+//! Every setting has a `section.key` name (`database.max_connections`), which is also its place
+//! in the file (`[database]` / `max_connections = 5`), its environment variable
+//! (`TUNNEL_MANAGER_DATABASE_MAX_CONNECTIONS`) and its flag (`--database-max-connections 5`).
+
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use diesel::connection::SimpleConnection;
+use diesel::r2d2::{self, ConnectionManager, CustomizeConnection, Pool};
+use diesel::PgConnection;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use tracing::Level;
+
+/// Names the config file when `--config` is not given.
+pub const CONFIG_ENV: &str = "TUNNEL_MANAGER_CONFIG";
+
+/// A configuration that can be layered by [`load`]. Implemented by [`Config`]; a binary with
+/// settings of its own implements it for those.
+pub trait Settings: Default + DeserializeOwned {
+    /// Every setting, as `section.key`.
+    const KEYS: &'static [&'static str];
+    /// Prepended to a key, upper cased with `_` for `.`, to name its environment variable.
+    const ENV_PREFIX: &'static str;
+    /// Other environment variables setting a key, read before the prefixed ones.
+    const ENV_ALIASES: &'static [(&'static str, &'static str)];
+
+    /// Sets `key` from its string form, as found in the environment or on the command line.
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String>;
+
+    /// Everything wrong with the final configuration.
+    fn validate(&self) -> Vec<String>;
+}
+
+/// Every problem found while loading a configuration, so they can all be fixed in one go.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ConfigError {
+    pub errors: Vec<String>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid configuration:")?;
+        for error in &self.errors {
+            write!(f, "\n  - {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// The environment variable for `key`.
+pub fn env_var<T: Settings>(key: &str) -> String {
+    format!("{}{}", T::ENV_PREFIX, key.replace('.', "_").to_uppercase())
+}
+
+/// The command line flag for `key`.
+pub fn flag(key: &str) -> String {
+    format!("--{}", key.replace(['.', '_'], "-"))
+}
+
+/// Loads a configuration from the file named by `--config` (or [`CONFIG_ENV`]), then `env`, then
+/// the flags at the start of `args`. Parsing stops at the first argument that is not a flag,
+/// which is returned with everything after it.
+pub fn load<T: Settings>(
+    args: &[String],
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<(T, Vec<String>), ConfigError> {
+    let mut errors = Vec::new();
+
+    // Flags are collected first, as one of them may name the file.
+    let mut file = env(CONFIG_ENV).map(PathBuf::from);
+    let mut flags = Vec::new();
+    let mut rest = args.iter();
+    let mut remaining = Vec::new();
+    while let Some(arg) = rest.next() {
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (arg.as_str(), None),
+        };
+
+        if !name.starts_with("--") || name == "--help" {
+            remaining.push(arg.clone());
+            remaining.extend(rest.cloned());
+            break;
+        }
+
+        let value = match inline.or_else(|| rest.next().cloned()) {
+            Some(value) => value,
+            None => {
+                errors.push(format!("{}: missing value", name));
+                break;
+            }
+        };
+
+        if name == "--config" {
+            file = Some(PathBuf::from(value));
+        } else {
+            match T::KEYS.iter().find(|key| flag(key) == name) {
+                Some(key) => flags.push((name.to_string(), *key, value)),
+                None => errors.push(format!("{}: unknown flag", name)),
+            }
+        }
+    }
+
+    let mut config = match &file {
+        Some(path) => match fs::read_to_string(path) {
+            Ok(text) => match toml::from_str(&text) {
+                Ok(config) => config,
+                Err(err) => {
+                    errors.push(format!("{}: {}", path.display(), err.to_string().trim_end()));
+                    T::default()
+                }
+            },
+            Err(err) => {
+                errors.push(format!("{}: {}", path.display(), err));
+                T::default()
+            }
+        },
+        None => T::default(),
+    };
+
+    let env_vars = T::ENV_ALIASES
+        .iter()
+        .map(|(var, key)| (var.to_string(), *key))
+        .chain(T::KEYS.iter().map(|key| (env_var::<T>(key), *key)));
+    for (var, key) in env_vars {
+        if let Some(value) = env(&var) {
+            if let Err(err) = config.set(key, &value) {
+                errors.push(format!("{} ({}): {}", var, key, err));
+            }
+        }
+    }
+
+    for (name, key, value) in flags {
+        if let Err(err) = config.set(key, &value) {
+            errors.push(format!("{} ({}): {}", name, key, err));
+        }
+    }
+
+    if errors.is_empty() {
+        errors = config.validate();
+    }
+
+    match errors.is_empty() {
+        true => Ok((config, remaining)),
+        false => Err(ConfigError { errors }),
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen: ListenConfig,
+    pub tls: TlsConfig,
+    pub database: DatabaseConfig,
+    pub auth: AuthConfig,
+    pub log: LogConfig,
+    pub render: RenderConfig,
+    pub notifications: NotificationConfig,
+    pub drift: DriftConfig,
+    pub resolver: ResolverConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListenConfig {
+    pub grpc_host: String,
+    pub grpc_port: u16,
+    /// The HTTP/JSON gateway and web UI.
+    pub http_host: String,
+    pub http_port: u16,
+    /// Origins browsers may call the gRPC port from over gRPC-Web; `*` for any.
+    pub cors_allowed_origins: Vec<String>,
+    pub request_timeout_secs: u64,
+    /// How long in-flight requests and database transactions get to finish on SIGINT/SIGTERM.
+    pub shutdown_timeout_secs: u64,
+}
+
+impl Default for ListenConfig {
+    fn default() -> Self {
+        ListenConfig {
+            grpc_host: "[::1]".to_string(),
+            grpc_port: 50051,
+            http_host: "[::1]".to_string(),
+            http_port: 8080,
+            cors_allowed_origins: vec![],
+            request_timeout_secs: 30,
+            shutdown_timeout_secs: 30,
+        }
+    }
+}
+
+impl ListenConfig {
+    pub fn grpc_addr(&self) -> Result<SocketAddr, String> {
+        socket_addr(&self.grpc_host, self.grpc_port)
+    }
+
+    pub fn http_addr(&self) -> Result<SocketAddr, String> {
+        socket_addr(&self.http_host, self.http_port)
+    }
+}
+
+fn socket_addr(host: &str, port: u16) -> Result<SocketAddr, String> {
+    format!("{}:{}", host, port)
+        .parse()
+        .map_err(|_| format!("{:?} is not an IP address (IPv6 addresses go in brackets)", host))
+}
+
+/// TLS for the gRPC port and the HTTP gateway, on when `cert` is set.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    /// PEM certificate chain.
+    pub cert: Option<PathBuf>,
+    /// PEM private key.
+    pub key: Option<PathBuf>,
+    /// PEM CA certificates; when set, clients must present a certificate signed by one.
+    pub client_ca: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub url: String,
+    pub max_connections: u32,
+    pub min_idle: Option<u32>,
+    /// How long to wait for a pooled connection.
+    pub connection_timeout_secs: u64,
+    /// Idle connections are closed after this long; 0 keeps them.
+    pub idle_timeout_secs: u64,
+    /// Connections are replaced after this long; 0 keeps them.
+    pub max_lifetime_secs: u64,
+    /// Statements running longer are cancelled; 0 lets them run.
+    pub statement_timeout_secs: u64,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            url: String::new(),
+            max_connections: 5,
+            min_idle: None,
+            connection_timeout_secs: 30,
+            idle_timeout_secs: 600,
+            max_lifetime_secs: 1800,
+            statement_timeout_secs: 0,
+        }
+    }
+}
+
+impl DatabaseConfig {
+    /// Builds the connection pool, failing if the database cannot be reached.
+    pub fn pool(
+        &self,
+        events: impl r2d2::HandleEvent + 'static,
+    ) -> Result<Pool<ConnectionManager<PgConnection>>, r2d2::PoolError> {
+        let nonzero = |secs| match secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        };
+
+        Pool::builder()
+            .test_on_check_out(true)
+            .max_size(self.max_connections)
+            .min_idle(self.min_idle)
+            .connection_timeout(Duration::from_secs(self.connection_timeout_secs))
+            .idle_timeout(nonzero(self.idle_timeout_secs))
+            .max_lifetime(nonzero(self.max_lifetime_secs))
+            .connection_customizer(Box::new(StatementTimeout(self.statement_timeout_secs)))
+            .event_handler(Box::new(events))
+            .build(ConnectionManager::new(self.url.clone()))
+    }
+}
+
+#[derive(Debug)]
+struct StatementTimeout(u64);
+
+impl CustomizeConnection<PgConnection, r2d2::Error> for StatementTimeout {
+    fn on_acquire(&self, conn: &mut PgConnection) -> Result<(), r2d2::Error> {
+        if self.0 > 0 {
+            conn.batch_execute(&format!("SET statement_timeout = {}", self.0 * 1000))
+                .map_err(r2d2::Error::QueryError)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Signs login tokens, which are then checked on every request. Required unless `disabled`.
+    pub token_secret: Option<String>,
+    /// Reads the secret from a file instead.
+    pub token_secret_file: Option<PathBuf>,
+    pub token_ttl_secs: u64,
+    /// Runs without a secret, accepting any token as an admin's. Only for development.
+    pub disabled: bool,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            token_secret: None,
+            token_secret_file: None,
+            token_ttl_secs: 7 * 24 * 3600,
+            disabled: false,
+        }
+    }
+}
+
+/// Secrets shorter than this are refused.
+const MIN_SECRET_LEN: usize = 32;
+
+impl AuthConfig {
+    /// The token secret, from wherever it is configured.
+    pub fn secret(&self) -> Result<Option<Vec<u8>>, String> {
+        let secret = match (&self.token_secret, &self.token_secret_file) {
+            (None, None) => return Ok(None),
+            (Some(secret), None) => secret.clone().into_bytes(),
+            (None, Some(path)) => fs::read_to_string(path)
+                .map_err(|err| format!("auth.token_secret_file: {}: {}", path.display(), err))?
+                .trim_end()
+                .as_bytes()
+                .to_vec(),
+            (Some(_), Some(_)) => return Err("set auth.token_secret or auth.token_secret_file, not both".to_string()),
+        };
+
+        match secret.len() >= MIN_SECRET_LEN {
+            true => Ok(Some(secret)),
+            false => Err(format!("the auth token secret must be at least {} bytes", MIN_SECRET_LEN)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub level: LogLevel,
+    pub format: LogFormat,
+    /// Colours and styles, for terminals.
+    pub ansi: bool,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            level: LogLevel::Info,
+            format: LogFormat::Full,
+            ansi: true,
+        }
+    }
+}
+
+impl LogConfig {
+    pub fn init(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let level = match self.level {
+            LogLevel::Trace => Level::TRACE,
+            LogLevel::Debug => Level::DEBUG,
+            LogLevel::Info => Level::INFO,
+            LogLevel::Warn => Level::WARN,
+            LogLevel::Error => Level::ERROR,
+        };
+        let builder = tracing_subscriber::fmt().with_max_level(level).with_ansi(self.ansi);
+
+        match self.format {
+            LogFormat::Full => builder.try_init(),
+            LogFormat::Compact => builder.compact().try_init(),
+            LogFormat::Pretty => builder.pretty().try_init(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.to_lowercase().as_str() {
+            "trace" => Ok(LogLevel::Trace),
+            "debug" => Ok(LogLevel::Debug),
+            "info" => Ok(LogLevel::Info),
+            "warn" => Ok(LogLevel::Warn),
+            "error" => Ok(LogLevel::Error),
+            _ => Err(format!("expected trace, debug, info, warn or error, got {:?}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Full,
+    Compact,
+    Pretty,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.to_lowercase().as_str() {
+            "full" => Ok(LogFormat::Full),
+            "compact" => Ok(LogFormat::Compact),
+            "pretty" => Ok(LogFormat::Pretty),
+            _ => Err(format!("expected full, compact or pretty, got {:?}", s)),
+        }
+    }
+}
+
+/// Defaults for the rendered router configurations.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderConfig {
+    /// Prefixes the names of the Cisco IKEv2 keyrings and profiles and the IPSec profiles
+    /// rendered for each IPSec peering.
+    pub ipsec_profile: String,
+    /// Cisco GRE keepalive period; off when unset.
+    pub keepalive_secs: Option<u32>,
+    /// Encrypts the pre-shared keys of IPSec peerings and the private keys of WireGuard endpoints
+    /// at rest. Neither kind of tunnel can be rendered without one.
+    pub psk_secret: Option<String>,
+    /// Reads the secret from a file instead.
+    pub psk_secret_file: Option<PathBuf>,
+    /// The IPv4 network WireGuard endpoints get their address inside the tunnels from.
+    pub wireguard_network: String,
+    /// The port a router's first WireGuard endpoint listens on; any others take the next ones.
+    pub wireguard_port: u16,
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        RenderConfig {
+            ipsec_profile: "HECNET".to_string(),
+            keepalive_secs: None,
+            psk_secret: None,
+            psk_secret_file: None,
+            wireguard_network: "10.254.0.0/16".to_string(),
+            wireguard_port: 51820,
+        }
+    }
+}
+
+impl RenderConfig {
+    /// The pre-shared key secret, from wherever it is configured.
+    pub fn psk_secret(&self) -> Result<Option<Vec<u8>>, String> {
+        let secret = match (&self.psk_secret, &self.psk_secret_file) {
+            (None, None) => return Ok(None),
+            (Some(secret), None) => secret.clone().into_bytes(),
+            (None, Some(path)) => fs::read_to_string(path)
+                .map_err(|err| format!("render.psk_secret_file: {}: {}", path.display(), err))?
+                .trim_end()
+                .as_bytes()
+                .to_vec(),
+            (Some(_), Some(_)) => return Err("set render.psk_secret or render.psk_secret_file, not both".to_string()),
+        };
+
+        match secret.len() >= MIN_SECRET_LEN {
+            true => Ok(Some(secret)),
+            false => Err(format!("the pre-shared key secret must be at least {} bytes", MIN_SECRET_LEN)),
+        }
+    }
+
+    /// The WireGuard network and its prefix length.
+    pub fn wireguard_network(&self) -> Result<(Ipv4Addr, u8), String> {
+        let invalid = || format!("{:?} is not an IPv4 network", self.wireguard_network);
+        let (address, len) = self.wireguard_network.split_once('/').ok_or_else(invalid)?;
+        let address: Ipv4Addr = address.parse().map_err(|_| invalid())?;
+        let len: u8 = len.parse().map_err(|_| invalid())?;
+
+        match len {
+            8..=30 => Ok((Ipv4Addr::from(u32::from(address) & (u32::MAX << (32 - len))), len)),
+            _ => Err(format!("a /{} network is too large or too small for WireGuard endpoints", len)),
+        }
+    }
+}
+
+/// The LISTEN/NOTIFY consumer behind `Mesh.Watch`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationConfig {
+    /// How often the listening connection is checked for notifications.
+    pub poll_interval_ms: u64,
+    /// How often the listening connection is pinged, so a dropped one is noticed.
+    pub keepalive_secs: u64,
+    /// How long to wait before reconnecting.
+    pub retry_secs: u64,
+    /// How often watchers poll anyway, for changes missed while reconnecting.
+    pub watch_interval_secs: u64,
+}
+
+impl Default for NotificationConfig {
+    fn default() -> Self {
+        NotificationConfig {
+            poll_interval_ms: 250,
+            keepalive_secs: 10,
+            retry_secs: 5,
+            watch_interval_secs: 10,
+        }
+    }
+}
+
+/// Checks of the configs agents read back from their routers.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DriftConfig {
+    /// How often agents are asked to read back each router's running config.
+    pub readback_interval_secs: u32,
+    /// Push the config again to routers found drifted, instead of only reporting them.
+    pub auto_remediate: bool,
+}
+
+impl Default for DriftConfig {
+    fn default() -> Self {
+        DriftConfig {
+            readback_interval_secs: 900,
+            auto_remediate: false,
+        }
+    }
+}
+
+/// The server's resolution of dynamic endpoints' hostnames.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResolverConfig {
+    pub enabled: bool,
+    /// DNS servers to ask, as IP addresses; those in `/etc/resolv.conf` when empty.
+    pub nameservers: Vec<String>,
+    /// How long to wait for each server to answer.
+    pub timeout_secs: u64,
+    /// Bounds on how long an answer is kept, whatever its TTL. Failures are retried after the
+    /// minimum, which is also how soon new dynamic endpoints are picked up.
+    pub min_ttl_secs: u64,
+    pub max_ttl_secs: u64,
+}
+
+impl Default for ResolverConfig {
+    fn default() -> Self {
+        ResolverConfig {
+            enabled: true,
+            nameservers: vec![],
+            timeout_secs: 5,
+            min_ttl_secs: 60,
+            max_ttl_secs: 3600,
+        }
+    }
+}
+
+impl ResolverConfig {
+    /// The configured DNS servers, on port 53 unless they say otherwise.
+    pub fn nameservers(&self) -> Result<Vec<SocketAddr>, String> {
+        self.nameservers
+            .iter()
+            .map(|server| {
+                server
+                    .parse::<SocketAddr>()
+                    .or_else(|_| server.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+                    .map_err(|_| format!("{:?} is not an IP address", server))
+            })
+            .collect()
+    }
+}
+
+fn parse<T: FromStr>(value: &str, what: &str) -> Result<T, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| format!("expected {}, got {:?}", what, value))
+}
+
+/// An empty value unsets an optional setting.
+fn optional<T: FromStr>(value: &str, what: &str) -> Result<Option<T>, String> {
+    match value.trim() {
+        "" => Ok(None),
+        value => parse(value, what).map(Some),
+    }
+}
+
+fn list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+fn readable(errors: &mut Vec<String>, key: &str, path: &Option<PathBuf>) {
+    if let Some(path) = path {
+        if let Err(err) = fs::File::open(path) {
+            errors.push(format!("{}: {}: {}", key, path.display(), err));
+        }
+    }
+}
+
+impl Settings for Config {
+    const KEYS: &'static [&'static str] = &[
+        "listen.grpc_host",
+        "listen.grpc_port",
+        "listen.http_host",
+        "listen.http_port",
+        "listen.cors_allowed_origins",
+        "listen.request_timeout_secs",
+        "listen.shutdown_timeout_secs",
+        "tls.cert",
+        "tls.key",
+        "tls.client_ca",
+        "database.url",
+        "database.max_connections",
+        "database.min_idle",
+        "database.connection_timeout_secs",
+        "database.idle_timeout_secs",
+        "database.max_lifetime_secs",
+        "database.statement_timeout_secs",
+        "auth.token_secret",
+        "auth.token_secret_file",
+        "auth.token_ttl_secs",
+        "auth.disabled",
+        "log.level",
+        "log.format",
+        "log.ansi",
+        "render.ipsec_profile",
+        "render.keepalive_secs",
+        "render.psk_secret",
+        "render.psk_secret_file",
+        "render.wireguard_network",
+        "render.wireguard_port",
+        "notifications.poll_interval_ms",
+        "notifications.keepalive_secs",
+        "notifications.retry_secs",
+        "notifications.watch_interval_secs",
+        "drift.readback_interval_secs",
+        "drift.auto_remediate",
+        "resolver.enabled",
+        "resolver.nameservers",
+        "resolver.timeout_secs",
+        "resolver.min_ttl_secs",
+        "resolver.max_ttl_secs",
+    ];
+
+    const ENV_PREFIX: &'static str = "TUNNEL_MANAGER_";
+
+    /// The variables the server read before it had a config file.
+    const ENV_ALIASES: &'static [(&'static str, &'static str)] = &[
+        ("DATABASE_URL", "database.url"),
+        ("DB_MAX_CONNECTION", "database.max_connections"),
+        ("GRPC_HOST", "listen.grpc_host"),
+        ("GRPC_PORT", "listen.grpc_port"),
+        ("HTTP_HOST", "listen.http_host"),
+        ("HTTP_PORT", "listen.http_port"),
+        ("CORS_ALLOWED_ORIGINS", "listen.cors_allowed_origins"),
+    ];
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let seconds = "a number of seconds";
+
+        match key {
+            "listen.grpc_host" => self.listen.grpc_host = value.trim().to_string(),
+            "listen.grpc_port" => self.listen.grpc_port = parse(value, "a port number")?,
+            "listen.http_host" => self.listen.http_host = value.trim().to_string(),
+            "listen.http_port" => self.listen.http_port = parse(value, "a port number")?,
+            "listen.cors_allowed_origins" => self.listen.cors_allowed_origins = list(value),
+            "listen.request_timeout_secs" => self.listen.request_timeout_secs = parse(value, seconds)?,
+            "listen.shutdown_timeout_secs" => self.listen.shutdown_timeout_secs = parse(value, seconds)?,
+            "tls.cert" => self.tls.cert = optional(value, "a path")?,
+            "tls.key" => self.tls.key = optional(value, "a path")?,
+            "tls.client_ca" => self.tls.client_ca = optional(value, "a path")?,
+            "database.url" => self.database.url = value.trim().to_string(),
+            "database.max_connections" => self.database.max_connections = parse(value, "a number")?,
+            "database.min_idle" => self.database.min_idle = optional(value, "a number")?,
+            "database.connection_timeout_secs" => self.database.connection_timeout_secs = parse(value, seconds)?,
+            "database.idle_timeout_secs" => self.database.idle_timeout_secs = parse(value, seconds)?,
+            "database.max_lifetime_secs" => self.database.max_lifetime_secs = parse(value, seconds)?,
+            "database.statement_timeout_secs" => self.database.statement_timeout_secs = parse(value, seconds)?,
+            "auth.token_secret" => self.auth.token_secret = optional(value, "a secret")?,
+            "auth.token_secret_file" => self.auth.token_secret_file = optional(value, "a path")?,
+            "auth.token_ttl_secs" => self.auth.token_ttl_secs = parse(value, seconds)?,
+            "auth.disabled" => self.auth.disabled = parse(value, "true or false")?,
+            "log.level" => self.log.level = value.trim().parse()?,
+            "log.format" => self.log.format = value.trim().parse()?,
+            "log.ansi" => self.log.ansi = parse(value, "true or false")?,
+            "render.ipsec_profile" => self.render.ipsec_profile = value.trim().to_string(),
+            "render.keepalive_secs" => self.render.keepalive_secs = optional(value, seconds)?,
+            "render.psk_secret" => self.render.psk_secret = optional(value, "a secret")?,
+            "render.psk_secret_file" => self.render.psk_secret_file = optional(value, "a path")?,
+            "render.wireguard_network" => self.render.wireguard_network = value.trim().to_string(),
+            "render.wireguard_port" => self.render.wireguard_port = parse(value, "a port number")?,
+            "notifications.poll_interval_ms" => self.notifications.poll_interval_ms = parse(value, "milliseconds")?,
+            "notifications.keepalive_secs" => self.notifications.keepalive_secs = parse(value, seconds)?,
+            "notifications.retry_secs" => self.notifications.retry_secs = parse(value, seconds)?,
+            "notifications.watch_interval_secs" => self.notifications.watch_interval_secs = parse(value, seconds)?,
+            "drift.readback_interval_secs" => self.drift.readback_interval_secs = parse(value, seconds)?,
+            "drift.auto_remediate" => self.drift.auto_remediate = parse(value, "true or false")?,
+            "resolver.enabled" => self.resolver.enabled = parse(value, "true or false")?,
+            "resolver.nameservers" => self.resolver.nameservers = list(value),
+            "resolver.timeout_secs" => self.resolver.timeout_secs = parse(value, seconds)?,
+            "resolver.min_ttl_secs" => self.resolver.min_ttl_secs = parse(value, seconds)?,
+            "resolver.max_ttl_secs" => self.resolver.max_ttl_secs = parse(value, seconds)?,
+            _ => return Err("unknown setting".to_string()),
+        }
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        let mut positive = |key: &str, value: u64| {
+            if value == 0 {
+                errors.push(format!("{} must be greater than 0", key));
+            }
+        };
+
+        positive("listen.request_timeout_secs", self.listen.request_timeout_secs);
+        positive("database.max_connections", self.database.max_connections.into());
+        positive("database.connection_timeout_secs", self.database.connection_timeout_secs);
+        positive("auth.token_ttl_secs", self.auth.token_ttl_secs);
+        positive("notifications.poll_interval_ms", self.notifications.poll_interval_ms);
+        positive("notifications.keepalive_secs", self.notifications.keepalive_secs);
+        positive("notifications.retry_secs", self.notifications.retry_secs);
+        positive("notifications.watch_interval_secs", self.notifications.watch_interval_secs);
+        positive("drift.readback_interval_secs", self.drift.readback_interval_secs.into());
+        positive("resolver.timeout_secs", self.resolver.timeout_secs);
+        positive("resolver.min_ttl_secs", self.resolver.min_ttl_secs);
+        positive("render.wireguard_port", self.render.wireguard_port.into());
+
+        if let Err(err) = self.listen.grpc_addr() {
+            errors.push(format!("listen.grpc_host: {}", err));
+        }
+        if let Err(err) = self.listen.http_addr() {
+            errors.push(format!("listen.http_host: {}", err));
+        }
+
+        if self.tls.cert.is_some() != self.tls.key.is_some() {
+            errors.push("tls.cert and tls.key must be set together".to_string());
+        }
+        if self.tls.client_ca.is_some() && self.tls.cert.is_none() {
+            errors.push("tls.client_ca needs tls.cert and tls.key".to_string());
+        }
+        readable(&mut errors, "tls.cert", &self.tls.cert);
+        readable(&mut errors, "tls.key", &self.tls.key);
+        readable(&mut errors, "tls.client_ca", &self.tls.client_ca);
+
+        if self.database.url.is_empty() {
+            errors.push("database.url is required (or DATABASE_URL)".to_string());
+        }
+        if self.database.min_idle.unwrap_or(0) > self.database.max_connections {
+            errors.push("database.min_idle cannot be more than database.max_connections".to_string());
+        }
+
+        match self.auth.secret() {
+            Ok(None) if !self.auth.disabled => errors.push(
+                "auth.token_secret or auth.token_secret_file is required, unless auth.disabled is set".to_string(),
+            ),
+            Ok(Some(_)) if self.auth.disabled => {
+                errors.push("auth.disabled cannot be set with a token secret".to_string())
+            }
+            Ok(_) => {}
+            Err(err) => errors.push(err),
+        }
+
+        if self.render.ipsec_profile.is_empty() || self.render.ipsec_profile.contains(char::is_whitespace) {
+            errors.push("render.ipsec_profile must be a single word".to_string());
+        }
+        if self.render.keepalive_secs == Some(0) {
+            errors.push("render.keepalive_secs must be greater than 0".to_string());
+        }
+        if let Err(err) = self.render.psk_secret() {
+            errors.push(err);
+        }
+        if let Err(err) = self.render.wireguard_network() {
+            errors.push(format!("render.wireguard_network: {}", err));
+        }
+
+        if self.resolver.max_ttl_secs < self.resolver.min_ttl_secs {
+            errors.push("resolver.max_ttl_secs cannot be less than resolver.min_ttl_secs".to_string());
+        }
+        if let Err(err) = self.resolver.nameservers() {
+            errors.push(format!("resolver.nameservers: {}", err));
+        }
+
+        errors
+    }
+}
@@ -0,0 +1,461 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use hyper::server::accept;
+use hyper::server::conn::AddrStream;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::server::TlsStream;
+use tonic::{Code, Status};
+use tower::{Service, ServiceExt};
+use tracing::{error, info};
+
+use crate::api::FILE_DESCRIPTOR_SET;
+use crate::gateway::json::Json;
+use crate::gateway::transcode::Descriptors;
+use crate::handlers::tunnels::FORWARDED_FOR;
+use crate::metrics::Exporter;
+use crate::shutdown::Shutdown;
+
+pub mod json;
+pub mod openapi;
+pub mod tls;
+pub mod transcode;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Values of the `{field}` segments of a matched path.
+pub type PathVars = Vec<(&'static str, String)>;
+
+/// An HTTP route and the RPC it is answered by. `{field}` segments of the path are copied into
+/// that field of the request message. Routes with a body take the rest of the request from the
+/// JSON body, the others from the query string.
+#[derive(Debug)]
+pub struct Route {
+    pub method: &'static str,
+    pub path: &'static str,
+    pub rpc: &'static str,
+    pub body: bool,
+}
+
+const fn route(method: &'static str, path: &'static str, rpc: &'static str, body: bool) -> Route {
+    Route {
+        method,
+        path,
+        rpc,
+        body,
+    }
+}
+
+pub const ROUTES: &[Route] = &[
+    route("POST", "/v1/login", "api.Auth/Login", true),
+    route("POST", "/v1/register", "api.Auth/Register", true),
+    route("GET", "/v1/users", "api.User/List", false),
+    route("GET", "/v1/users/{ID}", "api.User/Get", false),
+    route("POST", "/v1/users", "api.User/Add", true),
+    route("PATCH", "/v1/users/{ID}", "api.User/Update", true),
+    route("DELETE", "/v1/users/{ID}", "api.User/Delete", false),
+    route("GET", "/v1/users/{userID}/permissions", "api.PermissionMembership/GetUserPermissions", false),
+    route("GET", "/v1/permissions", "api.Permission/List", false),
+    route("GET", "/v1/permissions/{ID}", "api.Permission/Get", false),
+    route("POST", "/v1/permissions", "api.Permission/Add", true),
+    route("PATCH", "/v1/permissions/{ID}", "api.Permission/Update", true),
+    route("DELETE", "/v1/permissions/{ID}", "api.Permission/Delete", false),
+    route("GET", "/v1/permissions/{permission}/members", "api.PermissionMembership/GetPermissionMembers", false),
+    route("GET", "/v1/memberships", "api.PermissionMembership/List", false),
+    route("POST", "/v1/memberships", "api.PermissionMembership/Add", true),
+    route("PATCH", "/v1/memberships/{ID}", "api.PermissionMembership/Update", true),
+    route("DELETE", "/v1/memberships/{ID}", "api.PermissionMembership/Delete", false),
+    route("GET", "/v1/agents", "api.Agent/List", false),
+    route("GET", "/v1/agents/{ID}", "api.Agent/Get", false),
+    route("GET", "/v1/agents/{ID}/tree", "api.Agent/GetTree", false),
+    route("POST", "/v1/agents", "api.Agent/Register", true),
+    route("PATCH", "/v1/agents/{ID}", "api.Agent/Update", true),
+    route("DELETE", "/v1/agents/{ID}", "api.Agent/Unregister", false),
+    route("PUT", "/v1/agents/{UUID}/drivers", "api.Agent/SetDrivers", true),
+    route("GET", "/v1/routers", "api.Router/List", false),
+    route("GET", "/v1/routers/{ID}", "api.Router/Get", false),
+    route("POST", "/v1/routers", "api.Router/Add", true),
+    route("PATCH", "/v1/routers/{ID}", "api.Router/Update", true),
+    route("DELETE", "/v1/routers/{ID}", "api.Router/Delete", false),
+    route("POST", "/v1/routers/{ID}/repush", "api.Router/Repush", false),
+    route("GET", "/v1/routers/{ID}/config", "api.Router/Render", false),
+    route("POST", "/v1/routers/{router}/pushes", "api.Router/ReportPush", true),
+    route("GET", "/v1/routers/{router}/pushes", "api.Router/PushHistory", false),
+    route("GET", "/v1/routers/{ID}/sync-status", "api.Router/SyncStatus", false),
+    route("GET", "/v1/sync-status", "api.Router/SyncStatus", false),
+    route("POST", "/v1/routers/{router}/readback", "api.Router/ReportReadback", true),
+    route("GET", "/v1/routers/{ID}/drift", "api.Router/GetDrift", false),
+    route("GET", "/v1/drift", "api.Router/GetDrift", false),
+    route("GET", "/v1/router-types", "api.Router/ListTypes", false),
+    route("GET", "/v1/tunnels", "api.Tunnel/List", false),
+    route("GET", "/v1/tunnels/{ID}", "api.Tunnel/Get", false),
+    route("POST", "/v1/tunnels", "api.Tunnel/Add", true),
+    route("PATCH", "/v1/tunnels/{ID}", "api.Tunnel/Update", true),
+    route("DELETE", "/v1/tunnels/{ID}", "api.Tunnel/Delete", false),
+    route("POST", "/v1/tunnels/{tunnel}/address", "api.Tunnel/ReportAddress", true),
+    route("GET", "/v1/tunnels/{tunnel}/resolutions", "api.Tunnel/ResolutionHistory", false),
+    route("POST", "/v1/tunnels/{ID}/rotate-keys", "api.Tunnel/RotateKeys", false),
+    route("POST", "/v1/routers/{router}/rotate-keys", "api.Tunnel/RotateKeys", false),
+    route("GET", "/v1/mesh", "api.Mesh/Export", false),
+    route("POST", "/v1/mesh/apply", "api.Mesh/Apply", true),
+    route("GET", "/v1/health", "grpc.health.v1.Health/Check", false),
+];
+
+/// The largest request body the gateway reads, the largest message gRPC clients send by default.
+pub const MAX_BODY_SIZE: usize = 4 * 1024 * 1024;
+
+pub const OPENAPI_PATH: &str = "/v1/openapi.json";
+pub const METRICS_PATH: &str = "/metrics";
+
+/// The self-service web UI, served at `/`. It only talks to the routes above.
+const INDEX_HTML: &str = include_str!("../ui/index.html");
+
+/// HTTP status for a gRPC status code, the same mapping grpc-gateway uses.
+pub fn http_status(code: Code) -> StatusCode {
+    match code {
+        Code::Ok => StatusCode::OK,
+        Code::Cancelled => StatusCode::from_u16(499).unwrap(),
+        Code::InvalidArgument | Code::FailedPrecondition | Code::OutOfRange => StatusCode::BAD_REQUEST,
+        Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::AlreadyExists | Code::Aborted => StatusCode::CONFLICT,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        Code::Unknown | Code::Internal | Code::DataLoss => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Finds the route for a request, with the values of its `{field}` path segments. Fails with
+/// 404 for an unknown path and 405 for a known path with the wrong method.
+pub fn find_route(method: &str, path: &str) -> Result<(&'static Route, PathVars), StatusCode> {
+    let segments: Vec<&str> = path.trim_end_matches('/').split('/').collect();
+    let mut path_matched = false;
+
+    for route in ROUTES {
+        let template: Vec<&str> = route.path.split('/').collect();
+        if template.len() != segments.len() {
+            continue;
+        }
+
+        let mut vars = Vec::new();
+        let matched = template.iter().zip(&segments).all(|(t, s)| {
+            match t.strip_prefix('{').and_then(|t| t.strip_suffix('}')) {
+                Some(name) if !s.is_empty() => {
+                    vars.push((name, percent_decode(s)));
+                    true
+                }
+                Some(_) => false,
+                None => t == s,
+            }
+        });
+
+        if matched {
+            path_matched = true;
+            if route.method == method {
+                return Ok((route, vars));
+            }
+        }
+    }
+
+    match path_matched {
+        true => Err(StatusCode::METHOD_NOT_ALLOWED),
+        false => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+/// Serves the REST/JSON API on `addr`, over HTTPS with `tls`. Every request is transcoded to
+/// protobuf and sent through `grpc`, the same stack (interceptors included) the gRPC port serves,
+/// so both APIs share handlers, validation and authentication. `/metrics` is served from
+/// `exporter`, for Prometheus. Stops accepting when `shutdown` is triggered and returns once the
+/// requests in flight are answered.
+pub async fn serve<S, B>(
+    addr: SocketAddr,
+    tls: Option<Arc<ServerConfig>>,
+    grpc: S,
+    exporter: Exporter,
+    shutdown: Shutdown,
+) -> Result<(), BoxError>
+where
+    S: Service<Request<Body>, Response = Response<B>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Into<BoxError>,
+    B: HttpBody<Data = Bytes> + Send + Unpin + 'static,
+    B::Error: Into<BoxError>,
+{
+    let descriptors = Arc::new(Descriptors::decode(FILE_DESCRIPTOR_SET)?);
+    let openapi = Arc::new(openapi::document(&descriptors, ROUTES).to_string());
+
+    let service = move |client: SocketAddr| {
+        let (grpc, descriptors, openapi) = (grpc.clone(), descriptors.clone(), openapi.clone());
+        let exporter = exporter.clone();
+        service_fn(move |req| handle(req, client, grpc.clone(), descriptors.clone(), openapi.clone(), exporter.clone()))
+    };
+
+    match tls {
+        Some(tls) => {
+            let incoming = tls::incoming(TcpListener::bind(addr).await?, tls);
+            let make_service = make_service_fn(move |conn: &TlsStream<TcpStream>| {
+                let client = conn.get_ref().0.peer_addr();
+                let service = client.map(&service);
+                async move { service }
+            });
+
+            hyper::Server::builder(accept::from_stream(incoming))
+                .serve(make_service)
+                .with_graceful_shutdown(async move { shutdown.triggered().await })
+                .await?;
+        }
+        None => {
+            let make_service = make_service_fn(move |conn: &AddrStream| {
+                let service = service(conn.remote_addr());
+                async move { Ok::<_, Infallible>(service) }
+            });
+
+            hyper::Server::try_bind(&addr)?
+                .serve(make_service)
+                .with_graceful_shutdown(async move { shutdown.triggered().await })
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn handle<S, B>(
+    req: Request<Body>,
+    client: SocketAddr,
+    grpc: S,
+    descriptors: Arc<Descriptors>,
+    openapi: Arc<String>,
+    exporter: Exporter,
+) -> Result<Response<Body>, Infallible>
+where
+    S: Service<Request<Body>, Response = Response<B>>,
+    S::Error: Into<BoxError>,
+    B: HttpBody<Data = Bytes> + Unpin,
+    B::Error: Into<BoxError>,
+{
+    info!(message = "Got an HTTP request", method = %req.method(), path = %req.uri().path());
+
+    if req.method() == Method::GET {
+        match req.uri().path() {
+            OPENAPI_PATH => return Ok(json_response(StatusCode::OK, openapi.to_string())),
+            METRICS_PATH => {
+                let mut response = Response::new(Body::from(exporter.render().await));
+                response
+                    .headers_mut()
+                    .insert(CONTENT_TYPE, HeaderValue::from_static("text/plain; version=0.0.4"));
+                return Ok(response);
+            }
+            "/" | "/index.html" => {
+                let mut response = Response::new(Body::from(INDEX_HTML));
+                response
+                    .headers_mut()
+                    .insert(CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8"));
+                return Ok(response);
+            }
+            _ => {}
+        }
+    }
+
+    let (route, vars) = match find_route(req.method().as_str(), req.uri().path()) {
+        Ok(found) => found,
+        Err(http) => {
+            let message = format!("no route for {} {}", req.method(), req.uri().path());
+            return Ok(error_response(http, &Status::unimplemented(message)));
+        }
+    };
+
+    let (parts, body) = req.into_parts();
+    let body = match route.body {
+        true => match read_body(body, MAX_BODY_SIZE).await {
+            Ok(body) => body,
+            Err((http, status)) => return Ok(error_response(http, &status)),
+        },
+        false => Bytes::new(),
+    };
+
+    match transcode(Request::from_parts(parts, body), client, route, vars, grpc, &descriptors).await {
+        Ok(json) => Ok(json_response(StatusCode::OK, json.to_string())),
+        Err(status) => Ok(error_response(http_status(status.code()), &status)),
+    }
+}
+
+/// Reads a request body of at most `limit` bytes. Longer ones fail with 413, without being read
+/// past the limit.
+pub async fn read_body(mut body: Body, limit: usize) -> Result<Bytes, (StatusCode, Status)> {
+    let too_large = || {
+        let status = Status::resource_exhausted(format!("the request body is larger than {} bytes", limit));
+        (StatusCode::PAYLOAD_TOO_LARGE, status)
+    };
+
+    // A Content-Length over the limit is refused before anything is read.
+    if body.size_hint().lower() > limit as u64 {
+        return Err(too_large());
+    }
+
+    let mut data = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|err| (StatusCode::BAD_REQUEST, Status::invalid_argument(err.to_string())))?;
+        if data.len() + chunk.len() > limit {
+            return Err(too_large());
+        }
+        data.extend_from_slice(&chunk);
+    }
+
+    Ok(data.into())
+}
+
+async fn transcode<S, B>(
+    req: Request<Bytes>,
+    client: SocketAddr,
+    route: &Route,
+    vars: PathVars,
+    grpc: S,
+    descriptors: &Descriptors,
+) -> Result<Json, Status>
+where
+    S: Service<Request<Body>, Response = Response<B>>,
+    S::Error: Into<BoxError>,
+    B: HttpBody<Data = Bytes> + Unpin,
+    B::Error: Into<BoxError>,
+{
+    let method = descriptors
+        .method(route.rpc)
+        .ok_or_else(|| Status::internal(format!("{} is not in the descriptor set", route.rpc)))?;
+
+    let mut request = Json::object();
+    if !route.body {
+        for pair in req.uri().query().unwrap_or_default().split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            request.insert(&percent_decode(key), percent_decode(value));
+        }
+    }
+
+    let authorization = req.headers().get(AUTHORIZATION).cloned();
+
+    if route.body {
+        let body = req.body();
+        if !body.iter().all(u8::is_ascii_whitespace) {
+            let text = std::str::from_utf8(body).map_err(|err| Status::invalid_argument(err.to_string()))?;
+            request = Json::parse(text).map_err(Status::invalid_argument)?;
+        }
+    }
+
+    for (name, value) in vars {
+        request.insert(name, value);
+    }
+
+    let message = descriptors
+        .to_proto(&method.input, &request)
+        .map_err(Status::invalid_argument)?;
+
+    // A single uncompressed gRPC frame: flag, big-endian length, message.
+    let mut frame = Vec::with_capacity(message.len() + 5);
+    frame.push(0);
+    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&message);
+
+    let mut grpc_request = Request::builder()
+        .method(Method::POST)
+        .uri(format!("/{}", route.rpc))
+        .header(CONTENT_TYPE, "application/grpc")
+        .header("te", "trailers")
+        .header(FORWARDED_FOR, client.ip().to_string());
+    if let Some(authorization) = authorization {
+        grpc_request = grpc_request.header("authorization", authorization);
+    }
+    let grpc_request = grpc_request
+        .body(Body::from(frame))
+        .map_err(|err| Status::internal(err.to_string()))?;
+
+    let response = grpc
+        .oneshot(grpc_request)
+        .await
+        .map_err(|err| Status::from_error(err.into()))?;
+
+    // Errors usually arrive as a trailers-only response, with the status in the headers.
+    if let Some(status) = Status::from_header_map(response.headers()) {
+        if status.code() != Code::Ok {
+            return Err(status);
+        }
+    }
+
+    let mut body = response.into_body();
+    let mut data = Vec::new();
+    while let Some(chunk) = body.data().await {
+        data.extend_from_slice(&chunk.map_err(|err| Status::from_error(err.into()))?);
+    }
+
+    let trailers = body.trailers().await.map_err(|err| Status::from_error(err.into()))?;
+    if let Some(status) = trailers.as_ref().and_then(Status::from_header_map) {
+        if status.code() != Code::Ok {
+            return Err(status);
+        }
+    }
+
+    let message = match data.get(..5) {
+        Some([0, len @ ..]) => {
+            let size = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+            data.get(5..5 + size)
+                .ok_or_else(|| Status::internal("truncated gRPC response"))?
+        }
+        _ => return Err(Status::internal("unexpected gRPC response")),
+    };
+
+    descriptors
+        .to_json(&method.output, message)
+        .map_err(Status::internal)
+}
+
+fn error_response(http: StatusCode, status: &Status) -> Response<Body> {
+    error!(message = "HTTP request failed", code = ?status.code(), status = status.message());
+
+    let body = Json::object()
+        .with("code", status.code() as i64)
+        .with("message", status.message());
+    json_response(http, body.to_string())
+}
+
+fn json_response(status: StatusCode, body: String) -> Response<Body> {
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                match std::str::from_utf8(&bytes[i + 1..i + 3]).ok().and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                    Some(b) => {
+                        out.push(b);
+                        i += 2;
+                    }
+                    None => out.push(b'%'),
+                }
+            }
+            b => out.push(b),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
@@ -0,0 +1,194 @@
+use std::collections::BTreeSet;
+
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::FieldDescriptorProto;
+
+use crate::gateway::json::Json;
+use crate::gateway::transcode::{json_name, Descriptors};
+use crate::gateway::Route;
+
+/// Builds the OpenAPI 3 document for `routes`, with a schema for every message they use taken
+/// from the descriptor set, so it always matches what the gateway actually accepts.
+pub fn document(descriptors: &Descriptors, routes: &[Route]) -> Json {
+    let mut paths = Json::object();
+    let mut referenced = BTreeSet::new();
+
+    for route in routes {
+        let method = match descriptors.method(route.rpc) {
+            Some(method) => method,
+            None => continue,
+        };
+        let input = match descriptors.message(&method.input) {
+            Some(input) => input,
+            None => continue,
+        };
+
+        let path_vars: Vec<&str> = route
+            .path
+            .split('/')
+            .filter_map(|s| s.strip_prefix('{').and_then(|s| s.strip_suffix('}')))
+            .collect();
+
+        let mut parameters = Vec::new();
+        for field in &input.field {
+            let bound = path_vars.contains(&field.name()) || path_vars.contains(&json_name(field).as_str());
+            let scalar = field.label() != Label::Repeated
+                && (field.r#type() != Type::Message || field.type_name() == ".google.protobuf.Timestamp");
+
+            if bound {
+                parameters.push(
+                    Json::object()
+                        .with("name", field.name())
+                        .with("in", "path")
+                        .with("required", true)
+                        .with("schema", field_schema(descriptors, field, &mut referenced)),
+                );
+            } else if !route.body && scalar {
+                parameters.push(
+                    Json::object()
+                        .with("name", json_name(field))
+                        .with("in", "query")
+                        .with("schema", field_schema(descriptors, field, &mut referenced)),
+                );
+            }
+        }
+
+        let service = method.service.rsplit('.').next().unwrap_or_default();
+        let mut operation = Json::object()
+            .with("operationId", format!("{}_{}", service, method.name))
+            .with("tags", vec![Json::from(service)])
+            .with("parameters", parameters);
+
+        if route.body {
+            operation.insert(
+                "requestBody",
+                Json::object()
+                    .with("required", true)
+                    .with("content", json_content(schema_ref(&method.input, &mut referenced))),
+            );
+        }
+
+        operation.insert(
+            "responses",
+            Json::object()
+                .with(
+                    "200",
+                    Json::object()
+                        .with("description", "OK")
+                        .with("content", json_content(schema_ref(&method.output, &mut referenced))),
+                )
+                .with(
+                    "default",
+                    Json::object()
+                        .with("description", "The gRPC status of a failed call")
+                        .with("content", json_content(Json::object().with("$ref", "#/components/schemas/Status"))),
+                ),
+        );
+
+        let mut item = paths.get(route.path).cloned().unwrap_or_else(Json::object);
+        item.insert(&route.method.to_lowercase(), operation);
+        paths.insert(route.path, item);
+    }
+
+    let mut schemas = Json::object().with(
+        "Status",
+        Json::object().with("type", "object").with(
+            "properties",
+            Json::object()
+                .with("code", Json::object().with("type", "integer").with("format", "int32"))
+                .with("message", Json::object().with("type", "string")),
+        ),
+    );
+
+    // Message schemas pull in the messages of their own fields, so keep going until none are new.
+    let mut done = BTreeSet::new();
+    while let Some(name) = referenced.iter().find(|n| !done.contains(*n)).cloned() {
+        done.insert(name.clone());
+
+        let mut properties = Json::object();
+        if let Some(message) = descriptors.message(&name) {
+            for field in &message.field {
+                let schema = field_schema(descriptors, field, &mut referenced);
+                properties.insert(&json_name(field), schema);
+            }
+        }
+
+        schemas.insert(
+            name.trim_start_matches('.'),
+            Json::object().with("type", "object").with("properties", properties),
+        );
+    }
+
+    Json::object()
+        .with("openapi", "3.0.3")
+        .with(
+            "info",
+            Json::object()
+                .with("title", "Tunnel Manager")
+                .with("version", env!("CARGO_PKG_VERSION")),
+        )
+        .with("paths", paths)
+        .with(
+            "components",
+            Json::object().with("schemas", schemas).with(
+                "securitySchemes",
+                Json::object().with(
+                    "token",
+                    Json::object()
+                        .with("type", "apiKey")
+                        .with("in", "header")
+                        .with("name", "Authorization"),
+                ),
+            ),
+        )
+        .with("security", vec![Json::object().with("token", Vec::new())])
+}
+
+fn json_content(schema: Json) -> Json {
+    Json::object().with("application/json", Json::object().with("schema", schema))
+}
+
+fn schema_ref(type_name: &str, referenced: &mut BTreeSet<String>) -> Json {
+    referenced.insert(type_name.to_string());
+    Json::object().with(
+        "$ref",
+        format!("#/components/schemas/{}", type_name.trim_start_matches('.')),
+    )
+}
+
+fn field_schema(descriptors: &Descriptors, field: &FieldDescriptorProto, referenced: &mut BTreeSet<String>) -> Json {
+    let typed = |t: &str, format: Option<&str>| {
+        let schema = Json::object().with("type", t);
+        match format {
+            Some(format) => schema.with("format", format),
+            None => schema,
+        }
+    };
+
+    let schema = match field.r#type() {
+        Type::Int32 | Type::Sint32 | Type::Sfixed32 => typed("integer", Some("int32")),
+        Type::Uint32 | Type::Fixed32 => typed("integer", Some("int64")),
+        Type::Int64 | Type::Sint64 | Type::Sfixed64 => typed("string", Some("int64")),
+        Type::Uint64 | Type::Fixed64 => typed("string", Some("uint64")),
+        Type::Float => typed("number", Some("float")),
+        Type::Double => typed("number", Some("double")),
+        Type::Bool => typed("boolean", None),
+        Type::String => typed("string", None),
+        Type::Bytes => typed("string", Some("byte")),
+        Type::Enum => {
+            let values: Vec<Json> = descriptors
+                .enum_values(field.type_name())
+                .into_iter()
+                .map(Json::from)
+                .collect();
+            typed("string", None).with("enum", values)
+        }
+        Type::Message if field.type_name() == ".google.protobuf.Timestamp" => typed("string", Some("date-time")),
+        Type::Message | Type::Group => schema_ref(field.type_name(), referenced),
+    };
+
+    match field.label() {
+        Label::Repeated => typed("array", None).with("items", schema),
+        _ => schema,
+    }
+}
@@ -0,0 +1,103 @@
+use std::fs;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_core::Stream;
+use futures_util::StreamExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::rustls::server::AllowAnyAuthenticatedClient;
+use tokio_rustls::rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tracing::warn;
+
+use crate::config::TlsConfig;
+use crate::gateway::BoxError;
+
+/// How long a client gets to finish its handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Handshakes under way at once; further connections wait to be accepted.
+const MAX_HANDSHAKES: usize = 64;
+
+/// The TLS settings for the HTTP port, `None` if it is to stay plain HTTP. The same certificate
+/// as the gRPC port's, and like there, `tls.client_ca` makes clients present one of their own.
+pub fn server_config(tls: &TlsConfig) -> Result<Option<Arc<ServerConfig>>, BoxError> {
+    let (cert, key) = match (&tls.cert, &tls.key) {
+        (Some(cert), Some(key)) => (fs::read(cert)?, fs::read(key)?),
+        _ => return Ok(None),
+    };
+
+    let certs = rustls_pemfile::certs(&mut cert.as_slice())?.into_iter().map(Certificate).collect();
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match &tls.client_ca {
+        Some(client_ca) => {
+            let mut roots = RootCertStore::empty();
+            for ca in rustls_pemfile::certs(&mut fs::read(client_ca)?.as_slice())? {
+                roots.add(&Certificate(ca))?;
+            }
+            builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots))
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut config = builder.with_single_cert(certs, private_key(&key)?)?;
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(Some(Arc::new(config)))
+}
+
+fn private_key(mut pem: &[u8]) -> Result<PrivateKey, BoxError> {
+    use rustls_pemfile::Item;
+
+    while let Some(item) = rustls_pemfile::read_one(&mut pem)? {
+        if let Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) = item {
+            return Ok(PrivateKey(key));
+        }
+    }
+
+    Err("tls.key holds no private key".into())
+}
+
+/// The connections to `listener` that complete a TLS handshake. Those that fail, or take longer
+/// than [`HANDSHAKE_TIMEOUT`], are logged and dropped rather than ending the stream, which would
+/// stop the server.
+pub fn incoming(
+    listener: TcpListener,
+    config: Arc<ServerConfig>,
+) -> impl Stream<Item = io::Result<TlsStream<TcpStream>>> {
+    let acceptor = TlsAcceptor::from(config);
+
+    futures_util::stream::unfold(listener, |listener| async move {
+        let accepted = listener.accept().await;
+        Some((accepted, listener))
+    })
+    .map(move |accepted| {
+        let acceptor = acceptor.clone();
+        async move {
+            let (stream, client) = match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    // Out of file descriptors, most likely; give some a chance to be closed.
+                    warn!(message = "Could not accept an HTTPS connection", %err);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    return None;
+                }
+            };
+
+            match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => Some(Ok(stream)),
+                Ok(Err(err)) => {
+                    warn!(message = "TLS handshake failed", %client, %err);
+                    None
+                }
+                Err(_) => {
+                    warn!(message = "TLS handshake timed out", %client);
+                    None
+                }
+            }
+        }
+    })
+    .buffer_unordered(MAX_HANDSHAKES)
+    .filter_map(|stream| async move { stream })
+}
@@ -0,0 +1,512 @@
+use std::collections::HashMap;
+
+use prost::Message;
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::{DescriptorProto, EnumDescriptorProto, FieldDescriptorProto, FileDescriptorSet};
+
+use crate::gateway::json::Json;
+
+const TIMESTAMP: &str = ".google.protobuf.Timestamp";
+
+/// An RPC as described by the descriptor set, with fully qualified message names.
+#[derive(Debug, Clone)]
+pub struct Method {
+    pub service: String,
+    pub name: String,
+    pub input: String,
+    pub output: String,
+    pub server_streaming: bool,
+}
+
+/// The messages, enums and services of the API, indexed by fully qualified name (`.api.Foo`), so
+/// requests can be turned from JSON into protobuf and responses back again without generated
+/// code for each message.
+///
+/// The JSON follows the proto3 mapping: fields are keyed by their lowerCamelCase JSON name (the
+/// original field name is accepted too), 64-bit integers are strings, enums are their value
+/// names and timestamps are RFC 3339 strings.
+#[derive(Debug, Default)]
+pub struct Descriptors {
+    messages: HashMap<String, DescriptorProto>,
+    enums: HashMap<String, EnumDescriptorProto>,
+    methods: Vec<Method>,
+}
+
+impl Descriptors {
+    pub fn decode(bytes: &[u8]) -> Result<Descriptors, prost::DecodeError> {
+        let set = FileDescriptorSet::decode(bytes)?;
+        let mut descriptors = Descriptors::default();
+
+        for file in set.file {
+            let package = match file.package() {
+                "" => String::new(),
+                package => format!(".{}", package),
+            };
+
+            descriptors.add_types(&package, file.message_type, file.enum_type);
+
+            for service in file.service {
+                for method in &service.method {
+                    descriptors.methods.push(Method {
+                        service: format!("{}.{}", package, service.name()).trim_start_matches('.').to_string(),
+                        name: method.name().to_string(),
+                        input: method.input_type().to_string(),
+                        output: method.output_type().to_string(),
+                        server_streaming: method.server_streaming(),
+                    });
+                }
+            }
+        }
+
+        Ok(descriptors)
+    }
+
+    fn add_types(&mut self, scope: &str, messages: Vec<DescriptorProto>, enums: Vec<EnumDescriptorProto>) {
+        for e in enums {
+            self.enums.insert(format!("{}.{}", scope, e.name()), e);
+        }
+
+        for mut message in messages {
+            let name = format!("{}.{}", scope, message.name());
+            let nested = std::mem::take(&mut message.nested_type);
+            let nested_enums = std::mem::take(&mut message.enum_type);
+            self.add_types(&name, nested, nested_enums);
+            self.messages.insert(name, message);
+        }
+    }
+
+    /// Looks up an RPC by its gRPC path, `package.Service/Method`.
+    pub fn method(&self, path: &str) -> Option<&Method> {
+        let (service, name) = path.split_once('/')?;
+        self.methods.iter().find(|m| m.service == service && m.name == name)
+    }
+
+    pub fn message(&self, name: &str) -> Option<&DescriptorProto> {
+        self.messages.get(name)
+    }
+
+    pub fn enum_values(&self, name: &str) -> Vec<&str> {
+        match self.enums.get(name) {
+            Some(e) => e.value.iter().map(|v| v.name()).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Encodes a JSON object as the protobuf message `type_name`.
+    pub fn to_proto(&self, type_name: &str, json: &Json) -> Result<Vec<u8>, String> {
+        let mut buf = Vec::new();
+        self.encode_message(type_name, json, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Decodes the protobuf message `type_name` into a JSON object.
+    pub fn to_json(&self, type_name: &str, bytes: &[u8]) -> Result<Json, String> {
+        self.decode_message(type_name, bytes)
+    }
+
+    fn encode_message(&self, type_name: &str, json: &Json, buf: &mut Vec<u8>) -> Result<(), String> {
+        let message = self
+            .message(type_name)
+            .ok_or_else(|| format!("unknown message type {}", type_name))?;
+
+        let entries = match json {
+            Json::Object(entries) => entries,
+            _ => return Err(format!("expected an object for {}", type_name.trim_start_matches('.'))),
+        };
+
+        for (key, value) in entries {
+            let field = message
+                .field
+                .iter()
+                .find(|f| json_name(f) == *key || f.name() == key)
+                .ok_or_else(|| format!("unknown field \"{}\"", key))?;
+
+            match (value, field.label()) {
+                (Json::Null, _) => {}
+                (Json::Array(items), Label::Repeated) => {
+                    for item in items {
+                        self.encode_field(field, item, buf)?;
+                    }
+                }
+                (_, Label::Repeated) => return Err(format!("field \"{}\" must be an array", key)),
+                _ => self.encode_field(field, value, buf)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn encode_field(&self, field: &FieldDescriptorProto, value: &Json, buf: &mut Vec<u8>) -> Result<(), String> {
+        let number = field.number() as u64;
+        let invalid = || format!("invalid value {} for field \"{}\"", value, json_name(field));
+
+        match field.r#type() {
+            Type::Int32 | Type::Int64 | Type::Uint32 | Type::Uint64 | Type::Sint32 | Type::Sint64 => {
+                let text = number_text(value).ok_or_else(invalid)?;
+                let n = match field.r#type() {
+                    Type::Int32 => text.parse::<i32>().map(|n| n as i64 as u64).ok(),
+                    Type::Int64 => text.parse::<i64>().map(|n| n as u64).ok(),
+                    Type::Uint32 => text.parse::<u32>().map(|n| n as u64).ok(),
+                    Type::Uint64 => text.parse::<u64>().ok(),
+                    Type::Sint32 => text.parse::<i32>().map(|n| ((n << 1) ^ (n >> 31)) as u32 as u64).ok(),
+                    _ => text.parse::<i64>().map(|n| ((n << 1) ^ (n >> 63)) as u64).ok(),
+                };
+                write_varint(buf, number << 3);
+                write_varint(buf, n.ok_or_else(invalid)?);
+            }
+            Type::Bool => {
+                let b = match value {
+                    Json::Bool(b) => *b,
+                    Json::String(s) if s == "true" => true,
+                    Json::String(s) if s == "false" => false,
+                    _ => return Err(invalid()),
+                };
+                write_varint(buf, number << 3);
+                write_varint(buf, b as u64);
+            }
+            Type::Enum => {
+                let n = match value {
+                    Json::String(name) => self
+                        .enums
+                        .get(field.type_name())
+                        .and_then(|e| e.value.iter().find(|v| v.name() == name))
+                        .map(|v| v.number()),
+                    _ => number_text(value).and_then(|text| text.parse::<i32>().ok()),
+                };
+                write_varint(buf, number << 3);
+                write_varint(buf, n.ok_or_else(invalid)? as i64 as u64);
+            }
+            Type::Fixed32 | Type::Sfixed32 | Type::Float => {
+                let text = number_text(value).ok_or_else(invalid)?;
+                let bytes = match field.r#type() {
+                    Type::Fixed32 => text.parse::<u32>().map(u32::to_le_bytes).ok(),
+                    Type::Sfixed32 => text.parse::<i32>().map(i32::to_le_bytes).ok(),
+                    _ => text.parse::<f32>().map(f32::to_le_bytes).ok(),
+                };
+                write_varint(buf, number << 3 | 5);
+                buf.extend_from_slice(&bytes.ok_or_else(invalid)?);
+            }
+            Type::Fixed64 | Type::Sfixed64 | Type::Double => {
+                let text = number_text(value).ok_or_else(invalid)?;
+                let bytes = match field.r#type() {
+                    Type::Fixed64 => text.parse::<u64>().map(u64::to_le_bytes).ok(),
+                    Type::Sfixed64 => text.parse::<i64>().map(i64::to_le_bytes).ok(),
+                    _ => text.parse::<f64>().map(f64::to_le_bytes).ok(),
+                };
+                write_varint(buf, number << 3 | 1);
+                buf.extend_from_slice(&bytes.ok_or_else(invalid)?);
+            }
+            Type::String => {
+                let s = value.as_str().ok_or_else(invalid)?;
+                write_varint(buf, number << 3 | 2);
+                write_varint(buf, s.len() as u64);
+                buf.extend_from_slice(s.as_bytes());
+            }
+            Type::Message if field.type_name() == TIMESTAMP => {
+                let (seconds, nanos) = value.as_str().and_then(parse_timestamp).ok_or_else(invalid)?;
+                let mut inner = Vec::new();
+                write_varint(&mut inner, 1 << 3);
+                write_varint(&mut inner, seconds as u64);
+                write_varint(&mut inner, 2 << 3);
+                write_varint(&mut inner, nanos as u64);
+                write_varint(buf, number << 3 | 2);
+                write_varint(buf, inner.len() as u64);
+                buf.extend_from_slice(&inner);
+            }
+            Type::Message => {
+                let mut inner = Vec::new();
+                self.encode_message(field.type_name(), value, &mut inner)?;
+                write_varint(buf, number << 3 | 2);
+                write_varint(buf, inner.len() as u64);
+                buf.extend_from_slice(&inner);
+            }
+            Type::Bytes | Type::Group => {
+                return Err(format!("field \"{}\" cannot be set over HTTP", json_name(field)));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn decode_message(&self, type_name: &str, bytes: &[u8]) -> Result<Json, String> {
+        if type_name == TIMESTAMP {
+            let mut seconds = 0;
+            let mut nanos = 0;
+            for (number, value) in read_fields(bytes)? {
+                match (number, value) {
+                    (1, Wire::Varint(n)) => seconds = n as i64,
+                    (2, Wire::Varint(n)) => nanos = n as i32,
+                    _ => {}
+                }
+            }
+            return Ok(Json::String(format_timestamp(seconds, nanos)));
+        }
+
+        let message = self
+            .message(type_name)
+            .ok_or_else(|| format!("unknown message type {}", type_name))?;
+
+        let mut values: Vec<Vec<Json>> = vec![Vec::new(); message.field.len()];
+        for (number, wire) in read_fields(bytes)? {
+            if let Some(i) = message.field.iter().position(|f| f.number() as u32 == number) {
+                self.decode_field(&message.field[i], wire, &mut values[i])?;
+            }
+        }
+
+        let mut json = Json::object();
+        for (field, mut values) in message.field.iter().zip(values) {
+            let key = json_name(field);
+
+            if field.label() == Label::Repeated {
+                json.insert(&key, Json::Array(values));
+            } else if let Some(value) = values.pop() {
+                json.insert(&key, value);
+            } else if field.oneof_index.is_none() && field.r#type() != Type::Message {
+                json.insert(&key, self.default_value(field));
+            }
+        }
+
+        Ok(json)
+    }
+
+    fn decode_field(&self, field: &FieldDescriptorProto, wire: Wire, out: &mut Vec<Json>) -> Result<(), String> {
+        let value = match (field.r#type(), wire) {
+            (Type::String, Wire::Bytes(b)) => Json::String(String::from_utf8_lossy(b).into_owned()),
+            (Type::Message, Wire::Bytes(b)) => self.decode_message(field.type_name(), b)?,
+            (Type::Bytes, Wire::Bytes(_)) => Json::Null,
+            (_, Wire::Bytes(mut packed)) => {
+                // A packed repeated scalar: the same field over and over without keys.
+                while !packed.is_empty() {
+                    let wire = match field.r#type() {
+                        Type::Fixed32 | Type::Sfixed32 | Type::Float => Wire::Fixed32(take(&mut packed, 4)?),
+                        Type::Fixed64 | Type::Sfixed64 | Type::Double => Wire::Fixed64(take(&mut packed, 8)?),
+                        _ => Wire::Varint(read_varint(&mut packed)?),
+                    };
+                    self.decode_field(field, wire, out)?;
+                }
+                return Ok(());
+            }
+            (Type::Int32, Wire::Varint(n)) => Json::from(n as i32 as i64),
+            (Type::Uint32, Wire::Varint(n)) => Json::from(n as u32 as i64),
+            (Type::Sint32, Wire::Varint(n)) => Json::from(((n as u32 >> 1) as i32 ^ -((n & 1) as i32)) as i64),
+            (Type::Int64, Wire::Varint(n)) => Json::String((n as i64).to_string()),
+            (Type::Uint64, Wire::Varint(n)) => Json::String(n.to_string()),
+            (Type::Sint64, Wire::Varint(n)) => Json::String(((n >> 1) as i64 ^ -((n & 1) as i64)).to_string()),
+            (Type::Bool, Wire::Varint(n)) => Json::Bool(n != 0),
+            (Type::Enum, Wire::Varint(n)) => {
+                let n = n as i32;
+                match self
+                    .enums
+                    .get(field.type_name())
+                    .and_then(|e| e.value.iter().find(|v| v.number() == n))
+                {
+                    Some(v) => Json::from(v.name()),
+                    None => Json::from(n as i64),
+                }
+            }
+            (Type::Fixed32, Wire::Fixed32(b)) => Json::from(u32::from_le_bytes(b.try_into().unwrap()) as i64),
+            (Type::Sfixed32, Wire::Fixed32(b)) => Json::from(i32::from_le_bytes(b.try_into().unwrap()) as i64),
+            (Type::Float, Wire::Fixed32(b)) => float(f32::from_le_bytes(b.try_into().unwrap()) as f64),
+            (Type::Fixed64, Wire::Fixed64(b)) => Json::String(u64::from_le_bytes(b.try_into().unwrap()).to_string()),
+            (Type::Sfixed64, Wire::Fixed64(b)) => Json::String(i64::from_le_bytes(b.try_into().unwrap()).to_string()),
+            (Type::Double, Wire::Fixed64(b)) => float(f64::from_le_bytes(b.try_into().unwrap())),
+            _ => return Err(format!("wire type mismatch for field \"{}\"", field.name())),
+        };
+
+        out.push(value);
+        Ok(())
+    }
+
+    fn default_value(&self, field: &FieldDescriptorProto) -> Json {
+        match field.r#type() {
+            Type::String => Json::from(""),
+            Type::Bool => Json::Bool(false),
+            Type::Int64 | Type::Uint64 | Type::Sint64 | Type::Fixed64 | Type::Sfixed64 => Json::from("0"),
+            Type::Enum => self
+                .enum_values(field.type_name())
+                .first()
+                .map(|name| Json::from(*name))
+                .unwrap_or_else(|| Json::from(0)),
+            Type::Bytes | Type::Message | Type::Group => Json::Null,
+            _ => Json::from(0),
+        }
+    }
+}
+
+/// The proto3 JSON name of a field, falling back to computing it when protoc left it out.
+pub fn json_name(field: &FieldDescriptorProto) -> String {
+    if let Some(name) = &field.json_name {
+        return name.clone();
+    }
+
+    let mut name = String::new();
+    let mut upper = false;
+    for c in field.name().chars() {
+        match c {
+            '_' => upper = true,
+            c if upper => {
+                name.extend(c.to_uppercase());
+                upper = false;
+            }
+            c => name.push(c),
+        }
+    }
+    name
+}
+
+fn number_text(value: &Json) -> Option<&str> {
+    match value {
+        Json::Number(text) | Json::String(text) => Some(text),
+        _ => None,
+    }
+}
+
+fn float(n: f64) -> Json {
+    match n.is_finite() {
+        true => Json::Number(n.to_string()),
+        false => Json::String(if n.is_nan() { "NaN" } else if n > 0.0 { "Infinity" } else { "-Infinity" }.to_string()),
+    }
+}
+
+enum Wire<'a> {
+    Varint(u64),
+    Fixed64(&'a [u8]),
+    Bytes(&'a [u8]),
+    Fixed32(&'a [u8]),
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Result<u64, String> {
+    let mut n = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&b, rest) = bytes.split_first().ok_or("truncated varint")?;
+        *bytes = rest;
+        n |= ((b & 0x7f) as u64) << shift;
+        if b < 0x80 {
+            return Ok(n);
+        }
+    }
+    Err("varint too long".to_string())
+}
+
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8], String> {
+    if bytes.len() < len {
+        return Err("truncated message".to_string());
+    }
+    let (head, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(head)
+}
+
+fn read_fields(mut bytes: &[u8]) -> Result<Vec<(u32, Wire<'_>)>, String> {
+    let mut fields = Vec::new();
+
+    while !bytes.is_empty() {
+        let key = read_varint(&mut bytes)?;
+        let wire = match key & 7 {
+            0 => Wire::Varint(read_varint(&mut bytes)?),
+            1 => Wire::Fixed64(take(&mut bytes, 8)?),
+            2 => {
+                let len = read_varint(&mut bytes)? as usize;
+                Wire::Bytes(take(&mut bytes, len)?)
+            }
+            5 => Wire::Fixed32(take(&mut bytes, 4)?),
+            wire_type => return Err(format!("unsupported wire type {}", wire_type)),
+        };
+        fields.push(((key >> 3) as u32, wire));
+    }
+
+    Ok(fields)
+}
+
+/// Formats seconds and nanoseconds since the epoch as RFC 3339 in UTC, with as many fractional
+/// digits (0, 3, 6 or 9) as the value needs.
+pub fn format_timestamp(seconds: i64, nanos: i32) -> String {
+    let days = seconds.div_euclid(86400);
+    let secs = seconds.rem_euclid(86400);
+
+    // Days to civil date, from Howard Hinnant's chrono-compatible algorithms.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    let fraction = match nanos {
+        0 => String::new(),
+        n if n % 1_000_000 == 0 => format!(".{:03}", n / 1_000_000),
+        n if n % 1_000 == 0 => format!(".{:06}", n / 1_000),
+        n => format!(".{:09}", n),
+    };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        fraction
+    )
+}
+
+/// Parses an RFC 3339 timestamp (`2024-05-01T12:00:00Z`, optionally with a fraction and a
+/// numeric offset) into seconds and nanoseconds since the epoch.
+pub fn parse_timestamp(text: &str) -> Option<(i64, i32)> {
+    let num = |s: &str| -> Option<i64> {
+        match s.bytes().all(|b| b.is_ascii_digit()) && !s.is_empty() {
+            true => s.parse().ok(),
+            false => None,
+        }
+    };
+
+    let (date, time) = text.split_once(['T', 't', ' '])?;
+    let mut date = date.splitn(3, '-');
+    let (year, month, day) = (num(date.next()?)?, num(date.next()?)?, num(date.next()?)?);
+
+    let (time, offset) = match time.strip_suffix(['Z', 'z']) {
+        Some(time) => (time, 0),
+        None => {
+            let at = time.rfind(['+', '-'])?;
+            let (hours, minutes) = time[at + 1..].split_once(':')?;
+            let offset = (num(hours)? * 60 + num(minutes)?) * 60;
+            (&time[..at], if &time[at..at + 1] == "-" { -offset } else { offset })
+        }
+    };
+
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let mut time = time.splitn(3, ':');
+    let (hour, minute, second) = (num(time.next()?)?, num(time.next()?)?, num(time.next()?)?);
+
+    let nanos = match fraction {
+        "" => 0,
+        f if f.len() <= 9 => num(f)? * 10i64.pow(9 - f.len() as u32),
+        _ => return None,
+    };
+
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    // Civil date to days, the inverse of the algorithm in `format_timestamp`.
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    Some((days * 86400 + hour * 3600 + minute * 60 + second - offset, nanos as i32))
+}
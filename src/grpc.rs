@@ -0,0 +1,15 @@
+//! The standard gRPC protocols the server implements next to its own API.
+
+pub mod health {
+    pub mod v1 {
+        #![allow(clippy::derive_partial_eq_without_eq)]
+        tonic::include_proto!("grpc.health.v1");
+    }
+}
+
+pub mod reflection {
+    pub mod v1alpha {
+        #![allow(clippy::derive_partial_eq_without_eq)]
+        tonic::include_proto!("grpc.reflection.v1alpha");
+    }
+}
@@ -0,0 +1,290 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::{self, HeaderMap, HeaderValue};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use tonic::body::{empty_body, BoxBody};
+use tonic::Status;
+use tower::{Layer, Service};
+
+const GRPC_WEB: &str = "application/grpc-web";
+const GRPC_WEB_TEXT: &str = "application/grpc-web-text";
+
+const ALLOWED_HEADERS: &str = "authorization,content-type,grpc-timeout,x-grpc-web,x-user-agent";
+const EXPOSED_HEADERS: &str = "grpc-status,grpc-message,grpc-status-details-bin";
+
+/// Which origins browsers may call the gRPC port from. `*` allows any origin.
+#[derive(Debug, Clone, Default)]
+pub struct Cors {
+    origins: Vec<String>,
+}
+
+impl Cors {
+    /// Parses a comma separated list of origins, like `https://a.example.org,http://localhost:3000`.
+    pub fn new(origins: &str) -> Cors {
+        Cors {
+            origins: origins
+                .split(',')
+                .map(|o| o.trim().trim_end_matches('/').to_string())
+                .filter(|o| !o.is_empty())
+                .collect(),
+        }
+    }
+
+    /// The `Access-Control-Allow-Origin` value for a request from `origin`, if it is allowed.
+    pub fn allow_origin(&self, origin: &HeaderValue) -> Option<HeaderValue> {
+        if self.origins.iter().any(|o| o == "*") {
+            return Some(HeaderValue::from_static("*"));
+        }
+
+        let origin_str = origin.to_str().ok()?;
+        match self.origins.iter().any(|o| o == origin_str) {
+            true => Some(origin.clone()),
+            false => None,
+        }
+    }
+}
+
+/// Lets browsers call the gRPC services directly over gRPC-Web, on the same port and through the
+/// same services and interceptors as native gRPC clients.
+///
+/// gRPC-Web requests are rewritten into plain gRPC (decoding `grpc-web-text` bodies from base64),
+/// and the response trailers are moved into the body, where HTTP/1.1 and `fetch` can see them.
+/// CORS preflights are answered here, for the origins in [`Cors`]. Everything else passes
+/// through untouched.
+#[derive(Debug, Clone)]
+pub struct GrpcWebLayer {
+    cors: Arc<Cors>,
+}
+
+impl GrpcWebLayer {
+    pub fn new(cors: Cors) -> GrpcWebLayer {
+        GrpcWebLayer { cors: Arc::new(cors) }
+    }
+}
+
+impl<S> Layer<S> for GrpcWebLayer {
+    type Service = GrpcWeb<S>;
+
+    fn layer(&self, inner: S) -> GrpcWeb<S> {
+        GrpcWeb {
+            inner,
+            cors: self.cors.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GrpcWeb<S> {
+    inner: S,
+    cors: Arc<Cors>,
+}
+
+type BoxFuture<T, E> = Pin<Box<dyn Future<Output = Result<T, E>> + Send>>;
+
+impl<S> Service<Request<Body>> for GrpcWeb<S>
+where
+    S: Service<Request<Body>, Response = Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response<BoxBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        // The clone may not be ready yet, so call the one poll_ready was called on.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let cors = self.cors.clone();
+
+        let origin = req.headers().get(header::ORIGIN).cloned();
+        let allow_origin = origin.as_ref().and_then(|o| cors.allow_origin(o));
+
+        if req.method() == Method::OPTIONS && req.headers().contains_key(header::ACCESS_CONTROL_REQUEST_METHOD) {
+            return Box::pin(async move { Ok(preflight(&req, allow_origin)) });
+        }
+
+        let content_type = match req.headers().get(header::CONTENT_TYPE).and_then(|c| c.to_str().ok()) {
+            Some(c) if c.starts_with(GRPC_WEB) => c.to_string(),
+            _ => return Box::pin(inner.call(req)),
+        };
+        let text = content_type.starts_with(GRPC_WEB_TEXT);
+
+        Box::pin(async move {
+            let req = match grpc_request(req, text).await {
+                Ok(req) => req,
+                Err(status) => return Ok(status.to_http()),
+            };
+
+            let mut response = inner.call(req).await?;
+
+            let headers = response.headers_mut();
+            headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(match text {
+                    true => "application/grpc-web-text+proto",
+                    false => "application/grpc-web+proto",
+                }),
+            );
+            if let Some(allow_origin) = allow_origin {
+                headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+                headers.insert(header::ACCESS_CONTROL_EXPOSE_HEADERS, HeaderValue::from_static(EXPOSED_HEADERS));
+                headers.append(header::VARY, HeaderValue::from_static("origin"));
+            }
+
+            Ok(response.map(|body| {
+                GrpcWebBody {
+                    inner: body,
+                    text,
+                    trailers_sent: false,
+                }
+                .boxed_unsync()
+            }))
+        })
+    }
+}
+
+/// Answers a CORS preflight, refusing origins that are not configured.
+fn preflight(req: &Request<Body>, allow_origin: Option<HeaderValue>) -> Response<BoxBody> {
+    let mut response = Response::new(empty_body());
+
+    let allow_origin = match allow_origin {
+        Some(allow_origin) => allow_origin,
+        None => {
+            *response.status_mut() = StatusCode::FORBIDDEN;
+            return response;
+        }
+    };
+
+    *response.status_mut() = StatusCode::NO_CONTENT;
+    let allow_headers = req
+        .headers()
+        .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+        .cloned()
+        .unwrap_or_else(|| HeaderValue::from_static(ALLOWED_HEADERS));
+
+    let headers = response.headers_mut();
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+    headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, HeaderValue::from_static("POST, OPTIONS"));
+    headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, allow_headers);
+    headers.insert(header::ACCESS_CONTROL_MAX_AGE, HeaderValue::from_static("86400"));
+    headers.append(header::VARY, HeaderValue::from_static("origin"));
+    response
+}
+
+/// Turns a gRPC-Web request into the gRPC request the services expect.
+async fn grpc_request(req: Request<Body>, text: bool) -> Result<Request<Body>, Status> {
+    let (mut parts, body) = req.into_parts();
+
+    parts
+        .headers
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+    parts.headers.remove(header::CONTENT_LENGTH);
+
+    let body = match text {
+        true => {
+            let encoded = hyper::body::to_bytes(body)
+                .await
+                .map_err(|err| Status::internal(err.to_string()))?;
+            let encoded: Vec<u8> = encoded.into_iter().filter(|b| !b.is_ascii_whitespace()).collect();
+            Body::from(decode_base64(&encoded).map_err(Status::invalid_argument)?)
+        }
+        false => body,
+    };
+
+    Ok(Request::from_parts(parts, body))
+}
+
+/// Clients may send several base64 chunks back to back, each with its own padding. Padded chunks
+/// are always a multiple of four characters, so decoding four at a time handles both.
+fn decode_base64(encoded: &[u8]) -> Result<Vec<u8>, String> {
+    if !encoded.len().is_multiple_of(4) {
+        return Err("grpc-web-text body is not valid base64".to_string());
+    }
+
+    let mut decoded = Vec::with_capacity(encoded.len() / 4 * 3);
+    for group in encoded.chunks(4) {
+        decoded.extend(base64::decode(group).map_err(|err| err.to_string())?);
+    }
+    Ok(decoded)
+}
+
+/// The response body with its trailers appended as a final gRPC-Web frame (flag `0x80`).
+struct GrpcWebBody {
+    inner: BoxBody,
+    text: bool,
+    trailers_sent: bool,
+}
+
+impl GrpcWebBody {
+    fn encode(&self, data: Bytes) -> Bytes {
+        match self.text {
+            true => Bytes::from(base64::encode(data)),
+            false => data,
+        }
+    }
+}
+
+impl HttpBody for GrpcWebBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, Status>>> {
+        if self.trailers_sent {
+            return Poll::Ready(None);
+        }
+
+        match Pin::new(&mut self.inner).poll_data(cx) {
+            Poll::Ready(Some(Ok(data))) => return Poll::Ready(Some(Ok(self.encode(data)))),
+            Poll::Ready(Some(Err(status))) => return Poll::Ready(Some(Err(status))),
+            Poll::Ready(None) => {}
+            Poll::Pending => return Poll::Pending,
+        }
+
+        let trailers = match Pin::new(&mut self.inner).poll_trailers(cx) {
+            Poll::Ready(Ok(trailers)) => trailers.unwrap_or_default(),
+            Poll::Ready(Err(status)) => return Poll::Ready(Some(Err(status))),
+            Poll::Pending => return Poll::Pending,
+        };
+        self.trailers_sent = true;
+
+        // A trailers-only response already carried its status in the headers.
+        if trailers.is_empty() {
+            return Poll::Ready(None);
+        }
+
+        let frame = trailer_frame(&trailers);
+        Poll::Ready(Some(Ok(self.encode(frame))))
+    }
+
+    fn poll_trailers(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<Option<HeaderMap>, Status>> {
+        Poll::Ready(Ok(None))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.trailers_sent
+    }
+}
+
+fn trailer_frame(trailers: &HeaderMap) -> Bytes {
+    let mut block = Vec::new();
+    for (name, value) in trailers {
+        block.extend_from_slice(name.as_str().as_bytes());
+        block.extend_from_slice(b":");
+        block.extend_from_slice(value.as_bytes());
+        block.extend_from_slice(b"\r\n");
+    }
+
+    let mut frame = Vec::with_capacity(block.len() + 5);
+    frame.push(0x80);
+    frame.extend_from_slice(&(block.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&block);
+    Bytes::from(frame)
+}
@@ -1,7 +1,10 @@
 pub mod agents;
+pub mod health;
 pub mod login;
+pub mod mesh;
 pub mod permission_membership;
 pub mod permissions;
+pub mod reflection;
 pub mod routers;
 pub mod tunnels;
 pub mod users;
@@ -3,8 +3,12 @@ use diesel::r2d2::{ConnectionManager, Pool};
 use tonic::{Request, Response, Status};
 use tracing::{error, info, instrument};
 
-use crate::api::{AgentData, AgentRequest, AgentsData};
+use crate::api::{
+    AgentData, AgentDriversRequest, AgentListRequest, AgentRequest, AgentsData, AgentTreesResponse,
+};
+use crate::api::agent_request::IdUuidOrOwner;
 use crate::api::agent_server::Agent;
+use crate::storage::access::Access;
 use crate::storage::agents;
 
 #[derive(Debug)]
@@ -16,16 +20,27 @@ impl AgentService {
     pub fn new(pool: Pool<ConnectionManager<PgConnection>>) -> Self {
         Self { pool }
     }
+
+    /// Fails unless the caller owns every agent `id_uuid_or_owner` matches.
+    async fn check_agents(&self, access: &Access, id_uuid_or_owner: &IdUuidOrOwner) -> Result<(), Status> {
+        match id_uuid_or_owner {
+            IdUuidOrOwner::Id(agent_id) => access.check_agent(&self.pool, *agent_id).await,
+            IdUuidOrOwner::Uuid(agent_uuid) => access.check_agent_uuid(&self.pool, agent_uuid).await,
+            IdUuidOrOwner::Owner(owner) => access.check_owner(*owner),
+        }
+    }
 }
 
 #[tonic::async_trait]
 impl Agent for AgentService {
     #[instrument]
-    async fn list(&self, request: Request<()>) -> Result<Response<AgentsData>, Status> {
+    async fn list(&self, request: Request<AgentListRequest>) -> Result<Response<AgentsData>, Status> {
         info!(message = "Got a list request", ?request);
 
-        match agents::Agent::all(&self.pool).await {
-            Ok(result) => Ok(Response::new(AgentsData { agents: result })),
+        let access = Access::of(&self.pool, &request).await?;
+
+        match agents::Agent::all(&self.pool, request.into_inner(), access.owner()).await {
+            Ok(result) => Ok(Response::new(result)),
             Err(status) => {
                 error!(
                     message = "Error getting list of agents",
@@ -40,11 +55,16 @@ impl Agent for AgentService {
     async fn get(&self, request: Request<AgentRequest>) -> Result<Response<AgentsData>, Status> {
         info!(message = "Got a get request", ?request);
 
+        let access = Access::of(&self.pool, &request).await?;
         let req = request.into_inner();
 
+        if let Some(id_uuid_or_owner) = &req.id_uuid_or_owner {
+            self.check_agents(&access, id_uuid_or_owner).await?;
+        }
+
         match req.id_uuid_or_owner {
             Some(id_uuid_or_owner) => match agents::Agent::get(&self.pool, &id_uuid_or_owner).await {
-                Ok(result) => Ok(Response::new(AgentsData { agents: result })),
+                Ok(result) => Ok(Response::new(AgentsData { agents: result, ..Default::default() })),
                 Err(status) => {
                     error!(
                         message = "Error getting agent",
@@ -61,6 +81,7 @@ impl Agent for AgentService {
     async fn register(&self, request: Request<AgentData>) -> Result<Response<AgentData>, Status> {
         info!(message = "Got an add request", ?request);
 
+        let access = Access::of(&self.pool, &request).await?;
         let req = request.into_inner();
 
         if req.uuid.is_empty() {
@@ -70,6 +91,7 @@ impl Agent for AgentService {
         if req.owner == 0 {
             return Err(Status::invalid_argument("owner is required"));
         }
+        access.check_owner(req.owner)?;
 
         match agents::Agent::add(&self.pool, req).await {
             Ok(result) => Ok(Response::new(result)),
@@ -84,8 +106,13 @@ impl Agent for AgentService {
     async fn unregister(&self, request: Request<AgentRequest>) -> Result<Response<()>, Status> {
         info!(message = "Got a delete request", ?request);
 
+        let access = Access::of(&self.pool, &request).await?;
         let req = request.into_inner();
 
+        if let Some(id_uuid_or_owner) = &req.id_uuid_or_owner {
+            self.check_agents(&access, id_uuid_or_owner).await?;
+        }
+
         match req.id_uuid_or_owner {
             Some(id_uuid_or_owner) => match agents::Agent::delete(&self.pool, id_uuid_or_owner).await {
                 Ok(_) => Ok(Response::new(())),
@@ -105,10 +132,15 @@ impl Agent for AgentService {
     async fn update(&self, request: Request<AgentData>) -> Result<Response<AgentData>, Status> {
         info!(message = "Got an update request", ?request);
 
+        let access = Access::of(&self.pool, &request).await?;
         let req = request.into_inner();
         if req.id.is_none() {
             return Err(Status::invalid_argument("Agent id required"));
         }
+        access.check_agent(&self.pool, req.id.unwrap()).await?;
+        if req.owner != 0 {
+            access.check_owner(req.owner)?;
+        }
 
         match agents::Agent::update(&self.pool, req).await {
             Ok(result) => Ok(Response::new(result)),
@@ -121,4 +153,54 @@ impl Agent for AgentService {
             }
         }
     }
+
+    #[instrument]
+    async fn get_tree(&self, request: Request<AgentRequest>) -> Result<Response<AgentTreesResponse>, Status> {
+        info!(message = "Got a get tree request", ?request);
+
+        let access = Access::of(&self.pool, &request).await?;
+        let req = request.into_inner();
+
+        if let Some(id_uuid_or_owner) = &req.id_uuid_or_owner {
+            self.check_agents(&access, id_uuid_or_owner).await?;
+        }
+
+        match req.id_uuid_or_owner {
+            Some(id_uuid_or_owner) => match agents::Agent::tree(&self.pool, &id_uuid_or_owner).await {
+                Ok(result) => Ok(Response::new(AgentTreesResponse { agents: result })),
+                Err(status) => {
+                    error!(
+                        message = "Error getting agent tree",
+                        status = status.message()
+                    );
+                    return Err(status);
+                }
+            },
+            None => Err(Status::invalid_argument("Agent id, uuid or owner required")),
+        }
+    }
+
+    #[instrument]
+    async fn set_drivers(&self, request: Request<AgentDriversRequest>) -> Result<Response<()>, Status> {
+        info!(message = "Got a set drivers request", ?request);
+
+        let access = Access::of(&self.pool, &request).await?;
+        let req = request.into_inner();
+
+        if req.uuid.is_empty() {
+            return Err(Status::invalid_argument("uuid is required"));
+        }
+        access.check_agent_uuid(&self.pool, &req.uuid).await?;
+
+        match agents::Agent::set_drivers(&self.pool, &req).await {
+            Ok(()) => Ok(Response::new(())),
+            Err(status) => {
+                error!(
+                    message = "Error setting agent drivers",
+                    status = status.message()
+                );
+                return Err(status);
+            }
+        }
+    }
 }
@@ -0,0 +1,145 @@
+use std::time::Duration;
+
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::sql_query;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+use tracing::{info, instrument, warn};
+
+use crate::grpc::health::v1::health_check_response::ServingStatus;
+use crate::grpc::health::v1::health_server::Health;
+use crate::grpc::health::v1::{HealthCheckRequest, HealthCheckResponse};
+use crate::shutdown::{self, Shutdown};
+use crate::storage::changes::ChangeListener;
+
+/// How long a check waits for a database connection before reporting NOT_SERVING.
+const DB_TIMEOUT: Duration = Duration::from_secs(1);
+/// How often watchers re-check.
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The services whose health only depends on the database.
+const DATABASE_SERVICES: &[&str] = &[
+    "api.Auth",
+    "api.Agent",
+    "api.Router",
+    "api.Tunnel",
+    "api.User",
+    "api.Permission",
+    "api.PermissionMembership",
+];
+
+/// Implements `grpc.health.v1.Health`. The server as a whole (the empty service name) and every
+/// API service are serving while the database answers; `api.Mesh` also needs the change
+/// listener, which Watch streams rely on to be timely. Everything is NOT_SERVING once the server
+/// is shutting down, so load balancers stop sending it work.
+#[derive(Debug, Clone)]
+pub struct HealthService {
+    pool: Pool<ConnectionManager<PgConnection>>,
+    listener: ChangeListener,
+    shutdown: Shutdown,
+}
+
+impl HealthService {
+    pub fn new(pool: Pool<ConnectionManager<PgConnection>>, listener: ChangeListener, shutdown: Shutdown) -> Self {
+        Self { pool, listener, shutdown }
+    }
+
+    /// The status of `service`, `None` if the server does not know it.
+    pub async fn status(&self, service: &str) -> Option<ServingStatus> {
+        let running = !self.shutdown.is_triggered();
+        let serving = match service {
+            "" => running && self.database_available().await,
+            "api.Mesh" => running && self.database_available().await && self.listener.is_connected(),
+            "grpc.health.v1.Health" | "grpc.reflection.v1alpha.ServerReflection" => running,
+            s if DATABASE_SERVICES.contains(&s) => running && self.database_available().await,
+            _ => return None,
+        };
+
+        match serving {
+            true => Some(ServingStatus::Serving),
+            false => Some(ServingStatus::NotServing),
+        }
+    }
+
+    /// Whether the database answers, asked on a blocking thread so waiting for it holds up no
+    /// other requests.
+    async fn database_available(&self) -> bool {
+        let pool = self.pool.clone();
+        let available = tokio::task::spawn_blocking(move || {
+            let conn = &mut match pool.get_timeout(DB_TIMEOUT) {
+                Ok(conn) => conn,
+                Err(err) => {
+                    warn!(message = "Database unavailable", %err);
+                    return false;
+                }
+            };
+
+            sql_query("SELECT 1").execute(conn).is_ok()
+        });
+
+        available.await.unwrap_or(false)
+    }
+}
+
+fn response(status: ServingStatus) -> HealthCheckResponse {
+    HealthCheckResponse { status: status as i32 }
+}
+
+#[tonic::async_trait]
+impl Health for HealthService {
+    type WatchStream = ReceiverStream<Result<HealthCheckResponse, Status>>;
+
+    #[instrument]
+    async fn check(&self, request: Request<HealthCheckRequest>) -> Result<Response<HealthCheckResponse>, Status> {
+        info!(message = "Got a check request", ?request);
+
+        let service = request.into_inner().service;
+        match self.status(&service).await {
+            Some(status) => Ok(Response::new(response(status))),
+            None => Err(Status::not_found(format!("unknown service {}", service))),
+        }
+    }
+
+    #[instrument]
+    async fn watch(&self, request: Request<HealthCheckRequest>) -> Result<Response<Self::WatchStream>, Status> {
+        info!(message = "Got a watch request", ?request);
+
+        let service = request.into_inner().service;
+        let health = self.clone();
+        let (tx, rx) = mpsc::channel(1);
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(WATCH_INTERVAL);
+            let mut last_status = None;
+
+            while !tx.is_closed() {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = health.shutdown.triggered() => {
+                        // Watchers see NOT_SERVING first, then the stream ends so the drain is
+                        // not held up by it.
+                        if health.status(&service).await.is_some() {
+                            let _ = tx.send(Ok(response(ServingStatus::NotServing))).await;
+                        }
+                        let _ = tx.send(Err(shutdown::reconnect())).await;
+                        break;
+                    }
+                }
+
+                let status = health.status(&service).await.unwrap_or(ServingStatus::ServiceUnknown);
+                if last_status == Some(status) {
+                    continue;
+                }
+                last_status = Some(status);
+
+                if tx.send(Ok(response(status))).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
@@ -0,0 +1,127 @@
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+use tracing::{error, info, instrument};
+
+use crate::api::{MeshApplyRequest, MeshExportResponse, MeshPlan, MeshStatus};
+use crate::api::mesh_server::Mesh;
+use crate::mesh::MeshDocument;
+use crate::shutdown::{self, Shutdown};
+use crate::storage::access::Access;
+use crate::storage::changes::ChangeListener;
+use crate::storage::mesh;
+
+#[derive(Debug)]
+pub struct MeshService {
+    pool: Pool<ConnectionManager<PgConnection>>,
+    listener: ChangeListener,
+    shutdown: Shutdown,
+}
+
+impl MeshService {
+    pub fn new(pool: Pool<ConnectionManager<PgConnection>>, listener: ChangeListener, shutdown: Shutdown) -> Self {
+        Self { pool, listener, shutdown }
+    }
+}
+
+#[tonic::async_trait]
+impl Mesh for MeshService {
+    type WatchStream = ReceiverStream<Result<MeshStatus, Status>>;
+
+    #[instrument]
+    async fn export(&self, request: Request<()>) -> Result<Response<MeshExportResponse>, Status> {
+        info!(message = "Got an export request", ?request);
+
+        Access::of(&self.pool, &request).await?.require_admin()?;
+
+        match mesh::Mesh::export(&self.pool).await {
+            Ok(result) => Ok(Response::new(MeshExportResponse { document: result.to_toml() })),
+            Err(status) => {
+                error!(
+                    message = "Error exporting mesh",
+                    status = status.message()
+                );
+                return Err(status);
+            }
+        }
+    }
+
+    #[instrument(skip(request))]
+    async fn apply(&self, request: Request<MeshApplyRequest>) -> Result<Response<MeshPlan>, Status> {
+        info!(message = "Got an apply request");
+
+        Access::of(&self.pool, &request).await?.require_admin()?;
+
+        let req = request.into_inner();
+
+        let document = match MeshDocument::from_toml(&req.document) {
+            Ok(document) => document,
+            Err(err) => return Err(Status::invalid_argument(format!("invalid mesh document: {}", err))),
+        };
+
+        match mesh::Mesh::apply(&self.pool, document, req.dry_run, &req.expected_plan_hash).await {
+            Ok(result) => Ok(Response::new(result)),
+            Err(status) => {
+                error!(
+                    message = "Error applying mesh",
+                    status = status.message()
+                );
+                return Err(status);
+            }
+        }
+    }
+
+    #[instrument]
+    async fn watch(&self, request: Request<()>) -> Result<Response<Self::WatchStream>, Status> {
+        info!(message = "Got a watch request", ?request);
+
+        Access::of(&self.pool, &request).await?.require_admin()?;
+
+        let pool = self.pool.clone();
+        let listener = self.listener.clone();
+        let shutdown = self.shutdown.clone();
+        let mut changes = listener.subscribe();
+        let (tx, rx) = mpsc::channel(1);
+
+        tokio::spawn(async move {
+            // Woken up by every change the listener reports, and on the interval for the changes
+            // it missed while reconnecting.
+            let mut interval = tokio::time::interval(listener.watch_interval());
+            let mut last_version = None;
+
+            while !tx.is_closed() {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = changes.changed() => {}
+                    _ = shutdown.triggered() => {
+                        // Ended rather than dropped, so agents know to reconnect.
+                        let _ = tx.send(Err(shutdown::reconnect())).await;
+                        break;
+                    }
+                }
+
+                let status = match mesh::Mesh::version(&pool).await {
+                    Ok(version) if last_version.as_ref() == Some(&version) => continue,
+                    Ok(version) => {
+                        last_version = Some(version);
+                        mesh::Mesh::status(&pool).await
+                    }
+                    Err(status) => Err(status),
+                };
+
+                if let Err(status) = &status {
+                    error!(message = "Error watching mesh", status = status.message());
+                }
+
+                let failed = status.is_err();
+                if tx.send(status).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
@@ -5,6 +5,7 @@ use tracing::{error, info, instrument};
 
 use crate::api::{PermissionMembershipData, PermissionMembershipRequest, PermissionMembershipsData};
 use crate::api::permission_membership_server::PermissionMembership;
+use crate::storage::access::Access;
 use crate::storage::permission_membership;
 
 #[derive(Debug)]
@@ -24,6 +25,8 @@ impl PermissionMembership for PermissionMembershipService {
     async fn list(&self, request: Request<()>) -> Result<Response<PermissionMembershipsData>, Status> {
         info!(message = "Got a list request", ?request);
 
+        Access::of(&self.pool, &request).await?.require_admin()?;
+
         match permission_membership::PermissionMembership::all(&self.pool).await {
             Ok(result) => Ok(Response::new(PermissionMembershipsData { memberships: result })),
             Err(status) => {
@@ -40,6 +43,8 @@ impl PermissionMembership for PermissionMembershipService {
     async fn get_permission_members(&self, request: Request<PermissionMembershipRequest>) -> Result<Response<PermissionMembershipsData>, Status> {
         info!(message = "Got a get request", ?request);
 
+        Access::of(&self.pool, &request).await?.require_admin()?;
+
         let req = request.into_inner();
 
         match req.id_permission_or_userid {
@@ -61,6 +66,8 @@ impl PermissionMembership for PermissionMembershipService {
     async fn get_user_permissions(&self, request: Request<PermissionMembershipRequest>) -> Result<Response<PermissionMembershipsData>, Status> {
         info!(message = "Got a get request", ?request);
 
+        Access::of(&self.pool, &request).await?.require_admin()?;
+
         let req = request.into_inner();
 
         match req.id_permission_or_userid {
@@ -82,6 +89,8 @@ impl PermissionMembership for PermissionMembershipService {
     async fn add(&self, request: Request<PermissionMembershipData>) -> Result<Response<PermissionMembershipData>, Status> {
         info!(message = "Got an add request", ?request);
 
+        Access::of(&self.pool, &request).await?.require_admin()?;
+
         let req = request.into_inner();
 
         if req.permission == 0 {
@@ -105,6 +114,8 @@ impl PermissionMembership for PermissionMembershipService {
     async fn delete(&self, request: Request<PermissionMembershipRequest>) -> Result<Response<PermissionMembershipData>, Status> {
         info!(message = "Got a delete request", ?request);
 
+        Access::of(&self.pool, &request).await?.require_admin()?;
+
         let req = request.into_inner();
 
         match req.id_permission_or_userid {
@@ -126,6 +137,8 @@ impl PermissionMembership for PermissionMembershipService {
     async fn update(&self, request: Request<PermissionMembershipData>) -> Result<Response<PermissionMembershipData>, Status> {
         info!(message = "Got an update request", ?request);
 
+        Access::of(&self.pool, &request).await?.require_admin()?;
+
         let req = request.into_inner();
         if req.id.is_none() {
             return Err(Status::invalid_argument("PermissionMembership id required"));
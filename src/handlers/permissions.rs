@@ -3,8 +3,9 @@ use diesel::r2d2::{ConnectionManager, Pool};
 use tonic::{Request, Response, Status};
 use tracing::{error, info, instrument};
 
-use crate::api::{PermissionData, PermissionRequest, PermissionsData};
+use crate::api::{PermissionData, PermissionListRequest, PermissionRequest, PermissionsData};
 use crate::api::permission_server::Permission;
+use crate::storage::access::Access;
 use crate::storage::permissions;
 
 #[derive(Debug)]
@@ -21,11 +22,13 @@ impl PermissionService {
 #[tonic::async_trait]
 impl Permission for PermissionService {
     #[instrument]
-    async fn list(&self, request: Request<()>) -> Result<Response<PermissionsData>, Status> {
+    async fn list(&self, request: Request<PermissionListRequest>) -> Result<Response<PermissionsData>, Status> {
         info!(message = "Got a list request", ?request);
 
-        match permissions::Permission::all(&self.pool).await {
-            Ok(result) => Ok(Response::new(PermissionsData { permissions: result })),
+        Access::of(&self.pool, &request).await?.require_admin()?;
+
+        match permissions::Permission::all(&self.pool, request.into_inner()).await {
+            Ok(result) => Ok(Response::new(result)),
             Err(status) => {
                 error!(
                     message = "Error getting list of permissions",
@@ -40,6 +43,8 @@ impl Permission for PermissionService {
     async fn get(&self, request: Request<PermissionRequest>) -> Result<Response<PermissionData>, Status> {
         info!(message = "Got a get request", ?request);
 
+        Access::of(&self.pool, &request).await?.require_admin()?;
+
         let req = request.into_inner();
 
         match req.id_or_name {
@@ -61,6 +66,8 @@ impl Permission for PermissionService {
     async fn add(&self, request: Request<PermissionData>) -> Result<Response<PermissionData>, Status> {
         info!(message = "Got an add request", ?request);
 
+        Access::of(&self.pool, &request).await?.require_admin()?;
+
         let req = request.into_inner();
 
         if req.name.is_empty() {
@@ -84,6 +91,8 @@ impl Permission for PermissionService {
     async fn delete(&self, request: Request<PermissionRequest>) -> Result<Response<PermissionData>, Status> {
         info!(message = "Got a delete request", ?request);
 
+        Access::of(&self.pool, &request).await?.require_admin()?;
+
         let req = request.into_inner();
 
         match req.id_or_name {
@@ -105,6 +114,8 @@ impl Permission for PermissionService {
     async fn update(&self, request: Request<PermissionData>) -> Result<Response<PermissionData>, Status> {
         info!(message = "Got an update request", ?request);
 
+        Access::of(&self.pool, &request).await?.require_admin()?;
+
         let req = request.into_inner();
 
         match permissions::Permission::update(&self.pool, req).await {
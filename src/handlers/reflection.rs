@@ -0,0 +1,183 @@
+use std::sync::Arc;
+
+use prost::Message;
+use prost_types::{DescriptorProto, FileDescriptorProto, FileDescriptorSet};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Code, Request, Response, Status, Streaming};
+use tracing::{error, info, instrument};
+
+use crate::grpc::reflection::v1alpha::server_reflection_request::MessageRequest;
+use crate::grpc::reflection::v1alpha::server_reflection_response::MessageResponse;
+use crate::grpc::reflection::v1alpha::server_reflection_server::ServerReflection;
+use crate::grpc::reflection::v1alpha::{
+    ErrorResponse, ExtensionNumberResponse, FileDescriptorResponse, ListServiceResponse, ServerReflectionRequest,
+    ServerReflectionResponse, ServiceResponse,
+};
+
+/// Implements `grpc.reflection.v1alpha.ServerReflection` from the descriptor set build.rs
+/// writes, so grpcurl and similar tools can discover the API without the .proto files.
+#[derive(Debug, Clone)]
+pub struct ReflectionService {
+    files: Arc<Vec<FileDescriptorProto>>,
+    services: Arc<Vec<String>>,
+}
+
+impl ReflectionService {
+    /// `services` are the fully qualified names of the services the server registered; only
+    /// those are listed, although every file in the set can be looked up.
+    pub fn new(descriptor_set: &[u8], services: &[&str]) -> Result<Self, prost::DecodeError> {
+        Ok(Self {
+            files: Arc::new(FileDescriptorSet::decode(descriptor_set)?.file),
+            services: Arc::new(services.iter().map(|s| s.to_string()).collect()),
+        })
+    }
+
+    /// Answers one reflection request.
+    pub fn respond(&self, request: ServerReflectionRequest) -> ServerReflectionResponse {
+        let message_response = match &request.message_request {
+            Some(MessageRequest::ListServices(_)) => MessageResponse::ListServicesResponse(ListServiceResponse {
+                service: self
+                    .services
+                    .iter()
+                    .map(|name| ServiceResponse { name: name.clone() })
+                    .collect(),
+            }),
+            Some(MessageRequest::FileByFilename(name)) => match self.files.iter().find(|f| f.name() == name) {
+                Some(file) => self.file_response(file),
+                None => error_response(Code::NotFound, format!("file {} not found", name)),
+            },
+            Some(MessageRequest::FileContainingSymbol(symbol)) => {
+                match self.files.iter().find(|f| defines(f, symbol)) {
+                    Some(file) => self.file_response(file),
+                    None => error_response(Code::NotFound, format!("symbol {} not found", symbol)),
+                }
+            }
+            Some(MessageRequest::AllExtensionNumbersOfType(name)) => {
+                match self.files.iter().any(|f| defines(f, name)) {
+                    // None of our protos declare extensions.
+                    true => MessageResponse::AllExtensionNumbersResponse(ExtensionNumberResponse {
+                        base_type_name: name.clone(),
+                        extension_number: vec![],
+                    }),
+                    false => error_response(Code::NotFound, format!("type {} not found", name)),
+                }
+            }
+            Some(MessageRequest::FileContainingExtension(extension)) => error_response(
+                Code::NotFound,
+                format!(
+                    "extension {} of {} not found",
+                    extension.extension_number, extension.containing_type
+                ),
+            ),
+            None => error_response(Code::InvalidArgument, "empty request".to_string()),
+        };
+
+        ServerReflectionResponse {
+            valid_host: request.host.clone(),
+            original_request: Some(request),
+            message_response: Some(message_response),
+        }
+    }
+
+    /// `file` followed by everything it imports, directly or not, as clients expect.
+    fn file_response(&self, file: &FileDescriptorProto) -> MessageResponse {
+        let mut files = vec![file];
+        let mut i = 0;
+        while i < files.len() {
+            for dependency in &files[i].dependency {
+                if files.iter().all(|f| f.name() != dependency) {
+                    if let Some(f) = self.files.iter().find(|f| f.name() == dependency) {
+                        files.push(f);
+                    }
+                }
+            }
+            i += 1;
+        }
+
+        MessageResponse::FileDescriptorResponse(FileDescriptorResponse {
+            file_descriptor_proto: files.iter().map(|f| f.encode_to_vec()).collect(),
+        })
+    }
+}
+
+fn error_response(code: Code, message: String) -> MessageResponse {
+    MessageResponse::ErrorResponse(ErrorResponse {
+        error_code: code as i32,
+        error_message: message,
+    })
+}
+
+/// Whether `file` declares the fully qualified `symbol`: a service, method, message or enum.
+fn defines(file: &FileDescriptorProto, symbol: &str) -> bool {
+    let name = match file.package() {
+        "" => symbol,
+        package => match symbol.strip_prefix(package).and_then(|s| s.strip_prefix('.')) {
+            Some(name) => name,
+            None => return false,
+        },
+    };
+
+    let in_service = file.service.iter().any(|service| {
+        name == service.name()
+            || service
+                .method
+                .iter()
+                .any(|method| name == format!("{}.{}", service.name(), method.name()))
+    });
+
+    in_service
+        || file.enum_type.iter().any(|e| name == e.name())
+        || file.message_type.iter().any(|message| defines_message(message, name))
+}
+
+fn defines_message(message: &DescriptorProto, name: &str) -> bool {
+    if name == message.name() {
+        return true;
+    }
+
+    match name.strip_prefix(message.name()).and_then(|s| s.strip_prefix('.')) {
+        Some(nested) => {
+            message.enum_type.iter().any(|e| nested == e.name())
+                || message.nested_type.iter().any(|m| defines_message(m, nested))
+        }
+        None => false,
+    }
+}
+
+#[tonic::async_trait]
+impl ServerReflection for ReflectionService {
+    type ServerReflectionInfoStream = ReceiverStream<Result<ServerReflectionResponse, Status>>;
+
+    #[instrument(skip(self, request))]
+    async fn server_reflection_info(
+        &self,
+        request: Request<Streaming<ServerReflectionRequest>>,
+    ) -> Result<Response<Self::ServerReflectionInfoStream>, Status> {
+        info!(message = "Got a reflection request");
+
+        let mut requests = request.into_inner();
+        let reflection = self.clone();
+        let (tx, rx) = mpsc::channel(1);
+
+        tokio::spawn(async move {
+            loop {
+                let response = match requests.message().await {
+                    Ok(Some(request)) => Ok(reflection.respond(request)),
+                    Ok(None) => break,
+                    Err(status) => {
+                        error!(message = "Error reading reflection request", status = status.message());
+                        Err(status)
+                    }
+                };
+
+                let failed = response.is_err();
+                if tx.send(response).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
@@ -3,29 +3,49 @@ use diesel::r2d2::{ConnectionManager, Pool};
 use tonic::{Request, Response, Status};
 use tracing::{error, info, instrument};
 
-use crate::api::{RouterAddRequest, RouterRequest, RouterResponse, RoutersResponse, RouterUpdateRequest};
+use crate::api::{DriftReport, DriftResponse, PushHistoryRequest, PushHistoryResponse, PushRecord, PushResult, RouterAddRequest, RouterConfig, RouterListRequest, RouterRequest, RouterResponse, RoutersResponse, RouterTypesResponse, RouterUpdateRequest, RunningConfig, SyncStatusResponse};
+use crate::api::router_request::IdOrAgent;
 use crate::api::router_server::Router;
+use crate::config::{DriftConfig, RenderConfig};
+use crate::metrics::Metrics;
+use crate::storage::access::Access;
+use crate::storage::drift::Readback;
+use crate::storage::drivers::RouterType;
+use crate::storage::pushes::Push;
 use crate::storage::routers;
 
 #[derive(Debug)]
 pub struct RouterService {
     pool: Pool<ConnectionManager<PgConnection>>,
+    render: RenderConfig,
+    drift: DriftConfig,
+    metrics: Metrics,
 }
 
 impl RouterService {
-    pub fn new(pool: Pool<ConnectionManager<PgConnection>>) -> Self {
-        Self { pool }
+    pub fn new(pool: Pool<ConnectionManager<PgConnection>>, render: RenderConfig, drift: DriftConfig, metrics: Metrics) -> Self {
+        Self { pool, render, drift, metrics }
+    }
+
+    /// Fails unless the caller owns every router `id_or_agent` matches.
+    async fn check_routers(&self, access: &Access, id_or_agent: &IdOrAgent) -> Result<(), Status> {
+        match id_or_agent {
+            IdOrAgent::Id(router_id) => access.check_router(&self.pool, *router_id).await,
+            IdOrAgent::Agent(agent_id) => access.check_agent(&self.pool, *agent_id).await,
+        }
     }
 }
 
 #[tonic::async_trait]
 impl Router for RouterService {
     #[instrument]
-    async fn list(&self, request: Request<()>) -> Result<Response<RoutersResponse>, Status> {
+    async fn list(&self, request: Request<RouterListRequest>) -> Result<Response<RoutersResponse>, Status> {
         info!(message = "Got a list request", ?request);
 
-        match routers::Router::all(&self.pool).await {
-            Ok(result) => Ok(Response::new(RoutersResponse { routers: result })),
+        let access = Access::of(&self.pool, &request).await?;
+
+        match routers::Router::all(&self.pool, request.into_inner(), access.owner()).await {
+            Ok(result) => Ok(Response::new(result)),
             Err(status) => {
                 error!(
                     message = "Error getting list of routers",
@@ -37,14 +57,19 @@ impl Router for RouterService {
     }
 
     #[instrument]
-    async fn get(&self, request: Request<RouterRequest>) -> Result<Response<RouterResponse>, Status> {
+    async fn get(&self, request: Request<RouterRequest>) -> Result<Response<RoutersResponse>, Status> {
         info!(message = "Got a get request", ?request);
 
+        let access = Access::of(&self.pool, &request).await?;
         let req = request.into_inner();
 
+        if let Some(id_or_agent) = &req.id_or_agent {
+            self.check_routers(&access, id_or_agent).await?;
+        }
+
         match req.id_or_agent {
             Some(id_or_agent) => match routers::Router::get(&self.pool, &id_or_agent).await {
-                Ok(result) => Ok(Response::new(result)),
+                Ok(result) => Ok(Response::new(RoutersResponse { routers: result, ..Default::default() })),
                 Err(status) => {
                     error!(
                         message = "Error getting router",
@@ -61,13 +86,15 @@ impl Router for RouterService {
     async fn add(&self, request: Request<RouterAddRequest>) -> Result<Response<RouterResponse>, Status> {
         info!(message = "Got an add request", ?request);
 
+        let access = Access::of(&self.pool, &request).await?;
         let req = request.into_inner();
 
         if req.agent == 0 {
             return Err(Status::invalid_argument("agent is required"));
         }
+        access.check_agent(&self.pool, req.agent).await?;
 
-        match routers::Router::add(&self.pool, req).await {
+        match routers::Router::add(&self.pool, req, &self.render).await {
             Ok(result) => Ok(Response::new(result)),
             Err(status) => {
                 error!(message = "Error adding router", status = status.message());
@@ -80,11 +107,19 @@ impl Router for RouterService {
     async fn delete(&self, request: Request<RouterRequest>) -> Result<Response<RouterResponse>, Status> {
         info!(message = "Got a delete request", ?request);
 
+        let access = Access::of(&self.pool, &request).await?;
         let req = request.into_inner();
 
+        if let Some(id_or_agent) = &req.id_or_agent {
+            self.check_routers(&access, id_or_agent).await?;
+        }
+
         match req.id_or_agent {
-            Some(id_or_agent) => match routers::Router::delete(&self.pool, id_or_agent).await {
-                Ok(_) => Ok(Response::new(RouterResponse::default())),
+            Some(id_or_agent) => match routers::Router::delete(&self.pool, id_or_agent, req.dry_run, &self.render).await {
+                Ok((_, config_diffs)) => Ok(Response::new(RouterResponse {
+                    config_diffs,
+                    ..Default::default()
+                })),
                 Err(status) => {
                     error!(
                         message = "Error deleting router",
@@ -101,12 +136,17 @@ impl Router for RouterService {
     async fn update(&self, request: Request<RouterUpdateRequest>) -> Result<Response<RouterResponse>, Status> {
         info!(message = "Got an update request", ?request);
 
+        let access = Access::of(&self.pool, &request).await?;
         let req = request.into_inner();
         if req.id == 0 {
             return Err(Status::invalid_argument("Router id required"));
         }
+        access.check_router(&self.pool, req.id).await?;
+        if let Some(agent_id) = req.agent {
+            access.check_agent(&self.pool, agent_id).await?;
+        }
 
-        match routers::Router::update(&self.pool, req).await {
+        match routers::Router::update(&self.pool, req, &self.render).await {
             Ok(result) => Ok(Response::new(result)),
             Err(status) => {
                 error!(
@@ -117,4 +157,204 @@ impl Router for RouterService {
             }
         }
     }
+
+    #[instrument]
+    async fn repush(&self, request: Request<RouterRequest>) -> Result<Response<RouterResponse>, Status> {
+        info!(message = "Got a repush request", ?request);
+
+        let access = Access::of(&self.pool, &request).await?;
+        let req = request.into_inner();
+
+        if let Some(id_or_agent) = &req.id_or_agent {
+            self.check_routers(&access, id_or_agent).await?;
+        }
+
+        match req.id_or_agent {
+            Some(IdOrAgent::Id(router_id)) => match routers::Router::repush(&self.pool, router_id).await {
+                Ok(result) => Ok(Response::new(result)),
+                Err(status) => {
+                    error!(
+                        message = "Error repushing router",
+                        status = status.message()
+                    );
+                    return Err(status);
+                }
+            },
+            _ => Err(Status::invalid_argument("Router id required")),
+        }
+    }
+
+    #[instrument]
+    async fn render(&self, request: Request<RouterRequest>) -> Result<Response<RouterConfig>, Status> {
+        info!(message = "Got a render request", ?request);
+
+        let access = Access::of(&self.pool, &request).await?;
+        let req = request.into_inner();
+
+        if let Some(id_or_agent) = &req.id_or_agent {
+            self.check_routers(&access, id_or_agent).await?;
+        }
+
+        match req.id_or_agent {
+            Some(IdOrAgent::Id(router_id)) => match routers::Router::render(&self.pool, router_id, &self.render).await {
+                Ok(result) => {
+                    self.metrics.observe_render(&result.router_type, true);
+                    Ok(Response::new(result))
+                }
+                Err(status) => {
+                    // The router type is not known when the router could not be loaded.
+                    self.metrics.observe_render("unknown", false);
+                    error!(
+                        message = "Error rendering router configuration",
+                        status = status.message()
+                    );
+                    return Err(status);
+                }
+            },
+            _ => Err(Status::invalid_argument("Router id required")),
+        }
+    }
+
+    #[instrument]
+    async fn report_push(&self, request: Request<PushResult>) -> Result<Response<PushRecord>, Status> {
+        info!(message = "Got a report push request", ?request);
+
+        let access = Access::of(&self.pool, &request).await?;
+        let req = request.into_inner();
+
+        if req.router <= 0 {
+            return Err(Status::invalid_argument("router is required"));
+        }
+        access.check_router(&self.pool, req.router).await?;
+        if req.config_hash.is_empty() {
+            return Err(Status::invalid_argument("config_hash is required"));
+        }
+        if req.driver.is_empty() {
+            return Err(Status::invalid_argument("driver is required"));
+        }
+
+        match Push::report(&self.pool, &req, &self.render).await {
+            Ok(result) => Ok(Response::new(result)),
+            Err(status) => {
+                error!(
+                    message = "Error recording push result",
+                    status = status.message()
+                );
+                return Err(status);
+            }
+        }
+    }
+
+    #[instrument]
+    async fn push_history(&self, request: Request<PushHistoryRequest>) -> Result<Response<PushHistoryResponse>, Status> {
+        info!(message = "Got a push history request", ?request);
+
+        let access = Access::of(&self.pool, &request).await?;
+        let req = request.into_inner();
+
+        if req.router <= 0 {
+            return Err(Status::invalid_argument("router is required"));
+        }
+        access.check_router(&self.pool, req.router).await?;
+
+        match Push::history(&self.pool, req).await {
+            Ok(result) => Ok(Response::new(result)),
+            Err(status) => {
+                error!(
+                    message = "Error getting push history",
+                    status = status.message()
+                );
+                return Err(status);
+            }
+        }
+    }
+
+    #[instrument]
+    async fn sync_status(&self, request: Request<RouterRequest>) -> Result<Response<SyncStatusResponse>, Status> {
+        info!(message = "Got a sync status request", ?request);
+
+        let access = Access::of(&self.pool, &request).await?;
+        let req = request.into_inner();
+
+        match &req.id_or_agent {
+            Some(id_or_agent) => self.check_routers(&access, id_or_agent).await?,
+            None => access.require_admin()?,
+        }
+
+        match Push::sync_status(&self.pool, req.id_or_agent.as_ref(), &self.render).await {
+            Ok(result) => Ok(Response::new(result)),
+            Err(status) => {
+                error!(
+                    message = "Error getting sync status",
+                    status = status.message()
+                );
+                return Err(status);
+            }
+        }
+    }
+
+    // Running configs hold passwords and keys, so neither the span nor the log gets the request.
+    #[instrument(skip(request))]
+    async fn report_readback(&self, request: Request<RunningConfig>) -> Result<Response<DriftReport>, Status> {
+        info!(message = "Got a report readback request", router = request.get_ref().router);
+
+        let access = Access::of(&self.pool, &request).await?;
+        let req = request.into_inner();
+
+        if req.router <= 0 {
+            return Err(Status::invalid_argument("router is required"));
+        }
+        access.check_router(&self.pool, req.router).await?;
+
+        match Readback::report(&self.pool, &req, &self.render, &self.drift).await {
+            Ok(result) => Ok(Response::new(result)),
+            Err(status) => {
+                error!(
+                    message = "Error checking read back config",
+                    status = status.message()
+                );
+                return Err(status);
+            }
+        }
+    }
+
+    #[instrument]
+    async fn get_drift(&self, request: Request<RouterRequest>) -> Result<Response<DriftResponse>, Status> {
+        info!(message = "Got a get drift request", ?request);
+
+        let access = Access::of(&self.pool, &request).await?;
+        let req = request.into_inner();
+
+        match &req.id_or_agent {
+            Some(id_or_agent) => self.check_routers(&access, id_or_agent).await?,
+            None => access.require_admin()?,
+        }
+
+        match Readback::get(&self.pool, req.id_or_agent.as_ref(), &self.render, &self.drift).await {
+            Ok(result) => Ok(Response::new(result)),
+            Err(status) => {
+                error!(
+                    message = "Error getting drift",
+                    status = status.message()
+                );
+                return Err(status);
+            }
+        }
+    }
+
+    #[instrument]
+    async fn list_types(&self, request: Request<()>) -> Result<Response<RouterTypesResponse>, Status> {
+        info!(message = "Got a list router types request", ?request);
+
+        match RouterType::all(&self.pool).await {
+            Ok(result) => Ok(Response::new(result)),
+            Err(status) => {
+                error!(
+                    message = "Error listing router types",
+                    status = status.message()
+                );
+                return Err(status);
+            }
+        }
+    }
 }
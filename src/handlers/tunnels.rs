@@ -1,31 +1,54 @@
+use std::net::IpAddr;
+
 use diesel::prelude::*;
 use diesel::r2d2::{ConnectionManager, Pool};
 use tonic::{Request, Response, Status};
 use tracing::{error, info, instrument};
 
-use crate::api::{TunnelAddRequest, TunnelRequest, TunnelResponse, TunnelsResponse, TunnelUpdateRequest};
+use crate::api::{AddressReport, AddressReportResponse, ResolutionHistoryRequest, ResolutionHistoryResponse, RotateKeysResponse, TunnelAddRequest, TunnelListRequest, TunnelRequest, TunnelResponse, TunnelsResponse, TunnelUpdateRequest};
+use crate::api::tunnel_request::IdOrRouter;
 use crate::api::tunnel_server::Tunnel;
+use crate::config::RenderConfig;
+use crate::render;
+use crate::storage::access::Access;
+use crate::storage::psks::IpsecKey;
+use crate::storage::resolutions::AddressResolution;
 use crate::storage::tunnels;
 
+/// Set by the HTTP gateway to the address of its client, since tonic only knows the peer of
+/// connections it accepted itself.
+pub const FORWARDED_FOR: &str = "x-forwarded-for";
+
 #[derive(Debug)]
 pub struct TunnelService {
     pool: Pool<ConnectionManager<PgConnection>>,
+    render: RenderConfig,
 }
 
 impl TunnelService {
-    pub fn new(pool: Pool<ConnectionManager<PgConnection>>) -> Self {
-        Self { pool }
+    pub fn new(pool: Pool<ConnectionManager<PgConnection>>, render: RenderConfig) -> Self {
+        Self { pool, render }
+    }
+
+    /// Fails unless the caller owns every tunnel `id_or_router` matches.
+    async fn check_tunnels(&self, access: &Access, id_or_router: &IdOrRouter) -> Result<(), Status> {
+        match id_or_router {
+            IdOrRouter::Id(tunnel_id) => access.check_tunnel(&self.pool, *tunnel_id).await,
+            IdOrRouter::Router(router_id) => access.check_router(&self.pool, *router_id).await,
+        }
     }
 }
 
 #[tonic::async_trait]
 impl Tunnel for TunnelService {
     #[instrument]
-    async fn list(&self, request: Request<()>) -> Result<Response<TunnelsResponse>, Status> {
+    async fn list(&self, request: Request<TunnelListRequest>) -> Result<Response<TunnelsResponse>, Status> {
         info!(message = "Got a list request", ?request);
 
-        match tunnels::Tunnel::all(&self.pool).await {
-            Ok(result) => Ok(Response::new(TunnelsResponse { tunnels: result })),
+        let access = Access::of(&self.pool, &request).await?;
+
+        match tunnels::Tunnel::all(&self.pool, request.into_inner(), access.owner()).await {
+            Ok(result) => Ok(Response::new(result)),
             Err(status) => {
                 error!(
                     message = "Error getting list of tunnels",
@@ -37,14 +60,19 @@ impl Tunnel for TunnelService {
     }
 
     #[instrument]
-    async fn get(&self, request: Request<TunnelRequest>) -> Result<Response<TunnelResponse>, Status> {
+    async fn get(&self, request: Request<TunnelRequest>) -> Result<Response<TunnelsResponse>, Status> {
         info!(message = "Got a get request", ?request);
 
+        let access = Access::of(&self.pool, &request).await?;
         let req = request.into_inner();
 
+        if let Some(id_or_router) = &req.id_or_router {
+            self.check_tunnels(&access, id_or_router).await?;
+        }
+
         match req.id_or_router {
             Some(id_or_router) => match tunnels::Tunnel::get(&self.pool, &id_or_router).await {
-                Ok(result) => Ok(Response::new(result)),
+                Ok(result) => Ok(Response::new(TunnelsResponse { tunnels: result, ..Default::default() })),
                 Err(status) => {
                     error!(
                         message = "Error getting tunnel",
@@ -53,7 +81,7 @@ impl Tunnel for TunnelService {
                     return Err(status);
                 }
             },
-            None => Err(Status::invalid_argument("Tunnel id, uuid or owner required")),
+            None => Err(Status::invalid_argument("Tunnel id or router required")),
         }
     }
 
@@ -61,11 +89,13 @@ impl Tunnel for TunnelService {
     async fn add(&self, request: Request<TunnelAddRequest>) -> Result<Response<TunnelResponse>, Status> {
         info!(message = "Got an add request", ?request);
 
+        let access = Access::of(&self.pool, &request).await?;
         let req = request.into_inner();
 
         if req.router == 0 {
             return Err(Status::invalid_argument("router is required"));
         }
+        access.check_router(&self.pool, req.router).await?;
 
         if req.ip.is_empty() {
             return Err(Status::invalid_argument("ip is required"));
@@ -83,7 +113,10 @@ impl Tunnel for TunnelService {
             return Err(Status::invalid_argument("source is required"));
         }
 
-        match tunnels::Tunnel::add(&self.pool, req).await {
+        render::check_endpoint(&req.ip, false, &req.hostname, &req.description, &req.source)
+            .map_err(Status::invalid_argument)?;
+
+        match tunnels::Tunnel::add(&self.pool, req, &self.render).await {
             Ok(result) => Ok(Response::new(result)),
             Err(status) => {
                 error!(message = "Error adding tunnel", status = status.message());
@@ -96,11 +129,19 @@ impl Tunnel for TunnelService {
     async fn delete(&self, request: Request<TunnelRequest>) -> Result<Response<TunnelResponse>, Status> {
         info!(message = "Got a delete request", ?request);
 
-        // let req = request.into_inner();
+        let access = Access::of(&self.pool, &request).await?;
+        let req = request.into_inner();
+
+        if let Some(id_or_router) = &req.id_or_router {
+            self.check_tunnels(&access, id_or_router).await?;
+        }
 
-        match request.into_inner().id_or_router {
-            Some(id_or_router) => match tunnels::Tunnel::delete(&self.pool, id_or_router).await {
-                Ok(_) => Ok(Response::new(TunnelResponse::default())),
+        match req.id_or_router {
+            Some(id_or_router) => match tunnels::Tunnel::delete(&self.pool, id_or_router, req.dry_run, &self.render).await {
+                Ok((_, config_diffs)) => Ok(Response::new(TunnelResponse {
+                    config_diffs,
+                    ..Default::default()
+                })),
                 Err(status) => {
                     error!(
                         message = "Error deleting tunnel",
@@ -109,7 +150,7 @@ impl Tunnel for TunnelService {
                     return Err(status);
                 }
             },
-            None => Err(Status::invalid_argument("Tunnel id or email required")),
+            None => Err(Status::invalid_argument("Tunnel id or router required")),
         }
     }
 
@@ -117,7 +158,25 @@ impl Tunnel for TunnelService {
     async fn update(&self, request: Request<TunnelUpdateRequest>) -> Result<Response<TunnelResponse>, Status> {
         info!(message = "Got an update request", ?request);
 
-        match tunnels::Tunnel::update(&self.pool, request.into_inner()).await {
+        let access = Access::of(&self.pool, &request).await?;
+        let req = request.into_inner();
+
+        access.check_tunnel(&self.pool, req.id).await?;
+        if let Some(router_id) = req.router {
+            access.check_router(&self.pool, router_id).await?;
+        }
+
+        let checks = [
+            req.ip.as_deref().map(render::check_ip),
+            req.hostname.as_deref().map(render::check_hostname),
+            req.description.as_deref().map(|description| render::check_text("description", description)),
+            req.source.as_deref().map(|source| render::check_text("source", source)),
+        ];
+        if let Some(Err(err)) = checks.into_iter().flatten().find(Result::is_err) {
+            return Err(Status::invalid_argument(err));
+        }
+
+        match tunnels::Tunnel::update(&self.pool, req, &self.render).await {
             Ok(result) => Ok(Response::new(result)),
             Err(status) => {
                 error!(
@@ -128,4 +187,95 @@ impl Tunnel for TunnelService {
             }
         }
     }
+
+    #[instrument]
+    async fn report_address(&self, request: Request<AddressReport>) -> Result<Response<AddressReportResponse>, Status> {
+        info!(message = "Got a report address request", ?request);
+
+        let access = Access::of(&self.pool, &request).await?;
+        access.check_tunnel(&self.pool, request.get_ref().tunnel).await?;
+
+        // The header is only trusted without a peer address, i.e. from the gateway.
+        let seen = request.remote_addr().map(|addr| addr.ip()).or_else(|| {
+            request
+                .metadata()
+                .get(FORWARDED_FOR)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok())
+        });
+        let req = request.into_inner();
+
+        let address = match req.ip.as_str() {
+            "" => seen
+                .map(|addr: IpAddr| addr.to_canonical())
+                .ok_or_else(|| Status::invalid_argument("ip is required, the request's address is unknown"))?,
+            ip => ip
+                .parse()
+                .map_err(|_| Status::invalid_argument(format!("{} is not an IP address", ip)))?,
+        };
+
+        match tunnels::Tunnel::report_address(&self.pool, req.tunnel, address).await {
+            Ok(result) => Ok(Response::new(result)),
+            Err(status) => {
+                error!(
+                    message = "Error recording tunnel address",
+                    status = status.message()
+                );
+                return Err(status);
+            }
+        }
+    }
+
+    #[instrument]
+    async fn resolution_history(
+        &self,
+        request: Request<ResolutionHistoryRequest>,
+    ) -> Result<Response<ResolutionHistoryResponse>, Status> {
+        info!(message = "Got a resolution history request", ?request);
+
+        let access = Access::of(&self.pool, &request).await?;
+        let req = request.into_inner();
+
+        if req.tunnel <= 0 {
+            return Err(Status::invalid_argument("tunnel is required"));
+        }
+        access.check_tunnel(&self.pool, req.tunnel).await?;
+
+        match AddressResolution::history(&self.pool, req).await {
+            Ok(result) => Ok(Response::new(result)),
+            Err(status) => {
+                error!(
+                    message = "Error getting resolution history",
+                    status = status.message()
+                );
+                return Err(status);
+            }
+        }
+    }
+
+    #[instrument]
+    async fn rotate_keys(&self, request: Request<TunnelRequest>) -> Result<Response<RotateKeysResponse>, Status> {
+        info!(message = "Got a rotate keys request", ?request);
+
+        let access = Access::of(&self.pool, &request).await?;
+        let req = request.into_inner();
+
+        if let Some(id_or_router) = &req.id_or_router {
+            self.check_tunnels(&access, id_or_router).await?;
+        }
+
+        match req.id_or_router {
+            Some(id_or_router) => match IpsecKey::rotate(&self.pool, id_or_router).await {
+                Ok(result) => Ok(Response::new(result)),
+                Err(status) => {
+                    error!(
+                        message = "Error rotating pre-shared keys",
+                        status = status.message()
+                    );
+                    return Err(status);
+                }
+            },
+            None => Err(Status::invalid_argument("Tunnel id or router required")),
+        }
+    }
 }
@@ -3,8 +3,10 @@ use diesel::r2d2::{ConnectionManager, Pool};
 use tonic::{Request, Response, Status};
 use tracing::{error, info, instrument};
 
-use crate::api::{UserRequest, UsersResponse, UserResponse, UserAddRequest, UserUpdateRequest};
+use crate::api::{UserListRequest, UserRequest, UsersResponse, UserResponse, UserAddRequest, UserUpdateRequest};
+use crate::api::user_request::IdOrEmail;
 use crate::api::user_server::User;
+use crate::storage::access::Access;
 use crate::storage::users;
 
 #[derive(Debug)]
@@ -16,16 +18,27 @@ impl UserService {
     pub fn new(pool: Pool<ConnectionManager<PgConnection>>) -> Self {
         Self { pool }
     }
+
+    /// Fails unless the caller is an admin or the user `id_or_email` names.
+    async fn check_user(&self, access: &Access, id_or_email: &IdOrEmail) -> Result<(), Status> {
+        match id_or_email {
+            _ if access.is_admin() => Ok(()),
+            IdOrEmail::Id(user_id) => access.check_user(*user_id),
+            IdOrEmail::Email(_) => access.check_user(users::User::get(&self.pool, id_or_email).await?.id),
+        }
+    }
 }
 
 #[tonic::async_trait]
 impl User for UserService {
     #[instrument]
-    async fn list(&self, request: Request<()>) -> Result<Response<UsersResponse>, Status> {
+    async fn list(&self, request: Request<UserListRequest>) -> Result<Response<UsersResponse>, Status> {
         info!(message = "Got a list request", ?request);
 
-        match users::User::all(&self.pool).await {
-            Ok(result) => Ok(Response::new(UsersResponse { users: result })),
+        Access::of(&self.pool, &request).await?.require_admin()?;
+
+        match users::User::all(&self.pool, request.into_inner()).await {
+            Ok(result) => Ok(Response::new(result)),
             Err(status) => {
                 error!(
                     message = "Error getting list of users",
@@ -40,8 +53,13 @@ impl User for UserService {
     async fn get(&self, request: Request<UserRequest>) -> Result<Response<UserResponse>, Status> {
         info!(message = "Got a get request", ?request);
 
+        let access = Access::of(&self.pool, &request).await?;
         let req = request.into_inner();
 
+        if let Some(id_or_email) = &req.id_or_email {
+            self.check_user(&access, id_or_email).await?;
+        }
+
         match req.id_or_email {
             Some(id_or_email) => match users::User::get(&self.pool, &id_or_email).await {
                 Ok(result) => Ok(Response::new(result)),
@@ -61,6 +79,7 @@ impl User for UserService {
     async fn add(&self, request: Request<UserAddRequest>) -> Result<Response<UserResponse>, Status> {
         info!(message = "Got an add request", ?request);
 
+        Access::of(&self.pool, &request).await?.require_admin()?;
         let req = request.into_inner();
 
         if req.email.is_empty() {
@@ -80,8 +99,13 @@ impl User for UserService {
     async fn delete(&self, request: Request<UserRequest>) -> Result<Response<UserResponse>, Status> {
         info!(message = "Got a delete request", ?request);
 
+        let access = Access::of(&self.pool, &request).await?;
         let req = request.into_inner();
 
+        if let Some(id_or_email) = &req.id_or_email {
+            self.check_user(&access, id_or_email).await?;
+        }
+
         match req.id_or_email {
             Some(id_or_email) => match users::User::delete(&self.pool, id_or_email).await {
                 Ok(_) => Ok(Response::new(UserResponse::default())),
@@ -101,10 +125,12 @@ impl User for UserService {
     async fn update(&self, request: Request<UserUpdateRequest>) -> Result<Response<UserResponse>, Status> {
         info!(message = "Got an update request", ?request);
 
+        let access = Access::of(&self.pool, &request).await?;
         let req = request.into_inner();
         if req.id == 0 {
             return Err(Status::invalid_argument("User id required"));
         }
+        access.check_user(req.id)?;
 
         match users::User::update(&self.pool, req).await {
             Ok(result) => Ok(Response::new(result)),
@@ -0,0 +1,562 @@
+use std::collections::{BTreeMap, HashMap};
+
+use diesel::prelude::*;
+use diesel::sql_types::{Array, Nullable, Text};
+
+use crate::mesh::{MeshAgent, MeshDocument, MeshRouter, MeshTunnel};
+use crate::render;
+
+/// A row from the legacy database, keyed by lower-cased column name. Everything is kept as text
+/// because the legacy schema was hand-maintained and its column types are not reliable.
+pub type LegacyRow = HashMap<String, Option<String>>;
+
+/// The routers and tunnels tables of the old Python tunnel manager.
+#[derive(Debug, Default)]
+pub struct LegacyData {
+    pub routers: Vec<LegacyRow>,
+    pub tunnels: Vec<LegacyRow>,
+}
+
+/// The result of mapping legacy data onto the agents/routers/tunnels model: one placeholder agent
+/// per router owner, plus a line for every row that could not be carried over.
+#[derive(Debug, Default)]
+pub struct LegacyImport {
+    pub document: MeshDocument,
+    pub owners: Vec<String>,
+    pub unmapped: Vec<String>,
+}
+
+// Column names the legacy tables used over the years, in order of preference.
+const ROUTER_ID: &[&str] = &["id", "router_id"];
+const ROUTER_OWNER: &[&str] = &["owner", "owner_email", "email", "contact"];
+const ROUTER_TYPE: &[&str] = &["router_type", "type"];
+const ROUTER_CONN_TYPE: &[&str] = &["conn_type", "connection_type", "method"];
+const ROUTER_COMMUNITY: &[&str] = &["snmp_community", "community"];
+const ROUTER_SSH_USERNAME: &[&str] = &["ssh_username", "username"];
+const ROUTER_SSH_PASSWORD: &[&str] = &["ssh_password", "password"];
+const TUNNEL_ID: &[&str] = &["id", "tunnel_id", "tunnel"];
+const TUNNEL_ROUTER: &[&str] = &["router", "router_id"];
+const TUNNEL_VERSION: &[&str] = &["version"];
+const TUNNEL_IP: &[&str] = &["ip", "ip_address", "address", "destination"];
+const TUNNEL_DYNAMIC_IP: &[&str] = &["dynamic_ip", "dynamic"];
+const TUNNEL_IP_CLASS: &[&str] = &["ip_class", "ip_version"];
+const TUNNEL_HOSTNAME: &[&str] = &["hostname", "name", "host"];
+const TUNNEL_DESCRIPTION: &[&str] = &["description", "comment"];
+const TUNNEL_SOURCE: &[&str] = &["source", "source_ip", "src"];
+const TUNNEL_COST: &[&str] = &["cost", "metric"];
+const TUNNEL_TYPE: &[&str] = &["tunnel_type"];
+const TUNNEL_TOPOLOGY: &[&str] = &["topology_type", "topology"];
+
+/// Turns each row of a table into two parallel arrays so tables of any shape can be read.
+#[derive(QueryableByName)]
+struct GenericRow {
+    #[diesel(sql_type = Array<Text>)]
+    columns: Vec<String>,
+    #[diesel(sql_type = Array<Nullable<Text>>)]
+    values: Vec<Option<String>>,
+}
+
+impl LegacyData {
+    pub fn from_connection(
+        conn: &mut PgConnection,
+        routers_table: &str,
+        tunnels_table: &str,
+    ) -> QueryResult<LegacyData> {
+        Ok(LegacyData {
+            routers: load_table(conn, routers_table)?,
+            tunnels: load_table(conn, tunnels_table)?,
+        })
+    }
+
+    /// Reads the tables out of a plain-text `pg_dump`, in either the default `COPY` form or the
+    /// `--inserts`/`--column-inserts` forms.
+    pub fn from_dump(dump: &str, routers_table: &str, tunnels_table: &str) -> Result<LegacyData, String> {
+        let mut tables: HashMap<String, Vec<LegacyRow>> = HashMap::new();
+        let mut columns: HashMap<String, Vec<String>> = HashMap::new();
+        let mut lines = dump.lines();
+        let mut statement = String::new();
+
+        while let Some(line) = lines.next() {
+            let trimmed = line.trim();
+
+            if statement.is_empty() && (trimmed.is_empty() || trimmed.starts_with("--")) {
+                continue;
+            }
+
+            if statement.is_empty() && starts_with_keyword(trimmed, "COPY") {
+                let (table, cols) = parse_copy_header(trimmed)?;
+                let rows = tables.entry(table).or_default();
+
+                for data in lines.by_ref() {
+                    if data == "\\." {
+                        break;
+                    }
+
+                    let values = data.split('\t').map(unescape_copy).collect::<Vec<_>>();
+                    rows.push(cols.iter().cloned().zip(values).collect());
+                }
+
+                continue;
+            }
+
+            statement.push_str(line);
+            statement.push('\n');
+
+            if !statement_complete(&statement) {
+                continue;
+            }
+
+            let stmt = std::mem::take(&mut statement);
+            let stmt = stmt.trim();
+
+            if starts_with_keyword(stmt, "CREATE TABLE") {
+                let (table, cols) = parse_create_table(stmt)?;
+                columns.insert(table, cols);
+            } else if starts_with_keyword(stmt, "INSERT INTO") {
+                let (table, cols, rows) = parse_insert(stmt, &columns)?;
+                let entry = tables.entry(table).or_default();
+                for values in rows {
+                    entry.push(cols.iter().cloned().zip(values).collect());
+                }
+            }
+        }
+
+        Ok(LegacyData {
+            routers: tables.remove(routers_table).unwrap_or_default(),
+            tunnels: tables.remove(tunnels_table).unwrap_or_default(),
+        })
+    }
+
+    /// Maps the legacy rows onto a mesh document. Each distinct router owner gets a placeholder
+    /// agent (`legacy-<owner>`) holding all of that owner's routers.
+    pub fn map(&self) -> LegacyImport {
+        let mut import = LegacyImport::default();
+        let mut agents: BTreeMap<String, MeshAgent> = BTreeMap::new();
+        let mut router_owners: HashMap<i32, String> = HashMap::new();
+
+        for row in &self.routers {
+            let router_id = match int_field(row, ROUTER_ID) {
+                Ok(Some(router_id)) => router_id,
+                _ => {
+                    import.unmapped.push(format!("router {}: no usable id", describe(row)));
+                    continue;
+                }
+            };
+
+            let owner = match text_field(row, ROUTER_OWNER) {
+                Some(owner) => owner.to_lowercase(),
+                None => {
+                    import.unmapped.push(format!("router {}: no owner", router_id));
+                    continue;
+                }
+            };
+
+            let router_type = match text_field(row, ROUTER_TYPE).map(|t| normalize(&t, &["Cisco", "PyDECNet"])) {
+                Some(Err(t)) => {
+                    import.unmapped.push(format!("router {}: unknown router type {}", router_id, t));
+                    continue;
+                }
+                other => other.transpose().unwrap_or_default(),
+            };
+
+            let conn_type = match text_field(row, ROUTER_CONN_TYPE).map(|t| normalize(&t, &["SNMP", "SSH"])) {
+                Some(Err(t)) => {
+                    import.unmapped.push(format!("router {}: unknown connection type {}", router_id, t));
+                    continue;
+                }
+                other => other.transpose().unwrap_or_default(),
+            };
+
+            router_owners.insert(router_id, owner.clone());
+            agents
+                .entry(owner.clone())
+                .or_insert_with(|| MeshAgent {
+                    uuid: format!("legacy-{}", owner),
+                    description: format!("Imported from the legacy tunnel manager for {}", owner),
+                    owner: owner.clone(),
+                    routers: Vec::new(),
+                })
+                .routers
+                .push(MeshRouter {
+                    id: router_id,
+                    router_type,
+                    conn_type,
+                    snmp_community: text_field(row, ROUTER_COMMUNITY),
+                    ssh_username: text_field(row, ROUTER_SSH_USERNAME),
+                    ssh_password: text_field(row, ROUTER_SSH_PASSWORD),
+                    tunnels: Vec::new(),
+                });
+        }
+
+        for row in &self.tunnels {
+            match map_tunnel(row) {
+                Ok((router_id, tunnel)) => {
+                    let router = router_owners
+                        .get(&router_id)
+                        .and_then(|owner| agents.get_mut(owner))
+                        .and_then(|agent| agent.routers.iter_mut().find(|r| r.id == router_id));
+
+                    match router {
+                        Some(router) => router.tunnels.push(tunnel),
+                        None => import.unmapped.push(format!(
+                            "tunnel {}: router {} was not imported",
+                            tunnel.id, router_id
+                        )),
+                    }
+                }
+                Err(reason) => import
+                    .unmapped
+                    .push(format!("tunnel {}: {}", describe(row), reason)),
+            }
+        }
+
+        import.owners = agents.keys().cloned().collect();
+        import.document.agents = agents.into_values().collect();
+
+        if let Err(err) = import.document.validate() {
+            import.unmapped.push(err);
+        }
+
+        import
+    }
+}
+
+fn map_tunnel(row: &LegacyRow) -> Result<(i32, MeshTunnel), String> {
+    let tunnel_id = int_field(row, TUNNEL_ID)?.ok_or("no id")?;
+    if tunnel_id < 50 {
+        return Err(format!("tunnel index {} is below 50", tunnel_id));
+    }
+
+    let router_id = int_field(row, TUNNEL_ROUTER)?.ok_or("no router")?;
+
+    let ip_class = int_field(row, TUNNEL_IP_CLASS)?.unwrap_or(4);
+    if ip_class != 4 && ip_class != 6 {
+        return Err(format!("unknown ip class {}", ip_class));
+    }
+
+    let tunnel_type = match text_field(row, TUNNEL_TYPE) {
+        Some(t) => normalize(&t, &["GRE", "IPSec"]).map_err(|t| format!("unknown tunnel type {}", t))?,
+        None => "GRE".to_string(),
+    };
+
+    let topology_type = match text_field(row, TUNNEL_TOPOLOGY) {
+        Some(t) => normalize(&t, &["mesh", "hub", "spoke"]).map_err(|t| format!("unknown topology {}", t))?,
+        None => "mesh".to_string(),
+    };
+
+    let ip = text_field(row, TUNNEL_IP).ok_or("no ip")?;
+
+    let tunnel = MeshTunnel {
+        id: tunnel_id,
+        version: int_field(row, TUNNEL_VERSION)?.unwrap_or_default(),
+        hostname: text_field(row, TUNNEL_HOSTNAME).unwrap_or_else(|| ip.clone()),
+        ip,
+        dynamic_ip: bool_field(row, TUNNEL_DYNAMIC_IP)?.unwrap_or_default(),
+        ip_class,
+        description: text_field(row, TUNNEL_DESCRIPTION).unwrap_or_default(),
+        source: text_field(row, TUNNEL_SOURCE).ok_or("no source")?,
+        cost: int_field(row, TUNNEL_COST)?.unwrap_or(10),
+        tunnel_type,
+        topology_type,
+    };
+    render::check_endpoint(&tunnel.ip, tunnel.dynamic_ip, &tunnel.hostname, &tunnel.description, &tunnel.source)?;
+
+    Ok((router_id, tunnel))
+}
+
+fn load_table(conn: &mut PgConnection, table: &str) -> QueryResult<Vec<LegacyRow>> {
+    let query = format!(
+        "SELECT array_agg(f.key ORDER BY f.ordinality) AS columns, \
+                array_agg(f.value ORDER BY f.ordinality) AS values \
+         FROM {} t, LATERAL json_each_text(row_to_json(t)) WITH ORDINALITY f \
+         GROUP BY t.ctid",
+        quote_ident(table)
+    );
+
+    Ok(diesel::sql_query(query)
+        .load::<GenericRow>(conn)?
+        .into_iter()
+        .map(|r| r.columns.into_iter().map(|c| c.to_lowercase()).zip(r.values).collect())
+        .collect())
+}
+
+fn quote_ident(name: &str) -> String {
+    name.split('.')
+        .map(|part| format!("\"{}\"", part.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+fn field<'a>(row: &'a LegacyRow, names: &[&str]) -> Option<&'a str> {
+    names
+        .iter()
+        .filter_map(|name| row.get(*name))
+        .flatten()
+        .map(|v| v.trim())
+        .find(|v| !v.is_empty())
+}
+
+fn text_field(row: &LegacyRow, names: &[&str]) -> Option<String> {
+    field(row, names).map(|v| v.to_string())
+}
+
+fn int_field(row: &LegacyRow, names: &[&str]) -> Result<Option<i32>, String> {
+    field(row, names)
+        .map(|v| v.parse().map_err(|_| format!("{} is not a number", v)))
+        .transpose()
+}
+
+fn bool_field(row: &LegacyRow, names: &[&str]) -> Result<Option<bool>, String> {
+    field(row, names)
+        .map(|v| match v.to_lowercase().as_str() {
+            "t" | "true" | "1" | "yes" | "y" => Ok(true),
+            "f" | "false" | "0" | "no" | "n" => Ok(false),
+            _ => Err(format!("{} is not a boolean", v)),
+        })
+        .transpose()
+}
+
+/// Matches `value` case-insensitively against the spellings the new schema accepts.
+fn normalize(value: &str, allowed: &[&str]) -> Result<String, String> {
+    allowed
+        .iter()
+        .find(|a| a.eq_ignore_ascii_case(value))
+        .map(|a| a.to_string())
+        .ok_or_else(|| value.to_string())
+}
+
+fn describe(row: &LegacyRow) -> String {
+    field(row, &["id"]).unwrap_or("without id").to_string()
+}
+
+fn starts_with_keyword(statement: &str, keyword: &str) -> bool {
+    statement
+        .get(..keyword.len())
+        .map(|s| s.eq_ignore_ascii_case(keyword))
+        .unwrap_or(false)
+}
+
+/// True once `statement` ends in a semicolon that is not inside a quoted string.
+fn statement_complete(statement: &str) -> bool {
+    let mut in_string = false;
+    let mut last = ' ';
+
+    for c in statement.chars() {
+        if c == '\'' {
+            in_string = !in_string;
+        }
+        if !c.is_whitespace() {
+            last = c;
+        }
+    }
+
+    !in_string && last == ';'
+}
+
+/// Strips the schema and quoting from a table name: `public."Routers"` becomes `routers`.
+fn table_name(name: &str) -> String {
+    name.rsplit('.')
+        .next()
+        .unwrap_or(name)
+        .trim_matches('"')
+        .to_lowercase()
+}
+
+fn parse_column_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|c| c.trim().trim_matches('"').to_lowercase())
+        .collect()
+}
+
+/// Splits `name (list) ...` into the name and what is inside the parentheses, or `None` without
+/// a `(`. Nested parentheses, as in `varchar(64)`, stay part of the list.
+fn split_parenthesized<'a>(text: &'a str, what: &str) -> Result<Option<(&'a str, &'a str)>, String> {
+    let open = match text.find('(') {
+        Some(open) => open,
+        None => return Ok(None),
+    };
+    let mut depth = 0;
+
+    for (i, c) in text.char_indices().skip_while(|&(i, _)| i < open) {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 1 => return Ok(Some((text[..open].trim(), &text[open + 1..i]))),
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+
+    Err(format!("unterminated {}: {}", what, text))
+}
+
+/// Where `keyword` (ASCII) starts in `text` as a whole word, ignoring case, outside quotes and
+/// parentheses; so neither a column nor a string named `values` is taken for `VALUES`.
+fn find_keyword(text: &str, keyword: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let is_word = |b: u8| b.is_ascii_alphanumeric() || b == b'_' || b >= 0x80;
+    let mut quote = None;
+    let mut depth = 0;
+
+    for (i, &b) in bytes.iter().enumerate() {
+        match (quote, b) {
+            (Some(q), _) if b == q => quote = None,
+            (Some(_), _) => {}
+            (None, b'\'' | b'"') => quote = Some(b),
+            (None, b'(') => depth += 1,
+            (None, b')') => depth -= 1,
+            (None, _) if depth == 0 => {
+                let end = i + keyword.len();
+                let matches = bytes.get(i..end).is_some_and(|word| word.eq_ignore_ascii_case(keyword.as_bytes()));
+                let bounded = (i == 0 || !is_word(bytes[i - 1])) && !bytes.get(end).is_some_and(|&b| is_word(b));
+                if matches && bounded {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+fn parse_copy_header(line: &str) -> Result<(String, Vec<String>), String> {
+    let rest = line["COPY".len()..].trim();
+    let (table, columns) = split_parenthesized(rest, "COPY column list")?
+        .ok_or_else(|| format!("COPY without a column list: {}", line))?;
+
+    Ok((table_name(table), parse_column_list(columns)))
+}
+
+fn unescape_copy(field: &str) -> Option<String> {
+    if field == "\\N" {
+        return None;
+    }
+
+    let mut out = String::with_capacity(field.len());
+    let mut chars = field.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+
+    Some(out)
+}
+
+fn parse_create_table(statement: &str) -> Result<(String, Vec<String>), String> {
+    let rest = statement["CREATE TABLE".len()..].trim();
+    let (table, definitions) = split_parenthesized(rest, "CREATE TABLE")?.ok_or("CREATE TABLE without columns")?;
+
+    let mut columns = Vec::new();
+    let mut depth = 0;
+    let mut current = String::new();
+
+    for c in definitions.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                columns.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    columns.push(current);
+
+    let constraints = ["constraint", "primary", "unique", "check", "foreign", "exclude"];
+
+    Ok((
+        table_name(table),
+        columns
+            .iter()
+            .filter_map(|def| def.split_whitespace().next())
+            .map(|name| name.trim_matches('"').to_lowercase())
+            .filter(|name| !constraints.contains(&name.as_str()))
+            .collect(),
+    ))
+}
+
+type InsertRows = (String, Vec<String>, Vec<Vec<Option<String>>>);
+
+fn parse_insert(statement: &str, known_columns: &HashMap<String, Vec<String>>) -> Result<InsertRows, String> {
+    let rest = statement["INSERT INTO".len()..].trim();
+    let values_at = find_keyword(rest, "VALUES").ok_or_else(|| format!("INSERT without VALUES: {}", statement))?;
+    let target = rest[..values_at].trim();
+
+    let (table, columns) = match split_parenthesized(target, "INSERT column list")? {
+        Some((table, columns)) => (table_name(table), parse_column_list(columns)),
+        None => {
+            let table = table_name(target);
+            let columns = known_columns
+                .get(&table)
+                .cloned()
+                .ok_or_else(|| format!("INSERT into {} without a column list or CREATE TABLE", table))?;
+            (table, columns)
+        }
+    };
+
+    Ok((table, columns, parse_values(&rest[values_at + "VALUES".len()..])?))
+}
+
+/// Parses `(1, 'a', NULL), (2, 'it''s', true);` into rows of text values.
+fn parse_values(values: &str) -> Result<Vec<Vec<Option<String>>>, String> {
+    let mut rows = Vec::new();
+    let mut row: Vec<Option<String>> = Vec::new();
+    let mut chars = values.chars().peekable();
+    let mut in_row = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '(' if !in_row => in_row = true,
+            ')' if in_row => {
+                rows.push(std::mem::take(&mut row));
+                in_row = false;
+            }
+            '\'' if in_row => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\'') if chars.peek() == Some(&'\'') => {
+                            chars.next();
+                            value.push('\'');
+                        }
+                        Some('\'') => break,
+                        Some(c) => value.push(c),
+                        None => return Err("unterminated string in INSERT".to_string()),
+                    }
+                }
+                row.push(Some(value));
+            }
+            c if in_row && (c.is_alphanumeric() || c == '-' || c == '.') => {
+                let mut literal = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    if next == ',' || next == ')' || next == '\'' || next.is_whitespace() {
+                        break;
+                    }
+                    literal.push(next);
+                    chars.next();
+                }
+                // E'...' strings come through here as a bare E followed by a quoted value.
+                if literal.eq_ignore_ascii_case("e") && chars.peek() == Some(&'\'') {
+                    continue;
+                }
+                row.push(if literal.eq_ignore_ascii_case("null") { None } else { Some(literal) });
+            }
+            _ => {}
+        }
+    }
+
+    Ok(rows)
+}
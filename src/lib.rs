@@ -1,4 +1,20 @@
+#![allow(clippy::result_large_err)]
+
 pub mod api;
+pub mod auth;
+pub mod config;
+pub mod gateway;
+pub mod grpc;
+pub mod grpc_web;
 pub mod handlers;
+pub mod legacy;
+pub mod mesh;
+pub mod metrics;
+pub mod netlink;
+pub mod render;
+pub mod resolver;
 pub mod schema;
+pub mod server;
+pub mod shutdown;
 pub mod storage;
+pub mod wireguard;
@@ -0,0 +1,188 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use serde::Deserialize;
+
+use crate::render;
+
+/// Declarative description of the whole mesh: every agent, the routers it manages and the
+/// tunnels on each router. This is what `Mesh.Export` produces and `Mesh.Apply` consumes, so the
+/// mesh can be kept in Git instead of being edited row by row.
+///
+/// Agents are matched against the database by `uuid`, routers and tunnels by `id` (for tunnels
+/// that is also the `interface TunnelN` index), and owners by email.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MeshDocument {
+    #[serde(default)]
+    pub agents: Vec<MeshAgent>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MeshAgent {
+    pub uuid: String,
+    #[serde(default)]
+    pub description: String,
+    pub owner: String,
+    #[serde(default)]
+    pub routers: Vec<MeshRouter>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MeshRouter {
+    pub id: i32,
+    pub router_type: Option<String>,
+    pub conn_type: Option<String>,
+    /// Never exported; when left out, applying keeps the router's current one.
+    pub snmp_community: Option<String>,
+    pub ssh_username: Option<String>,
+    /// Never exported; when left out, applying keeps the router's current one.
+    pub ssh_password: Option<String>,
+    #[serde(default)]
+    pub tunnels: Vec<MeshTunnel>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MeshTunnel {
+    pub id: i32,
+    #[serde(default)]
+    pub version: i32,
+    pub ip: String,
+    #[serde(default)]
+    pub dynamic_ip: bool,
+    #[serde(default = "default_ip_class")]
+    pub ip_class: i32,
+    pub hostname: String,
+    pub description: String,
+    pub source: String,
+    #[serde(default = "default_cost")]
+    pub cost: i32,
+    #[serde(default = "default_tunnel_type")]
+    pub tunnel_type: String,
+    #[serde(default = "default_topology_type")]
+    pub topology_type: String,
+}
+
+// These mirror the column defaults of the tunnels table.
+fn default_ip_class() -> i32 {
+    4
+}
+
+fn default_cost() -> i32 {
+    10
+}
+
+fn default_tunnel_type() -> String {
+    "GRE".to_string()
+}
+
+fn default_topology_type() -> String {
+    "mesh".to_string()
+}
+
+impl MeshDocument {
+    pub fn from_toml(document: &str) -> Result<MeshDocument, String> {
+        let parsed: MeshDocument = toml::from_str(document).map_err(|e| e.to_string())?;
+        parsed.validate()?;
+        Ok(parsed)
+    }
+
+    /// Renders the document as TOML. This is done by hand rather than through serde so that the
+    /// field order is fixed and exports diff cleanly in Git. Router credentials are left out.
+    pub fn to_toml(&self) -> String {
+        let mut out = String::new();
+
+        for agent in &self.agents {
+            if !out.is_empty() {
+                out.push('\n');
+            }
+            out.push_str("[[agents]]\n");
+            write_str(&mut out, "uuid", &agent.uuid);
+            write_str(&mut out, "description", &agent.description);
+            write_str(&mut out, "owner", &agent.owner);
+
+            for router in &agent.routers {
+                out.push_str("\n[[agents.routers]]\n");
+                writeln!(out, "id = {}", router.id).unwrap();
+                write_opt_str(&mut out, "router_type", &router.router_type);
+                write_opt_str(&mut out, "conn_type", &router.conn_type);
+                write_opt_str(&mut out, "ssh_username", &router.ssh_username);
+
+                for tunnel in &router.tunnels {
+                    out.push_str("\n[[agents.routers.tunnels]]\n");
+                    writeln!(out, "id = {}", tunnel.id).unwrap();
+                    writeln!(out, "version = {}", tunnel.version).unwrap();
+                    write_str(&mut out, "ip", &tunnel.ip);
+                    writeln!(out, "dynamic_ip = {}", tunnel.dynamic_ip).unwrap();
+                    writeln!(out, "ip_class = {}", tunnel.ip_class).unwrap();
+                    write_str(&mut out, "hostname", &tunnel.hostname);
+                    write_str(&mut out, "description", &tunnel.description);
+                    write_str(&mut out, "source", &tunnel.source);
+                    writeln!(out, "cost = {}", tunnel.cost).unwrap();
+                    write_str(&mut out, "tunnel_type", &tunnel.tunnel_type);
+                    write_str(&mut out, "topology_type", &tunnel.topology_type);
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Checks the keys used to match entities against the database are unique.
+    pub fn validate(&self) -> Result<(), String> {
+        let mut agent_uuids = HashSet::new();
+        let mut router_ids = HashSet::new();
+        let mut tunnel_ids = HashSet::new();
+
+        for agent in &self.agents {
+            if agent.uuid.is_empty() {
+                return Err("agent uuid is required".to_string());
+            }
+            if !agent_uuids.insert(agent.uuid.as_str()) {
+                return Err(format!("agent {} is declared more than once", agent.uuid));
+            }
+
+            for router in &agent.routers {
+                if !router_ids.insert(router.id) {
+                    return Err(format!("router {} is declared more than once", router.id));
+                }
+
+                for tunnel in &router.tunnels {
+                    if !tunnel_ids.insert(tunnel.id) {
+                        return Err(format!("tunnel {} is declared more than once", tunnel.id));
+                    }
+                    render::check_endpoint(&tunnel.ip, tunnel.dynamic_ip, &tunnel.hostname, &tunnel.description, &tunnel.source)
+                        .map_err(|err| format!("tunnel {}: {}", tunnel.id, err))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn write_str(out: &mut String, key: &str, value: &str) {
+    out.push_str(key);
+    out.push_str(" = \"");
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => write!(out, "\\u{:04X}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push_str("\"\n");
+}
+
+fn write_opt_str(out: &mut String, key: &str, value: &Option<String>) {
+    if let Some(value) = value {
+        write_str(out, key, value);
+    }
+}
@@ -0,0 +1,416 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
+
+use diesel::r2d2::{self, ConnectionManager, HandleEvent, Pool};
+use diesel::PgConnection;
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::HeaderMap;
+use hyper::{Body, Request, Response};
+use prometheus::{Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry};
+use tonic::body::BoxBody;
+use tonic::{Code, Status};
+use tower::{BoxError, Layer, Service};
+use tracing::error;
+
+use crate::api::PushResult;
+use crate::server::SERVICES;
+use crate::storage::agents::Agent;
+
+/// The server's Prometheus metrics. RPCs are named like go-grpc-prometheus names them, so the
+/// usual dashboards work; everything else is prefixed `tunnel_manager_`.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    registry: Registry,
+    rpcs: IntCounterVec,
+    rpc_seconds: HistogramVec,
+    renders: IntCounterVec,
+    pool_connections: IntGaugeVec,
+    pool_max_connections: IntGauge,
+    pool_wait_seconds: Histogram,
+    pool_timeouts: IntCounter,
+    agents: IntGaugeVec,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        let rpc_labels = &["grpc_service", "grpc_method", "grpc_code"];
+        let metrics = Metrics {
+            registry: Registry::new(),
+            rpcs: IntCounterVec::new(
+                Opts::new("grpc_server_handled_total", "RPCs completed on the server, by code."),
+                rpc_labels,
+            )
+            .unwrap(),
+            rpc_seconds: HistogramVec::new(
+                HistogramOpts::new(
+                    "grpc_server_handling_seconds",
+                    "How long RPCs took, until the last message of streams.",
+                ),
+                rpc_labels,
+            )
+            .unwrap(),
+            renders: IntCounterVec::new(
+                Opts::new("tunnel_manager_config_renders_total", "Router configurations rendered."),
+                &["router_type", "result"],
+            )
+            .unwrap(),
+            pool_connections: IntGaugeVec::new(
+                Opts::new("tunnel_manager_db_pool_connections", "Database connections in the pool."),
+                &["state"],
+            )
+            .unwrap(),
+            pool_max_connections: IntGauge::new(
+                "tunnel_manager_db_pool_max_connections",
+                "The most connections the pool opens.",
+            )
+            .unwrap(),
+            pool_wait_seconds: Histogram::with_opts(
+                HistogramOpts::new(
+                    "tunnel_manager_db_pool_wait_seconds",
+                    "How long handlers waited for a database connection.",
+                )
+                .buckets(vec![0.0001, 0.001, 0.01, 0.1, 0.5, 1.0, 5.0, 30.0]),
+            )
+            .unwrap(),
+            pool_timeouts: IntCounter::new(
+                "tunnel_manager_db_pool_timeouts_total",
+                "Times no database connection was free within the connection timeout.",
+            )
+            .unwrap(),
+            agents: IntGaugeVec::new(
+                Opts::new("tunnel_manager_agents", "Registered agents."),
+                &[],
+            )
+            .unwrap(),
+        };
+
+        let registry = &metrics.registry;
+        registry.register(Box::new(metrics.rpcs.clone())).unwrap();
+        registry.register(Box::new(metrics.rpc_seconds.clone())).unwrap();
+        registry.register(Box::new(metrics.renders.clone())).unwrap();
+        registry.register(Box::new(metrics.pool_connections.clone())).unwrap();
+        registry.register(Box::new(metrics.pool_max_connections.clone())).unwrap();
+        registry.register(Box::new(metrics.pool_wait_seconds.clone())).unwrap();
+        registry.register(Box::new(metrics.pool_timeouts.clone())).unwrap();
+        registry.register(Box::new(metrics.agents.clone())).unwrap();
+
+        metrics
+    }
+
+    /// Records an RPC to `path` (`/package.Service/Method`). Services the server does not have
+    /// and methods it does not implement are recorded as `unknown`, so scanners cannot blow up
+    /// the number of series.
+    pub fn observe_rpc(&self, path: &str, code: Code, elapsed: Duration) {
+        let (service, method) = path.trim_start_matches('/').split_once('/').unwrap_or(("", ""));
+        let (service, method) = match (SERVICES.contains(&service), code) {
+            (false, _) => ("unknown", "unknown"),
+            (true, Code::Unimplemented) => (service, "unknown"),
+            (true, _) => (service, method),
+        };
+
+        let code = format!("{:?}", code);
+        let labels = &[service, method, code.as_str()];
+        self.rpcs.with_label_values(labels).inc();
+        self.rpc_seconds.with_label_values(labels).observe(elapsed.as_secs_f64());
+    }
+
+    pub fn observe_render(&self, router_type: &str, ok: bool) {
+        let result = match ok {
+            true => "ok",
+            false => "error",
+        };
+        self.renders.with_label_values(&[router_type, result]).inc();
+    }
+
+    /// Records connection checkouts; goes to [`crate::config::DatabaseConfig::pool`].
+    pub fn pool_events(&self) -> PoolEvents {
+        PoolEvents {
+            wait_seconds: self.pool_wait_seconds.clone(),
+            timeouts: self.pool_timeouts.clone(),
+        }
+    }
+}
+
+/// Feeds r2d2's checkout events into [`Metrics`].
+#[derive(Debug)]
+pub struct PoolEvents {
+    wait_seconds: Histogram,
+    timeouts: IntCounter,
+}
+
+impl HandleEvent for PoolEvents {
+    fn handle_checkout(&self, event: r2d2::event::CheckoutEvent) {
+        self.wait_seconds.observe(event.duration().as_secs_f64());
+    }
+
+    fn handle_timeout(&self, _event: r2d2::event::TimeoutEvent) {
+        self.timeouts.inc();
+    }
+}
+
+/// Serves [`Metrics`] as `/metrics`, reading the pool and agent gauges at scrape time.
+#[derive(Debug, Clone)]
+pub struct Exporter {
+    metrics: Metrics,
+    pool: Pool<ConnectionManager<PgConnection>>,
+}
+
+impl Exporter {
+    pub fn new(metrics: Metrics, pool: Pool<ConnectionManager<PgConnection>>) -> Exporter {
+        Exporter { metrics, pool }
+    }
+
+    /// The metrics in the Prometheus text format.
+    pub async fn render(&self) -> String {
+        let metrics = &self.metrics;
+
+        let state = self.pool.state();
+        let in_use = state.connections - state.idle_connections;
+        metrics.pool_connections.with_label_values(&["idle"]).set(state.idle_connections.into());
+        metrics.pool_connections.with_label_values(&["in_use"]).set(in_use.into());
+        metrics.pool_max_connections.set(self.pool.max_size().into());
+
+        // Stale numbers are worse than none, so the gauge is dropped when the query fails.
+        match Agent::count(&self.pool).await {
+            Ok(count) => metrics.agents.with_label_values(&[]).set(count),
+            Err(status) => {
+                error!(message = "Error counting agents", status = status.message());
+                metrics.agents.reset();
+            }
+        }
+
+        encode(&metrics.registry)
+    }
+}
+
+/// An agent's Prometheus metrics, for it to serve itself. The server only learns of the pushes
+/// agents get to report, so attempts are counted where they are made.
+#[derive(Debug, Clone)]
+pub struct AgentMetrics {
+    registry: Registry,
+    pushes: IntCounterVec,
+    push_failures: IntCounterVec,
+    push_seconds: HistogramVec,
+}
+
+impl Default for AgentMetrics {
+    fn default() -> Self {
+        AgentMetrics::new()
+    }
+}
+
+impl AgentMetrics {
+    pub fn new() -> AgentMetrics {
+        let metrics = AgentMetrics {
+            registry: Registry::new(),
+            pushes: IntCounterVec::new(
+                Opts::new("tunnel_manager_agent_push_attempts_total", "Configuration pushes attempted, by driver."),
+                &["driver"],
+            )
+            .unwrap(),
+            push_failures: IntCounterVec::new(
+                Opts::new("tunnel_manager_agent_push_failures_total", "Configuration pushes that failed, by driver."),
+                &["driver"],
+            )
+            .unwrap(),
+            push_seconds: HistogramVec::new(
+                HistogramOpts::new(
+                    "tunnel_manager_agent_push_duration_seconds",
+                    "How long configuration pushes took, by driver.",
+                )
+                .buckets(vec![0.1, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0]),
+                &["driver"],
+            )
+            .unwrap(),
+        };
+
+        let registry = &metrics.registry;
+        registry.register(Box::new(metrics.pushes.clone())).unwrap();
+        registry.register(Box::new(metrics.push_failures.clone())).unwrap();
+        registry.register(Box::new(metrics.push_seconds.clone())).unwrap();
+
+        metrics
+    }
+
+    /// Records a push, from the result the agent reports with `Router.ReportPush`. Its duration
+    /// is left out when the timestamps are missing or out of order.
+    pub fn observe_push(&self, result: &PushResult) {
+        let labels = &[result.driver.as_str()];
+        self.pushes.with_label_values(labels).inc();
+        if !result.success {
+            self.push_failures.with_label_values(labels).inc();
+        }
+
+        let time = |t: &Option<prost_types::Timestamp>| SystemTime::try_from(t.clone()?).ok();
+        if let (Some(started), Some(finished)) = (time(&result.started_at), time(&result.finished_at)) {
+            if let Ok(elapsed) = finished.duration_since(started) {
+                self.push_seconds.with_label_values(labels).observe(elapsed.as_secs_f64());
+            }
+        }
+    }
+
+    /// The metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        encode(&self.registry)
+    }
+}
+
+fn encode(registry: &Registry) -> String {
+    let mut buffer = Vec::new();
+    prometheus::TextEncoder::new().encode(&registry.gather(), &mut buffer).unwrap();
+    String::from_utf8(buffer).unwrap()
+}
+
+/// Records every RPC through the gRPC stack in [`Metrics`], once its status is known: from the
+/// headers of trailers-only responses, from the trailers otherwise, or as `Cancelled` if the
+/// client goes away first.
+#[derive(Debug, Clone)]
+pub struct MetricsLayer {
+    metrics: Metrics,
+}
+
+impl MetricsLayer {
+    pub fn new(metrics: Metrics) -> MetricsLayer {
+        MetricsLayer { metrics }
+    }
+}
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = RpcMetrics<S>;
+
+    fn layer(&self, inner: S) -> RpcMetrics<S> {
+        RpcMetrics {
+            inner,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RpcMetrics<S> {
+    inner: S,
+    metrics: Metrics,
+}
+
+type BoxFuture<T, E> = Pin<Box<dyn Future<Output = Result<T, E>> + Send>>;
+
+impl<S> Service<Request<Body>> for RpcMetrics<S>
+where
+    S: Service<Request<Body>, Response = Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Into<BoxError>,
+{
+    type Response = Response<BoxBody>;
+    type Error = BoxError;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        // The clone may not be ready yet, so call the one poll_ready was called on.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let rpc = Rpc {
+            metrics: self.metrics.clone(),
+            path: req.uri().path().to_string(),
+            start: Instant::now(),
+        };
+
+        Box::pin(async move {
+            let response = match inner.call(req).await {
+                Ok(response) => response,
+                Err(err) => {
+                    let err: BoxError = err.into();
+                    rpc.finish(match err.is::<tower::timeout::error::Elapsed>() {
+                        true => Code::DeadlineExceeded,
+                        false => Code::Unknown,
+                    });
+                    return Err(err);
+                }
+            };
+
+            if let Some(code) = grpc_status(response.headers()) {
+                rpc.finish(code);
+                return Ok(response);
+            }
+
+            Ok(response.map(|body| {
+                BoxBody::new(RpcBody {
+                    inner: body,
+                    rpc: Some(rpc),
+                })
+            }))
+        })
+    }
+}
+
+fn grpc_status(headers: &HeaderMap) -> Option<Code> {
+    let status = headers.get("grpc-status")?.to_str().ok()?.parse().ok()?;
+    Some(Code::from_i32(status))
+}
+
+struct Rpc {
+    metrics: Metrics,
+    path: String,
+    start: Instant,
+}
+
+impl Rpc {
+    fn finish(self, code: Code) {
+        self.metrics.observe_rpc(&self.path, code, self.start.elapsed());
+    }
+}
+
+/// The response body, recording the RPC when its trailers go out, or when it is dropped before.
+struct RpcBody {
+    inner: BoxBody,
+    rpc: Option<Rpc>,
+}
+
+impl HttpBody for RpcBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, Status>>> {
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Option<HeaderMap>, Status>> {
+        let trailers = match Pin::new(&mut self.inner).poll_trailers(cx) {
+            Poll::Ready(trailers) => trailers,
+            Poll::Pending => return Poll::Pending,
+        };
+
+        if let Some(rpc) = self.rpc.take() {
+            let code = match &trailers {
+                Ok(trailers) => trailers.as_ref().and_then(grpc_status).unwrap_or(Code::Unknown),
+                Err(status) => status.code(),
+            };
+            rpc.finish(code);
+        }
+
+        Poll::Ready(trailers)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
+
+impl Drop for RpcBody {
+    fn drop(&mut self) {
+        if let Some(rpc) = self.rpc.take() {
+            rpc.finish(Code::Cancelled);
+        }
+    }
+}
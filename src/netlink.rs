@@ -0,0 +1,441 @@
+//! A driver for routers that are the agent's own Linux host: the GRE interfaces of the router's
+//! GRE peerings are created, updated and removed over rtnetlink, without shelling out to `ip`.
+//! Interfaces are managed by name prefix; every interface starting with it that is not desired
+//! any more is removed.
+
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+use crate::render;
+use crate::storage::routers::Router;
+use crate::storage::tunnels::Tunnel;
+
+// From linux/netlink.h, linux/rtnetlink.h and linux/if_link.h.
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+const RTM_NEWLINK: u16 = 16;
+const RTM_DELLINK: u16 = 17;
+const RTM_GETLINK: u16 = 18;
+const NLM_F_REQUEST: u16 = 0x1;
+const NLM_F_ACK: u16 = 0x4;
+const NLM_F_DUMP: u16 = 0x300;
+const NLM_F_EXCL: u16 = 0x200;
+const NLM_F_CREATE: u16 = 0x400;
+const NLA_TYPE_MASK: u16 = 0x3fff;
+const IFLA_IFNAME: u16 = 3;
+const IFLA_MTU: u16 = 4;
+const IFLA_LINKINFO: u16 = 18;
+const IFLA_INFO_KIND: u16 = 1;
+const IFLA_INFO_DATA: u16 = 2;
+const IFLA_GRE_LOCAL: u16 = 6;
+const IFLA_GRE_REMOTE: u16 = 7;
+const IFLA_GRE_TTL: u16 = 8;
+const IFF_UP: u32 = 0x1;
+
+/// The lengths of `struct nlmsghdr` and `struct ifinfomsg`.
+const HEADER_LEN: usize = 16;
+const IFINFO_LEN: usize = 16;
+
+/// Interface names are at most this long, as `IFNAMSIZ` leaves room for the NUL.
+pub const MAX_NAME_LEN: usize = 15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GreKind {
+    /// Layer 3 GRE over IPv4.
+    Gre,
+    /// Ethernet over GRE over IPv4, for PyDECnet Ethernet circuits.
+    GreTap,
+    /// Layer 3 GRE over IPv6.
+    Ip6Gre,
+}
+
+impl GreKind {
+    /// The kernel's name for the link type.
+    pub fn name(&self) -> &'static str {
+        match self {
+            GreKind::Gre => "gre",
+            GreKind::GreTap => "gretap",
+            GreKind::Ip6Gre => "ip6gre",
+        }
+    }
+}
+
+/// A GRE interface as it should be.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GreInterface {
+    pub name: String,
+    pub kind: GreKind,
+    /// Any local address when `None`.
+    pub local: Option<IpAddr>,
+    pub remote: IpAddr,
+    /// The TTL, or hop limit over IPv6, of encapsulated packets.
+    pub ttl: u8,
+    pub mtu: u32,
+}
+
+/// How the host's GRE interfaces are set up.
+#[derive(Debug, Clone)]
+pub struct GreSettings {
+    /// Managed interfaces are named this followed by the peer's tunnel id.
+    pub prefix: String,
+    /// Whether IPv4 peerings get `gretap` interfaces rather than `gre` ones.
+    pub tap: bool,
+    pub mtu: u32,
+    pub ttl: u8,
+}
+
+impl Default for GreSettings {
+    fn default() -> Self {
+        GreSettings {
+            prefix: "hecnet".to_string(),
+            tap: false,
+            // Leaves room for GRE over IPv6 and for the Ethernet header of gretap.
+            mtu: 1400,
+            ttl: 64,
+        }
+    }
+}
+
+/// The GRE interfaces `router` should have among `tunnels`, one per GRE peering. Local endpoints
+/// whose source is an address are bound to it. Peers only known by hostname so far are left out
+/// until their address is found.
+pub fn desired(router: &Router, tunnels: &[Tunnel], settings: &GreSettings) -> Vec<GreInterface> {
+    render::links(router, tunnels)
+        .into_iter()
+        .filter(|(local, _)| local.tunnel_type == "GRE")
+        .filter_map(|(local, peer)| {
+            let remote = render::destination(peer).parse().ok()?;
+            let kind = match (local.ip_class, settings.tap) {
+                (6, _) => GreKind::Ip6Gre,
+                (_, true) => GreKind::GreTap,
+                (_, false) => GreKind::Gre,
+            };
+
+            Some(GreInterface {
+                name: format!("{}{}", settings.prefix, peer.id),
+                kind,
+                local: local.source.parse().ok(),
+                remote,
+                ttl: settings.ttl,
+                mtu: settings.mtu,
+            })
+        })
+        .collect()
+}
+
+/// A network interface of the host, as rtnetlink reports it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    pub index: i32,
+    pub name: String,
+    /// The link type, `None` for physical interfaces.
+    pub kind: Option<String>,
+    pub mtu: u32,
+    pub up: bool,
+    pub local: Option<IpAddr>,
+    pub remote: Option<IpAddr>,
+    pub ttl: Option<u8>,
+}
+
+impl Link {
+    fn matches(&self, interface: &GreInterface) -> bool {
+        self.kind.as_deref() == Some(interface.kind.name())
+            && self.local == interface.local
+            && self.remote == Some(interface.remote)
+            && self.ttl == Some(interface.ttl)
+            && self.mtu == interface.mtu
+            && self.up
+    }
+}
+
+/// The interfaces [`GreDriver::apply`] touched, by name.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Applied {
+    pub created: Vec<String>,
+    pub updated: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+pub struct GreDriver {
+    socket: OwnedFd,
+    seq: u32,
+    prefix: String,
+}
+
+impl GreDriver {
+    /// A driver managing the interfaces named `prefix` and something, in the network namespace
+    /// of the calling thread.
+    pub fn new(prefix: &str) -> io::Result<Self> {
+        let fd = unsafe { libc::socket(libc::AF_NETLINK, libc::SOCK_RAW | libc::SOCK_CLOEXEC, libc::NETLINK_ROUTE) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(GreDriver {
+            socket: unsafe { OwnedFd::from_raw_fd(fd) },
+            seq: 0,
+            prefix: prefix.to_string(),
+        })
+    }
+
+    /// Every interface of the host.
+    pub fn links(&mut self) -> io::Result<Vec<Link>> {
+        let mut links = Vec::new();
+        self.request(message(RTM_GETLINK, NLM_F_DUMP, 0, false), |kind, payload| {
+            if kind == RTM_NEWLINK {
+                links.extend(parse_link(payload));
+            }
+        })?;
+
+        Ok(links)
+    }
+
+    /// The interfaces named with the prefix.
+    pub fn managed(&mut self) -> io::Result<Vec<Link>> {
+        let prefix = self.prefix.clone();
+        Ok(self.links()?.into_iter().filter(|l| l.name.starts_with(&prefix)).collect())
+    }
+
+    /// Makes the managed interfaces exactly `desired`: interfaces that are not desired are
+    /// removed, missing ones created, and ones that differ changed in place, or replaced when
+    /// they are of another kind. Stops at the first error.
+    pub fn apply(&mut self, desired: &[GreInterface]) -> io::Result<Applied> {
+        for interface in desired {
+            if !interface.name.starts_with(&self.prefix) || interface.name.len() > MAX_NAME_LEN {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "{} is not a name starting with {} of at most {} bytes",
+                        interface.name, self.prefix, MAX_NAME_LEN
+                    ),
+                ));
+            }
+        }
+
+        let mut applied = Applied::default();
+        let existing = self.managed()?;
+
+        for link in &existing {
+            if !desired.iter().any(|i| i.name == link.name) {
+                self.delete(link.index)?;
+                applied.removed.push(link.name.clone());
+            }
+        }
+
+        for interface in desired {
+            match existing.iter().find(|l| l.name == interface.name) {
+                None => {
+                    self.create(interface)?;
+                    applied.created.push(interface.name.clone());
+                }
+                Some(link) if link.matches(interface) => {}
+                Some(link) if link.kind.as_deref() == Some(interface.kind.name()) => {
+                    let mut msg = message(RTM_NEWLINK, 0, link.index, true);
+                    interface_attrs(&mut msg, interface);
+                    self.request(msg, |_, _| {})?;
+                    applied.updated.push(interface.name.clone());
+                }
+                Some(link) => {
+                    self.delete(link.index)?;
+                    self.create(interface)?;
+                    applied.updated.push(interface.name.clone());
+                }
+            }
+        }
+
+        Ok(applied)
+    }
+
+    fn create(&mut self, interface: &GreInterface) -> io::Result<()> {
+        let mut msg = message(RTM_NEWLINK, NLM_F_CREATE | NLM_F_EXCL, 0, true);
+        attr(&mut msg, IFLA_IFNAME, &[interface.name.as_bytes(), &[0]].concat());
+        interface_attrs(&mut msg, interface);
+        self.request(msg, |_, _| {})
+    }
+
+    fn delete(&mut self, index: i32) -> io::Result<()> {
+        self.request(message(RTM_DELLINK, 0, index, false), |_, _| {})
+    }
+
+    /// Sends `msg` and hands each message of the answer to `each` with its type, until the
+    /// end of a dump or the acknowledgement. An error the kernel answers with is returned.
+    fn request(&mut self, mut msg: Vec<u8>, mut each: impl FnMut(u16, &[u8])) -> io::Result<()> {
+        self.seq = self.seq.wrapping_add(1);
+        let len = msg.len() as u32;
+        msg[0..4].copy_from_slice(&len.to_ne_bytes());
+        msg[8..12].copy_from_slice(&self.seq.to_ne_bytes());
+
+        let fd = self.socket.as_raw_fd();
+        if unsafe { libc::send(fd, msg.as_ptr().cast(), msg.len(), 0) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let n = unsafe { libc::recv(fd, buf.as_mut_ptr().cast(), buf.len(), 0) };
+            if n < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let mut data = &buf[..n as usize];
+            while data.len() >= HEADER_LEN {
+                let len = u32_at(data, 0) as usize;
+                if len < HEADER_LEN || len > data.len() {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated netlink message"));
+                }
+                let kind = u16::from_ne_bytes([data[4], data[5]]);
+                let seq = u32_at(data, 8);
+                let payload = &data[HEADER_LEN..len];
+                data = &data[align(len).min(data.len())..];
+
+                if seq != self.seq {
+                    continue;
+                }
+                match kind {
+                    NLMSG_DONE => return Ok(()),
+                    NLMSG_ERROR if payload.len() >= 4 => {
+                        return match i32::from_ne_bytes(payload[..4].try_into().unwrap()) {
+                            0 => Ok(()),
+                            errno => Err(io::Error::from_raw_os_error(-errno)),
+                        };
+                    }
+                    _ => each(kind, payload),
+                }
+            }
+        }
+    }
+}
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn u32_at(data: &[u8], at: usize) -> u32 {
+    u32::from_ne_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+/// A request header and `struct ifinfomsg` for the interface `index`, 0 for none, bringing it
+/// up if `up`. The length and sequence number are filled in when it is sent.
+fn message(kind: u16, flags: u16, index: i32, up: bool) -> Vec<u8> {
+    let mut msg = vec![0; HEADER_LEN + IFINFO_LEN];
+    msg[4..6].copy_from_slice(&kind.to_ne_bytes());
+    let flags = flags | NLM_F_REQUEST | if flags & NLM_F_DUMP == 0 { NLM_F_ACK } else { 0 };
+    msg[6..8].copy_from_slice(&flags.to_ne_bytes());
+    msg[HEADER_LEN..HEADER_LEN + 2].copy_from_slice(&(libc::AF_UNSPEC as u16).to_ne_bytes());
+    msg[HEADER_LEN + 4..HEADER_LEN + 8].copy_from_slice(&index.to_ne_bytes());
+    if up {
+        msg[HEADER_LEN + 8..HEADER_LEN + 12].copy_from_slice(&IFF_UP.to_ne_bytes());
+        msg[HEADER_LEN + 12..HEADER_LEN + 16].copy_from_slice(&IFF_UP.to_ne_bytes());
+    }
+    msg
+}
+
+fn attr(msg: &mut Vec<u8>, kind: u16, data: &[u8]) {
+    msg.extend_from_slice(&((4 + data.len()) as u16).to_ne_bytes());
+    msg.extend_from_slice(&kind.to_ne_bytes());
+    msg.extend_from_slice(data);
+    msg.resize(align(msg.len()), 0);
+}
+
+/// An attribute holding the attributes `nest` adds.
+fn nested(msg: &mut Vec<u8>, kind: u16, nest: impl FnOnce(&mut Vec<u8>)) {
+    let start = msg.len();
+    attr(msg, kind, &[]);
+    nest(msg);
+    let len = (msg.len() - start) as u16;
+    msg[start..start + 2].copy_from_slice(&len.to_ne_bytes());
+}
+
+fn address_bytes(address: &IpAddr) -> Vec<u8> {
+    match address {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    }
+}
+
+/// The MTU and link info of `interface`.
+fn interface_attrs(msg: &mut Vec<u8>, interface: &GreInterface) {
+    attr(msg, IFLA_MTU, &interface.mtu.to_ne_bytes());
+    nested(msg, IFLA_LINKINFO, |msg| {
+        attr(msg, IFLA_INFO_KIND, interface.kind.name().as_bytes());
+        nested(msg, IFLA_INFO_DATA, |msg| {
+            if let Some(local) = &interface.local {
+                attr(msg, IFLA_GRE_LOCAL, &address_bytes(local));
+            }
+            attr(msg, IFLA_GRE_REMOTE, &address_bytes(&interface.remote));
+            attr(msg, IFLA_GRE_TTL, &[interface.ttl]);
+        });
+    });
+}
+
+/// The attributes in `data`, by type.
+fn attrs(mut data: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut attrs = Vec::new();
+    while data.len() >= 4 {
+        let len = u16::from_ne_bytes([data[0], data[1]]) as usize;
+        if len < 4 || len > data.len() {
+            break;
+        }
+        attrs.push((u16::from_ne_bytes([data[2], data[3]]) & NLA_TYPE_MASK, &data[4..len]));
+        data = &data[align(len).min(data.len())..];
+    }
+    attrs
+}
+
+/// An address attribute; the unspecified address, which is how any address is reported, is `None`.
+fn parse_address(data: &[u8]) -> Option<IpAddr> {
+    let address = match data.len() {
+        4 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(data).ok()?)),
+        16 => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(data).ok()?)),
+        _ => return None,
+    };
+    Some(address).filter(|a| !a.is_unspecified())
+}
+
+fn parse_link(payload: &[u8]) -> Option<Link> {
+    if payload.len() < IFINFO_LEN {
+        return None;
+    }
+    let mut link = Link {
+        index: i32::from_ne_bytes(payload[4..8].try_into().unwrap()),
+        name: String::new(),
+        kind: None,
+        mtu: 0,
+        up: u32_at(payload, 8) & IFF_UP != 0,
+        local: None,
+        remote: None,
+        ttl: None,
+    };
+
+    for (kind, data) in attrs(&payload[IFINFO_LEN..]) {
+        match kind {
+            IFLA_IFNAME => link.name = String::from_utf8_lossy(data).trim_end_matches('\0').to_string(),
+            IFLA_MTU if data.len() == mem::size_of::<u32>() => link.mtu = u32_at(data, 0),
+            IFLA_LINKINFO => {
+                for (kind, data) in attrs(data) {
+                    match kind {
+                        IFLA_INFO_KIND => {
+                            link.kind = Some(String::from_utf8_lossy(data).trim_end_matches('\0').to_string())
+                        }
+                        IFLA_INFO_DATA => {
+                            for (kind, data) in attrs(data) {
+                                match kind {
+                                    IFLA_GRE_LOCAL => link.local = parse_address(data),
+                                    IFLA_GRE_REMOTE => link.remote = parse_address(data),
+                                    IFLA_GRE_TTL => link.ttl = data.first().copied(),
+                                    _ => {}
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Some(link)
+}
@@ -0,0 +1,869 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+use std::net::{IpAddr, Ipv4Addr};
+
+use crate::api::InterfaceDrift;
+use crate::config::RenderConfig;
+use crate::storage::routers::Router;
+use crate::storage::tunnels::Tunnel;
+
+/// The pre-shared keys of IPSec peerings, by [`psk_pair`].
+pub type Psks = HashMap<(i32, i32), String>;
+
+/// The WireGuard identity of an endpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WireGuardEndpoint {
+    /// Unknown when it could not be decrypted.
+    pub private_key: Option<String>,
+    pub public_key: String,
+    /// Its address inside the tunnels.
+    pub address: Ipv4Addr,
+    pub listen_port: u16,
+}
+
+/// The key material tunnels are rendered with.
+#[derive(Debug, Clone, Default)]
+pub struct Keys {
+    pub psks: Psks,
+    /// By tunnel id.
+    pub wireguard: HashMap<i32, WireGuardEndpoint>,
+}
+
+/// Marks the start of each `wg-quick` config in a rendered PyDECnet config, followed by the name
+/// of the interface.
+pub const WG_QUICK_MARKER: &str = "# wg-quick ";
+
+/// One kind of router the manager renders configuration for. Agents bring the transports that
+/// push it; the server only needs to know how the configuration looks.
+pub trait Renderer: Sync {
+    /// Its name in `routers.router_type`.
+    fn router_type(&self) -> &'static str;
+
+    fn description(&self) -> &'static str;
+
+    /// The `routers.conn_type`s agents reach such routers with.
+    fn conn_types(&self) -> &'static [&'static str];
+
+    /// Whether such routers can terminate `tunnel_type` tunnels.
+    fn supports(&self, tunnel_type: &str) -> bool;
+
+    /// The configuration of `router` for its `links`, removing the interfaces to the `removed`
+    /// peers.
+    fn render(
+        &self,
+        router: &Router,
+        links: &[(&Tunnel, &Tunnel)],
+        removed: &[i32],
+        keys: &Keys,
+        defaults: &RenderConfig,
+    ) -> Result<String, String>;
+
+    /// The lines of each interface the manager owns in `config`, in any order.
+    fn managed_interfaces(&self, config: &str) -> BTreeMap<String, Vec<String>>;
+
+    /// Writes the interface `name` back out as config.
+    fn write_interface(&self, out: &mut String, _name: &str, lines: &[String]) {
+        for line in lines {
+            writeln!(out, "{}", line).unwrap();
+        }
+    }
+
+    /// Whether an interface found on the router, with `lines`, is the manager's even though it no
+    /// longer renders it, rather than one configured by hand.
+    fn owns(&self, _lines: &[String]) -> bool {
+        true
+    }
+}
+
+/// Every router type the server renders for. Each is added to the `router_types` lookup table
+/// at startup, with its connection types, so a new one needs no migration.
+pub static RENDERERS: &[&dyn Renderer] = &[&Cisco, &PyDecnet, &MikroTik];
+
+/// The renderer of `router_type`, if the server has one.
+pub fn renderer(router_type: &str) -> Option<&'static dyn Renderer> {
+    RENDERERS.iter().copied().find(|r| r.router_type() == router_type)
+}
+
+/// Whether routers of `router_type` can terminate `tunnel_type` tunnels: IOS and RouterOS have
+/// no WireGuard here, and PyDECnet hosts no IPSec. Router types without a renderer take anything.
+pub fn supports(router_type: &str, tunnel_type: &str) -> bool {
+    renderer(router_type).is_none_or(|r| r.supports(tunnel_type))
+}
+
+/// Whether the tunnel endpoints `a` and `b`, on different routers, should be connected: same
+/// address family and tunnel type, and topologies that pair up. Hubs connect to everyone, mesh
+/// members to each other and to hubs, spokes only to hubs.
+pub fn peers(a: &Tunnel, b: &Tunnel) -> bool {
+    let topologies = match (a.topology_type.as_str(), b.topology_type.as_str()) {
+        ("hub", _) | (_, "hub") => true,
+        (x, y) => x == "mesh" && y == "mesh",
+    };
+
+    a.router != b.router && a.ip_class == b.ip_class && a.tunnel_type == b.tunnel_type && topologies
+}
+
+/// The peers `router` gets a tunnel interface for, each with the local endpoint it is reached
+/// from. `tunnels` is every tunnel in the mesh, the router's own included. A peer reachable from
+/// more than one local endpoint is only connected once, from the first.
+pub fn links<'a>(router: &Router, tunnels: &'a [Tunnel]) -> Vec<(&'a Tunnel, &'a Tunnel)> {
+    let mut seen = HashSet::new();
+    let mut links = Vec::new();
+
+    for local in tunnels.iter().filter(|t| t.router == router.id) {
+        for peer in tunnels.iter().filter(|peer| peers(local, peer)) {
+            if seen.insert(peer.id) {
+                links.push((local, peer));
+            }
+        }
+    }
+
+    links.sort_by_key(|(_, peer)| peer.id);
+    links
+}
+
+/// Checks the fields of a tunnel endpoint that other routers' configs are rendered from, where
+/// a line break in any of them would start a command of its own: `ip` must be an address (or
+/// empty while that of a dynamic endpoint is not known), `hostname` a DNS name, and
+/// `description` and `source` free of control characters.
+pub fn check_endpoint(ip: &str, dynamic_ip: bool, hostname: &str, description: &str, source: &str) -> Result<(), String> {
+    if !(ip.is_empty() && dynamic_ip) {
+        check_ip(ip)?;
+    }
+    check_hostname(hostname)?;
+    check_text("description", description)?;
+    check_text("source", source)
+}
+
+pub fn check_ip(ip: &str) -> Result<(), String> {
+    match ip.parse::<IpAddr>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("ip {:?} is not an IP address", ip)),
+    }
+}
+
+/// Fails unless `hostname` is a DNS name: dot separated labels of letters, digits and inner
+/// hyphens, at most 63 characters each and 253 in all. Addresses, which tunnels imported without
+/// a hostname have instead, pass too.
+pub fn check_hostname(hostname: &str) -> Result<(), String> {
+    let name = hostname.strip_suffix('.').unwrap_or(hostname);
+    let valid = hostname.parse::<IpAddr>().is_ok()
+        || !name.is_empty()
+        && name.len() <= 253
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+
+    match valid {
+        true => Ok(()),
+        false => Err(format!("hostname {:?} is not a valid DNS name", hostname)),
+    }
+}
+
+pub fn check_text(field: &str, value: &str) -> Result<(), String> {
+    match value.chars().any(char::is_control) {
+        true => Err(format!("{} {:?} contains control characters", field, value)),
+        false => Ok(()),
+    }
+}
+
+/// Identifies the peering of the endpoints `a` and `b` the same way from both ends: their ids,
+/// lowest first.
+pub fn psk_pair(a: &Tunnel, b: &Tunnel) -> (i32, i32) {
+    (a.id.min(b.id), a.id.max(b.id))
+}
+
+/// Renders the configuration `router` needs to join the mesh: one tunnel to every peer, named
+/// after the peer's tunnel index so the interface is the same on every router. `pushed` are the
+/// peers the router was last pushed an interface for; those it no longer links to are removed.
+/// IPSec peerings are protected with their key from `keys`, and WireGuard ones set up with the
+/// identities of both ends from there; neither can be rendered without them.
+pub fn render(
+    router: &Router,
+    tunnels: &[Tunnel],
+    pushed: &[i32],
+    keys: &Keys,
+    defaults: &RenderConfig,
+) -> Result<String, String> {
+    let links = links(router, tunnels);
+    let mut removed: Vec<i32> = pushed
+        .iter()
+        .filter(|p| !links.iter().any(|(_, peer)| peer.id == **p))
+        .copied()
+        .collect();
+    removed.sort_unstable();
+    removed.dedup();
+
+    // Refused here too, for rows written before the API checked them.
+    for tunnel in links.iter().flat_map(|(local, peer)| [*local, *peer]) {
+        check_endpoint(&tunnel.ip, tunnel.dynamic_ip, &tunnel.hostname, &tunnel.description, &tunnel.source)
+            .map_err(|err| format!("tunnel {} cannot be rendered: {}", tunnel.id, err))?;
+    }
+
+    match router.router_type.as_deref() {
+        Some(router_type) => match renderer(router_type) {
+            Some(renderer) => renderer.render(router, &links, &removed, keys, defaults),
+            None => Err(format!("cannot render configuration for router type {}", router_type)),
+        },
+        None => Err(format!("router {} has no router_type", router.id)),
+    }
+}
+
+/// The peer tunnels a rendered config has an interface for, from their names.
+pub fn configured_peers(router_type: &str, config: &str) -> Vec<i32> {
+    managed_interfaces(router_type, config)
+        .keys()
+        .filter_map(|name| name.strip_prefix("Tunnel").or_else(|| name.strip_prefix("gre-"))?.parse().ok())
+        .collect()
+}
+
+/// Splits a rendered PyDECnet config into the PyDECnet part and the `wg-quick` config of each
+/// WireGuard interface, by name, for the agent to write to `/etc/wireguard/NAME.conf`.
+pub fn wg_quick_configs(config: &str) -> (String, BTreeMap<String, String>) {
+    let mut pydecnet = String::new();
+    let mut configs = BTreeMap::new();
+    let mut current: Option<(String, String)> = None;
+
+    for line in config.lines() {
+        if let Some(name) = line.strip_prefix(WG_QUICK_MARKER) {
+            configs.extend(current.take());
+            current = Some((name.trim().to_string(), String::new()));
+            continue;
+        }
+        let out = match &mut current {
+            Some((_, out)) => out,
+            None => &mut pydecnet,
+        };
+        writeln!(out, "{}", line).unwrap();
+    }
+    configs.extend(current);
+
+    (pydecnet, configs)
+}
+
+/// Identifies a rendered configuration: the hex SHA-256 of its text. Agents report it with every
+/// push, so the server can tell which routers run the configuration they should.
+pub fn config_hash(config: &str) -> String {
+    ring::digest::digest(&ring::digest::SHA256, config.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// The parts of a router's config the tunnel manager owns, by interface: the `interface TunnelN`
+/// blocks of a Cisco config, the `circuit gre-N` lines of a PyDECnet one, the `TunnelN` GRE
+/// interfaces of a RouterOS one. Lines come with their whitespace collapsed and sorted, since
+/// routers keep their own order within an interface.
+pub fn managed_interfaces(router_type: &str, config: &str) -> BTreeMap<String, Vec<String>> {
+    let mut interfaces = renderer(router_type).map(|r| r.managed_interfaces(config)).unwrap_or_default();
+
+    for lines in interfaces.values_mut() {
+        lines.sort();
+    }
+    interfaces
+}
+
+/// Only the managed interfaces of `config`, as [`managed_interfaces`] finds them, written back
+/// out as config.
+pub fn managed_config(router_type: &str, config: &str) -> String {
+    let mut out = String::new();
+
+    if let Some(renderer) = renderer(router_type) {
+        for (name, lines) in managed_interfaces(router_type, config) {
+            renderer.write_interface(&mut out, &name, &lines);
+        }
+    }
+
+    out
+}
+
+/// How the managed interfaces of a router's `running` config differ from its `desired` one:
+/// `missing` from the router, `changed` on it, or `unexpected` there when the manager no longer
+/// renders them. Interfaces the router type's renderer does not think are the manager's, like
+/// Cisco tunnels without a `HECnet:` description, were configured by hand and are left alone.
+pub fn drift(router_type: &str, desired: &str, running: &str) -> Vec<InterfaceDrift> {
+    let mut desired = managed_interfaces(router_type, desired);
+    let running = managed_interfaces(router_type, running);
+    let mut drift = Vec::new();
+
+    for (name, running_lines) in running {
+        let (kind, desired_lines) = match desired.remove(&name) {
+            Some(desired_lines) if desired_lines == running_lines => continue,
+            Some(desired_lines) => ("changed", desired_lines),
+            None if renderer(router_type).is_some_and(|r| !r.owns(&running_lines)) => continue,
+            None => ("unexpected", Vec::new()),
+        };
+
+        drift.push(InterfaceDrift {
+            interface: name,
+            kind: kind.to_string(),
+            desired: desired_lines.join("\n"),
+            running: running_lines.join("\n"),
+        });
+    }
+
+    drift.extend(desired.into_iter().map(|(name, lines)| InterfaceDrift {
+        interface: name,
+        kind: "missing".to_string(),
+        desired: lines.join("\n"),
+        running: String::new(),
+    }));
+    drift.sort_by(|a, b| a.interface.cmp(&b.interface));
+    drift
+}
+
+/// A unified diff of `old` against `new` with three lines of context, empty when they are equal.
+pub fn unified_diff(old_name: &str, new_name: &str, old: &str, new: &str) -> String {
+    const CONTEXT: usize = 3;
+
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+
+    // lcs[i][j] is the length of the longest common subsequence of a[i..] and b[j..].
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = match a[i] == b[j] {
+                true => lcs[i + 1][j + 1] + 1,
+                false => lcs[i + 1][j].max(lcs[i][j + 1]),
+            };
+        }
+    }
+
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            ops.push((' ', a[i]));
+            i += 1;
+            j += 1;
+        } else if i < a.len() && (j == b.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(('-', a[i]));
+            i += 1;
+        } else {
+            ops.push(('+', b[j]));
+            j += 1;
+        }
+    }
+
+    let changes: Vec<usize> = (0..ops.len()).filter(|&k| ops[k].0 != ' ').collect();
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    let mut out = String::new();
+    writeln!(out, "--- {}", old_name).unwrap();
+    writeln!(out, "+++ {}", new_name).unwrap();
+
+    let mut k = 0;
+    while k < changes.len() {
+        // Changes closer than twice the context share a hunk.
+        let first = changes[k];
+        let mut last = first;
+        while k + 1 < changes.len() && changes[k + 1] - last <= 2 * CONTEXT + 1 {
+            k += 1;
+            last = changes[k];
+        }
+        k += 1;
+
+        let hunk = &ops[first.saturating_sub(CONTEXT)..(last + CONTEXT + 1).min(ops.len())];
+        let before = &ops[..first.saturating_sub(CONTEXT)];
+        let old_line = before.iter().filter(|(op, _)| *op != '+').count();
+        let new_line = before.iter().filter(|(op, _)| *op != '-').count();
+        let old_count = hunk.iter().filter(|(op, _)| *op != '+').count();
+        let new_count = hunk.iter().filter(|(op, _)| *op != '-').count();
+
+        // An empty side is numbered after the line it would follow, like diff -u does.
+        writeln!(
+            out,
+            "@@ -{},{} +{},{} @@",
+            old_line + usize::from(old_count > 0),
+            old_count,
+            new_line + usize::from(new_count > 0),
+            new_count
+        )
+        .unwrap();
+        for (op, line) in hunk {
+            writeln!(out, "{}{}", op, line).unwrap();
+        }
+    }
+
+    out
+}
+
+/// The address to reach a peer on: its IP, or its hostname when the address changes and
+/// neither its agent nor the server's resolver has found the current one yet.
+pub(crate) fn destination(peer: &Tunnel) -> &str {
+    match (peer.dynamic_ip, peer.ip_reported_at, peer.ip_resolved_at) {
+        (true, None, None) => &peer.hostname,
+        _ => &peer.ip,
+    }
+}
+
+/// The IKEv2 keyring and profile and the IPSec profile of one IPSec peering, all named `name`.
+/// Peers still known by hostname are matched on it as their IKE identity.
+fn render_cisco_crypto(out: &mut String, name: &str, peer: &Tunnel, psk: &str) {
+    let destination = destination(peer);
+    let (address, identity) = match destination.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => (format!("address {}", ip), format!("address {} 255.255.255.255", ip)),
+        Ok(IpAddr::V6(ip)) => (format!("address {}/128", ip), format!("address ipv6 {}/128", ip)),
+        Err(_) => (format!("identity fqdn {}", destination), format!("fqdn {}", destination)),
+    };
+
+    writeln!(out, "crypto ikev2 keyring {}", name).unwrap();
+    writeln!(out, " peer tunnel-{}", peer.id).unwrap();
+    writeln!(out, "  {}", address).unwrap();
+    writeln!(out, "  pre-shared-key {}", psk).unwrap();
+    writeln!(out, " !").unwrap();
+    writeln!(out, "!").unwrap();
+    writeln!(out, "crypto ikev2 profile {}", name).unwrap();
+    writeln!(out, " match identity remote {}", identity).unwrap();
+    writeln!(out, " authentication remote pre-share").unwrap();
+    writeln!(out, " authentication local pre-share").unwrap();
+    writeln!(out, " keyring local {}", name).unwrap();
+    writeln!(out, "!").unwrap();
+    writeln!(out, "crypto ipsec profile {}", name).unwrap();
+    writeln!(out, " set ikev2-profile {}", name).unwrap();
+    writeln!(out, "!").unwrap();
+}
+
+fn render_cisco(
+    router: &Router,
+    links: &[(&Tunnel, &Tunnel)],
+    removed: &[i32],
+    psks: &Psks,
+    defaults: &RenderConfig,
+) -> Result<String, String> {
+    let mut out = String::new();
+    writeln!(out, "! HECnet tunnels for router {}, rendered by the tunnel manager.", router.id).unwrap();
+    writeln!(out, "!").unwrap();
+
+    for peer in removed {
+        writeln!(out, "no interface Tunnel{}", peer).unwrap();
+        writeln!(out, "!").unwrap();
+    }
+
+    // The crypto blocks come first, since the interfaces refer to them.
+    for (local, peer) in links.iter().filter(|(local, _)| local.tunnel_type == "IPSec") {
+        let (a, b) = psk_pair(local, peer);
+        let psk = psks.get(&(a, b)).ok_or_else(|| {
+            format!("no usable pre-shared key for tunnels {} and {} (is render.psk_secret set?)", a, b)
+        })?;
+        render_cisco_crypto(&mut out, &format!("{}-Tunnel{}", defaults.ipsec_profile, peer.id), peer, psk);
+    }
+
+    for (local, peer) in links {
+        if local.tunnel_type == "WireGuard" {
+            writeln!(out, "! Tunnel {} to {} skipped: IOS does not support WireGuard.", peer.id, peer.hostname).unwrap();
+            writeln!(out, "!").unwrap();
+            continue;
+        }
+
+        writeln!(out, "interface Tunnel{}", peer.id).unwrap();
+        writeln!(out, " description HECnet: {} ({})", peer.hostname, peer.description).unwrap();
+        writeln!(out, " no ip address").unwrap();
+        writeln!(out, " decnet cost {}", local.cost).unwrap();
+        writeln!(out, " tunnel source {}", local.source).unwrap();
+        writeln!(out, " tunnel destination {}", destination(peer)).unwrap();
+        writeln!(out, " tunnel mode gre {}", if local.ip_class == 6 { "ipv6" } else { "ip" }).unwrap();
+        if let Some(keepalive) = defaults.keepalive_secs {
+            writeln!(out, " keepalive {} 3", keepalive).unwrap();
+        }
+        if local.tunnel_type == "IPSec" {
+            writeln!(out, " tunnel protection ipsec profile {}-Tunnel{}", defaults.ipsec_profile, peer.id).unwrap();
+        }
+        writeln!(out, "!").unwrap();
+    }
+
+    Ok(out)
+}
+
+/// How a WireGuard peer is reached, for `Endpoint =`: its address or hostname and the port its
+/// interface for the local endpoint listens on.
+fn wireguard_endpoint(peer: &Tunnel, port: u16) -> String {
+    match destination(peer).parse::<IpAddr>() {
+        Ok(IpAddr::V6(ip)) => format!("[{}]:{}", ip, port),
+        _ => format!("{}:{}", destination(peer), port),
+    }
+}
+
+fn render_pydecnet(
+    router: &Router,
+    links: &[(&Tunnel, &Tunnel)],
+    removed: &[i32],
+    wireguard: &HashMap<i32, WireGuardEndpoint>,
+) -> Result<String, String> {
+    let mut out = String::new();
+    writeln!(out, "# HECnet tunnels for router {}, rendered by the tunnel manager.", router.id).unwrap();
+
+    // PyDECnet drops circuits that are no longer in its config when it restarts.
+    for peer in removed {
+        writeln!(out, "# Circuit gre-{} removed: the peering is gone.", peer).unwrap();
+    }
+
+    let identity = |tunnel: &Tunnel| {
+        wireguard
+            .get(&tunnel.id)
+            .ok_or_else(|| format!("no WireGuard keys for tunnel {} (is render.psk_secret set?)", tunnel.id))
+    };
+
+    // WireGuard peerings are GRE circuits between the ends' addresses inside the WireGuard
+    // tunnel, so PyDECnet sees them like any other.
+    let mut wg_peers: BTreeMap<i32, Vec<(&Tunnel, &WireGuardEndpoint)>> = BTreeMap::new();
+    for (local, peer) in links {
+        let (destination, source) = match local.tunnel_type.as_str() {
+            "GRE" => (destination(peer).to_string(), local.source.clone()),
+            "WireGuard" => {
+                let (local_identity, peer_identity) = (identity(local)?, identity(peer)?);
+                wg_peers.entry(local.id).or_default().push((peer, peer_identity));
+                (peer_identity.address.to_string(), local_identity.address.to_string())
+            }
+            _ => {
+                writeln!(out, "# Tunnel {} to {} skipped: PyDECnet does not support {}.", peer.id, peer.hostname, local.tunnel_type).unwrap();
+                continue;
+            }
+        };
+
+        writeln!(
+            out,
+            "circuit gre-{} GRE {} --source {} --cost {}",
+            peer.id,
+            destination,
+            source,
+            local.cost
+        )
+        .unwrap();
+    }
+
+    // One interface per local endpoint, with its peers. Each end listens on its own port, which
+    // is where the other end sends to.
+    for (local_id, peers) in wg_peers {
+        let local = links.iter().map(|(local, _)| *local).find(|l| l.id == local_id).unwrap();
+        let identity = identity(local)?;
+        let private_key = identity.private_key.as_deref().ok_or_else(|| {
+            format!("the WireGuard private key of tunnel {} cannot be decrypted", local.id)
+        })?;
+
+        writeln!(out, "{}wg{}", WG_QUICK_MARKER, local.id).unwrap();
+        writeln!(out, "[Interface]").unwrap();
+        writeln!(out, "PrivateKey = {}", private_key).unwrap();
+        writeln!(out, "Address = {}/32", identity.address).unwrap();
+        writeln!(out, "ListenPort = {}", identity.listen_port).unwrap();
+        for (peer, peer_identity) in peers {
+            writeln!(out).unwrap();
+            writeln!(out, "[Peer]").unwrap();
+            // Not the description: wg-quick has no quoting for free text.
+            writeln!(out, "# {} (tunnel {})", peer.hostname, peer.id).unwrap();
+            writeln!(out, "PublicKey = {}", peer_identity.public_key).unwrap();
+            writeln!(out, "Endpoint = {}", wireguard_endpoint(peer, peer_identity.listen_port)).unwrap();
+            writeln!(out, "AllowedIPs = {}/32", peer_identity.address).unwrap();
+            // Keeps the NAT mapping of an endpoint behind a residential line open.
+            if local.dynamic_ip {
+                writeln!(out, "PersistentKeepalive = 25").unwrap();
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+struct Cisco;
+
+impl Renderer for Cisco {
+    fn router_type(&self) -> &'static str {
+        "Cisco"
+    }
+
+    fn description(&self) -> &'static str {
+        "Cisco IOS"
+    }
+
+    fn conn_types(&self) -> &'static [&'static str] {
+        &["SNMP", "SSH"]
+    }
+
+    fn supports(&self, tunnel_type: &str) -> bool {
+        tunnel_type != "WireGuard"
+    }
+
+    fn render(
+        &self,
+        router: &Router,
+        links: &[(&Tunnel, &Tunnel)],
+        removed: &[i32],
+        keys: &Keys,
+        defaults: &RenderConfig,
+    ) -> Result<String, String> {
+        render_cisco(router, links, removed, &keys.psks, defaults)
+    }
+
+    fn managed_interfaces(&self, config: &str) -> BTreeMap<String, Vec<String>> {
+        let mut interfaces = BTreeMap::new();
+        let mut current: Option<(String, Vec<String>)> = None;
+
+        for line in config.lines() {
+            let line = line.trim_end();
+            match &mut current {
+                Some((_, lines)) if line.starts_with(' ') => lines.push(normalize(line)),
+                _ => {
+                    interfaces.extend(current.take());
+                    if line.starts_with("interface Tunnel") {
+                        current = Some((normalize(&line["interface ".len()..]), Vec::new()));
+                    }
+                }
+            }
+        }
+        interfaces.extend(current);
+
+        interfaces
+    }
+
+    fn write_interface(&self, out: &mut String, name: &str, lines: &[String]) {
+        writeln!(out, "interface {}", name).unwrap();
+        for line in lines {
+            writeln!(out, " {}", line).unwrap();
+        }
+        writeln!(out, "!").unwrap();
+    }
+
+    fn owns(&self, lines: &[String]) -> bool {
+        lines.iter().any(|l| l.starts_with("description HECnet:"))
+    }
+}
+
+struct PyDecnet;
+
+impl Renderer for PyDecnet {
+    fn router_type(&self) -> &'static str {
+        "PyDECNet"
+    }
+
+    fn description(&self) -> &'static str {
+        "PyDECnet on Linux"
+    }
+
+    fn conn_types(&self) -> &'static [&'static str] {
+        &["SSH"]
+    }
+
+    fn supports(&self, tunnel_type: &str) -> bool {
+        tunnel_type != "IPSec"
+    }
+
+    fn render(
+        &self,
+        router: &Router,
+        links: &[(&Tunnel, &Tunnel)],
+        removed: &[i32],
+        keys: &Keys,
+        _defaults: &RenderConfig,
+    ) -> Result<String, String> {
+        render_pydecnet(router, links, removed, &keys.wireguard)
+    }
+
+    fn managed_interfaces(&self, config: &str) -> BTreeMap<String, Vec<String>> {
+        let mut interfaces = BTreeMap::new();
+
+        for line in config.lines().map(normalize) {
+            if let Some(name) = line.strip_prefix("circuit ").and_then(|rest| rest.split(' ').next()) {
+                if name.starts_with("gre-") {
+                    interfaces.insert(name.to_string(), vec![line.clone()]);
+                }
+            }
+        }
+
+        interfaces
+    }
+}
+
+struct MikroTik;
+
+impl Renderer for MikroTik {
+    fn router_type(&self) -> &'static str {
+        "MikroTik"
+    }
+
+    fn description(&self) -> &'static str {
+        "MikroTik RouterOS"
+    }
+
+    fn conn_types(&self) -> &'static [&'static str] {
+        &["SSH"]
+    }
+
+    fn supports(&self, tunnel_type: &str) -> bool {
+        tunnel_type != "WireGuard"
+    }
+
+    fn render(
+        &self,
+        router: &Router,
+        links: &[(&Tunnel, &Tunnel)],
+        removed: &[i32],
+        keys: &Keys,
+        defaults: &RenderConfig,
+    ) -> Result<String, String> {
+        render_mikrotik(router, links, removed, &keys.psks, defaults)
+    }
+
+    fn managed_interfaces(&self, config: &str) -> BTreeMap<String, Vec<String>> {
+        let mut interfaces: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        let mut section = String::new();
+
+        // Exports wrap long commands with a backslash at the end of the line.
+        let config = config.replace("\\\r\n", "").replace("\\\n", "");
+        for line in config.lines().map(str::trim) {
+            if line.starts_with('/') {
+                section = normalize(line);
+                continue;
+            }
+            if section != "/interface gre" && section != "/interface gre6" {
+                continue;
+            }
+
+            let (name, params) = match line.strip_prefix("set [find name=") {
+                Some(rest) => match rest.split_once(']') {
+                    Some((name, params)) => (Some(name.to_string()), routeros_params(params)),
+                    None => continue,
+                },
+                None => match line.strip_prefix("add ") {
+                    Some(params) => (None, routeros_params(params)),
+                    None => continue,
+                },
+            };
+            let name = match name.or_else(|| {
+                params.iter().find_map(|p| p.strip_prefix("name=")).map(|n| n.trim_matches('"').to_string())
+            }) {
+                Some(name) if name.starts_with("Tunnel") => name,
+                _ => continue,
+            };
+
+            interfaces
+                .entry(name)
+                .or_default()
+                .extend(params.into_iter().filter(|p| !p.starts_with("name=")));
+        }
+
+        interfaces
+    }
+
+    fn write_interface(&self, out: &mut String, name: &str, lines: &[String]) {
+        let v6 = lines.iter().any(|l| l.starts_with("remote-address=") && l.contains(':'));
+        writeln!(out, "/interface {}", if v6 { "gre6" } else { "gre" }).unwrap();
+        writeln!(out, "set [find name={}] {}", name, lines.join(" ")).unwrap();
+    }
+
+    fn owns(&self, lines: &[String]) -> bool {
+        lines
+            .iter()
+            .any(|l| l.starts_with("comment=\"HECnet:") || l.starts_with("comment=HECnet:"))
+    }
+}
+
+/// A line with its whitespace collapsed.
+fn normalize(line: &str) -> String {
+    line.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// A RouterOS string, quoted.
+fn routeros_quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | '$') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// The `key=value` parameters of a RouterOS command, split on whitespace outside quotes.
+fn routeros_params(params: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut current = String::new();
+    let (mut quoted, mut escaped) = (false, false);
+
+    for c in params.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    out.push(std::mem::take(&mut current));
+                }
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    if !current.is_empty() {
+        out.push(current);
+    }
+
+    out
+}
+
+/// A RouterOS script: each tunnel is added if it does not exist yet and then set, so importing
+/// it again changes only what differs. Peers whose address is not known yet are left out, as
+/// RouterOS only takes addresses.
+fn render_mikrotik(
+    router: &Router,
+    links: &[(&Tunnel, &Tunnel)],
+    removed: &[i32],
+    psks: &Psks,
+    defaults: &RenderConfig,
+) -> Result<String, String> {
+    let mut out = String::new();
+    writeln!(out, "# HECnet tunnels for router {}, rendered by the tunnel manager.", router.id).unwrap();
+
+    for peer in removed {
+        writeln!(out, "/interface gre remove [find name=Tunnel{}]", peer).unwrap();
+        writeln!(out, "/interface gre6 remove [find name=Tunnel{}]", peer).unwrap();
+    }
+
+    for (local, peer) in links {
+        if local.tunnel_type == "WireGuard" {
+            writeln!(out, "# Tunnel {} to {} skipped: WireGuard is not rendered for RouterOS.", peer.id, peer.hostname).unwrap();
+            continue;
+        }
+        let remote = match destination(peer).parse::<IpAddr>() {
+            Ok(remote) => remote,
+            Err(_) => {
+                writeln!(out, "# Tunnel {} to {} skipped: its address is not known yet.", peer.id, peer.hostname).unwrap();
+                continue;
+            }
+        };
+        let name = format!("Tunnel{}", peer.id);
+
+        let mut params = vec![
+            format!("comment={}", routeros_quote(&format!("HECnet: {} ({})", peer.hostname, peer.description))),
+            format!("remote-address={}", remote),
+        ];
+        if let Ok(source) = local.source.parse::<IpAddr>() {
+            params.push(format!("local-address={}", source));
+        }
+        if let Some(keepalive) = defaults.keepalive_secs {
+            params.push(format!("keepalive={}s,3", keepalive));
+        }
+        if local.tunnel_type == "IPSec" {
+            let (a, b) = psk_pair(local, peer);
+            let psk = psks.get(&(a, b)).ok_or_else(|| {
+                format!("no usable pre-shared key for tunnels {} and {} (is render.psk_secret set?)", a, b)
+            })?;
+            params.push(format!("ipsec-secret={}", routeros_quote(psk)));
+        }
+
+        writeln!(out, "/interface {}", if remote.is_ipv6() { "gre6" } else { "gre" }).unwrap();
+        writeln!(out, ":if ([:len [find name={}]] = 0) do={{ add name={} remote-address={} }}", name, name, remote).unwrap();
+        writeln!(out, "set [find name={}] {}", name, params.join(" ")).unwrap();
+    }
+
+    Ok(out)
+}
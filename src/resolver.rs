@@ -0,0 +1,325 @@
+//! Server-side resolution of dynamic endpoints' hostnames. Each hostname is resolved again once
+//! its answer's TTL runs out, and a new address is fanned out to the routers peering with the
+//! endpoint, the same as when an agent reports one.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::PgConnection;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::{error, info, warn};
+
+use crate::config::ResolverConfig;
+use crate::shutdown::Shutdown;
+use crate::storage::resolutions::AddressResolution;
+
+/// The addresses a hostname resolved to, and how long they can be kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resolution {
+    pub addresses: Vec<IpAddr>,
+    pub ttl: Duration,
+}
+
+/// Looks hostnames up. Blocking; the resolver task calls it off the async runtime.
+pub trait Resolve: fmt::Debug + Send + Sync {
+    /// The IPv6 (`AAAA`) or IPv4 (`A`) addresses of `hostname`.
+    fn resolve(&self, hostname: &str, ipv6: bool) -> Result<Resolution, String>;
+}
+
+/// Asks DNS servers over UDP, in order, until one answers.
+#[derive(Debug, Clone)]
+pub struct DnsResolver {
+    nameservers: Vec<SocketAddr>,
+    timeout: Duration,
+}
+
+impl DnsResolver {
+    pub fn new(nameservers: Vec<SocketAddr>, timeout: Duration) -> DnsResolver {
+        DnsResolver { nameservers, timeout }
+    }
+
+    /// Uses the `nameserver` lines of `/etc/resolv.conf`.
+    pub fn system(timeout: Duration) -> DnsResolver {
+        let nameservers = fs::read_to_string("/etc/resolv.conf")
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("nameserver"))
+            .filter_map(|server| server.trim().parse::<IpAddr>().ok())
+            .map(|ip| SocketAddr::new(ip, 53))
+            .collect();
+
+        DnsResolver::new(nameservers, timeout)
+    }
+
+    fn ask(&self, server: SocketAddr, query: &[u8], id: u16, qtype: u16) -> Result<Resolution, String> {
+        let local = match server {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        };
+        let socket = UdpSocket::bind(local).map_err(|err| err.to_string())?;
+        socket.set_read_timeout(Some(self.timeout)).map_err(|err| err.to_string())?;
+        socket.connect(server).map_err(|err| err.to_string())?;
+        socket.send(query).map_err(|err| err.to_string())?;
+
+        let mut buf = [0; 1232];
+        loop {
+            let len = socket.recv(&mut buf).map_err(|err| err.to_string())?;
+            // Anything else is a late answer to an earlier query, or not an answer at all.
+            if len >= 2 && u16::from_be_bytes([buf[0], buf[1]]) == id {
+                return parse_answer(&buf[..len], qtype);
+            }
+        }
+    }
+}
+
+impl Resolve for DnsResolver {
+    fn resolve(&self, hostname: &str, ipv6: bool) -> Result<Resolution, String> {
+        let qtype = if ipv6 { TYPE_AAAA } else { TYPE_A };
+        let id = rand::random();
+        let query = build_query(id, hostname, qtype)?;
+
+        let mut last_error = "no DNS servers configured".to_string();
+        for server in &self.nameservers {
+            match self.ask(*server, &query, id, qtype) {
+                Ok(resolution) => return Ok(resolution),
+                Err(err) => last_error = format!("{}: {}", server, err),
+            }
+        }
+
+        Err(last_error)
+    }
+}
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+/// A recursive query for the `qtype` records of `hostname`.
+fn build_query(id: u16, hostname: &str, qtype: u16) -> Result<Vec<u8>, String> {
+    let mut query = Vec::with_capacity(hostname.len() + 18);
+    query.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired, one question.
+    query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+
+    for label in hostname.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("{:?} is not a valid hostname", hostname));
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&qtype.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+
+    Ok(query)
+}
+
+/// The `qtype` addresses in a DNS answer, kept for the shortest TTL among its records.
+fn parse_answer(message: &[u8], qtype: u16) -> Result<Resolution, String> {
+    let malformed = || "malformed DNS answer".to_string();
+    let u16_at = |pos: usize| {
+        message
+            .get(pos..pos + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            .ok_or_else(malformed)
+    };
+
+    let flags = u16_at(2)?;
+    match flags & 0x000f {
+        0 => {}
+        3 => return Err("no such host".to_string()),
+        rcode => return Err(format!("DNS server failure (rcode {})", rcode)),
+    }
+
+    let (questions, answers) = (u16_at(4)?, u16_at(6)?);
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = skip_name(message, pos).ok_or_else(malformed)? + 4;
+    }
+
+    let mut addresses = Vec::new();
+    let mut ttl = u32::MAX;
+    for _ in 0..answers {
+        pos = skip_name(message, pos).ok_or_else(malformed)?;
+        let (rtype, class) = (u16_at(pos)?, u16_at(pos + 2)?);
+        let record_ttl = (u32::from(u16_at(pos + 4)?) << 16) | u32::from(u16_at(pos + 6)?);
+        let len = usize::from(u16_at(pos + 8)?);
+        let data = message.get(pos + 10..pos + 10 + len).ok_or_else(malformed)?;
+        pos += 10 + len;
+
+        ttl = ttl.min(record_ttl);
+        match (rtype, class, data.len()) {
+            (TYPE_A, CLASS_IN, 4) => addresses.push(IpAddr::from(<[u8; 4]>::try_from(data).unwrap())),
+            (TYPE_AAAA, CLASS_IN, 16) => addresses.push(IpAddr::from(<[u8; 16]>::try_from(data).unwrap())),
+            _ => {}
+        }
+    }
+
+    if addresses.is_empty() {
+        let family = if qtype == TYPE_AAAA { "IPv6" } else { "IPv4" };
+        return Err(format!("no {} address", family));
+    }
+
+    Ok(Resolution {
+        addresses,
+        ttl: Duration::from_secs(ttl.into()),
+    })
+}
+
+/// The position after the name at `pos`, which may end in a compression pointer.
+fn skip_name(message: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *message.get(pos)? as usize;
+        match len {
+            0 => return Some(pos + 1),
+            len if len & 0xc0 == 0xc0 => return Some(pos + 2),
+            len => pos += 1 + len,
+        }
+    }
+}
+
+/// Answers from a fixed map of hostnames, for tests and for pinning hostnames by hand.
+#[derive(Debug, Default)]
+pub struct StaticResolver {
+    hosts: Mutex<HashMap<String, Vec<IpAddr>>>,
+    ttl: Duration,
+}
+
+impl StaticResolver {
+    pub fn new(ttl: Duration) -> StaticResolver {
+        StaticResolver {
+            hosts: Mutex::new(HashMap::new()),
+            ttl,
+        }
+    }
+
+    /// Has `hostname` resolve to `addresses` from now on; to nothing when they are empty.
+    pub fn set(&self, hostname: &str, addresses: Vec<IpAddr>) {
+        self.hosts.lock().unwrap().insert(hostname.to_string(), addresses);
+    }
+}
+
+impl Resolve for StaticResolver {
+    fn resolve(&self, hostname: &str, ipv6: bool) -> Result<Resolution, String> {
+        let hosts = self.hosts.lock().unwrap();
+        let addresses: Vec<IpAddr> = hosts
+            .get(hostname)
+            .ok_or_else(|| "no such host".to_string())?
+            .iter()
+            .filter(|address| address.is_ipv6() == ipv6)
+            .copied()
+            .collect();
+
+        match addresses.is_empty() {
+            true => Err(format!("no {} address", if ipv6 { "IPv6" } else { "IPv4" })),
+            false => Ok(Resolution {
+                addresses,
+                ttl: self.ttl,
+            }),
+        }
+    }
+}
+
+/// Keeps the IP of every dynamic endpoint named by a hostname up to date.
+#[derive(Debug)]
+pub struct Resolver {
+    pool: Pool<ConnectionManager<PgConnection>>,
+    resolve: Arc<dyn Resolve>,
+    min_ttl: Duration,
+    max_ttl: Duration,
+    /// When each tunnel's hostname is next resolved.
+    due: HashMap<i32, Instant>,
+}
+
+impl Resolver {
+    pub fn new(
+        pool: Pool<ConnectionManager<PgConnection>>,
+        resolve: Arc<dyn Resolve>,
+        config: &ResolverConfig,
+    ) -> Resolver {
+        Resolver {
+            pool,
+            resolve,
+            min_ttl: Duration::from_secs(config.min_ttl_secs),
+            max_ttl: Duration::from_secs(config.max_ttl_secs),
+            due: HashMap::new(),
+        }
+    }
+
+    /// Resolves the hostnames whose TTL ran out, and those of endpoints not seen before.
+    /// Returns when the next one is due, at most the minimum TTL away so new endpoints are
+    /// picked up.
+    pub async fn pass(&mut self) -> Instant {
+        let now = Instant::now();
+        let retry = now + self.min_ttl;
+
+        let candidates = match AddressResolution::candidates(&self.pool).await {
+            Ok(candidates) => candidates,
+            Err(status) => {
+                error!(message = "Could not load dynamic endpoints", status = status.message());
+                return retry;
+            }
+        };
+        self.due.retain(|id, _| candidates.iter().any(|t| t.id == *id));
+
+        for tunnel in candidates {
+            if self.due.get(&tunnel.id).is_some_and(|due| *due > now) {
+                continue;
+            }
+
+            let resolve = self.resolve.clone();
+            let (hostname, ipv6) = (tunnel.hostname.clone(), tunnel.ip_class == 6);
+            let resolution = tokio::task::spawn_blocking(move || resolve.resolve(&hostname, ipv6))
+                .await
+                .unwrap_or_else(|err| Err(err.to_string()));
+
+            // The current address is kept while it is still among the answers, so round-robin
+            // records do not churn every router's config.
+            let (outcome, ttl) = match resolution {
+                Ok(resolution) => {
+                    let current = tunnel.ip.parse::<IpAddr>().ok();
+                    let address = match current.filter(|ip| resolution.addresses.contains(ip)) {
+                        Some(current) => current,
+                        None => *resolution.addresses.iter().min().unwrap(),
+                    };
+                    (Ok(address), resolution.ttl.clamp(self.min_ttl, self.max_ttl))
+                }
+                Err(err) => {
+                    warn!(message = "Could not resolve dynamic endpoint", tunnel = tunnel.id, %err);
+                    (Err(err), self.min_ttl)
+                }
+            };
+
+            match AddressResolution::record(&self.pool, &tunnel, &outcome, ttl).await {
+                Ok(peers) if !peers.is_empty() => {
+                    info!(message = "Dynamic endpoint moved", tunnel = tunnel.id, address = ?outcome, ?peers);
+                }
+                Ok(_) => {}
+                Err(status) => error!(message = "Could not record resolution", status = status.message()),
+            }
+            self.due.insert(tunnel.id, now + ttl);
+        }
+
+        self.due.values().copied().min().map_or(retry, |next| next.min(retry))
+    }
+
+    /// Runs passes until `shutdown` is triggered.
+    pub fn spawn(mut self, shutdown: Shutdown) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                let next = self.pass().await;
+                tokio::select! {
+                    _ = tokio::time::sleep_until(next) => {}
+                    _ = shutdown.triggered() => return,
+                }
+            }
+        })
+    }
+}
@@ -1,15 +1,99 @@
 // @generated automatically by Diesel CLI.
 
 diesel::table! {
+    use diesel::sql_types::*;
+    use crate::storage::sql_types::Timestamptz;
+
+    address_resolutions (id) {
+        id -> Int4,
+        tunnel -> Int4,
+        address -> Nullable<Varchar>,
+        error -> Text,
+        ttl_secs -> Int4,
+        resolved_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use crate::storage::sql_types::Timestamptz;
+
+    agent_drivers (agent, router_type, conn_type) {
+        agent -> Int4,
+        router_type -> Varchar,
+        conn_type -> Varchar,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use crate::storage::sql_types::Timestamptz;
+
     agents (id) {
         id -> Int4,
         uuid -> Varchar,
         description -> Varchar,
         owner -> Int4,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use crate::storage::sql_types::Timestamptz;
+
+    config_pushes (id) {
+        id -> Int4,
+        router -> Int4,
+        config_hash -> Varchar,
+        driver -> Varchar,
+        started_at -> Timestamptz,
+        finished_at -> Timestamptz,
+        success -> Bool,
+        error_output -> Text,
+        reported_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use crate::storage::sql_types::Timestamptz;
+
+    config_readbacks (router) {
+        router -> Int4,
+        config -> Text,
+        read_at -> Timestamptz,
+        reported_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use crate::storage::sql_types::Timestamptz;
+
+    conn_types (name) {
+        name -> Varchar,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use crate::storage::sql_types::Timestamptz;
+
+    ipsec_keys (tunnel_a, tunnel_b) {
+        tunnel_a -> Int4,
+        tunnel_b -> Int4,
+        ciphertext -> Bytea,
+        created_at -> Timestamptz,
     }
 }
 
 diesel::table! {
+    use diesel::sql_types::*;
+    use crate::storage::sql_types::Timestamptz;
+
     permission_membership (id) {
         id -> Int4,
         permission -> Int4,
@@ -18,6 +102,9 @@ diesel::table! {
 }
 
 diesel::table! {
+    use diesel::sql_types::*;
+    use crate::storage::sql_types::Timestamptz;
+
     permissions (id) {
         id -> Int4,
         name -> Varchar,
@@ -26,6 +113,31 @@ diesel::table! {
 }
 
 diesel::table! {
+    use diesel::sql_types::*;
+    use crate::storage::sql_types::Timestamptz;
+
+    pushed_peers (router, peer) {
+        router -> Int4,
+        peer -> Int4,
+        pushed_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use crate::storage::sql_types::Timestamptz;
+
+    router_types (name) {
+        name -> Varchar,
+        description -> Varchar,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use crate::storage::sql_types::Timestamptz;
+
     routers (id) {
         id -> Int4,
         agent -> Int4,
@@ -34,10 +146,15 @@ diesel::table! {
         ssh_password -> Nullable<Varchar>,
         conn_type -> Nullable<Varchar>,
         router_type -> Nullable<Varchar>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
     }
 }
 
 diesel::table! {
+    use diesel::sql_types::*;
+    use crate::storage::sql_types::Timestamptz;
+
     tunnels (id) {
         id -> Int4,
         version -> Int4,
@@ -51,28 +168,66 @@ diesel::table! {
         cost -> Int4,
         tunnel_type -> Varchar,
         topology_type -> Varchar,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        ip_reported_at -> Nullable<Timestamptz>,
+        ip_resolved_at -> Nullable<Timestamptz>,
     }
 }
 
 diesel::table! {
+    use diesel::sql_types::*;
+    use crate::storage::sql_types::Timestamptz;
+
     users (id) {
         id -> Int4,
         email -> Varchar,
         password -> Varchar,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use crate::storage::sql_types::Timestamptz;
+
+    wireguard_endpoints (tunnel) {
+        tunnel -> Int4,
+        public_key -> Varchar,
+        private_ciphertext -> Bytea,
+        address -> Varchar,
+        listen_port -> Int4,
+        created_at -> Timestamptz,
     }
 }
 
+diesel::joinable!(address_resolutions -> tunnels (tunnel));
+diesel::joinable!(agent_drivers -> agents (agent));
 diesel::joinable!(agents -> users (owner));
+diesel::joinable!(config_pushes -> routers (router));
+diesel::joinable!(config_readbacks -> routers (router));
 diesel::joinable!(permission_membership -> permissions (permission));
 diesel::joinable!(permission_membership -> users (user_id));
+diesel::joinable!(pushed_peers -> routers (router));
 diesel::joinable!(routers -> agents (agent));
 diesel::joinable!(tunnels -> routers (router));
+diesel::joinable!(wireguard_endpoints -> tunnels (tunnel));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    address_resolutions,
+    agent_drivers,
     agents,
+    config_pushes,
+    config_readbacks,
+    conn_types,
+    ipsec_keys,
     permission_membership,
     permissions,
+    pushed_peers,
+    router_types,
     routers,
     tunnels,
     users,
+    wireguard_endpoints,
 );
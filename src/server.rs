@@ -0,0 +1,73 @@
+use std::sync::Arc;
+
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use tonic::transport::server::Router;
+use tonic::transport::{NamedService, Server};
+
+use crate::api::*;
+use crate::auth::{AuthInterceptor, Tokens};
+use crate::config::Config;
+use crate::grpc::health::v1::health_server::HealthServer;
+use crate::grpc::reflection::v1alpha::server_reflection_server::ServerReflectionServer;
+use crate::handlers::*;
+use crate::metrics::Metrics;
+use crate::shutdown::Shutdown;
+use crate::storage::changes::ChangeListener;
+
+/// Every service the server registers, as reflection lists them.
+pub const SERVICES: &[&str] = &[
+    <auth_server::AuthServer<login::AuthService> as NamedService>::NAME,
+    <agent_server::AgentServer<agents::AgentService> as NamedService>::NAME,
+    <router_server::RouterServer<routers::RouterService> as NamedService>::NAME,
+    <tunnel_server::TunnelServer<tunnels::TunnelService> as NamedService>::NAME,
+    <user_server::UserServer<users::UserService> as NamedService>::NAME,
+    <permission_server::PermissionServer<permissions::PermissionService> as NamedService>::NAME,
+    <permission_membership_server::PermissionMembershipServer<
+        permission_membership::PermissionMembershipService,
+    > as NamedService>::NAME,
+    <mesh_server::MeshServer<mesh::MeshService> as NamedService>::NAME,
+    <HealthServer<health::HealthService> as NamedService>::NAME,
+    <ServerReflectionServer<reflection::ReflectionService> as NamedService>::NAME,
+];
+
+/// Adds every service to `server`. The API services check the authorization header, and their
+/// handlers what the caller may do with [`Access`](crate::storage::access::Access); Auth, health
+/// and reflection do not, so clients can log in and probes and grpcurl need no token.
+#[allow(clippy::too_many_arguments)]
+pub fn services<L: Clone>(
+    server: &mut Server<L>,
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    listener: &ChangeListener,
+    reflection: &reflection::ReflectionService,
+    tokens: &Arc<Tokens>,
+    config: &Config,
+    metrics: &Metrics,
+    shutdown: &Shutdown,
+) -> Router<L> {
+    let auth_interceptor = AuthInterceptor::new(tokens.clone());
+    let auth = login::AuthService::new(pool.clone(), tokens.clone());
+    let agent = agents::AgentService::new(pool.clone());
+    let router = routers::RouterService::new(pool.clone(), config.render.clone(), config.drift.clone(), metrics.clone());
+    let tunnel = tunnels::TunnelService::new(pool.clone(), config.render.clone());
+    let user = users::UserService::new(pool.clone());
+    let permission = permissions::PermissionService::new(pool.clone());
+    let membership = permission_membership::PermissionMembershipService::new(pool.clone());
+    let mesh = mesh::MeshService::new(pool.clone(), listener.clone(), shutdown.clone());
+    let health = health::HealthService::new(pool.clone(), listener.clone(), shutdown.clone());
+
+    server
+        .add_service(auth_server::AuthServer::new(auth))
+        .add_service(agent_server::AgentServer::with_interceptor(agent, auth_interceptor.clone()))
+        .add_service(router_server::RouterServer::with_interceptor(router, auth_interceptor.clone()))
+        .add_service(tunnel_server::TunnelServer::with_interceptor(tunnel, auth_interceptor.clone()))
+        .add_service(user_server::UserServer::with_interceptor(user, auth_interceptor.clone()))
+        .add_service(permission_server::PermissionServer::with_interceptor(permission, auth_interceptor.clone()))
+        .add_service(permission_membership_server::PermissionMembershipServer::with_interceptor(
+            membership,
+            auth_interceptor.clone(),
+        ))
+        .add_service(mesh_server::MeshServer::with_interceptor(mesh, auth_interceptor.clone()))
+        .add_service(HealthServer::new(health))
+        .add_service(ServerReflectionServer::new(reflection.clone()))
+}
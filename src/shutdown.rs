@@ -0,0 +1,58 @@
+use std::sync::Arc;
+
+use tokio::sync::watch;
+use tonic::Status;
+
+/// Set off once when the server starts shutting down, so long-running streams can end instead of
+/// holding the connection drain up.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    tx: Arc<watch::Sender<bool>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Shutdown::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        let (tx, _) = watch::channel(false);
+        Shutdown { tx: Arc::new(tx) }
+    }
+
+    pub fn trigger(&self) {
+        self.tx.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.tx.borrow()
+    }
+
+    /// Completes once `trigger` has been called, straight away if it already has.
+    pub async fn triggered(&self) {
+        let mut rx = self.tx.subscribe();
+        // The sender lives as long as self, so this only returns once triggered.
+        let _ = rx.wait_for(|triggered| *triggered).await;
+    }
+}
+
+/// What streams end with when the server shuts down. UNAVAILABLE is the code clients retry on,
+/// against another server behind the same address.
+pub fn reconnect() -> Status {
+    Status::unavailable("server is shutting down, reconnect")
+}
+
+/// Completes on the first SIGINT or SIGTERM.
+pub async fn signal() -> std::io::Result<&'static str> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+
+    Ok(tokio::select! {
+        _ = interrupt.recv() => "SIGINT",
+        _ = terminate.recv() => "SIGTERM",
+    })
+}
@@ -1,8 +1,21 @@
+pub mod access;
 pub mod agents;
+pub mod changes;
+pub mod drift;
+pub mod drivers;
 pub mod helpers;
+pub mod keys;
 pub mod login;
+pub mod mesh;
+pub mod pagination;
 pub mod permission_membership;
 pub mod permissions;
+pub mod preview;
+pub mod psks;
+pub mod pushes;
+pub mod resolutions;
 pub mod routers;
+pub mod sql_types;
 pub mod tunnels;
 pub mod users;
+pub mod wireguard;
@@ -0,0 +1,171 @@
+//! What the caller of a request may do. Holders of the admin permission may do anything; other
+//! users only what concerns the agents they own, and the routers and tunnels of those agents.
+
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use tonic::{Request, Status};
+use tracing::instrument;
+
+use crate::auth::{Caller, UserId};
+use crate::schema::{agents, permission_membership, permissions, routers, tunnels};
+use crate::storage::helpers::sql_err_to_grpc_error;
+
+/// The permission that lets its members manage users, permissions and the whole mesh.
+pub const ADMIN_PERMISSION: &str = "admin";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    user: Option<i32>,
+    admin: bool,
+}
+
+/// Whether `user_id` holds the admin permission.
+pub(crate) fn is_admin(conn: &mut PgConnection, user_id: i32) -> QueryResult<bool> {
+    permission_membership::table
+        .inner_join(permissions::table)
+        .filter(permission_membership::user_id.eq(user_id))
+        .filter(permissions::name.eq(ADMIN_PERMISSION))
+        .count()
+        .get_result::<i64>(conn)
+        .map(|count| count > 0)
+}
+
+/// Makes the user with `email` an admin, for the first admin to be made without an API call.
+/// `false` if they already were one.
+pub fn grant_admin(conn: &mut PgConnection, email: &str) -> QueryResult<bool> {
+    use crate::schema::users;
+
+    conn.transaction(|conn| {
+        let user_id = users::table.filter(users::email.eq(email)).select(users::id).first::<i32>(conn)?;
+        if is_admin(conn, user_id)? {
+            return Ok(false);
+        }
+
+        let permission = permissions::table
+            .filter(permissions::name.eq(ADMIN_PERMISSION))
+            .select(permissions::id)
+            .first::<i32>(conn)?;
+        diesel::insert_into(permission_membership::table)
+            .values((permission_membership::permission.eq(permission), permission_membership::user_id.eq(user_id)))
+            .execute(conn)?;
+        Ok(true)
+    })
+}
+
+impl Access {
+    /// The access of whoever sent `request`, which must have been through the auth interceptor.
+    #[instrument(skip(request))]
+    pub async fn of<T>(pool: &Pool<ConnectionManager<PgConnection>>, request: &Request<T>) -> Result<Access, Status> {
+        match request.extensions().get::<Caller>() {
+            Some(Caller::Anyone) => Ok(Access { user: None, admin: false }),
+            Some(Caller::Developer) => Ok(Access { user: None, admin: true }),
+            Some(Caller::User(UserId(user_id))) => {
+                let conn = &mut pool.get().map_err(|err| Status::unavailable(err.to_string()))?;
+                let admin = is_admin(conn, *user_id).map_err(sql_err_to_grpc_error)?;
+                Ok(Access { user: Some(*user_id), admin })
+            }
+            None => Err(Status::unauthenticated("Token not found")),
+        }
+    }
+
+    /// The user sending the request, `None` if tokens are not signed.
+    pub fn user(&self) -> Option<i32> {
+        self.user
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.admin
+    }
+
+    /// The owner whose agents, routers and tunnels the caller may see, `None` for all of them.
+    pub fn owner(&self) -> Option<i32> {
+        match self.admin {
+            true => None,
+            false => self.user,
+        }
+    }
+
+    pub fn require_admin(&self) -> Result<(), Status> {
+        match self.admin {
+            true => Ok(()),
+            false => Err(Status::permission_denied("the admin permission is required")),
+        }
+    }
+
+    /// Fails unless the caller is an admin or `user_id` themselves.
+    pub fn check_user(&self, user_id: i32) -> Result<(), Status> {
+        match self.admin || self.user == Some(user_id) {
+            true => Ok(()),
+            false => Err(Status::permission_denied(format!("user {} is someone else", user_id))),
+        }
+    }
+
+    /// Fails unless the caller may act for agents owned by `owner`.
+    pub fn check_owner(&self, owner: i32) -> Result<(), Status> {
+        match self.admin || self.user == Some(owner) {
+            true => Ok(()),
+            false => Err(Status::permission_denied(format!("agents of user {} belong to someone else", owner))),
+        }
+    }
+
+    /// Fails unless the caller owns agent `agent_id`. Agents that do not exist are left for the
+    /// request to report.
+    #[instrument]
+    pub async fn check_agent(&self, pool: &Pool<ConnectionManager<PgConnection>>, agent_id: i32) -> Result<(), Status> {
+        self.check(pool, format!("agent {}", agent_id), |conn| {
+            agents::table.find(agent_id).select(agents::owner).first(conn).optional()
+        })
+    }
+
+    #[instrument]
+    pub async fn check_agent_uuid(&self, pool: &Pool<ConnectionManager<PgConnection>>, uuid: &str) -> Result<(), Status> {
+        self.check(pool, format!("agent {}", uuid), |conn| {
+            agents::table.filter(agents::uuid.eq(uuid)).select(agents::owner).first(conn).optional()
+        })
+    }
+
+    /// Fails unless the caller owns the agent of router `router_id`.
+    #[instrument]
+    pub async fn check_router(&self, pool: &Pool<ConnectionManager<PgConnection>>, router_id: i32) -> Result<(), Status> {
+        self.check(pool, format!("router {}", router_id), |conn| {
+            routers::table
+                .inner_join(agents::table)
+                .filter(routers::id.eq(router_id))
+                .select(agents::owner)
+                .first(conn)
+                .optional()
+        })
+    }
+
+    /// Fails unless the caller owns the agent of the router of tunnel `tunnel_id`.
+    #[instrument]
+    pub async fn check_tunnel(&self, pool: &Pool<ConnectionManager<PgConnection>>, tunnel_id: i32) -> Result<(), Status> {
+        self.check(pool, format!("tunnel {}", tunnel_id), |conn| {
+            tunnels::table
+                .inner_join(routers::table.inner_join(agents::table))
+                .filter(tunnels::id.eq(tunnel_id))
+                .select(agents::owner)
+                .first(conn)
+                .optional()
+        })
+    }
+
+    fn check(
+        &self,
+        pool: &Pool<ConnectionManager<PgConnection>>,
+        what: String,
+        owner: impl FnOnce(&mut PgConnection) -> QueryResult<Option<i32>>,
+    ) -> Result<(), Status> {
+        if self.admin {
+            return Ok(());
+        }
+
+        let conn = &mut pool.get().map_err(|err| Status::unavailable(err.to_string()))?;
+        match owner(conn).map_err(sql_err_to_grpc_error)? {
+            Some(owner) if self.user != Some(owner) => {
+                Err(Status::permission_denied(format!("{} belongs to someone else", what)))
+            }
+            _ => Ok(()),
+        }
+    }
+}
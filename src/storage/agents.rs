@@ -1,20 +1,33 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
 use diesel::prelude::*;
 use diesel::r2d2::{ConnectionManager, Pool};
 use tonic::Status;
 use tracing::instrument;
 
 use crate::api::agent_request::IdUuidOrOwner;
-use crate::api::AgentData;
+use crate::api::{AgentData, AgentDriversRequest, AgentListRequest, AgentTree, AgentsData, RouterTree};
 use crate::schema::agents;
 use crate::schema::agents::dsl::*;
-use crate::storage::helpers::sql_err_to_grpc_error;
+use crate::storage::drivers::{agent_drivers, set_agent_drivers};
+use crate::storage::helpers::{sql_err_to_grpc_error, timestamp_to_system_time};
+use crate::storage::pagination::{
+    contains_pattern, next_page, page_size, seek, time_key_value, OrderBy, PageToken,
+};
+use crate::storage::pushes::touch_peers;
+use crate::storage::routers::Router;
+use crate::storage::sql_types::UtcTime;
+use crate::storage::tunnels::Tunnel;
 
-#[derive(Queryable, Default, Debug)]
+#[derive(Queryable, Identifiable, Debug)]
 pub struct Agent {
     pub id: i32,
     pub uuid: String,
     pub description: String,
     pub owner: i32,
+    pub created_at: SystemTime,
+    pub updated_at: SystemTime,
 }
 
 #[derive(Insertable)]
@@ -40,6 +53,9 @@ impl From<Agent> for AgentData {
             uuid: a.uuid,
             description: Some(a.description),
             owner: a.owner,
+            created_at: Some(a.created_at.into()),
+            updated_at: Some(a.updated_at.into()),
+            ..Default::default()
         }
     }
 }
@@ -51,19 +67,87 @@ impl From<&Agent> for AgentData {
             uuid: a.uuid.clone(),
             description: Some(a.description.clone()),
             owner: a.owner,
+            created_at: Some(a.created_at.into()),
+            updated_at: Some(a.updated_at.into()),
+            ..Default::default()
         }
     }
 }
 
+/// Fills in the drivers of each of `results`.
+fn with_drivers(conn: &mut PgConnection, mut results: Vec<AgentData>) -> QueryResult<Vec<AgentData>> {
+    let agent_ids: Vec<i32> = results.iter().filter_map(|a| a.id).collect();
+    let mut drivers = agent_drivers(conn, &agent_ids)?;
+
+    for data in results.iter_mut() {
+        data.drivers = data.id.and_then(|agent_id| drivers.remove(&agent_id)).unwrap_or_default();
+    }
+
+    Ok(results)
+}
+
 impl Agent {
+    pub const ORDER_FIELDS: [&'static str; 5] = ["id", "uuid", "owner", "created_at", "updated_at"];
+
+    fn page_key(&self, field: &str) -> (String, i32) {
+        let value = match field {
+            "uuid" => self.uuid.clone(),
+            "owner" => self.owner.to_string(),
+            "created_at" => time_key_value(&self.created_at),
+            "updated_at" => time_key_value(&self.updated_at),
+            _ => self.id.to_string(),
+        };
+
+        (value, self.id)
+    }
+
+    /// The agents `list_request` asks for, of those owned by `visible_to` unless it is `None`.
     #[instrument]
     pub async fn all(
         pool: &Pool<ConnectionManager<PgConnection>>,
-    ) -> Result<Vec<AgentData>, Status> {
+        list_request: AgentListRequest,
+        visible_to: Option<i32>,
+    ) -> Result<AgentsData, Status> {
+        let order = OrderBy::parse(&list_request.order_by, &Agent::ORDER_FIELDS)?;
+        let after = PageToken::decode(&list_request.page_token, &order)?;
+        let limit = page_size(list_request.page_size)?;
         let conn = &mut pool.get().unwrap();
+        let mut query = agents.into_boxed();
+
+        if let Some(since) = list_request.updated_since {
+            query = query.filter(updated_at.ge(UtcTime(timestamp_to_system_time(since)?)));
+        }
+
+        if let Some(owner_id) = list_request.owner {
+            query = query.filter(owner.eq(owner_id));
+        }
+
+        if let Some(owner_id) = visible_to {
+            query = query.filter(owner.eq(owner_id));
+        }
 
-        match agents.load::<Agent>(conn) {
-            Ok(results) => Ok(results.iter().map(|t| t.into()).collect()),
+        if let Some(needle) = list_request.description_contains {
+            query = query.filter(description.ilike(contains_pattern(&needle)));
+        }
+
+        query = match order.field.as_str() {
+            "uuid" => seek!(query, uuid, id, order, after.map(|t| t.string_key())),
+            "owner" => seek!(query, owner, id, order, after.map(|t| t.int_key()).transpose()?),
+            "created_at" => seek!(query, created_at, id, order, after.map(|t| t.time_key()).transpose()?),
+            "updated_at" => seek!(query, updated_at, id, order, after.map(|t| t.time_key()).transpose()?),
+            _ => seek!(query, id, id, order, after.map(|t| t.int_key()).transpose()?),
+        };
+
+        match query.limit(limit + 1).load::<Agent>(conn) {
+            Ok(mut results) => {
+                let next_page_token = next_page(&mut results, limit, &order, Agent::page_key);
+                let results = results.iter().map(|a| a.into()).collect();
+
+                Ok(AgentsData {
+                    agents: with_drivers(conn, results).map_err(sql_err_to_grpc_error)?,
+                    next_page_token,
+                })
+            }
             Err(err) => Err(sql_err_to_grpc_error(err)),
         }
     }
@@ -75,26 +159,49 @@ impl Agent {
     ) -> Result<Vec<AgentData>, Status> {
         let conn = &mut pool.get().unwrap();
 
-        match id_uuid_or_owner {
-            IdUuidOrOwner::Id(agent_id) => match agents.find(agent_id).load::<Agent>(conn) {
-                Ok(results) => Ok(results.iter().map(|t| t.into()).collect()),
-                Err(err) => Err(sql_err_to_grpc_error(err)),
-            },
-            IdUuidOrOwner::Uuid(agent_uuid) => {
-                match agents.filter(uuid.eq(agent_uuid)).load::<Agent>(conn) {
-                    Ok(results) => Ok(results.iter().map(|t| t.into()).collect()),
-                    Err(err) => Err(sql_err_to_grpc_error(err)),
-                }
-            }
-            IdUuidOrOwner::Owner(agent_owner) => {
-                match agents.filter(owner.eq(agent_owner)).load::<Agent>(conn) {
-                    Ok(results) => Ok(results.iter().map(|t| t.into()).collect()),
-                    Err(err) => Err(sql_err_to_grpc_error(err)),
-                }
-            }
+        let results = match id_uuid_or_owner {
+            IdUuidOrOwner::Id(agent_id) => agents.find(agent_id).load::<Agent>(conn),
+            IdUuidOrOwner::Uuid(agent_uuid) => agents.filter(uuid.eq(agent_uuid)).load::<Agent>(conn),
+            IdUuidOrOwner::Owner(agent_owner) => agents.filter(owner.eq(agent_owner)).load::<Agent>(conn),
+        };
+
+        match results {
+            Ok(results) => with_drivers(conn, results.iter().map(|t| t.into()).collect())
+                .map_err(sql_err_to_grpc_error),
+            Err(err) => Err(sql_err_to_grpc_error(err)),
         }
     }
 
+    /// Loads the matching agents along with all of their routers and each router's tunnels, in
+    /// three queries rather than one per router.
+    #[instrument]
+    pub async fn tree(
+        pool: &Pool<ConnectionManager<PgConnection>>,
+        id_uuid_or_owner: &IdUuidOrOwner,
+    ) -> Result<Vec<AgentTree>, Status> {
+        let conn = &mut pool.get().unwrap();
+
+        let agent_rows = match id_uuid_or_owner {
+            IdUuidOrOwner::Id(agent_id) => agents.filter(id.eq(agent_id)).load::<Agent>(conn),
+            IdUuidOrOwner::Uuid(agent_uuid) => agents.filter(uuid.eq(agent_uuid)).load::<Agent>(conn),
+            IdUuidOrOwner::Owner(agent_owner) => agents
+                .filter(owner.eq(agent_owner))
+                .order(id.asc())
+                .load::<Agent>(conn),
+        }
+        .map_err(sql_err_to_grpc_error)?;
+
+        load_trees(conn, agent_rows).map_err(sql_err_to_grpc_error)
+    }
+
+    /// Like `tree`, for every agent.
+    pub fn all_trees(conn: &mut PgConnection) -> QueryResult<Vec<AgentTree>> {
+        agents
+            .order(id.asc())
+            .load::<Agent>(conn)
+            .and_then(|agent_rows| load_trees(conn, agent_rows))
+    }
+
     #[instrument]
     pub async fn add(
         pool: &Pool<ConnectionManager<PgConnection>>,
@@ -144,32 +251,100 @@ impl Agent {
         }
     }
 
+    /// Unregisters the matching agents along with their routers and tunnels. Routers that were
+    /// pushed an interface to one of those tunnels get a config that removes it.
     #[instrument]
     pub async fn delete(
         pool: &Pool<ConnectionManager<PgConnection>>,
         id_uuid_or_owner: IdUuidOrOwner,
     ) -> Result<usize, Status> {
+        use crate::schema::{routers, tunnels};
+
         let conn = &mut pool.get().unwrap();
 
-        match id_uuid_or_owner {
-            IdUuidOrOwner::Id(agent_id) => {
-                match diesel::delete(agents.find(agent_id)).execute(conn) {
-                    Ok(results) => Ok(results),
-                    Err(err) => Err(sql_err_to_grpc_error(err)),
-                }
-            }
-            IdUuidOrOwner::Uuid(agent_uuid) => {
-                match diesel::delete(agents.filter(uuid.eq(agent_uuid))).execute(conn) {
-                    Ok(results) => Ok(results),
-                    Err(err) => Err(sql_err_to_grpc_error(err)),
-                }
-            }
-            IdUuidOrOwner::Owner(owner_id) => {
-                match diesel::delete(agents.filter(owner.eq(owner_id))).execute(conn) {
-                    Ok(results) => Ok(results),
-                    Err(err) => Err(sql_err_to_grpc_error(err)),
-                }
-            }
+        let result = conn.transaction(|conn| {
+            let agent_ids = match id_uuid_or_owner {
+                IdUuidOrOwner::Id(agent_id) => agents.filter(id.eq(agent_id)).select(id).load::<i32>(conn)?,
+                IdUuidOrOwner::Uuid(agent_uuid) => agents.filter(uuid.eq(agent_uuid)).select(id).load::<i32>(conn)?,
+                IdUuidOrOwner::Owner(owner_id) => agents.filter(owner.eq(owner_id)).select(id).load::<i32>(conn)?,
+            };
+            let router_ids = routers::table
+                .filter(routers::agent.eq_any(&agent_ids))
+                .select(routers::id)
+                .load::<i32>(conn)?;
+
+            let tunnel_ids = diesel::delete(tunnels::table.filter(tunnels::router.eq_any(&router_ids)))
+                .returning(tunnels::id)
+                .get_results::<i32>(conn)?;
+            touch_peers(conn, &tunnel_ids)?;
+            diesel::delete(routers::table.filter(routers::id.eq_any(&router_ids))).execute(conn)?;
+
+            diesel::delete(agents.filter(id.eq_any(&agent_ids))).execute(conn)
+        });
+
+        match result {
+            Ok(results) => Ok(results),
+            Err(err) => Err(sql_err_to_grpc_error(err)),
         }
     }
 }
+
+impl Agent {
+    /// Replaces the drivers the agent with the request's uuid said it had.
+    #[instrument]
+    pub async fn set_drivers(
+        pool: &Pool<ConnectionManager<PgConnection>>,
+        request: &AgentDriversRequest,
+    ) -> Result<(), Status> {
+        let conn = &mut pool.get().unwrap();
+
+        let agent_id = match agents.filter(uuid.eq(&request.uuid)).select(id).first::<i32>(conn) {
+            Ok(agent_id) => agent_id,
+            Err(diesel::result::Error::NotFound) => {
+                return Err(Status::not_found(format!("no agent with uuid {}", request.uuid)))
+            }
+            Err(err) => return Err(sql_err_to_grpc_error(err)),
+        };
+
+        conn.transaction(|conn| set_agent_drivers(conn, agent_id, &request.drivers))
+            .map_err(sql_err_to_grpc_error)
+    }
+
+    /// Counts the registered agents, for the metrics.
+    #[instrument]
+    pub async fn count(pool: &Pool<ConnectionManager<PgConnection>>) -> Result<i64, Status> {
+        // Scrapes should fail fast rather than hang or panic while the database is down.
+        let conn = &mut pool
+            .get_timeout(Duration::from_secs(1))
+            .map_err(|err| Status::unavailable(err.to_string()))?;
+
+        agents.count().get_result::<i64>(conn).map_err(sql_err_to_grpc_error)
+    }
+}
+
+fn load_trees(conn: &mut PgConnection, agent_rows: Vec<Agent>) -> QueryResult<Vec<AgentTree>> {
+    let router_rows = Router::belonging_to(&agent_rows)
+        .order(crate::schema::routers::id.asc())
+        .load::<Router>(conn)?;
+
+    let tunnel_rows = Tunnel::belonging_to(&router_rows)
+        .order(crate::schema::tunnels::id.asc())
+        .load::<Tunnel>(conn)?
+        .grouped_by(&router_rows);
+
+    let mut routers_by_agent: HashMap<i32, Vec<RouterTree>> = HashMap::new();
+    for (r, t) in router_rows.into_iter().zip(tunnel_rows) {
+        routers_by_agent.entry(r.agent).or_default().push(RouterTree {
+            tunnels: t.iter().map(|t| t.into()).collect(),
+            router: Some(r.into()),
+        });
+    }
+
+    Ok(agent_rows
+        .into_iter()
+        .map(|a| AgentTree {
+            routers: routers_by_agent.remove(&a.id).unwrap_or_default(),
+            agent: Some(a.into()),
+        })
+        .collect())
+}
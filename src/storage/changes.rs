@@ -0,0 +1,131 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use diesel::prelude::*;
+use diesel::sql_query;
+use tokio::sync::watch;
+use tracing::{error, info};
+
+use crate::config::NotificationConfig;
+
+/// The channel the triggers of the mesh_notifications migration notify on.
+pub const CHANNEL: &str = "mesh_changes";
+
+/// Listens for changes to agents, routers and tunnels on a dedicated connection, as `LISTEN`
+/// does not survive being returned to the pool. Reconnects when the connection drops.
+#[derive(Debug, Clone)]
+pub struct ChangeListener {
+    changes: watch::Receiver<u64>,
+    connected: Arc<AtomicBool>,
+    stopping: Arc<AtomicBool>,
+    thread: Arc<Mutex<Option<JoinHandle<()>>>>,
+    watch_interval: Duration,
+}
+
+impl ChangeListener {
+    pub fn spawn(database_url: String, settings: &NotificationConfig) -> ChangeListener {
+        let (tx, changes) = watch::channel(0);
+        let connected = Arc::new(AtomicBool::new(false));
+        let stopping = Arc::new(AtomicBool::new(false));
+
+        let listener_connected = connected.clone();
+        let listener_stopping = stopping.clone();
+        let thread_settings = settings.clone();
+        let thread = thread::Builder::new()
+            .name("change-listener".to_string())
+            .spawn(move || loop {
+                let result = listen(&database_url, &thread_settings, &listener_connected, &listener_stopping, &tx);
+                if let Err(err) = result {
+                    error!(message = "Change listener disconnected", %err);
+                }
+                listener_connected.store(false, Ordering::Relaxed);
+
+                // Waits out the retry in poll intervals, so stopping is not held up by it.
+                let retry = Instant::now();
+                while retry.elapsed() < Duration::from_secs(thread_settings.retry_secs) {
+                    if tx.is_closed() || listener_stopping.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    thread::sleep(Duration::from_millis(thread_settings.poll_interval_ms));
+                }
+            })
+            .expect("Could not start change listener");
+
+        ChangeListener {
+            changes,
+            connected,
+            stopping,
+            thread: Arc::new(Mutex::new(Some(thread))),
+            watch_interval: Duration::from_secs(settings.watch_interval_secs),
+        }
+    }
+
+    /// Stops listening, `UNLISTEN`s and closes the connection, and waits for the thread to end.
+    /// Blocks for up to a poll interval; watchers keep their receivers but see no more changes.
+    pub fn stop(&self) {
+        self.stopping.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.lock().unwrap().take() {
+            if thread.join().is_err() {
+                error!(message = "Change listener panicked");
+            }
+        }
+    }
+
+    /// Whether the listener is currently connected. Changes made while it is not are missed.
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    /// A receiver that is marked changed after every batch of changes.
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.changes.clone()
+    }
+
+    /// How often watchers should poll anyway, for the changes missed while reconnecting.
+    pub fn watch_interval(&self) -> Duration {
+        self.watch_interval
+    }
+}
+
+fn listen(
+    database_url: &str,
+    settings: &NotificationConfig,
+    connected: &AtomicBool,
+    stopping: &AtomicBool,
+    tx: &watch::Sender<u64>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let conn = &mut PgConnection::establish(database_url)?;
+    sql_query(format!("LISTEN {}", CHANNEL)).execute(conn)?;
+
+    connected.store(true, Ordering::Relaxed);
+    info!(message = "Change listener connected", channel = CHANNEL);
+
+    let mut last_keepalive = Instant::now();
+    while !tx.is_closed() && !stopping.load(Ordering::Relaxed) {
+        let mut changed = false;
+        for notification in conn.notifications_iter() {
+            notification?;
+            changed = true;
+        }
+
+        if changed {
+            tx.send_modify(|count| *count += 1);
+        }
+
+        // A dropped connection is only noticed once something is sent on it.
+        if last_keepalive.elapsed() >= Duration::from_secs(settings.keepalive_secs) {
+            sql_query("SELECT 1").execute(conn)?;
+            last_keepalive = Instant::now();
+        }
+
+        // Notifications are read without blocking, so they are polled for.
+        thread::sleep(Duration::from_millis(settings.poll_interval_ms));
+    }
+
+    sql_query("UNLISTEN *").execute(conn)?;
+    info!(message = "Change listener stopped", channel = CHANNEL);
+
+    Ok(())
+}
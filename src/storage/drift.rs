@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::result::Error;
+use tonic::Status;
+use tracing::instrument;
+
+use crate::api::router_request::IdOrAgent;
+use crate::api::{DriftReport, DriftResponse, RunningConfig};
+use crate::config::{DriftConfig, RenderConfig};
+use crate::render::{self, Keys};
+use crate::schema::{config_readbacks, routers, tunnels};
+use crate::storage::helpers::{sql_err_to_grpc_error, timestamp_to_system_time};
+use crate::storage::keys::keys;
+use crate::storage::routers::Router;
+use crate::storage::sql_types::{now, UtcTime};
+use crate::storage::tunnels::Tunnel;
+
+#[derive(Queryable, Identifiable, Associations, Debug)]
+#[diesel(belongs_to(Router, foreign_key = router))]
+#[diesel(primary_key(router))]
+#[diesel(table_name = config_readbacks)]
+pub struct Readback {
+    pub router: i32,
+    pub config: String,
+    pub read_at: SystemTime,
+    pub reported_at: SystemTime,
+}
+
+#[derive(Insertable, AsChangeset, Clone)]
+#[diesel(table_name = config_readbacks)]
+pub struct NewReadback<'a> {
+    pub router: i32,
+    pub config: &'a str,
+    #[diesel(serialize_as = UtcTime)]
+    pub read_at: SystemTime,
+    #[diesel(serialize_as = UtcTime)]
+    pub reported_at: SystemTime,
+}
+
+/// Compares the last config read back from `router` with the one rendered for it now.
+fn compare(
+    router: &Router,
+    all_tunnels: &[Tunnel],
+    keys: &Keys,
+    readback: Option<&Readback>,
+    defaults: &RenderConfig,
+) -> DriftReport {
+    let mut report = DriftReport {
+        router: router.id,
+        agent: router.agent,
+        read_at: readback.map(|r| r.read_at.into()),
+        ..Default::default()
+    };
+
+    // Removals of stale peerings are not interfaces, so they are left out of the comparison.
+    let desired = match render::render(router, all_tunnels, &[], keys, defaults) {
+        Ok(config) => config,
+        Err(err) => {
+            report.render_error = err;
+            return report;
+        }
+    };
+
+    if let Some(readback) = readback {
+        let router_type = router.router_type.as_deref().unwrap_or_default();
+        report.interfaces = render::drift(router_type, &desired, &readback.config);
+        report.drifted = !report.interfaces.is_empty();
+    }
+
+    report
+}
+
+impl Readback {
+    /// Keeps the managed interfaces of a config read back from a router and compares them with
+    /// the rendered config. Drifted routers get their config pushed again with
+    /// `drift.auto_remediate`, the way `Router.Repush` does it.
+    #[instrument(skip(running))]
+    pub async fn report(
+        pool: &Pool<ConnectionManager<PgConnection>>,
+        running: &RunningConfig,
+        defaults: &RenderConfig,
+        drift: &DriftConfig,
+    ) -> Result<DriftReport, Status> {
+        let read_at = timestamp_to_system_time(
+            running
+                .read_at
+                .clone()
+                .ok_or_else(|| Status::invalid_argument("read_at is required"))?,
+        )?;
+        let conn = &mut pool.get().unwrap();
+
+        let router = match routers::table.find(running.router).first::<Router>(conn) {
+            Ok(router) => router,
+            Err(Error::NotFound) => return Err(Status::not_found(format!("no router with id {}", running.router))),
+            Err(err) => return Err(sql_err_to_grpc_error(err)),
+        };
+        let all_tunnels = tunnels::table
+            .order(tunnels::id)
+            .load::<Tunnel>(conn)
+            .map_err(sql_err_to_grpc_error)?;
+
+        let keys = keys(conn, std::slice::from_ref(&router), &all_tunnels, defaults).map_err(sql_err_to_grpc_error)?;
+
+        let managed = render::managed_config(router.router_type.as_deref().unwrap_or_default(), &running.config);
+        let readback = NewReadback {
+            router: router.id,
+            config: &managed,
+            read_at,
+            reported_at: SystemTime::now(),
+        };
+        let readback = diesel::insert_into(config_readbacks::table)
+            .values(readback.clone())
+            .on_conflict(config_readbacks::router)
+            .do_update()
+            .set(readback)
+            .get_result::<Readback>(conn)
+            .map_err(sql_err_to_grpc_error)?;
+
+        let mut report = compare(&router, &all_tunnels, &keys, Some(&readback), defaults);
+        report.readback_interval_secs = drift.readback_interval_secs;
+
+        if report.drifted && drift.auto_remediate {
+            diesel::update(routers::table.find(router.id))
+                .set(routers::updated_at.eq(now()))
+                .execute(conn)
+                .map_err(sql_err_to_grpc_error)?;
+            report.remediate = true;
+        }
+
+        Ok(report)
+    }
+
+    /// Drift of every matching router (all of them without `id_or_agent`), against the last
+    /// config read back from it.
+    #[instrument]
+    pub async fn get(
+        pool: &Pool<ConnectionManager<PgConnection>>,
+        id_or_agent: Option<&IdOrAgent>,
+        defaults: &RenderConfig,
+        drift: &DriftConfig,
+    ) -> Result<DriftResponse, Status> {
+        let conn = &mut pool.get().unwrap();
+
+        let mut query = routers::table.order(routers::id).into_boxed();
+        query = match id_or_agent {
+            Some(IdOrAgent::Id(router_id)) => query.filter(routers::id.eq(*router_id)),
+            Some(IdOrAgent::Agent(agent_id)) => query.filter(routers::agent.eq(*agent_id)),
+            None => query,
+        };
+
+        let router_rows = query.load::<Router>(conn).map_err(sql_err_to_grpc_error)?;
+        let all_tunnels = tunnels::table
+            .order(tunnels::id)
+            .load::<Tunnel>(conn)
+            .map_err(sql_err_to_grpc_error)?;
+        let router_ids: Vec<i32> = router_rows.iter().map(|r| r.id).collect();
+        let readbacks: HashMap<i32, Readback> = config_readbacks::table
+            .filter(config_readbacks::router.eq_any(&router_ids))
+            .load::<Readback>(conn)
+            .map_err(sql_err_to_grpc_error)?
+            .into_iter()
+            .map(|r| (r.router, r))
+            .collect();
+        let keys = keys(conn, &router_rows, &all_tunnels, defaults).map_err(sql_err_to_grpc_error)?;
+
+        Ok(DriftResponse {
+            routers: router_rows
+                .iter()
+                .map(|r| DriftReport {
+                    readback_interval_secs: drift.readback_interval_secs,
+                    ..compare(r, &all_tunnels, &keys, readbacks.get(&r.id), defaults)
+                })
+                .collect(),
+        })
+    }
+}
@@ -0,0 +1,168 @@
+//! The router and connection types routers can have, as rows of the `router_types` and
+//! `conn_types` lookup tables, and the drivers agents say they have.
+
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use tonic::Status;
+use tracing::instrument;
+
+use crate::api::{RouterDriver, RouterType as RouterTypeData, RouterTypesResponse};
+use crate::render::{self, RENDERERS};
+use crate::schema::{agent_drivers, conn_types, router_types};
+use crate::storage::helpers::sql_err_to_grpc_error;
+
+#[derive(Queryable, Debug)]
+#[diesel(table_name = router_types)]
+pub struct RouterType {
+    pub name: String,
+    pub description: String,
+    pub created_at: SystemTime,
+}
+
+impl RouterType {
+    /// Adds the router type of every renderer, and the connection types it expects, to the lookup
+    /// tables. Types that are there already are left alone.
+    pub fn register(conn: &mut PgConnection) -> QueryResult<()> {
+        let types: Vec<_> = RENDERERS
+            .iter()
+            .map(|r| (router_types::name.eq(r.router_type()), router_types::description.eq(r.description())))
+            .collect();
+        diesel::insert_into(router_types::table)
+            .values(types)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+
+        let conns: Vec<_> = RENDERERS
+            .iter()
+            .flat_map(|r| r.conn_types())
+            .map(|name| conn_types::name.eq(*name))
+            .collect();
+        diesel::insert_into(conn_types::table)
+            .values(conns)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+
+        Ok(())
+    }
+
+    /// Every router and connection type, by name.
+    #[instrument]
+    pub async fn all(pool: &Pool<ConnectionManager<PgConnection>>) -> Result<RouterTypesResponse, Status> {
+        let conn = &mut pool.get().unwrap();
+
+        let types = router_types::table
+            .order(router_types::name)
+            .load::<RouterType>(conn)
+            .map_err(sql_err_to_grpc_error)?;
+        let conn_types = conn_types::table
+            .order(conn_types::name)
+            .select(conn_types::name)
+            .load::<String>(conn)
+            .map_err(sql_err_to_grpc_error)?;
+
+        Ok(RouterTypesResponse {
+            router_types: types
+                .into_iter()
+                .map(|t| {
+                    let renderer = render::renderer(&t.name);
+                    RouterTypeData {
+                        renderable: renderer.is_some(),
+                        conn_types: renderer
+                            .map(|r| r.conn_types().iter().map(|c| c.to_string()).collect())
+                            .unwrap_or_default(),
+                        name: t.name,
+                        description: t.description,
+                    }
+                })
+                .collect(),
+            conn_types,
+        })
+    }
+}
+
+/// Replaces the drivers `agent_id` said it has.
+pub(crate) fn set_agent_drivers(conn: &mut PgConnection, agent_id: i32, drivers: &[RouterDriver]) -> QueryResult<()> {
+    diesel::delete(agent_drivers::table.filter(agent_drivers::agent.eq(agent_id))).execute(conn)?;
+
+    let rows: Vec<_> = drivers
+        .iter()
+        .map(|d| {
+            (
+                agent_drivers::agent.eq(agent_id),
+                agent_drivers::router_type.eq(&d.router_type),
+                agent_drivers::conn_type.eq(&d.conn_type),
+            )
+        })
+        .collect();
+    diesel::insert_into(agent_drivers::table)
+        .values(rows)
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// The drivers of `agent_ids`, by agent.
+pub(crate) fn agent_drivers(conn: &mut PgConnection, agent_ids: &[i32]) -> QueryResult<HashMap<i32, Vec<RouterDriver>>> {
+    let rows = agent_drivers::table
+        .filter(agent_drivers::agent.eq_any(agent_ids))
+        .order((agent_drivers::agent, agent_drivers::router_type, agent_drivers::conn_type))
+        .load::<(i32, String, String)>(conn)?;
+
+    let mut drivers: HashMap<i32, Vec<RouterDriver>> = HashMap::new();
+    for (agent, router_type, conn_type) in rows {
+        drivers.entry(agent).or_default().push(RouterDriver { router_type, conn_type });
+    }
+    Ok(drivers)
+}
+
+/// Checks that a router of `agent_id` can be of `router_type` and reached over `conn_type`: both
+/// must be known, and if the agent said which drivers it has, it must have one for them.
+pub(crate) fn check_driver(
+    conn: &mut PgConnection,
+    agent_id: i32,
+    router_type: Option<&str>,
+    conn_type: Option<&str>,
+) -> Result<(), Status> {
+    if let Some(router_type) = router_type {
+        let known = router_types::table
+            .find(router_type)
+            .count()
+            .get_result::<i64>(conn)
+            .map_err(sql_err_to_grpc_error)?;
+        if known == 0 {
+            return Err(Status::invalid_argument(format!("unknown router type {:?}", router_type)));
+        }
+    }
+
+    if let Some(conn_type) = conn_type {
+        let known = conn_types::table
+            .find(conn_type)
+            .count()
+            .get_result::<i64>(conn)
+            .map_err(sql_err_to_grpc_error)?;
+        if known == 0 {
+            return Err(Status::invalid_argument(format!("unknown connection type {:?}", conn_type)));
+        }
+    }
+
+    let drivers = agent_drivers(conn, &[agent_id]).map_err(sql_err_to_grpc_error)?;
+    match drivers.get(&agent_id) {
+        Some(drivers)
+            if !drivers.iter().any(|d| {
+                router_type.is_none_or(|t| t == d.router_type) && conn_type.is_none_or(|c| c == d.conn_type)
+            }) =>
+        {
+            Err(Status::failed_precondition(format!(
+                "agent {} has no driver for {} over {}",
+                agent_id,
+                router_type.unwrap_or("any router type"),
+                conn_type.unwrap_or("any connection")
+            )))
+        }
+        _ => Ok(()),
+    }
+}
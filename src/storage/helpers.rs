@@ -1,4 +1,9 @@
+use std::time::SystemTime;
+
+use diesel::r2d2::{ConnectionManager, Pool};
 use diesel::result::Error;
+use diesel::PgConnection;
+use prost_types::Timestamp;
 use tonic::Status;
 use bcrypt::BcryptError;
 
@@ -15,3 +20,24 @@ pub fn bcrypt_err_to_grpc_error(error: BcryptError) -> Status {
         _ => Status::internal(error.to_string()),
     }
 }
+
+/// Runs `queries` on a blocking thread, for the work that is repeated often enough that waiting
+/// for a connection or the database must not hold up a runtime thread. An exhausted pool fails
+/// with UNAVAILABLE rather than panicking.
+pub async fn blocking<T: Send + 'static>(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    queries: impl FnOnce(&mut PgConnection) -> Result<T, Status> + Send + 'static,
+) -> Result<T, Status> {
+    let pool = pool.clone();
+    tokio::task::spawn_blocking(move || {
+        let conn = &mut pool.get().map_err(|err| Status::unavailable(err.to_string()))?;
+        queries(conn)
+    })
+    .await
+    .map_err(|err| Status::internal(err.to_string()))?
+}
+
+pub fn timestamp_to_system_time(timestamp: Timestamp) -> Result<SystemTime, Status> {
+    SystemTime::try_from(timestamp)
+        .map_err(|_| Status::invalid_argument("invalid timestamp".to_string()))
+}
@@ -0,0 +1,56 @@
+//! The key material tunnels are rendered with, kept encrypted with `render.psk_secret`: AES-256-GCM
+//! under the SHA-256 of the secret, with a random nonce stored ahead of each ciphertext.
+
+use diesel::prelude::*;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+
+use crate::config::RenderConfig;
+use crate::render::Keys;
+use crate::storage::psks::psks;
+use crate::storage::routers::Router;
+use crate::storage::tunnels::Tunnel;
+use crate::storage::wireguard::endpoints;
+
+pub(crate) fn cipher(secret: &[u8]) -> LessSafeKey {
+    let key = ring::digest::digest(&ring::digest::SHA256, secret);
+    LessSafeKey::new(UnboundKey::new(&AES_256_GCM, key.as_ref()).unwrap())
+}
+
+/// Encrypts `plaintext`, bound to `aad` so it cannot be moved to another row.
+pub(crate) fn seal(cipher: &LessSafeKey, aad: &str, plaintext: &str) -> Vec<u8> {
+    let nonce: [u8; NONCE_LEN] = rand::random();
+    let mut sealed = plaintext.as_bytes().to_vec();
+    cipher
+        .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(aad), &mut sealed)
+        .unwrap();
+
+    [&nonce[..], &sealed].concat()
+}
+
+/// Decrypts what [`seal`] encrypted with the same `aad`; `None` when that fails.
+pub(crate) fn open(cipher: &LessSafeKey, aad: &str, ciphertext: &[u8]) -> Option<String> {
+    if ciphertext.len() < NONCE_LEN {
+        return None;
+    }
+    let (nonce, sealed) = ciphertext.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).ok()?;
+    let mut sealed = sealed.to_vec();
+    let plaintext = cipher.open_in_place(nonce, Aad::from(aad), &mut sealed).ok()?;
+
+    String::from_utf8(plaintext.to_vec()).ok()
+}
+
+/// Everything `routers` need rendered among `all_tunnels`: the pre-shared keys of their IPSec
+/// peerings and the WireGuard identities of their WireGuard endpoints and peers, generating
+/// what does not exist yet.
+pub(crate) fn keys(
+    conn: &mut PgConnection,
+    routers: &[Router],
+    all_tunnels: &[Tunnel],
+    defaults: &RenderConfig,
+) -> QueryResult<Keys> {
+    Ok(Keys {
+        psks: psks(conn, routers, all_tunnels, defaults)?,
+        wireguard: endpoints(conn, routers, all_tunnels, defaults)?,
+    })
+}
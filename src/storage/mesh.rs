@@ -0,0 +1,624 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+use std::time::SystemTime;
+
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::dsl::{count_star, max};
+use diesel::result::Error;
+use ring::digest::{Context, SHA256};
+use tonic::Status;
+use tracing::instrument;
+
+use crate::api::{AgentStatus, MeshChange, MeshPlan, MeshStatus};
+use crate::legacy::LegacyImport;
+use crate::mesh::{MeshAgent, MeshDocument, MeshRouter, MeshTunnel};
+use crate::schema::{agents, config_pushes, routers, tunnels, users};
+use crate::storage::agents::Agent;
+use crate::storage::helpers::{blocking, sql_err_to_grpc_error};
+use crate::storage::pushes;
+use crate::storage::routers::Router;
+use crate::storage::tunnels::Tunnel;
+
+/// Leaves the SNMP community and SSH password out: exports end up in Git.
+impl From<&Router> for MeshRouter {
+    fn from(r: &Router) -> MeshRouter {
+        MeshRouter {
+            id: r.id,
+            router_type: r.router_type.clone(),
+            conn_type: r.conn_type.clone(),
+            snmp_community: None,
+            ssh_username: r.ssh_username.clone(),
+            ssh_password: None,
+            tunnels: Vec::new(),
+        }
+    }
+}
+
+impl From<&Tunnel> for MeshTunnel {
+    fn from(t: &Tunnel) -> MeshTunnel {
+        MeshTunnel {
+            id: t.id,
+            version: t.version,
+            ip: t.ip.clone(),
+            dynamic_ip: t.dynamic_ip,
+            ip_class: t.ip_class,
+            hostname: t.hostname.clone(),
+            description: t.description.clone(),
+            source: t.source.clone(),
+            cost: t.cost,
+            tunnel_type: t.tunnel_type.clone(),
+            topology_type: t.topology_type.clone(),
+        }
+    }
+}
+
+pub struct Mesh;
+
+/// Row count and latest `updated_at` of each mesh table. While it stays the same, so does the mesh.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeshVersion(Vec<(i64, Option<SystemTime>)>);
+
+impl Mesh {
+    #[instrument]
+    pub async fn export(
+        pool: &Pool<ConnectionManager<PgConnection>>,
+    ) -> Result<MeshDocument, Status> {
+        let conn = &mut pool.get().unwrap();
+
+        load_document(conn).map_err(sql_err_to_grpc_error)
+    }
+
+    #[instrument]
+    pub async fn version(
+        pool: &Pool<ConnectionManager<PgConnection>>,
+    ) -> Result<MeshVersion, Status> {
+        blocking(pool, |conn| load_version(conn).map_err(sql_err_to_grpc_error)).await
+    }
+
+    /// Every agent with its routers and tunnels, for the dashboard. Each router comes with the
+    /// outcome of its last reported push.
+    #[instrument]
+    pub async fn status(
+        pool: &Pool<ConnectionManager<PgConnection>>,
+    ) -> Result<MeshStatus, Status> {
+        blocking(pool, |conn| load_status(conn).map_err(sql_err_to_grpc_error)).await
+    }
+
+    /// Diffs `desired` against the database and applies the resulting plan in a single
+    /// transaction. With `dry_run` the transaction is rolled back, so only the plan is returned.
+    #[instrument]
+    pub async fn apply(
+        pool: &Pool<ConnectionManager<PgConnection>>,
+        desired: MeshDocument,
+        dry_run: bool,
+        expected_plan_hash: &str,
+    ) -> Result<MeshPlan, Status> {
+        let conn = &mut pool.get().unwrap();
+        let owners = resolve_owners(conn, &desired)?;
+
+        let mut changes = Vec::new();
+        let mut plan_hash = String::new();
+        let mut stale_plan = false;
+
+        let result = conn.transaction::<_, Error, _>(|conn| {
+            changes = apply_changes(conn, &desired, &owners)?;
+            plan_hash = hash_plan(&changes);
+
+            if !expected_plan_hash.is_empty() && expected_plan_hash != plan_hash {
+                stale_plan = true;
+                return Err(Error::RollbackTransaction);
+            }
+
+            if dry_run {
+                return Err(Error::RollbackTransaction);
+            }
+
+            Ok(())
+        });
+
+        match result {
+            _ if stale_plan => Err(Status::failed_precondition(
+                "the mesh changed since the plan was made, run plan again",
+            )),
+            Ok(()) | Err(Error::RollbackTransaction) => Ok(MeshPlan {
+                changes,
+                applied: !dry_run,
+                plan_hash,
+            }),
+            Err(err) => Err(sql_err_to_grpc_error(err)),
+        }
+    }
+
+    /// Adds the agents of a legacy import to the existing mesh. Owners without an account get a
+    /// user with an unusable password. Routers and tunnels whose id is already taken are left
+    /// alone and returned as skipped, so the import can be re-run safely.
+    #[instrument(skip(import))]
+    pub async fn import_legacy(
+        pool: &Pool<ConnectionManager<PgConnection>>,
+        import: &LegacyImport,
+        dry_run: bool,
+    ) -> Result<(MeshPlan, Vec<String>), Status> {
+        let conn = &mut pool.get().unwrap();
+
+        let mut changes = Vec::new();
+        let mut skipped = Vec::new();
+
+        let result = conn.transaction::<_, Error, _>(|conn| {
+            let placeholders: Vec<_> = import
+                .owners
+                .iter()
+                .map(|email| (users::email.eq(email), users::password.eq("!")))
+                .collect();
+
+            diesel::insert_into(users::table)
+                .values(&placeholders)
+                .on_conflict(users::email)
+                .do_nothing()
+                .execute(conn)?;
+
+            let owners: HashMap<String, i32> = users::table
+                .select((users::email, users::id))
+                .load::<(String, i32)>(conn)?
+                .into_iter()
+                .collect();
+
+            let mut desired = load_document(conn)?;
+            skipped = merge(&mut desired, &import.document);
+            changes = apply_changes(conn, &desired, &owners)?;
+
+            if dry_run {
+                return Err(Error::RollbackTransaction);
+            }
+
+            Ok(())
+        });
+
+        match result {
+            Ok(()) | Err(Error::RollbackTransaction) => Ok((
+                MeshPlan {
+                    plan_hash: hash_plan(&changes),
+                    changes,
+                    applied: !dry_run,
+                },
+                skipped,
+            )),
+            Err(err) => Err(sql_err_to_grpc_error(err)),
+        }
+    }
+}
+
+/// Adds the routers and tunnels of `import` to `desired` where their ids are still free.
+fn merge(desired: &mut MeshDocument, import: &MeshDocument) -> Vec<String> {
+    let mut router_ids: HashSet<i32> = HashSet::new();
+    let mut tunnel_ids: HashSet<i32> = HashSet::new();
+    let mut skipped = Vec::new();
+
+    for router in desired.agents.iter().flat_map(|a| &a.routers) {
+        router_ids.insert(router.id);
+        tunnel_ids.extend(router.tunnels.iter().map(|t| t.id));
+    }
+
+    for agent in &import.agents {
+        let mut routers = Vec::new();
+
+        for router in &agent.routers {
+            if !router_ids.insert(router.id) {
+                skipped.push(format!("router {}: id already exists", router.id));
+                continue;
+            }
+
+            let mut router = router.clone();
+            router.tunnels.retain(|t| {
+                let free = tunnel_ids.insert(t.id);
+                if !free {
+                    skipped.push(format!("tunnel {}: id already exists", t.id));
+                }
+                free
+            });
+            routers.push(router);
+        }
+
+        match desired.agents.iter_mut().find(|a| a.uuid == agent.uuid) {
+            Some(existing) => existing.routers.extend(routers),
+            None if !routers.is_empty() => desired.agents.push(MeshAgent {
+                routers,
+                ..agent.clone()
+            }),
+            None => {}
+        }
+    }
+
+    skipped
+}
+
+fn load_version(conn: &mut PgConnection) -> QueryResult<MeshVersion> {
+    let tables = [
+        agents::table.select((count_star(), max(agents::updated_at))).first(conn),
+        routers::table.select((count_star(), max(routers::updated_at))).first(conn),
+        tunnels::table.select((count_star(), max(tunnels::updated_at))).first(conn),
+        config_pushes::table
+            .select((count_star(), max(config_pushes::finished_at)))
+            .first(conn),
+    ];
+
+    tables.into_iter().collect::<QueryResult<Vec<_>>>().map(MeshVersion)
+}
+
+fn load_status(conn: &mut PgConnection) -> QueryResult<MeshStatus> {
+    let trees = Agent::all_trees(conn)?;
+
+    let router_ids: Vec<i32> = trees
+        .iter()
+        .flat_map(|t| t.routers.iter().filter_map(|r| r.router.as_ref()?.id))
+        .collect();
+    let last_pushes: HashMap<i32, _> = pushes::latest(conn, &router_ids, false)?
+        .into_iter()
+        .map(|p| (p.router, p))
+        .collect();
+
+    Ok(MeshStatus {
+        agents: trees
+            .into_iter()
+            .map(|tree| {
+                let pushes = tree
+                    .routers
+                    .iter()
+                    .filter_map(|r| last_pushes.get(&r.router.as_ref()?.id?))
+                    .map(|p| p.into())
+                    .collect();
+
+                AgentStatus {
+                    tree: Some(tree),
+                    state: "unknown".to_string(),
+                    last_seen: None,
+                    pushes,
+                }
+            })
+            .collect(),
+        generated_at: Some(SystemTime::now().into()),
+    })
+}
+
+
+fn load_document(conn: &mut PgConnection) -> QueryResult<MeshDocument> {
+    let emails: HashMap<i32, String> = users::table
+        .select((users::id, users::email))
+        .load::<(i32, String)>(conn)?
+        .into_iter()
+        .collect();
+    let agent_rows = agents::table.order(agents::id.asc()).load::<Agent>(conn)?;
+    let router_rows = routers::table.order(routers::id.asc()).load::<Router>(conn)?;
+    let tunnel_rows = tunnels::table.order(tunnels::id.asc()).load::<Tunnel>(conn)?;
+
+    Ok(MeshDocument {
+        agents: agent_rows
+            .iter()
+            .map(|a| MeshAgent {
+                uuid: a.uuid.clone(),
+                description: a.description.clone(),
+                owner: emails.get(&a.owner).cloned().unwrap_or_default(),
+                routers: router_rows
+                    .iter()
+                    .filter(|r| r.agent == a.id)
+                    .map(|r| MeshRouter {
+                        tunnels: tunnel_rows
+                            .iter()
+                            .filter(|t| t.router == r.id)
+                            .map(|t| t.into())
+                            .collect(),
+                        ..r.into()
+                    })
+                    .collect(),
+            })
+            .collect(),
+    })
+}
+
+/// Maps every owner email in the document to a user id, failing on any that are unknown.
+fn resolve_owners(
+    conn: &mut PgConnection,
+    desired: &MeshDocument,
+) -> Result<HashMap<String, i32>, Status> {
+    let wanted: HashSet<&str> = desired.agents.iter().map(|a| a.owner.as_str()).collect();
+
+    let owners: HashMap<String, i32> = users::table
+        .filter(users::email.eq_any(&wanted))
+        .select((users::email, users::id))
+        .load::<(String, i32)>(conn)
+        .map_err(sql_err_to_grpc_error)?
+        .into_iter()
+        .collect();
+
+    let mut unknown: Vec<&str> = wanted
+        .into_iter()
+        .filter(|email| !owners.contains_key(*email))
+        .collect();
+
+    if !unknown.is_empty() {
+        unknown.sort_unstable();
+        return Err(Status::invalid_argument(format!(
+            "unknown owner: {}",
+            unknown.join(", ")
+        )));
+    }
+
+    Ok(owners)
+}
+
+/// Brings the database in line with `desired`, returning what was changed. Parents are created
+/// before children and children are removed before parents, so rows can move between parents.
+fn apply_changes(
+    conn: &mut PgConnection,
+    desired: &MeshDocument,
+    owners: &HashMap<String, i32>,
+) -> QueryResult<Vec<MeshChange>> {
+    let current_agents: HashMap<String, Agent> = agents::table
+        .load::<Agent>(conn)?
+        .into_iter()
+        .map(|a| (a.uuid.clone(), a))
+        .collect();
+    let current_routers: HashMap<i32, Router> = routers::table
+        .load::<Router>(conn)?
+        .into_iter()
+        .map(|r| (r.id, r))
+        .collect();
+    let current_tunnels: HashMap<i32, Tunnel> = tunnels::table
+        .load::<Tunnel>(conn)?
+        .into_iter()
+        .map(|t| (t.id, t))
+        .collect();
+    let emails: HashMap<i32, &String> = owners.iter().map(|(email, id)| (*id, email)).collect();
+
+    let mut changes = Vec::new();
+    let mut routers_added = false;
+    let mut tunnels_added = false;
+    let mut removed_tunnels = Vec::new();
+    let mut removed_from = Vec::new();
+
+    for agent in &desired.agents {
+        let owner_id = owners[&agent.owner];
+
+        let agent_id = match current_agents.get(&agent.uuid) {
+            None => {
+                changes.push(change("add", "agent", &agent.uuid, Vec::new()));
+                diesel::insert_into(agents::table)
+                    .values((
+                        agents::uuid.eq(&agent.uuid),
+                        agents::description.eq(&agent.description),
+                        agents::owner.eq(owner_id),
+                    ))
+                    .returning(agents::id)
+                    .get_result::<i32>(conn)?
+            }
+            Some(existing) => {
+                let mut details = Vec::new();
+                diff(&mut details, "description", &existing.description, &agent.description);
+                if existing.owner != owner_id {
+                    let old_owner = emails
+                        .get(&existing.owner)
+                        .map(|e| e.to_string())
+                        .unwrap_or_else(|| format!("user {}", existing.owner));
+                    diff(&mut details, "owner", &old_owner, &agent.owner);
+                }
+
+                if !details.is_empty() {
+                    changes.push(change("update", "agent", &agent.uuid, details));
+                    diesel::update(agents::table.find(existing.id))
+                        .set((
+                            agents::description.eq(&agent.description),
+                            agents::owner.eq(owner_id),
+                        ))
+                        .execute(conn)?;
+                }
+
+                existing.id
+            }
+        };
+
+        for router in &agent.routers {
+            // Exports leave credentials out, so a router without them in the file keeps its own.
+            let current = current_routers.get(&router.id);
+            let snmp_community = router
+                .snmp_community
+                .clone()
+                .or_else(|| current.and_then(|r| r.snmp_community.clone()));
+            let ssh_password = router
+                .ssh_password
+                .clone()
+                .or_else(|| current.and_then(|r| r.ssh_password.clone()));
+
+            let router_values = (
+                routers::agent.eq(agent_id),
+                routers::snmp_community.eq(&snmp_community),
+                routers::ssh_username.eq(&router.ssh_username),
+                routers::ssh_password.eq(&ssh_password),
+                routers::conn_type.eq(&router.conn_type),
+                routers::router_type.eq(&router.router_type),
+            );
+
+            match current {
+                None => {
+                    changes.push(change("add", "router", &router.id, Vec::new()));
+                    diesel::insert_into(routers::table)
+                        .values((routers::id.eq(router.id), router_values))
+                        .execute(conn)?;
+                    routers_added = true;
+                }
+                Some(existing) => {
+                    let mut details = Vec::new();
+                    if existing.agent != agent_id {
+                        details.push(format!("agent: moved to {}", agent.uuid));
+                    }
+                    diff(&mut details, "router_type", &existing.router_type, &router.router_type);
+                    diff(&mut details, "conn_type", &existing.conn_type, &router.conn_type);
+                    diff_secret(&mut details, "snmp_community", &existing.snmp_community, &snmp_community);
+                    diff(&mut details, "ssh_username", &existing.ssh_username, &router.ssh_username);
+                    diff_secret(&mut details, "ssh_password", &existing.ssh_password, &ssh_password);
+
+                    if !details.is_empty() {
+                        changes.push(change("update", "router", &router.id, details));
+                        diesel::update(routers::table.find(router.id))
+                            .set(router_values)
+                            .execute(conn)?;
+                    }
+                }
+            }
+
+            for tunnel in &router.tunnels {
+                let tunnel_values = (
+                    tunnels::version.eq(tunnel.version),
+                    tunnels::router.eq(router.id),
+                    tunnels::ip.eq(&tunnel.ip),
+                    tunnels::dynamic_ip.eq(tunnel.dynamic_ip),
+                    tunnels::ip_class.eq(tunnel.ip_class),
+                    tunnels::hostname.eq(&tunnel.hostname),
+                    tunnels::description.eq(&tunnel.description),
+                    tunnels::source.eq(&tunnel.source),
+                    tunnels::cost.eq(tunnel.cost),
+                    tunnels::tunnel_type.eq(&tunnel.tunnel_type),
+                    tunnels::topology_type.eq(&tunnel.topology_type),
+                );
+
+                match current_tunnels.get(&tunnel.id) {
+                    None => {
+                        changes.push(change("add", "tunnel", &tunnel.id, Vec::new()));
+                        diesel::insert_into(tunnels::table)
+                            .values((tunnels::id.eq(tunnel.id), tunnel_values))
+                            .execute(conn)?;
+                        tunnels_added = true;
+                    }
+                    Some(existing) => {
+                        let mut details = Vec::new();
+                        diff(&mut details, "router", &existing.router, &router.id);
+                        diff(&mut details, "version", &existing.version, &tunnel.version);
+                        diff(&mut details, "ip", &existing.ip, &tunnel.ip);
+                        diff(&mut details, "dynamic_ip", &existing.dynamic_ip, &tunnel.dynamic_ip);
+                        diff(&mut details, "ip_class", &existing.ip_class, &tunnel.ip_class);
+                        diff(&mut details, "hostname", &existing.hostname, &tunnel.hostname);
+                        diff(&mut details, "description", &existing.description, &tunnel.description);
+                        diff(&mut details, "source", &existing.source, &tunnel.source);
+                        diff(&mut details, "cost", &existing.cost, &tunnel.cost);
+                        diff(&mut details, "tunnel_type", &existing.tunnel_type, &tunnel.tunnel_type);
+                        diff(&mut details, "topology_type", &existing.topology_type, &tunnel.topology_type);
+
+                        if !details.is_empty() {
+                            changes.push(change("update", "tunnel", &tunnel.id, details));
+                            diesel::update(tunnels::table.find(tunnel.id))
+                                .set(tunnel_values)
+                                .execute(conn)?;
+                        }
+
+                        if existing.router != router.id {
+                            removed_tunnels.push(tunnel.id);
+                            removed_from.push(existing.router);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let desired_routers: HashSet<i32> = desired
+        .agents
+        .iter()
+        .flat_map(|a| a.routers.iter().map(|r| r.id))
+        .collect();
+    let desired_tunnels: HashSet<i32> = desired
+        .agents
+        .iter()
+        .flat_map(|a| a.routers.iter())
+        .flat_map(|r| r.tunnels.iter().map(|t| t.id))
+        .collect();
+
+    let mut stale_tunnels: Vec<i32> = current_tunnels
+        .keys()
+        .filter(|tunnel_id| !desired_tunnels.contains(tunnel_id))
+        .copied()
+        .collect();
+    stale_tunnels.sort_unstable();
+    for tunnel_id in stale_tunnels {
+        changes.push(change("delete", "tunnel", &tunnel_id, Vec::new()));
+        diesel::delete(tunnels::table.find(tunnel_id)).execute(conn)?;
+        removed_tunnels.push(tunnel_id);
+        removed_from.push(current_tunnels[&tunnel_id].router);
+    }
+
+    // As with Tunnel.Delete and Tunnel.Update, the routers deleted and moved tunnels leave, and
+    // those that were pushed an interface to them, get a config without their peerings.
+    pushes::touch_removed(conn, &removed_tunnels, &removed_from)?;
+
+    let mut stale_routers: Vec<i32> = current_routers
+        .keys()
+        .filter(|router_id| !desired_routers.contains(router_id))
+        .copied()
+        .collect();
+    stale_routers.sort_unstable();
+    for router_id in stale_routers {
+        changes.push(change("delete", "router", &router_id, Vec::new()));
+        diesel::delete(routers::table.find(router_id)).execute(conn)?;
+    }
+
+    let mut stale_agents: Vec<&Agent> = current_agents
+        .values()
+        .filter(|a| !desired.agents.iter().any(|d| d.uuid == a.uuid))
+        .collect();
+    stale_agents.sort_unstable_by_key(|a| a.id);
+    for agent in stale_agents {
+        changes.push(change("delete", "agent", &agent.uuid, Vec::new()));
+        diesel::delete(agents::table.find(agent.id)).execute(conn)?;
+    }
+
+    // Rows added with explicit ids leave the serial sequences behind, which would make the next
+    // plain Add collide with them.
+    if routers_added {
+        diesel::sql_query("SELECT setval(pg_get_serial_sequence('routers', 'id'), (SELECT MAX(id) FROM routers))")
+            .execute(conn)?;
+    }
+
+    if tunnels_added {
+        diesel::sql_query("SELECT setval(pg_get_serial_sequence('tunnels', 'id'), (SELECT MAX(id) FROM tunnels))")
+            .execute(conn)?;
+    }
+
+    Ok(changes)
+}
+
+fn change(action: &str, kind: &str, key: &dyn ToString, details: Vec<String>) -> MeshChange {
+    MeshChange {
+        action: action.to_string(),
+        kind: kind.to_string(),
+        key: key.to_string(),
+        details,
+    }
+}
+
+fn diff<T: PartialEq + Debug + ?Sized>(details: &mut Vec<String>, field: &str, current: &T, desired: &T) {
+    if current != desired {
+        details.push(format!("{}: {:?} -> {:?}", field, current, desired));
+    }
+}
+
+/// Like `diff`, but never puts credentials into the plan.
+fn diff_secret(details: &mut Vec<String>, field: &str, current: &Option<String>, desired: &Option<String>) {
+    if current != desired {
+        details.push(format!("{}: changed", field));
+    }
+}
+
+/// The hex SHA-256 of a plan. Each string goes in after its length, so that different plans
+/// never feed in the same bytes.
+fn hash_plan(changes: &[MeshChange]) -> String {
+    let mut context = Context::new(&SHA256);
+
+    for c in changes {
+        let fields = [&c.action, &c.kind, &c.key].into_iter().chain(&c.details);
+        context.update(&(c.details.len() as u64).to_be_bytes());
+        for field in fields {
+            context.update(&(field.len() as u64).to_be_bytes());
+            context.update(field.as_bytes());
+        }
+    }
+
+    context.finish().as_ref().iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,225 @@
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tonic::Status;
+
+use crate::storage::sql_types::UtcTime;
+
+pub const DEFAULT_PAGE_SIZE: i64 = 100;
+pub const MAX_PAGE_SIZE: i64 = 1000;
+
+/// Parsed form of a list request's `order_by`, e.g. `"hostname"` or `"updated_at desc"`.
+#[derive(Debug, PartialEq)]
+pub struct OrderBy {
+    pub field: String,
+    pub descending: bool,
+}
+
+impl OrderBy {
+    pub fn parse(order_by: &str, allowed: &[&str]) -> Result<OrderBy, Status> {
+        let mut parts = order_by.split_whitespace();
+
+        let field = parts.next().unwrap_or("id");
+        if !allowed.contains(&field) {
+            return Err(Status::invalid_argument(format!(
+                "cannot order by {}, expected one of: {}",
+                field,
+                allowed.join(", ")
+            )));
+        }
+
+        let descending = match parts.next().map(|d| d.to_ascii_lowercase()) {
+            None => false,
+            Some(d) if d == "asc" => false,
+            Some(d) if d == "desc" => true,
+            Some(d) => {
+                return Err(Status::invalid_argument(format!(
+                    "invalid order direction {}, expected asc or desc",
+                    d
+                )))
+            }
+        };
+
+        if parts.next().is_some() {
+            return Err(Status::invalid_argument("order_by takes a single field"));
+        }
+
+        Ok(OrderBy {
+            field: field.to_string(),
+            descending,
+        })
+    }
+}
+
+/// The canonical form, `"hostname"` or `"updated_at desc"`.
+impl fmt::Display for OrderBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.descending {
+            true => write!(f, "{} desc", self.field),
+            false => write!(f, "{}", self.field),
+        }
+    }
+}
+
+/// Cursor pointing just past the last row of a page: the value of the ordered field and the row
+/// id, which breaks ties between rows sharing that value. It carries the order it was issued for,
+/// field and direction, since it points somewhere else in any other.
+#[derive(Debug, PartialEq)]
+pub struct PageToken {
+    pub order_by: String,
+    pub value: String,
+    pub id: i32,
+}
+
+impl PageToken {
+    pub fn new(order: &OrderBy, value: String, id: i32) -> PageToken {
+        PageToken {
+            order_by: order.to_string(),
+            value,
+            id,
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        format!("{}\0{}\0{}", self.order_by, self.value, self.id)
+            .bytes()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    /// Decodes `page_token`, which must have been issued for the same `order_by`.
+    pub fn decode(page_token: &str, order: &OrderBy) -> Result<Option<PageToken>, Status> {
+        if page_token.is_empty() {
+            return Ok(None);
+        }
+
+        let invalid = || Status::invalid_argument("invalid page_token");
+
+        if !page_token.len().is_multiple_of(2) {
+            return Err(invalid());
+        }
+
+        let bytes = (0..page_token.len())
+            .step_by(2)
+            .map(|i| {
+                let pair = page_token.get(i..i + 2).ok_or_else(invalid)?;
+                u8::from_str_radix(pair, 16).map_err(|_| invalid())
+            })
+            .collect::<Result<Vec<u8>, Status>>()?;
+        let decoded = String::from_utf8(bytes).map_err(|_| invalid())?;
+
+        let mut parts = decoded.splitn(3, '\0');
+        let (order_by, value, last_id) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(order_by), Some(value), Some(last_id)) => (order_by, value, last_id),
+            _ => return Err(invalid()),
+        };
+
+        if order_by != order.to_string() {
+            return Err(Status::invalid_argument("page_token was issued for a different order_by"));
+        }
+
+        Ok(Some(PageToken {
+            order_by: order_by.to_string(),
+            value: value.to_string(),
+            id: last_id.parse().map_err(|_| invalid())?,
+        }))
+    }
+
+    pub fn string_key(&self) -> (String, i32) {
+        (self.value.clone(), self.id)
+    }
+
+    pub fn int_key(&self) -> Result<(i32, i32), Status> {
+        match self.value.parse() {
+            Ok(value) => Ok((value, self.id)),
+            Err(_) => Err(Status::invalid_argument("invalid page_token")),
+        }
+    }
+
+    pub fn time_key(&self) -> Result<(UtcTime, i32), Status> {
+        match self.value.parse() {
+            Ok(nanos) => Ok((UtcTime(UNIX_EPOCH + Duration::from_nanos(nanos)), self.id)),
+            Err(_) => Err(Status::invalid_argument("invalid page_token")),
+        }
+    }
+}
+
+pub fn time_key_value(time: &SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+pub fn page_size(requested: i32) -> Result<i64, Status> {
+    match requested {
+        0 => Ok(DEFAULT_PAGE_SIZE),
+        size if size < 0 => Err(Status::invalid_argument("page_size must not be negative")),
+        size => Ok(i64::from(size).min(MAX_PAGE_SIZE)),
+    }
+}
+
+/// Builds an `ILIKE` pattern matching `needle` anywhere, with wildcards in it escaped.
+pub fn contains_pattern(needle: &str) -> String {
+    let escaped = needle
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+
+    format!("%{}%", escaped)
+}
+
+/// Orders a boxed query by `$column` (then `$id` as a tie breaker) and, when a page token was
+/// given, skips every row up to and including the one it points at.
+macro_rules! seek {
+    ($query:expr, $column:expr, $id:expr, $order:expr, $after:expr) => {{
+        let mut query = $query;
+
+        if let Some((value, last_id)) = $after {
+            query = if $order.descending {
+                query.filter(
+                    $column
+                        .lt(value.clone())
+                        .or($column.eq(value).and($id.lt(last_id))),
+                )
+            } else {
+                query.filter(
+                    $column
+                        .gt(value.clone())
+                        .or($column.eq(value).and($id.gt(last_id))),
+                )
+            };
+        }
+
+        if $order.descending {
+            query.order(($column.desc(), $id.desc()))
+        } else {
+            query.order(($column.asc(), $id.asc()))
+        }
+    }};
+}
+
+pub(crate) use seek;
+
+/// Splits off the extra row fetched past `limit` and returns the token for the next page, or an
+/// empty string if this was the last one.
+pub fn next_page<T>(
+    rows: &mut Vec<T>,
+    limit: i64,
+    order: &OrderBy,
+    key: impl Fn(&T, &str) -> (String, i32),
+) -> String {
+    if rows.len() as i64 <= limit {
+        return String::new();
+    }
+
+    rows.truncate(limit as usize);
+
+    match rows.last() {
+        Some(last) => {
+            let (value, last_id) = key(last, &order.field);
+            PageToken::new(order, value, last_id).encode()
+        }
+        None => String::new(),
+    }
+}
@@ -4,10 +4,11 @@ use tonic::Status;
 use tracing::instrument;
 
 use crate::api::permission_request::IdOrName;
-use crate::api::PermissionData;
+use crate::api::{PermissionData, PermissionListRequest, PermissionsData};
 use crate::schema::permissions;
 use crate::schema::permissions::dsl::*;
 use crate::storage::helpers::sql_err_to_grpc_error;
+use crate::storage::pagination::{contains_pattern, next_page, page_size, seek, OrderBy, PageToken};
 
 #[derive(Queryable, Default, Debug)]
 pub struct Permission {
@@ -51,14 +52,46 @@ impl From<&Permission> for PermissionData {
 }
 
 impl Permission {
+    pub const ORDER_FIELDS: [&'static str; 2] = ["id", "name"];
+
+    fn page_key(&self, field: &str) -> (String, i32) {
+        let value = match field {
+            "name" => self.name.clone(),
+            _ => self.id.to_string(),
+        };
+
+        (value, self.id)
+    }
+
     #[instrument]
     pub async fn all(
         pool: &Pool<ConnectionManager<PgConnection>>,
-    ) -> Result<Vec<PermissionData>, Status> {
+        list_request: PermissionListRequest,
+    ) -> Result<PermissionsData, Status> {
+        let order = OrderBy::parse(&list_request.order_by, &Permission::ORDER_FIELDS)?;
+        let after = PageToken::decode(&list_request.page_token, &order)?;
+        let limit = page_size(list_request.page_size)?;
         let conn = &mut pool.get().unwrap();
+        let mut query = permissions.into_boxed();
 
-        match permissions.load::<Permission>(conn) {
-            Ok(results) => Ok(results.iter().map(|t| t.into()).collect()),
+        if let Some(needle) = list_request.name_contains {
+            query = query.filter(name.ilike(contains_pattern(&needle)));
+        }
+
+        query = match order.field.as_str() {
+            "name" => seek!(query, name, id, order, after.map(|t| t.string_key())),
+            _ => seek!(query, id, id, order, after.map(|t| t.int_key()).transpose()?),
+        };
+
+        match query.limit(limit + 1).load::<Permission>(conn) {
+            Ok(mut results) => {
+                let next_page_token = next_page(&mut results, limit, &order, Permission::page_key);
+
+                Ok(PermissionsData {
+                    permissions: results.iter().map(|p| p.into()).collect(),
+                    next_page_token,
+                })
+            }
             Err(err) => Err(sql_err_to_grpc_error(err)),
         }
     }
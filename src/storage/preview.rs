@@ -0,0 +1,88 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use diesel::prelude::*;
+use diesel::result::Error;
+use tonic::Status;
+
+use crate::api::ConfigDiff;
+use crate::config::RenderConfig;
+use crate::render;
+use crate::schema::{routers, tunnels};
+use crate::storage::helpers::sql_err_to_grpc_error;
+use crate::storage::keys::keys;
+use crate::storage::pushes::pushed_peers;
+use crate::storage::routers::Router;
+use crate::storage::tunnels::Tunnel;
+
+/// The config of every router, rendered from what `conn` sees now.
+fn render_all(conn: &mut PgConnection, defaults: &RenderConfig) -> QueryResult<BTreeMap<i32, Result<String, String>>> {
+    let all_routers = routers::table.order(routers::id).load::<Router>(conn)?;
+    let all_tunnels = tunnels::table.order(tunnels::id).load::<Tunnel>(conn)?;
+    let router_ids: Vec<i32> = all_routers.iter().map(|r| r.id).collect();
+    let pushed = pushed_peers(conn, &router_ids)?;
+    let keys = keys(conn, &all_routers, &all_tunnels, defaults)?;
+
+    Ok(all_routers
+        .iter()
+        .map(|r| {
+            let pushed = pushed.get(&r.id).map_or(&[][..], Vec::as_slice);
+            (r.id, render::render(r, &all_tunnels, pushed, &keys, defaults))
+        })
+        .collect())
+}
+
+/// Makes `change`, or with `dry_run` makes it in a transaction that is rolled back and returns
+/// how it alters the config of each router, for admins to look at before they commit to it.
+pub(crate) fn preview<T>(
+    conn: &mut PgConnection,
+    dry_run: bool,
+    defaults: &RenderConfig,
+    change: impl FnOnce(&mut PgConnection) -> QueryResult<T>,
+) -> Result<(T, Vec<ConfigDiff>), Status> {
+    if !dry_run {
+        return change(conn).map(|value| (value, Vec::new())).map_err(sql_err_to_grpc_error);
+    }
+
+    let before = render_all(conn, defaults).map_err(sql_err_to_grpc_error)?;
+    let mut outcome = None;
+
+    let result = conn.transaction::<(), Error, _>(|conn| {
+        let value = change(conn)?;
+        outcome = Some((value, render_all(conn, defaults)?));
+        Err(Error::RollbackTransaction)
+    });
+
+    let (value, after) = match (result, outcome) {
+        (Ok(()) | Err(Error::RollbackTransaction), Some(outcome)) => outcome,
+        (Err(err), _) => return Err(sql_err_to_grpc_error(err)),
+        (Ok(()), None) => unreachable!("the transaction is always rolled back"),
+    };
+
+    let router_ids: BTreeSet<i32> = before.keys().chain(after.keys()).copied().collect();
+    let diffs = router_ids
+        .into_iter()
+        .filter_map(|id| {
+            let (old, new) = (before.get(&id), after.get(&id));
+            if old == new {
+                return None;
+            }
+
+            let text = |config: Option<&Result<String, String>>| match config {
+                Some(Ok(config)) => config.clone(),
+                _ => String::new(),
+            };
+            let name = format!("router-{}", id);
+
+            Some(ConfigDiff {
+                router: id,
+                diff: render::unified_diff(&format!("a/{}", name), &format!("b/{}", name), &text(old), &text(new)),
+                render_error: match new {
+                    Some(Err(err)) => err.clone(),
+                    _ => String::new(),
+                },
+            })
+        })
+        .collect();
+
+    Ok((value, diffs))
+}
@@ -0,0 +1,180 @@
+//! The pre-shared keys of IPSec peerings. Each pair of endpoints gets a random key the first time
+//! it is rendered, kept encrypted with `render.psk_secret` so a database dump alone does not give
+//! the keys away.
+
+use std::collections::BTreeSet;
+use std::time::SystemTime;
+
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use tonic::Status;
+use tracing::{instrument, warn};
+
+use crate::api::tunnel_request::IdOrRouter;
+use crate::api::RotateKeysResponse;
+use crate::config::RenderConfig;
+use crate::render::{self, Psks};
+use crate::schema::{ipsec_keys, routers, tunnels};
+use crate::storage::helpers::sql_err_to_grpc_error;
+use crate::storage::keys::{cipher, open, seal};
+use crate::storage::routers::Router;
+use crate::storage::sql_types::now;
+use crate::storage::tunnels::Tunnel;
+
+/// Pre-shared keys are this many letters and digits, which IOS takes without quoting.
+const PSK_LEN: usize = 40;
+
+#[derive(Queryable, Debug)]
+#[diesel(table_name = ipsec_keys)]
+pub struct IpsecKey {
+    pub tunnel_a: i32,
+    pub tunnel_b: i32,
+    /// The nonce, then the sealed key.
+    pub ciphertext: Vec<u8>,
+    pub created_at: SystemTime,
+}
+
+/// Binds a sealed key to its peering, so it cannot be moved to another one.
+fn aad((a, b): (i32, i32)) -> String {
+    format!("{}-{}", a, b)
+}
+
+fn generate() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(PSK_LEN)
+        .map(char::from)
+        .collect()
+}
+
+/// The keys of the IPSec peerings `routers` have among `all_tunnels`, generating those that do
+/// not exist yet. Without `render.psk_secret` there are none, and IPSec tunnels fail to render.
+pub(crate) fn psks(
+    conn: &mut PgConnection,
+    routers: &[Router],
+    all_tunnels: &[Tunnel],
+    defaults: &RenderConfig,
+) -> QueryResult<Psks> {
+    let pairs: BTreeSet<(i32, i32)> = routers
+        .iter()
+        .flat_map(|r| render::links(r, all_tunnels))
+        .filter(|(local, _)| local.tunnel_type == "IPSec")
+        .map(|(local, peer)| render::psk_pair(local, peer))
+        .collect();
+    let secret = match defaults.psk_secret() {
+        Ok(Some(secret)) if !pairs.is_empty() => secret,
+        _ => return Ok(Psks::new()),
+    };
+    let cipher = cipher(&secret);
+
+    let firsts: Vec<i32> = pairs.iter().map(|(a, _)| *a).collect();
+    let load = |conn: &mut PgConnection| {
+        ipsec_keys::table
+            .filter(ipsec_keys::tunnel_a.eq_any(&firsts))
+            .load::<IpsecKey>(conn)
+            .map(|keys| {
+                keys.into_iter()
+                    .filter(|k| pairs.contains(&(k.tunnel_a, k.tunnel_b)))
+                    .collect::<Vec<_>>()
+            })
+    };
+
+    let mut keys = load(conn)?;
+    let missing: Vec<_> = pairs
+        .iter()
+        .filter(|pair| !keys.iter().any(|k| (k.tunnel_a, k.tunnel_b) == **pair))
+        .map(|&(a, b)| {
+            (
+                ipsec_keys::tunnel_a.eq(a),
+                ipsec_keys::tunnel_b.eq(b),
+                ipsec_keys::ciphertext.eq(seal(&cipher, &aad((a, b)), &generate())),
+            )
+        })
+        .collect();
+
+    // Renders for both ends may generate a key at once; the first one stored is used by both.
+    if !missing.is_empty() {
+        diesel::insert_into(ipsec_keys::table)
+            .values(missing)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+        keys = load(conn)?;
+    }
+
+    let mut psks = Psks::new();
+    for key in keys {
+        let pair = (key.tunnel_a, key.tunnel_b);
+        match open(&cipher, &aad(pair), &key.ciphertext) {
+            Some(psk) => {
+                psks.insert(pair, psk);
+            }
+            None => warn!(
+                message = "Could not decrypt pre-shared key; was render.psk_secret changed?",
+                tunnel_a = key.tunnel_a,
+                tunnel_b = key.tunnel_b
+            ),
+        }
+    }
+
+    Ok(psks)
+}
+
+impl IpsecKey {
+    /// Drops the keys of every IPSec peering of the matching tunnels, so new ones are generated
+    /// the next time they are rendered, and marks the routers at both ends as changed. Returns
+    /// how many keys were dropped, and those routers.
+    #[instrument]
+    pub async fn rotate(
+        pool: &Pool<ConnectionManager<PgConnection>>,
+        id_or_router: IdOrRouter,
+    ) -> Result<RotateKeysResponse, Status> {
+        let conn = &mut pool.get().unwrap();
+
+        let result = conn.transaction(|conn| {
+            let tunnel_ids = match id_or_router {
+                IdOrRouter::Id(tunnel_id) => vec![tunnels::table
+                    .find(tunnel_id)
+                    .select(tunnels::id)
+                    .first::<i32>(conn)?],
+                IdOrRouter::Router(router_id) => tunnels::table
+                    .filter(tunnels::router.eq(router_id))
+                    .select(tunnels::id)
+                    .load::<i32>(conn)?,
+            };
+
+            let rotated = diesel::delete(
+                ipsec_keys::table.filter(
+                    ipsec_keys::tunnel_a
+                        .eq_any(&tunnel_ids)
+                        .or(ipsec_keys::tunnel_b.eq_any(&tunnel_ids)),
+                ),
+            )
+            .returning((ipsec_keys::tunnel_a, ipsec_keys::tunnel_b))
+            .get_results::<(i32, i32)>(conn)?;
+
+            let ends: Vec<i32> = rotated.iter().flat_map(|(a, b)| [*a, *b]).collect();
+            let mut changed = diesel::update(
+                routers::table.filter(
+                    routers::id.eq_any(
+                        tunnels::table
+                            .filter(tunnels::id.eq_any(&ends))
+                            .select(tunnels::router),
+                    ),
+                ),
+            )
+            .set(routers::updated_at.eq(now()))
+            .returning(routers::id)
+            .get_results::<i32>(conn)?;
+            changed.sort_unstable();
+
+            Ok(RotateKeysResponse {
+                rotated: rotated.len() as i32,
+                routers: changed,
+            })
+        });
+
+        result.map_err(sql_err_to_grpc_error)
+    }
+}
@@ -0,0 +1,352 @@
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::result::{DatabaseErrorKind, Error};
+use tonic::Status;
+use tracing::instrument;
+
+use crate::api::router_request::IdOrAgent;
+use crate::api::{
+    PushHistoryRequest, PushHistoryResponse, PushRecord, PushResult, RouterPushStatus, RouterSync,
+    SyncStatusResponse,
+};
+use crate::config::RenderConfig;
+use crate::render;
+use crate::schema::{config_pushes, pushed_peers, routers, tunnels};
+use crate::storage::helpers::{sql_err_to_grpc_error, timestamp_to_system_time};
+use crate::storage::pagination::{next_page, page_size, seek, time_key_value, OrderBy, PageToken};
+use crate::storage::keys::keys;
+use crate::storage::routers::Router;
+use crate::storage::sql_types::{now, UtcTime};
+use crate::storage::tunnels::Tunnel;
+
+/// How much of a router's error output is kept per push.
+pub const MAX_ERROR_OUTPUT: usize = 64 * 1024;
+
+#[derive(Queryable, Identifiable, Associations, Debug)]
+#[diesel(belongs_to(Router, foreign_key = router))]
+#[diesel(table_name = config_pushes)]
+pub struct Push {
+    pub id: i32,
+    pub router: i32,
+    pub config_hash: String,
+    pub driver: String,
+    pub started_at: SystemTime,
+    pub finished_at: SystemTime,
+    pub success: bool,
+    pub error_output: String,
+    pub reported_at: SystemTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = config_pushes)]
+pub struct NewPush<'a> {
+    pub router: i32,
+    pub config_hash: &'a str,
+    pub driver: &'a str,
+    #[diesel(serialize_as = UtcTime)]
+    pub started_at: SystemTime,
+    #[diesel(serialize_as = UtcTime)]
+    pub finished_at: SystemTime,
+    pub success: bool,
+    pub error_output: &'a str,
+}
+
+impl From<&Push> for PushRecord {
+    fn from(p: &Push) -> PushRecord {
+        PushRecord {
+            id: p.id,
+            router: p.router,
+            config_hash: p.config_hash.clone(),
+            driver: p.driver.clone(),
+            started_at: Some(p.started_at.into()),
+            finished_at: Some(p.finished_at.into()),
+            success: p.success,
+            error_output: p.error_output.clone(),
+            reported_at: Some(p.reported_at.into()),
+        }
+    }
+}
+
+impl From<&Push> for RouterPushStatus {
+    fn from(p: &Push) -> RouterPushStatus {
+        RouterPushStatus {
+            router: p.router,
+            success: p.success,
+            finished_at: Some(p.finished_at.into()),
+            error: p.error_output.clone(),
+        }
+    }
+}
+
+/// The start of `output`, at most [`MAX_ERROR_OUTPUT`] bytes, cut on a character boundary.
+fn truncate(output: &str) -> &str {
+    let mut end = output.len().min(MAX_ERROR_OUTPUT);
+    while !output.is_char_boundary(end) {
+        end -= 1;
+    }
+    &output[..end]
+}
+
+/// The latest push of each of `router_ids`, or their latest successful one.
+pub(crate) fn latest(conn: &mut PgConnection, router_ids: &[i32], successful_only: bool) -> QueryResult<Vec<Push>> {
+    let query = config_pushes::table
+        .filter(config_pushes::router.eq_any(router_ids))
+        .distinct_on(config_pushes::router)
+        .order((
+            config_pushes::router,
+            config_pushes::finished_at.desc(),
+            config_pushes::id.desc(),
+        ));
+
+    match successful_only {
+        true => query.filter(config_pushes::success.eq(true)).load::<Push>(conn),
+        false => query.load::<Push>(conn),
+    }
+}
+
+/// The peers each of `router_ids` was last pushed an interface for.
+pub(crate) fn pushed_peers(conn: &mut PgConnection, router_ids: &[i32]) -> QueryResult<HashMap<i32, Vec<i32>>> {
+    let rows = pushed_peers::table
+        .filter(pushed_peers::router.eq_any(router_ids))
+        .select((pushed_peers::router, pushed_peers::peer))
+        .order((pushed_peers::router, pushed_peers::peer))
+        .load::<(i32, i32)>(conn)?;
+
+    let mut peers: HashMap<i32, Vec<i32>> = HashMap::new();
+    for (router, peer) in rows {
+        peers.entry(router).or_default().push(peer);
+    }
+    Ok(peers)
+}
+
+/// Marks every router that was pushed an interface for one of `tunnel_ids` as changed, so its
+/// agent pushes the config that removes it.
+pub(crate) fn touch_peers(conn: &mut PgConnection, tunnel_ids: &[i32]) -> QueryResult<usize> {
+    let peered = pushed_peers::table
+        .filter(pushed_peers::peer.eq_any(tunnel_ids))
+        .select(pushed_peers::router);
+
+    diesel::update(routers::table.filter(routers::id.eq_any(peered)))
+        .set(routers::updated_at.eq(now()))
+        .execute(conn)
+}
+
+/// Like `touch_peers`, for `tunnel_ids` deleted from or moved off `from_routers`: those routers
+/// also get a config without the tunnels' peerings.
+pub(crate) fn touch_removed(conn: &mut PgConnection, tunnel_ids: &[i32], from_routers: &[i32]) -> QueryResult<usize> {
+    let left = diesel::update(routers::table.filter(routers::id.eq_any(from_routers)))
+        .set(routers::updated_at.eq(now()))
+        .execute(conn)?;
+
+    Ok(left + touch_peers(conn, tunnel_ids)?)
+}
+
+/// Records the interfaces of `config`, just pushed to `router`. Peers it no longer has an
+/// interface for are forgotten, and the router is marked changed so the config it gets next
+/// stops removing them.
+fn record_peers(conn: &mut PgConnection, router: &Router, config: &str) -> QueryResult<()> {
+    let configured = render::configured_peers(router.router_type.as_deref().unwrap_or_default(), config);
+
+    let forgotten = diesel::delete(
+        pushed_peers::table
+            .filter(pushed_peers::router.eq(router.id))
+            .filter(pushed_peers::peer.ne_all(&configured)),
+    )
+    .execute(conn)?;
+    diesel::insert_into(pushed_peers::table)
+        .values(
+            configured
+                .iter()
+                .map(|peer| (pushed_peers::router.eq(router.id), pushed_peers::peer.eq(peer)))
+                .collect::<Vec<_>>(),
+        )
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+
+    if forgotten > 0 {
+        diesel::update(routers::table.find(router.id))
+            .set(routers::updated_at.eq(now()))
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+impl Push {
+    pub const ORDER_FIELDS: [&'static str; 2] = ["id", "finished_at"];
+
+    fn page_key(&self, field: &str) -> (String, i32) {
+        let value = match field {
+            "finished_at" => time_key_value(&self.finished_at),
+            _ => self.id.to_string(),
+        };
+
+        (value, self.id)
+    }
+
+    /// Records the outcome of a push. A successful push of the config rendered now also records
+    /// which peers the router has interfaces for, so they can be removed once the peering goes.
+    #[instrument(skip(result))]
+    pub async fn report(
+        pool: &Pool<ConnectionManager<PgConnection>>,
+        result: &PushResult,
+        defaults: &RenderConfig,
+    ) -> Result<PushRecord, Status> {
+        let required = |field| Status::invalid_argument(format!("{} is required", field));
+        let new_push = NewPush {
+            router: result.router,
+            config_hash: &result.config_hash,
+            driver: &result.driver,
+            started_at: timestamp_to_system_time(result.started_at.clone().ok_or_else(|| required("started_at"))?)?,
+            finished_at: timestamp_to_system_time(result.finished_at.clone().ok_or_else(|| required("finished_at"))?)?,
+            success: result.success,
+            error_output: truncate(&result.error_output),
+        };
+
+        if new_push.finished_at < new_push.started_at {
+            return Err(Status::invalid_argument("finished_at is before started_at"));
+        }
+
+        let conn = &mut pool.get().unwrap();
+
+        let push = match diesel::insert_into(config_pushes::table)
+            .values(new_push)
+            .get_result::<Push>(conn)
+        {
+            Ok(push) => push,
+            Err(Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _)) => {
+                return Err(Status::not_found(format!("no router with id {}", result.router)))
+            }
+            Err(err) => return Err(sql_err_to_grpc_error(err)),
+        };
+
+        if push.success {
+            let router = routers::table
+                .find(push.router)
+                .first::<Router>(conn)
+                .map_err(sql_err_to_grpc_error)?;
+            let all_tunnels = tunnels::table
+                .order(tunnels::id)
+                .load::<Tunnel>(conn)
+                .map_err(sql_err_to_grpc_error)?;
+            let pushed = pushed_peers(conn, &[router.id])
+                .map_err(sql_err_to_grpc_error)?
+                .remove(&router.id)
+                .unwrap_or_default();
+            let keys = keys(conn, std::slice::from_ref(&router), &all_tunnels, defaults).map_err(sql_err_to_grpc_error)?;
+
+            // Only the config rendered now says which interfaces the router has; an older one
+            // may still be on its way.
+            if let Ok(config) = render::render(&router, &all_tunnels, &pushed, &keys, defaults) {
+                if render::config_hash(&config) == push.config_hash {
+                    record_peers(conn, &router, &config).map_err(sql_err_to_grpc_error)?;
+                }
+            }
+        }
+
+        Ok((&push).into())
+    }
+
+    #[instrument]
+    pub async fn history(
+        pool: &Pool<ConnectionManager<PgConnection>>,
+        request: PushHistoryRequest,
+    ) -> Result<PushHistoryResponse, Status> {
+        let order = match request.order_by.as_str() {
+            "" => OrderBy::parse("id desc", &Push::ORDER_FIELDS)?,
+            order_by => OrderBy::parse(order_by, &Push::ORDER_FIELDS)?,
+        };
+        let after = PageToken::decode(&request.page_token, &order)?;
+        let limit = page_size(request.page_size)?;
+        let conn = &mut pool.get().unwrap();
+        let mut query = config_pushes::table
+            .filter(config_pushes::router.eq(request.router))
+            .into_boxed();
+
+        query = match order.field.as_str() {
+            "finished_at" => seek!(
+                query,
+                config_pushes::finished_at,
+                config_pushes::id,
+                order,
+                after.map(|t| t.time_key()).transpose()?
+            ),
+            _ => seek!(
+                query,
+                config_pushes::id,
+                config_pushes::id,
+                order,
+                after.map(|t| t.int_key()).transpose()?
+            ),
+        };
+
+        match query.limit(limit + 1).load::<Push>(conn) {
+            Ok(mut results) => {
+                let next_page_token = next_page(&mut results, limit, &order, Push::page_key);
+
+                Ok(PushHistoryResponse {
+                    pushes: results.iter().map(|p| p.into()).collect(),
+                    next_page_token,
+                })
+            }
+            Err(err) => Err(sql_err_to_grpc_error(err)),
+        }
+    }
+
+    /// Renders the config of every matching router (all of them without `id_or_agent`) and
+    /// compares it with what was last pushed to it.
+    #[instrument]
+    pub async fn sync_status(
+        pool: &Pool<ConnectionManager<PgConnection>>,
+        id_or_agent: Option<&IdOrAgent>,
+        defaults: &RenderConfig,
+    ) -> Result<SyncStatusResponse, Status> {
+        let conn = &mut pool.get().unwrap();
+
+        let mut query = routers::table.order(routers::id).into_boxed();
+        query = match id_or_agent {
+            Some(IdOrAgent::Id(router_id)) => query.filter(routers::id.eq(*router_id)),
+            Some(IdOrAgent::Agent(agent_id)) => query.filter(routers::agent.eq(*agent_id)),
+            None => query,
+        };
+
+        let router_rows = query.load::<Router>(conn).map_err(sql_err_to_grpc_error)?;
+        let all_tunnels = tunnels::table
+            .order(tunnels::id)
+            .load::<Tunnel>(conn)
+            .map_err(sql_err_to_grpc_error)?;
+
+        let router_ids: Vec<i32> = router_rows.iter().map(|r| r.id).collect();
+        let by_router = |pushes: Vec<Push>| pushes.into_iter().map(|p| (p.router, p)).collect::<HashMap<_, _>>();
+        let mut last_pushes = by_router(latest(conn, &router_ids, false).map_err(sql_err_to_grpc_error)?);
+        let successful = by_router(latest(conn, &router_ids, true).map_err(sql_err_to_grpc_error)?);
+        let pushed = pushed_peers(conn, &router_ids).map_err(sql_err_to_grpc_error)?;
+        let keys = keys(conn, &router_rows, &all_tunnels, defaults).map_err(sql_err_to_grpc_error)?;
+
+        let routers = router_rows
+            .iter()
+            .map(|r| {
+                let pushed = pushed.get(&r.id).map_or(&[][..], Vec::as_slice);
+                let (desired_hash, render_error) = match render::render(r, &all_tunnels, pushed, &keys, defaults) {
+                    Ok(config) => (render::config_hash(&config), String::new()),
+                    Err(err) => (String::new(), err),
+                };
+                let pushed_hash = successful.get(&r.id).map(|p| p.config_hash.clone()).unwrap_or_default();
+
+                RouterSync {
+                    router: r.id,
+                    agent: r.agent,
+                    behind: pushed_hash != desired_hash,
+                    desired_hash,
+                    render_error,
+                    pushed_hash,
+                    last_push: last_pushes.remove(&r.id).map(|p| (&p).into()),
+                }
+            })
+            .collect();
+
+        Ok(SyncStatusResponse { routers })
+    }
+}
@@ -0,0 +1,173 @@
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime};
+
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use tonic::Status;
+use tracing::instrument;
+
+use crate::api::{self, ResolutionHistoryRequest, ResolutionHistoryResponse};
+use crate::schema::{address_resolutions, tunnels};
+use crate::storage::helpers::sql_err_to_grpc_error;
+use crate::storage::pagination::{next_page, page_size, seek, time_key_value, OrderBy, PageToken};
+use crate::storage::sql_types::now;
+use crate::storage::tunnels::{touch_peer_routers, Tunnel};
+
+#[derive(Queryable, Identifiable, Associations, Debug)]
+#[diesel(belongs_to(Tunnel, foreign_key = tunnel))]
+#[diesel(table_name = address_resolutions)]
+pub struct AddressResolution {
+    pub id: i32,
+    pub tunnel: i32,
+    pub address: Option<String>,
+    pub error: String,
+    pub ttl_secs: i32,
+    pub resolved_at: SystemTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = address_resolutions)]
+pub struct NewAddressResolution<'a> {
+    pub tunnel: i32,
+    pub address: Option<&'a str>,
+    pub error: &'a str,
+    pub ttl_secs: i32,
+}
+
+impl From<&AddressResolution> for api::AddressResolution {
+    fn from(r: &AddressResolution) -> api::AddressResolution {
+        api::AddressResolution {
+            id: r.id,
+            tunnel: r.tunnel,
+            ip: r.address.clone().unwrap_or_default(),
+            error: r.error.clone(),
+            ttl_secs: r.ttl_secs,
+            resolved_at: Some(r.resolved_at.into()),
+        }
+    }
+}
+
+impl AddressResolution {
+    pub const ORDER_FIELDS: [&'static str; 2] = ["id", "resolved_at"];
+
+    fn page_key(&self, field: &str) -> (String, i32) {
+        let value = match field {
+            "resolved_at" => time_key_value(&self.resolved_at),
+            _ => self.id.to_string(),
+        };
+
+        (value, self.id)
+    }
+
+    /// Dynamic endpoints the server resolves the hostname of: those named by a hostname whose
+    /// agent does not report their address itself.
+    #[instrument]
+    pub async fn candidates(pool: &Pool<ConnectionManager<PgConnection>>) -> Result<Vec<Tunnel>, Status> {
+        let conn = &mut pool.get().unwrap();
+
+        match tunnels::table
+            .filter(tunnels::dynamic_ip.eq(true))
+            .filter(tunnels::ip_reported_at.is_null())
+            .order(tunnels::id)
+            .load::<Tunnel>(conn)
+        {
+            Ok(results) => Ok(results
+                .into_iter()
+                .filter(|t| !t.hostname.is_empty() && t.hostname.parse::<IpAddr>().is_err())
+                .collect()),
+            Err(err) => Err(sql_err_to_grpc_error(err)),
+        }
+    }
+
+    /// Records what resolving `tunnel`'s hostname came to, when it differs from the last time.
+    /// A new address becomes the tunnel's IP and marks the routers peering with it as changed;
+    /// those are returned.
+    #[instrument]
+    pub async fn record(
+        pool: &Pool<ConnectionManager<PgConnection>>,
+        tunnel: &Tunnel,
+        outcome: &Result<IpAddr, String>,
+        ttl: Duration,
+    ) -> Result<Vec<i32>, Status> {
+        let conn = &mut pool.get().unwrap();
+        let address = outcome.as_ref().ok().map(IpAddr::to_string);
+        let error = outcome.as_ref().err().map(String::as_str).unwrap_or_default();
+
+        let result = conn.transaction(|conn| {
+            let last = address_resolutions::table
+                .filter(address_resolutions::tunnel.eq(tunnel.id))
+                .order(address_resolutions::id.desc())
+                .first::<AddressResolution>(conn)
+                .optional()?;
+
+            if last.is_none_or(|last| last.address != address || last.error != error) {
+                diesel::insert_into(address_resolutions::table)
+                    .values(NewAddressResolution {
+                        tunnel: tunnel.id,
+                        address: address.as_deref(),
+                        error,
+                        ttl_secs: ttl.as_secs().try_into().unwrap_or(i32::MAX),
+                    })
+                    .execute(conn)?;
+            }
+
+            match &address {
+                Some(address) if *address != tunnel.ip || tunnel.ip_resolved_at.is_none() => {
+                    let tunnel = diesel::update(tunnels::table.find(tunnel.id))
+                        .set((tunnels::ip.eq(address), tunnels::ip_resolved_at.eq(now().nullable())))
+                        .get_result::<Tunnel>(conn)?;
+                    touch_peer_routers(conn, &tunnel)
+                }
+                _ => Ok(Vec::new()),
+            }
+        });
+
+        result.map_err(sql_err_to_grpc_error)
+    }
+
+    #[instrument]
+    pub async fn history(
+        pool: &Pool<ConnectionManager<PgConnection>>,
+        request: ResolutionHistoryRequest,
+    ) -> Result<ResolutionHistoryResponse, Status> {
+        let order = match request.order_by.as_str() {
+            "" => OrderBy::parse("id desc", &AddressResolution::ORDER_FIELDS)?,
+            order_by => OrderBy::parse(order_by, &AddressResolution::ORDER_FIELDS)?,
+        };
+        let after = PageToken::decode(&request.page_token, &order)?;
+        let limit = page_size(request.page_size)?;
+        let conn = &mut pool.get().unwrap();
+        let mut query = address_resolutions::table
+            .filter(address_resolutions::tunnel.eq(request.tunnel))
+            .into_boxed();
+
+        query = match order.field.as_str() {
+            "resolved_at" => seek!(
+                query,
+                address_resolutions::resolved_at,
+                address_resolutions::id,
+                order,
+                after.map(|t| t.time_key()).transpose()?
+            ),
+            _ => seek!(
+                query,
+                address_resolutions::id,
+                address_resolutions::id,
+                order,
+                after.map(|t| t.int_key()).transpose()?
+            ),
+        };
+
+        match query.limit(limit + 1).load::<AddressResolution>(conn) {
+            Ok(mut results) => {
+                let next_page_token = next_page(&mut results, limit, &order, AddressResolution::page_key);
+
+                Ok(ResolutionHistoryResponse {
+                    resolutions: results.iter().map(|r| r.into()).collect(),
+                    next_page_token,
+                })
+            }
+            Err(err) => Err(sql_err_to_grpc_error(err)),
+        }
+    }
+}
@@ -1,15 +1,29 @@
+use std::time::SystemTime;
+
 use diesel::prelude::*;
 use diesel::r2d2::{ConnectionManager, Pool};
 use tonic::Status;
 use tracing::instrument;
 
 use crate::api::router_request::IdOrAgent;
-use crate::api::{RouterResponse, RouterAddRequest, RouterUpdateRequest};
+use crate::api::{ConfigDiff, RouterConfig, RouterResponse, RoutersResponse, RouterAddRequest, RouterListRequest, RouterUpdateRequest};
+use crate::schema::agents;
 use crate::schema::routers;
 use crate::schema::routers::dsl::*;
-use crate::storage::helpers::sql_err_to_grpc_error;
+use crate::config::RenderConfig;
+use crate::render;
+use crate::storage::agents::Agent;
+use crate::storage::drivers::check_driver;
+use crate::storage::helpers::{sql_err_to_grpc_error, timestamp_to_system_time};
+use crate::storage::pagination::{next_page, page_size, seek, time_key_value, OrderBy, PageToken};
+use crate::storage::preview::preview;
+use crate::storage::keys::keys;
+use crate::storage::pushes::pushed_peers;
+use crate::storage::sql_types::{now, UtcTime};
+use crate::storage::tunnels::Tunnel;
 
-#[derive(Queryable, AsChangeset, Default, Debug)]
+#[derive(Queryable, Identifiable, Associations, Debug)]
+#[diesel(belongs_to(Agent, foreign_key = agent))]
 pub struct Router {
     pub id: i32,
     pub agent: i32,
@@ -18,6 +32,8 @@ pub struct Router {
     pub ssh_password: Option<String>,
     pub conn_type: Option<String>,
     pub router_type: Option<String>,
+    pub created_at: SystemTime,
+    pub updated_at: SystemTime,
 }
 
 #[derive(Insertable)]
@@ -47,11 +63,12 @@ impl From<Router> for RouterResponse {
         RouterResponse {
             id: Some(r.id),
             agent: Some(r.agent),
-            snmp_community: r.snmp_community,
             ssh_username: r.ssh_username,
-            ssh_password: r.ssh_password,
             conn_type: r.conn_type,
             router_type: r.router_type,
+            created_at: Some(r.created_at.into()),
+            updated_at: Some(r.updated_at.into()),
+            config_diffs: Vec::new(),
         }
     }
 }
@@ -61,24 +78,79 @@ impl From<&Router> for RouterResponse {
         RouterResponse {
             id: Some(r.id),
             agent: Some(r.agent),
-            snmp_community: r.snmp_community.clone(),
             ssh_username: r.ssh_username.clone(),
-            ssh_password: r.ssh_password.clone(),
             conn_type: r.conn_type.clone(),
             router_type: r.router_type.clone(),
+            created_at: Some(r.created_at.into()),
+            updated_at: Some(r.updated_at.into()),
+            config_diffs: Vec::new(),
         }
     }
 }
 
 impl Router {
+    pub const ORDER_FIELDS: [&'static str; 4] = ["id", "agent", "created_at", "updated_at"];
+
+    fn page_key(&self, field: &str) -> (String, i32) {
+        let value = match field {
+            "agent" => self.agent.to_string(),
+            "created_at" => time_key_value(&self.created_at),
+            "updated_at" => time_key_value(&self.updated_at),
+            _ => self.id.to_string(),
+        };
+
+        (value, self.id)
+    }
+
+    /// The routers `list_request` asks for, of the agents owned by `visible_to` unless it is `None`.
     #[instrument]
     pub async fn all(
         pool: &Pool<ConnectionManager<PgConnection>>,
-    ) -> Result<Vec<RouterResponse>, Status> {
+        list_request: RouterListRequest,
+        visible_to: Option<i32>,
+    ) -> Result<RoutersResponse, Status> {
+        let order = OrderBy::parse(&list_request.order_by, &Router::ORDER_FIELDS)?;
+        let after = PageToken::decode(&list_request.page_token, &order)?;
+        let limit = page_size(list_request.page_size)?;
         let conn = &mut pool.get().unwrap();
+        let mut query = routers.into_boxed();
+
+        if let Some(since) = list_request.updated_since {
+            query = query.filter(updated_at.ge(UtcTime(timestamp_to_system_time(since)?)));
+        }
 
-        match routers.load::<Router>(conn) {
-            Ok(results) => Ok(results.iter().map(|t| t.into()).collect()),
+        if let Some(agent_id) = list_request.agent {
+            query = query.filter(agent.eq(agent_id));
+        }
+
+        if let Some(owner_id) = visible_to {
+            query = query.filter(agent.eq_any(agents::table.filter(agents::owner.eq(owner_id)).select(agents::id)));
+        }
+
+        if let Some(r_type) = list_request.router_type {
+            query = query.filter(router_type.eq(r_type));
+        }
+
+        if let Some(c_type) = list_request.conn_type {
+            query = query.filter(conn_type.eq(c_type));
+        }
+
+        query = match order.field.as_str() {
+            "agent" => seek!(query, agent, id, order, after.map(|t| t.int_key()).transpose()?),
+            "created_at" => seek!(query, created_at, id, order, after.map(|t| t.time_key()).transpose()?),
+            "updated_at" => seek!(query, updated_at, id, order, after.map(|t| t.time_key()).transpose()?),
+            _ => seek!(query, id, id, order, after.map(|t| t.int_key()).transpose()?),
+        };
+
+        match query.limit(limit + 1).load::<Router>(conn) {
+            Ok(mut results) => {
+                let next_page_token = next_page(&mut results, limit, &order, Router::page_key);
+
+                Ok(RoutersResponse {
+                    routers: results.iter().map(|r| r.into()).collect(),
+                    next_page_token,
+                })
+            }
             Err(err) => Err(sql_err_to_grpc_error(err)),
         }
     }
@@ -87,17 +159,21 @@ impl Router {
     pub async fn get(
         pool: &Pool<ConnectionManager<PgConnection>>,
         id_or_agent: &IdOrAgent,
-    ) -> Result<RouterResponse, Status> {
+    ) -> Result<Vec<RouterResponse>, Status> {
         let conn = &mut pool.get().unwrap();
 
         match id_or_agent {
-            IdOrAgent::Id(user_id) => match routers.find(user_id).first::<Router>(conn) {
-                Ok(results) => Ok(results.into()),
+            IdOrAgent::Id(router_id) => match routers.find(router_id).first::<Router>(conn) {
+                Ok(results) => Ok(vec![results.into()]),
                 Err(err) => Err(sql_err_to_grpc_error(err)),
             },
             IdOrAgent::Agent(agent_id) => {
-                match routers.filter(agent.eq(agent_id)).first::<Router>(conn) {
-                    Ok(results) => Ok(results.into()),
+                match routers
+                    .filter(agent.eq(agent_id))
+                    .order(id.asc())
+                    .load::<Router>(conn)
+                {
+                    Ok(results) => Ok(results.iter().map(|r| r.into()).collect()),
                     Err(err) => Err(sql_err_to_grpc_error(err)),
                 }
             }
@@ -108,6 +184,7 @@ impl Router {
     pub async fn add(
         pool: &Pool<ConnectionManager<PgConnection>>,
         router_data: RouterAddRequest,
+        defaults: &RenderConfig,
     ) -> Result<RouterResponse, Status> {
         let new_community = router_data.snmp_community.unwrap_or_default();
         let new_username = router_data.ssh_username.unwrap_or_default();
@@ -123,20 +200,25 @@ impl Router {
             router_type: new_router_type.as_str(),
         };
         let conn = &mut pool.get().unwrap();
+        check_driver(conn, new_router.agent, Some(new_router.router_type), Some(new_router.conn_type))?;
 
-        match diesel::insert_into(routers)
-            .values(&new_router)
-            .get_result::<Router>(conn)
-        {
-            Ok(results) => Ok(results.into()),
-            Err(err) => Err(sql_err_to_grpc_error(err)),
-        }
+        let (router, config_diffs) = preview(conn, router_data.dry_run, defaults, |conn| {
+            diesel::insert_into(routers)
+                .values(&new_router)
+                .get_result::<Router>(conn)
+        })?;
+
+        Ok(RouterResponse {
+            config_diffs,
+            ..router.into()
+        })
     }
 
     #[instrument]
     pub async fn update(
         pool: &Pool<ConnectionManager<PgConnection>>,
         router_data: RouterUpdateRequest,
+        defaults: &RenderConfig,
     ) -> Result<RouterResponse, Status> {
         let conn = &mut pool.get().unwrap();
         let mut update = UpdateRouter::default();
@@ -145,32 +227,100 @@ impl Router {
             return Err(Status::invalid_argument("Router id is required"));
         }
 
-        if !router_data.agent.is_none() {
+        if router_data.agent.is_some() {
             update.agent = router_data.agent;
         }
 
-        if !router_data.snmp_community.is_none() {
+        if router_data.snmp_community.is_some() {
             update.snmp_community = router_data.snmp_community.clone();
         }
 
-        if !router_data.ssh_username.is_none() {
+        if router_data.ssh_username.is_some() {
             update.ssh_username = router_data.ssh_username.clone();
         }
 
-        if !router_data.ssh_password.is_none() {
+        if router_data.ssh_password.is_some() {
             update.ssh_password = router_data.ssh_password.clone();
         }
 
-        if !router_data.conn_type.is_none() {
+        if router_data.conn_type.is_some() {
             update.conn_type = router_data.conn_type.clone();
         }
 
-        if !router_data.router_type.is_none() {
+        if router_data.router_type.is_some() {
             update.router_type = router_data.router_type.clone();
         }
 
-        match diesel::update(routers.find(router_data.id))
-            .set(update)
+        // Tunnels the new router type cannot terminate could never agree with their peers.
+        if let Some(new_type) = update.router_type.as_deref() {
+            use crate::schema::tunnels;
+
+            let kinds = tunnels::table
+                .filter(tunnels::router.eq(router_data.id))
+                .select(tunnels::tunnel_type)
+                .distinct()
+                .load::<String>(conn)
+                .map_err(sql_err_to_grpc_error)?;
+            if let Some(kind) = kinds.iter().find(|kind| !render::supports(new_type, kind)) {
+                return Err(Status::failed_precondition(format!(
+                    "router {} has {} tunnels, which {} does not support",
+                    router_data.id, kind, new_type
+                )));
+            }
+        }
+
+        // The agent must have a driver for what the router ends up as.
+        if update.agent.is_some() || update.router_type.is_some() || update.conn_type.is_some() {
+            let existing = routers
+                .find(router_data.id)
+                .first::<Router>(conn)
+                .map_err(sql_err_to_grpc_error)?;
+            check_driver(
+                conn,
+                update.agent.unwrap_or(existing.agent),
+                update.router_type.as_deref().or(existing.router_type.as_deref()),
+                update.conn_type.as_deref().or(existing.conn_type.as_deref()),
+            )?;
+        }
+
+        let (router, config_diffs) = preview(conn, router_data.dry_run, defaults, |conn| {
+            diesel::update(routers.find(router_data.id))
+                .set(update)
+                .get_result::<Router>(conn)
+        })?;
+
+        Ok(RouterResponse {
+            config_diffs,
+            ..router.into()
+        })
+    }
+
+    #[instrument]
+    pub async fn delete(
+        pool: &Pool<ConnectionManager<PgConnection>>,
+        id_or_agent: IdOrAgent,
+        dry_run: bool,
+        defaults: &RenderConfig,
+    ) -> Result<(usize, Vec<ConfigDiff>), Status> {
+        let conn = &mut pool.get().unwrap();
+
+        preview(conn, dry_run, defaults, |conn| match id_or_agent {
+            IdOrAgent::Id(router_id) => diesel::delete(routers.find(router_id)).execute(conn),
+            IdOrAgent::Agent(agent_id) => diesel::delete(routers.filter(agent.eq(agent_id))).execute(conn),
+        })
+    }
+
+    /// Bumps `updated_at` without changing anything else, so agents syncing with `updated_since`
+    /// pick the router up and push its config again.
+    #[instrument]
+    pub async fn repush(
+        pool: &Pool<ConnectionManager<PgConnection>>,
+        router_id: i32,
+    ) -> Result<RouterResponse, Status> {
+        let conn = &mut pool.get().unwrap();
+
+        match diesel::update(routers.find(router_id))
+            .set(updated_at.eq(now()))
             .get_result::<Router>(conn)
         {
             Ok(results) => Ok(results.into()),
@@ -179,25 +329,43 @@ impl Router {
     }
 
     #[instrument]
-    pub async fn delete(
+    pub async fn render(
         pool: &Pool<ConnectionManager<PgConnection>>,
-        id_or_agent: IdOrAgent,
-    ) -> Result<usize, Status> {
+        router_id: i32,
+        defaults: &RenderConfig,
+    ) -> Result<RouterConfig, Status> {
+        use crate::schema::tunnels;
+
         let conn = &mut pool.get().unwrap();
 
-        match id_or_agent {
-            IdOrAgent::Id(router_id) => {
-                match diesel::delete(routers.find(router_id)).execute(conn) {
-                    Ok(results) => Ok(results),
-                    Err(err) => Err(sql_err_to_grpc_error(err)),
-                }
-            }
-            IdOrAgent::Agent(agent_id) => {
-                match diesel::delete(routers.filter(agent.eq(agent_id))).execute(conn) {
-                    Ok(results) => Ok(results),
-                    Err(err) => Err(sql_err_to_grpc_error(err)),
-                }
-            }
+        let router = match routers.find(router_id).first::<Router>(conn) {
+            Ok(router) => router,
+            Err(err) => return Err(sql_err_to_grpc_error(err)),
+        };
+
+        let all_tunnels = match tunnels::table.order(tunnels::id).load::<Tunnel>(conn) {
+            Ok(results) => results,
+            Err(err) => return Err(sql_err_to_grpc_error(err)),
+        };
+
+        let pushed = match pushed_peers(conn, &[router.id]) {
+            Ok(mut peers) => peers.remove(&router.id).unwrap_or_default(),
+            Err(err) => return Err(sql_err_to_grpc_error(err)),
+        };
+
+        let keys = match keys(conn, std::slice::from_ref(&router), &all_tunnels, defaults) {
+            Ok(keys) => keys,
+            Err(err) => return Err(sql_err_to_grpc_error(err)),
+        };
+
+        match render::render(&router, &all_tunnels, &pushed, &keys, defaults) {
+            Ok(config) => Ok(RouterConfig {
+                router: router.id,
+                router_type: router.router_type.unwrap_or_default(),
+                config_hash: render::config_hash(&config),
+                config,
+            }),
+            Err(message) => Err(Status::failed_precondition(message)),
         }
     }
 }
@@ -0,0 +1,56 @@
+use std::time::SystemTime;
+
+use diesel::deserialize::{self, FromSql};
+use diesel::expression::SqlLiteral;
+use diesel::pg::{Pg, PgValue};
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::ops::{Add, Sub};
+use diesel::sql_types::{self, Interval, SqlOrd};
+
+/// `TIMESTAMPTZ`, read as `SystemTime` and written as `UtcTime`. Diesel only maps its own
+/// `Timestamptz` to the chrono and time crates, but Postgres sends both timestamp types as
+/// microseconds since 2000-01-01 UTC, so this reuses the encoding diesel has for `SystemTime`.
+#[derive(Debug, Clone, Copy, Default, diesel::sql_types::SqlType, diesel::query_builder::QueryId)]
+#[diesel(postgres_type(oid = 1184, array_oid = 1185))]
+pub struct Timestamptz;
+
+impl SqlOrd for Timestamptz {}
+
+impl Add for Timestamptz {
+    type Rhs = Interval;
+    type Output = Timestamptz;
+}
+
+impl Sub for Timestamptz {
+    type Rhs = Interval;
+    type Output = Timestamptz;
+}
+
+impl FromSql<Timestamptz, Pg> for SystemTime {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        FromSql::<sql_types::Timestamp, Pg>::from_sql(bytes)
+    }
+}
+
+/// A `SystemTime` bound as a `TIMESTAMPTZ` parameter. Model fields stay `SystemTime` and name this
+/// with `#[diesel(serialize_as = UtcTime)]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, diesel::expression::AsExpression)]
+#[diesel(sql_type = Timestamptz)]
+pub struct UtcTime(pub SystemTime);
+
+impl From<SystemTime> for UtcTime {
+    fn from(time: SystemTime) -> Self {
+        UtcTime(time)
+    }
+}
+
+impl ToSql<Timestamptz, Pg> for UtcTime {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        ToSql::<sql_types::Timestamp, Pg>::to_sql(&self.0, out)
+    }
+}
+
+/// `CURRENT_TIMESTAMP`, in place of `diesel::dsl::now` which is a `TIMESTAMP`.
+pub fn now() -> SqlLiteral<Timestamptz> {
+    diesel::dsl::sql("CURRENT_TIMESTAMP")
+}
@@ -1,15 +1,30 @@
+use std::net::IpAddr;
+use std::time::SystemTime;
+
 use diesel::prelude::*;
 use diesel::r2d2::{ConnectionManager, Pool};
 use tonic::Status;
 use tracing::instrument;
 
-use crate::api::{TunnelAddRequest, TunnelResponse, TunnelUpdateRequest};
+use crate::api::{AddressReportResponse, ConfigDiff, TunnelAddRequest, TunnelListRequest, TunnelResponse, TunnelsResponse, TunnelUpdateRequest};
 use crate::api::tunnel_request::IdOrRouter;
+use crate::config::RenderConfig;
+use crate::render;
+use crate::schema::agents;
+use crate::schema::routers;
 use crate::schema::tunnels;
 use crate::schema::tunnels::dsl::*;
-use crate::storage::helpers::sql_err_to_grpc_error;
+use crate::storage::helpers::{sql_err_to_grpc_error, timestamp_to_system_time};
+use crate::storage::pagination::{
+    contains_pattern, next_page, page_size, seek, time_key_value, OrderBy, PageToken,
+};
+use crate::storage::preview::preview;
+use crate::storage::pushes::touch_removed;
+use crate::storage::routers::Router;
+use crate::storage::sql_types::{now, UtcTime};
 
-#[derive(Queryable, Default, Debug)]
+#[derive(Queryable, Identifiable, Associations, Debug)]
+#[diesel(belongs_to(Router, foreign_key = router))]
 pub struct Tunnel {
     pub id: i32,
     pub version: i32,
@@ -23,22 +38,26 @@ pub struct Tunnel {
     pub cost: i32,
     pub tunnel_type: String,
     pub topology_type: String,
+    pub created_at: SystemTime,
+    pub updated_at: SystemTime,
+    pub ip_reported_at: Option<SystemTime>,
+    pub ip_resolved_at: Option<SystemTime>,
 }
 
 #[derive(Insertable)]
 #[diesel(table_name = tunnels)]
 pub struct NewTunnel<'a> {
-    pub version: i32,
+    pub version: Option<i32>,
     pub router: i32,
     pub ip: &'a str,
-    pub dynamic_ip: bool,
-    pub ip_class: i32,
+    pub dynamic_ip: Option<bool>,
+    pub ip_class: Option<i32>,
     pub hostname: &'a str,
     pub description: &'a str,
     pub source: &'a str,
-    pub cost: i32,
-    pub tunnel_type: &'a str,
-    pub topology_type: &'a str,
+    pub cost: Option<i32>,
+    pub tunnel_type: Option<&'a str>,
+    pub topology_type: Option<&'a str>,
 }
 
 #[derive(AsChangeset, Default)]
@@ -57,6 +76,41 @@ pub struct UpdateTunnel {
     pub topology_type: Option<String>,
 }
 
+/// Marks the routers peering with `tunnel` as changed, after its address did, and returns them.
+pub(crate) fn touch_peer_routers(conn: &mut PgConnection, tunnel: &Tunnel) -> QueryResult<Vec<i32>> {
+    let others = tunnels.filter(router.ne(tunnel.router)).load::<Tunnel>(conn)?;
+    let mut peers: Vec<i32> = others
+        .iter()
+        .filter(|other| render::peers(tunnel, other))
+        .map(|other| other.router)
+        .collect();
+    peers.sort_unstable();
+    peers.dedup();
+
+    diesel::update(routers::table.filter(routers::id.eq_any(&peers)))
+        .set(routers::updated_at.eq(now()))
+        .execute(conn)?;
+
+    Ok(peers)
+}
+
+/// Fails unless a `kind` endpoint can go on router `router_id`, as [`render::supports`] says. An
+/// endpoint its router cannot terminate could never agree with its peers.
+fn check_tunnel_type(conn: &mut PgConnection, router_id: i32, kind: &str) -> Result<(), Status> {
+    match routers::table
+        .find(router_id)
+        .select(routers::router_type)
+        .first::<Option<String>>(conn)
+        .optional()
+    {
+        Ok(Some(Some(router_type))) if !render::supports(&router_type, kind) => Err(Status::failed_precondition(
+            format!("router {} runs {}, which does not support {} tunnels", router_id, router_type, kind),
+        )),
+        Ok(_) => Ok(()),
+        Err(err) => Err(sql_err_to_grpc_error(err)),
+    }
+}
+
 impl From<Tunnel> for TunnelResponse {
     fn from(t: Tunnel) -> TunnelResponse {
         TunnelResponse {
@@ -72,6 +126,11 @@ impl From<Tunnel> for TunnelResponse {
             cost: t.cost,
             tunnel_type: t.tunnel_type,
             topology_type: t.topology_type,
+            created_at: Some(t.created_at.into()),
+            updated_at: Some(t.updated_at.into()),
+            config_diffs: Vec::new(),
+            ip_reported_at: t.ip_reported_at.map(Into::into),
+            ip_resolved_at: t.ip_resolved_at.map(Into::into),
         }
     }
 }
@@ -91,19 +150,117 @@ impl From<&Tunnel> for TunnelResponse {
             cost: t.cost,
             tunnel_type: t.tunnel_type.clone(),
             topology_type: t.topology_type.clone(),
+            created_at: Some(t.created_at.into()),
+            updated_at: Some(t.updated_at.into()),
+            config_diffs: Vec::new(),
+            ip_reported_at: t.ip_reported_at.map(Into::into),
+            ip_resolved_at: t.ip_resolved_at.map(Into::into),
         }
     }
 }
 
 impl Tunnel {
+    pub const ORDER_FIELDS: [&'static str; 7] = [
+        "id",
+        "router",
+        "hostname",
+        "cost",
+        "topology_type",
+        "created_at",
+        "updated_at",
+    ];
+
+    fn page_key(&self, field: &str) -> (String, i32) {
+        let value = match field {
+            "router" => self.router.to_string(),
+            "hostname" => self.hostname.clone(),
+            "cost" => self.cost.to_string(),
+            "topology_type" => self.topology_type.clone(),
+            "created_at" => time_key_value(&self.created_at),
+            "updated_at" => time_key_value(&self.updated_at),
+            _ => self.id.to_string(),
+        };
+
+        (value, self.id)
+    }
+
+    /// The tunnels `list_request` asks for, of the agents owned by `visible_to` unless it is `None`.
     #[instrument]
     pub async fn all(
         pool: &Pool<ConnectionManager<PgConnection>>,
-    ) -> Result<Vec<TunnelResponse>, Status> {
+        list_request: TunnelListRequest,
+        visible_to: Option<i32>,
+    ) -> Result<TunnelsResponse, Status> {
+        let order = OrderBy::parse(&list_request.order_by, &Tunnel::ORDER_FIELDS)?;
+        let after = PageToken::decode(&list_request.page_token, &order)?;
+        let limit = page_size(list_request.page_size)?;
         let conn = &mut pool.get().unwrap();
+        let mut query = tunnels.into_boxed();
+
+        if let Some(since) = list_request.updated_since {
+            query = query.filter(updated_at.ge(UtcTime(timestamp_to_system_time(since)?)));
+        }
+
+        if let Some(router_id) = list_request.router {
+            query = query.filter(router.eq(router_id));
+        }
+
+        if let Some(owner_id) = visible_to {
+            query = query.filter(
+                router.eq_any(
+                    routers::table
+                        .inner_join(agents::table)
+                        .filter(agents::owner.eq(owner_id))
+                        .select(routers::id),
+                ),
+            );
+        }
+
+        if let Some(agent_id) = list_request.agent {
+            query = query.filter(
+                router.eq_any(
+                    routers::table
+                        .filter(routers::agent.eq(agent_id))
+                        .select(routers::id),
+                ),
+            );
+        }
+
+        if let Some(topology) = list_request.topology_type {
+            query = query.filter(topology_type.eq(topology));
+        }
+
+        if let Some(class) = list_request.ip_class {
+            query = query.filter(ip_class.eq(class));
+        }
+
+        if let Some(needle) = list_request.hostname_contains {
+            query = query.filter(hostname.ilike(contains_pattern(&needle)));
+        }
+
+        if let Some(tun_type) = list_request.tunnel_type {
+            query = query.filter(tunnel_type.eq(tun_type));
+        }
 
-        match tunnels.load::<Tunnel>(conn) {
-            Ok(results) => Ok(results.iter().map(|t| t.into()).collect()),
+        query = match order.field.as_str() {
+            "router" => seek!(query, router, id, order, after.map(|t| t.int_key()).transpose()?),
+            "hostname" => seek!(query, hostname, id, order, after.map(|t| t.string_key())),
+            "cost" => seek!(query, cost, id, order, after.map(|t| t.int_key()).transpose()?),
+            "topology_type" => seek!(query, topology_type, id, order, after.map(|t| t.string_key())),
+            "created_at" => seek!(query, created_at, id, order, after.map(|t| t.time_key()).transpose()?),
+            "updated_at" => seek!(query, updated_at, id, order, after.map(|t| t.time_key()).transpose()?),
+            _ => seek!(query, id, id, order, after.map(|t| t.int_key()).transpose()?),
+        };
+
+        match query.limit(limit + 1).load::<Tunnel>(conn) {
+            Ok(mut results) => {
+                let next_page_token = next_page(&mut results, limit, &order, Tunnel::page_key);
+
+                Ok(TunnelsResponse {
+                    tunnels: results.iter().map(|t| t.into()).collect(),
+                    next_page_token,
+                })
+            }
             Err(err) => Err(sql_err_to_grpc_error(err)),
         }
     }
@@ -112,17 +269,21 @@ impl Tunnel {
     pub async fn get(
         pool: &Pool<ConnectionManager<PgConnection>>,
         id_or_router: &IdOrRouter,
-    ) -> Result<TunnelResponse, Status> {
+    ) -> Result<Vec<TunnelResponse>, Status> {
         let conn = &mut pool.get().unwrap();
 
         match id_or_router {
-            IdOrRouter::Id(user_id) => match tunnels.find(user_id).first::<Tunnel>(conn) {
-                Ok(results) => Ok(results.into()),
+            IdOrRouter::Id(tunnel_id) => match tunnels.find(tunnel_id).first::<Tunnel>(conn) {
+                Ok(results) => Ok(vec![results.into()]),
                 Err(err) => Err(sql_err_to_grpc_error(err)),
             },
             IdOrRouter::Router(router_id) => {
-                match tunnels.filter(router.eq(router_id)).first::<Tunnel>(conn) {
-                    Ok(results) => Ok(results.into()),
+                match tunnels
+                    .filter(router.eq(router_id))
+                    .order(id.asc())
+                    .load::<Tunnel>(conn)
+                {
+                    Ok(results) => Ok(results.iter().map(|t| t.into()).collect()),
                     Err(err) => Err(sql_err_to_grpc_error(err)),
                 }
             }
@@ -133,110 +294,202 @@ impl Tunnel {
     pub async fn add(
         pool: &Pool<ConnectionManager<PgConnection>>,
         tunnel_data: TunnelAddRequest,
+        defaults: &RenderConfig,
     ) -> Result<TunnelResponse, Status> {
-        let tun_type = tunnel_data.tunnel_type.unwrap_or_default();
-        let top_type = tunnel_data.topology_type.unwrap_or_default();
+        // Fields left unset fall back to the column defaults.
         let new_user = NewTunnel {
-            version: tunnel_data.version.unwrap_or_default(),
+            version: tunnel_data.version,
             router: tunnel_data.router,
             ip: tunnel_data.ip.as_str(),
-            dynamic_ip: tunnel_data.dynamic_ip.unwrap_or_default(),
-            ip_class: tunnel_data.ip_class.unwrap_or_default(),
+            dynamic_ip: tunnel_data.dynamic_ip,
+            ip_class: tunnel_data.ip_class,
             description: tunnel_data.description.as_str(),
             source: tunnel_data.source.as_str(),
-            cost: tunnel_data.cost.unwrap_or_default(),
-            tunnel_type: tun_type.as_str(),
+            cost: tunnel_data.cost,
+            tunnel_type: tunnel_data.tunnel_type.as_deref(),
             hostname: tunnel_data.hostname.as_str(),
-            topology_type: top_type.as_str(),
+            topology_type: tunnel_data.topology_type.as_deref(),
         };
         let conn = &mut pool.get().unwrap();
 
-        match diesel::insert_into(tunnels)
-            .values(&new_user)
-            .get_result::<Tunnel>(conn)
-        {
-            Ok(results) => Ok(results.into()),
-            Err(err) => Err(sql_err_to_grpc_error(err)),
+        if let Some(kind) = new_user.tunnel_type {
+            check_tunnel_type(conn, new_user.router, kind)?;
         }
+
+        let (tunnel, config_diffs) = preview(conn, tunnel_data.dry_run, defaults, |conn| {
+            diesel::insert_into(tunnels)
+                .values(&new_user)
+                .get_result::<Tunnel>(conn)
+        })?;
+
+        Ok(TunnelResponse {
+            config_diffs,
+            ..tunnel.into()
+        })
     }
 
     #[instrument]
     pub async fn update(
         pool: &Pool<ConnectionManager<PgConnection>>,
         tunnel_data: TunnelUpdateRequest,
+        defaults: &RenderConfig,
     ) -> Result<TunnelResponse, Status> {
         let conn = &mut pool.get().unwrap();
         let mut update = UpdateTunnel::default();
 
-        if !tunnel_data.version.is_none() {
+        if tunnel_data.version.is_some() {
             update.version = tunnel_data.version;
         }
 
-        if !tunnel_data.router.is_none() {
+        if tunnel_data.router.is_some() {
             update.router = tunnel_data.router;
         }
 
-        if !tunnel_data.ip.is_none() {
+        if tunnel_data.ip.is_some() {
             update.ip = tunnel_data.ip;
         }
 
-        if !tunnel_data.ip_class.is_none()  {
+        if tunnel_data.ip_class.is_some() {
             update.ip_class = tunnel_data.ip_class;
         }
 
-        if !tunnel_data.description.is_none() {
+        if tunnel_data.description.is_some() {
             update.description = tunnel_data.description;
         }
 
-        if !tunnel_data.source.is_none() {
+        if tunnel_data.source.is_some() {
             update.source = tunnel_data.source;
         }
 
-        if !tunnel_data.cost.is_none()  {
+        if tunnel_data.cost.is_some() {
             update.cost = tunnel_data.cost;
         }
 
-        if !tunnel_data.tunnel_type.is_none() {
+        if tunnel_data.tunnel_type.is_some() {
             update.tunnel_type = tunnel_data.tunnel_type;
         }
 
-        if !tunnel_data.hostname.is_none() {
+        if tunnel_data.hostname.is_some() {
             update.hostname = tunnel_data.hostname;
         }
 
-        if !tunnel_data.topology_type.is_none() {
+        if tunnel_data.topology_type.is_some() {
             update.topology_type = tunnel_data.topology_type;
         }
 
-        match diesel::update(tunnels.find(tunnel_data.id))
-            .set(update)
-            .get_result::<Tunnel>(conn)
-        {
-            Ok(results) => Ok(results.into()),
-            Err(err) => Err(sql_err_to_grpc_error(err)),
+        let mut moved_from = None;
+        if update.router.is_some() || update.tunnel_type.is_some() {
+            // A tunnel that does not exist is left for the update to report.
+            if let Ok(current) = tunnels.find(tunnel_data.id).first::<Tunnel>(conn) {
+                check_tunnel_type(
+                    conn,
+                    update.router.unwrap_or(current.router),
+                    update.tunnel_type.as_deref().unwrap_or(&current.tunnel_type),
+                )?;
+                moved_from = update.router.filter(|to| *to != current.router).map(|_| current.router);
+            }
         }
+
+        // The router the tunnel leaves, and those that were pushed an interface to it, get a
+        // config without its old peerings.
+        let (tunnel, config_diffs) = preview(conn, tunnel_data.dry_run, defaults, |conn| {
+            let tunnel = diesel::update(tunnels.find(tunnel_data.id))
+                .set(update)
+                .get_result::<Tunnel>(conn)?;
+            if let Some(from) = moved_from {
+                touch_removed(conn, &[tunnel.id], &[from])?;
+            }
+            Ok(tunnel)
+        })?;
+
+        Ok(TunnelResponse {
+            config_diffs,
+            ..tunnel.into()
+        })
     }
 
     #[instrument]
     pub async fn delete(
         pool: &Pool<ConnectionManager<PgConnection>>,
         id_or_router: IdOrRouter,
-    ) -> Result<usize, Status> {
+        dry_run: bool,
+        defaults: &RenderConfig,
+    ) -> Result<(usize, Vec<ConfigDiff>), Status> {
         let conn = &mut pool.get().unwrap();
 
-        match id_or_router {
-            IdOrRouter::Id(permission_id) => {
-                match diesel::delete(tunnels.find(permission_id)).execute(conn) {
-                    Ok(results) => Ok(results),
-                    Err(err) => Err(sql_err_to_grpc_error(err)),
-                }
-            }
-            IdOrRouter::Router(router_id) => {
-                match diesel::delete(tunnels.filter(router.eq(router_id))).execute(conn) {
-                    Ok(results) => Ok(results),
-                    Err(err) => Err(sql_err_to_grpc_error(err)),
-                }
+        // The routers of deleted tunnels, and those that were pushed an interface to one, get a
+        // config without its peerings.
+        preview(conn, dry_run, defaults, |conn| {
+            let deleted = match id_or_router {
+                IdOrRouter::Id(tunnel_id) => diesel::delete(tunnels.find(tunnel_id))
+                    .returning((id, router))
+                    .get_results::<(i32, i32)>(conn)?,
+                IdOrRouter::Router(router_id) => diesel::delete(tunnels.filter(router.eq(router_id)))
+                    .returning((id, router))
+                    .get_results::<(i32, i32)>(conn)?,
+            };
+            let (deleted, from): (Vec<i32>, Vec<i32>) = deleted.into_iter().unzip();
+            touch_removed(conn, &deleted, &from)?;
+            Ok(deleted.len())
+        })
+    }
+
+    /// Records the public address the agent of a dynamic endpoint found for it. A new address
+    /// only marks the routers peering with the endpoint as changed, since no other config
+    /// mentions it.
+    #[instrument]
+    pub async fn report_address(
+        pool: &Pool<ConnectionManager<PgConnection>>,
+        tunnel_id: i32,
+        address: IpAddr,
+    ) -> Result<AddressReportResponse, Status> {
+        let conn = &mut pool.get().unwrap();
+
+        let tunnel = match tunnels.find(tunnel_id).first::<Tunnel>(conn) {
+            Ok(tunnel) => tunnel,
+            Err(diesel::result::Error::NotFound) => {
+                return Err(Status::not_found(format!("no tunnel with id {}", tunnel_id)))
             }
+            Err(err) => return Err(sql_err_to_grpc_error(err)),
+        };
+
+        if !tunnel.dynamic_ip {
+            return Err(Status::failed_precondition(format!("tunnel {} does not have a dynamic IP", tunnel_id)));
+        }
+
+        let class = if address.is_ipv4() { 4 } else { 6 };
+        if class != tunnel.ip_class {
+            return Err(Status::invalid_argument(format!(
+                "{} is not an IPv{} address, as tunnel {} needs",
+                address, tunnel.ip_class, tunnel_id
+            )));
+        }
+
+        let address = address.to_string();
+        if tunnel.ip == address && tunnel.ip_reported_at.is_some() {
+            return Ok(AddressReportResponse {
+                tunnel: Some(tunnel.into()),
+                changed: false,
+                peers: Vec::new(),
+            });
+        }
+
+        let result = conn.transaction(|conn| {
+            let tunnel = diesel::update(tunnels.find(tunnel_id))
+                .set((ip.eq(&address), ip_reported_at.eq(now().nullable())))
+                .get_result::<Tunnel>(conn)?;
+
+            let peers = touch_peer_routers(conn, &tunnel)?;
+            Ok((tunnel, peers))
+        });
+
+        match result {
+            Ok((tunnel, peers)) => Ok(AddressReportResponse {
+                tunnel: Some(tunnel.into()),
+                changed: true,
+                peers,
+            }),
+            Err(err) => Err(sql_err_to_grpc_error(err)),
         }
     }
 }
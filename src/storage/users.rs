@@ -1,19 +1,27 @@
+use std::time::SystemTime;
+
 use diesel::prelude::*;
 use diesel::r2d2::{ConnectionManager, Pool};
 use tonic::Status;
 use tracing::instrument;
 
 use crate::api::user_request::IdOrEmail;
-use crate::api::{UserResponse, UserAddRequest, UserUpdateRequest};
+use crate::api::{UserResponse, UsersResponse, UserAddRequest, UserListRequest, UserUpdateRequest};
 use crate::schema::users;
 use crate::schema::users::dsl::*;
-use crate::storage::helpers::sql_err_to_grpc_error;
+use crate::storage::helpers::{sql_err_to_grpc_error, timestamp_to_system_time};
+use crate::storage::pagination::{
+    contains_pattern, next_page, page_size, seek, time_key_value, OrderBy, PageToken,
+};
+use crate::storage::sql_types::UtcTime;
 
-#[derive(Queryable, Default, Debug)]
+#[derive(Queryable, Debug)]
 pub struct User {
     pub id: i32,
     pub email: String,
     pub password: String,
+    pub created_at: SystemTime,
+    pub updated_at: SystemTime,
 }
 
 #[derive(Insertable)]
@@ -33,6 +41,8 @@ impl From<User> for UserResponse {
         UserResponse {
             id: u.id,
             email: u.email,
+            created_at: Some(u.created_at.into()),
+            updated_at: Some(u.updated_at.into()),
         }
     }
 }
@@ -42,19 +52,61 @@ impl From<&User> for UserResponse {
         UserResponse {
             id: u.id,
             email: u.email.clone(),
+            created_at: Some(u.created_at.into()),
+            updated_at: Some(u.updated_at.into()),
         }
     }
 }
 
 impl User {
+    pub const ORDER_FIELDS: [&'static str; 4] = ["id", "email", "created_at", "updated_at"];
+
+    fn page_key(&self, field: &str) -> (String, i32) {
+        let value = match field {
+            "email" => self.email.clone(),
+            "created_at" => time_key_value(&self.created_at),
+            "updated_at" => time_key_value(&self.updated_at),
+            _ => self.id.to_string(),
+        };
+
+        (value, self.id)
+    }
+
     #[instrument]
     pub async fn all(
         pool: &Pool<ConnectionManager<PgConnection>>,
-    ) -> Result<Vec<UserResponse>, Status> {
+        list_request: UserListRequest,
+    ) -> Result<UsersResponse, Status> {
+        let order = OrderBy::parse(&list_request.order_by, &User::ORDER_FIELDS)?;
+        let after = PageToken::decode(&list_request.page_token, &order)?;
+        let limit = page_size(list_request.page_size)?;
         let conn = &mut pool.get().unwrap();
+        let mut query = users.into_boxed();
+
+        if let Some(since) = list_request.updated_since {
+            query = query.filter(updated_at.ge(UtcTime(timestamp_to_system_time(since)?)));
+        }
 
-        match users.load::<User>(conn) {
-            Ok(results) => Ok(results.iter().map(|t| t.into()).collect()),
+        if let Some(needle) = list_request.email_contains {
+            query = query.filter(email.ilike(contains_pattern(&needle)));
+        }
+
+        query = match order.field.as_str() {
+            "email" => seek!(query, email, id, order, after.map(|t| t.string_key())),
+            "created_at" => seek!(query, created_at, id, order, after.map(|t| t.time_key()).transpose()?),
+            "updated_at" => seek!(query, updated_at, id, order, after.map(|t| t.time_key()).transpose()?),
+            _ => seek!(query, id, id, order, after.map(|t| t.int_key()).transpose()?),
+        };
+
+        match query.limit(limit + 1).load::<User>(conn) {
+            Ok(mut results) => {
+                let next_page_token = next_page(&mut results, limit, &order, User::page_key);
+
+                Ok(UsersResponse {
+                    users: results.iter().map(|u| u.into()).collect(),
+                    next_page_token,
+                })
+            }
             Err(err) => Err(sql_err_to_grpc_error(err)),
         }
     }
@@ -111,7 +163,7 @@ impl User {
             return Err(Status::invalid_argument("User id is required"));
         }
 
-        if !user_data.email.is_none() {
+        if user_data.email.is_some() {
             update.email = user_data.email;
         }
 
@@ -0,0 +1,140 @@
+//! The WireGuard identities of WireGuard endpoints: a keypair, an address inside the tunnels from
+//! `render.wireguard_network` and a port to listen on, allocated the first time the endpoint or
+//! one of its peers is rendered.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::net::Ipv4Addr;
+use std::time::SystemTime;
+
+use diesel::prelude::*;
+use tracing::warn;
+
+use crate::config::RenderConfig;
+use crate::render::{self, WireGuardEndpoint};
+use crate::schema::{tunnels, wireguard_endpoints};
+use crate::storage::keys::{cipher, open, seal};
+use crate::storage::routers::Router;
+use crate::storage::tunnels::Tunnel;
+use crate::wireguard::keypair;
+
+#[derive(Queryable, Debug)]
+#[diesel(table_name = wireguard_endpoints)]
+pub struct WireGuardIdentity {
+    pub tunnel: i32,
+    pub public_key: String,
+    /// The nonce, then the sealed private key.
+    pub private_ciphertext: Vec<u8>,
+    pub address: String,
+    pub listen_port: i32,
+    pub created_at: SystemTime,
+}
+
+/// Binds a sealed private key to its endpoint.
+fn aad(tunnel: i32) -> String {
+    format!("wireguard-{}", tunnel)
+}
+
+/// The identities of the WireGuard endpoints `routers` have among `all_tunnels` and of their
+/// peers, allocating those that do not exist yet. Without `render.psk_secret` there are none,
+/// and WireGuard tunnels fail to render.
+pub(crate) fn endpoints(
+    conn: &mut PgConnection,
+    routers: &[Router],
+    all_tunnels: &[Tunnel],
+    defaults: &RenderConfig,
+) -> QueryResult<HashMap<i32, WireGuardEndpoint>> {
+    let ids: BTreeSet<i32> = routers
+        .iter()
+        .flat_map(|r| render::links(r, all_tunnels))
+        .filter(|(local, _)| local.tunnel_type == "WireGuard")
+        .flat_map(|(local, peer)| [local.id, peer.id])
+        .collect();
+    let (secret, (network, len)) = match (defaults.psk_secret(), defaults.wireguard_network()) {
+        (Ok(Some(secret)), Ok(network)) if !ids.is_empty() => (secret, network),
+        _ => return Ok(HashMap::new()),
+    };
+    let cipher = cipher(&secret);
+    let load = |conn: &mut PgConnection| {
+        wireguard_endpoints::table
+            .filter(wireguard_endpoints::tunnel.eq_any(&ids))
+            .load::<WireGuardIdentity>(conn)
+    };
+
+    let mut identities = load(conn)?;
+    let missing: Vec<&Tunnel> = all_tunnels
+        .iter()
+        .filter(|t| ids.contains(&t.id) && !identities.iter().any(|i| i.tunnel == t.id))
+        .collect();
+
+    if !missing.is_empty() {
+        let used_addresses: HashSet<String> = wireguard_endpoints::table
+            .select(wireguard_endpoints::address)
+            .load::<String>(conn)?
+            .into_iter()
+            .collect();
+        let mut used_ports: HashSet<(i32, i32)> = wireguard_endpoints::table
+            .inner_join(tunnels::table)
+            .select((tunnels::router, wireguard_endpoints::listen_port))
+            .load::<(i32, i32)>(conn)?
+            .into_iter()
+            .collect();
+
+        // The lowest free host addresses, leaving out the network and broadcast addresses.
+        let mut free = (1..(1u32 << (32 - len)) - 1)
+            .map(|host| Ipv4Addr::from(u32::from(network) + host).to_string())
+            .filter(|address| !used_addresses.contains(address));
+
+        let mut rows = Vec::new();
+        for tunnel in missing {
+            let address = match free.next() {
+                Some(address) => address,
+                None => {
+                    warn!(message = "No address left in render.wireguard_network", tunnel = tunnel.id);
+                    break;
+                }
+            };
+            let port = (i32::from(defaults.wireguard_port)..=i32::from(u16::MAX))
+                .find(|port| !used_ports.contains(&(tunnel.router, *port)))
+                .unwrap_or_default();
+            used_ports.insert((tunnel.router, port));
+
+            let (private_key, public_key) = keypair();
+            rows.push((
+                wireguard_endpoints::tunnel.eq(tunnel.id),
+                wireguard_endpoints::public_key.eq(public_key),
+                wireguard_endpoints::private_ciphertext.eq(seal(&cipher, &aad(tunnel.id), &private_key)),
+                wireguard_endpoints::address.eq(address),
+                wireguard_endpoints::listen_port.eq(port),
+            ));
+        }
+
+        // A render allocating for the same endpoints at once wins or takes the address first;
+        // endpoints left without an identity get one the next time around.
+        diesel::insert_into(wireguard_endpoints::table)
+            .values(rows)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+        identities = load(conn)?;
+    }
+
+    Ok(identities
+        .into_iter()
+        .filter_map(|identity| {
+            let private_key = open(&cipher, &aad(identity.tunnel), &identity.private_ciphertext);
+            if private_key.is_none() {
+                warn!(
+                    message = "Could not decrypt WireGuard private key; was render.psk_secret changed?",
+                    tunnel = identity.tunnel
+                );
+            }
+
+            let endpoint = WireGuardEndpoint {
+                private_key,
+                public_key: identity.public_key,
+                address: identity.address.parse().ok()?,
+                listen_port: u16::try_from(identity.listen_port).ok()?,
+            };
+            Some((identity.tunnel, endpoint))
+        })
+        .collect())
+}
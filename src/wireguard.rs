@@ -0,0 +1,184 @@
+//! WireGuard keys: X25519 (RFC 7748) keypairs in the base64 form `wg` and `wg-quick` use.
+//! The field arithmetic works on five 51-bit limbs, and the ladder is constant time.
+
+/// A field element mod 2^255 - 19, least significant limb first.
+type Fe = [u64; 5];
+
+const MASK: u64 = (1 << 51) - 1;
+
+fn fe_from_bytes(b: &[u8; 32]) -> Fe {
+    let load = |i: usize| u64::from_le_bytes(b[i..i + 8].try_into().unwrap());
+    [
+        load(0) & MASK,
+        (load(6) >> 3) & MASK,
+        (load(12) >> 6) & MASK,
+        (load(19) >> 1) & MASK,
+        (load(24) >> 12) & MASK,
+    ]
+}
+
+fn carry(mut h: Fe) -> Fe {
+    for i in 0..4 {
+        h[i + 1] += h[i] >> 51;
+        h[i] &= MASK;
+    }
+    h[0] += 19 * (h[4] >> 51);
+    h[4] &= MASK;
+    h
+}
+
+fn fe_to_bytes(h: Fe) -> [u8; 32] {
+    let mut h = carry(carry(h));
+
+    // Subtract p once more if h >= p, which is when h + 19 carries past bit 255.
+    let mut q = (h[0] + 19) >> 51;
+    for limb in &h[1..] {
+        q = (limb + q) >> 51;
+    }
+    h[0] += 19 * q;
+    for i in 0..4 {
+        h[i + 1] += h[i] >> 51;
+        h[i] &= MASK;
+    }
+    h[4] &= MASK;
+
+    let words = [
+        h[0] | (h[1] << 51),
+        (h[1] >> 13) | (h[2] << 38),
+        (h[2] >> 26) | (h[3] << 25),
+        (h[3] >> 39) | (h[4] << 12),
+    ];
+    let mut out = [0; 32];
+    for (chunk, word) in out.chunks_mut(8).zip(words) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    out
+}
+
+fn add(f: &Fe, g: &Fe) -> Fe {
+    [f[0] + g[0], f[1] + g[1], f[2] + g[2], f[3] + g[3], f[4] + g[4]]
+}
+
+/// f - g, with 2p added so no limb goes negative.
+fn sub(f: &Fe, g: &Fe) -> Fe {
+    const TWO_P0: u64 = 0xfffffffffffda;
+    const TWO_P: u64 = 0xffffffffffffe;
+    carry([
+        f[0] + TWO_P0 - g[0],
+        f[1] + TWO_P - g[1],
+        f[2] + TWO_P - g[2],
+        f[3] + TWO_P - g[3],
+        f[4] + TWO_P - g[4],
+    ])
+}
+
+fn mul(f: &Fe, g: &Fe) -> Fe {
+    let m = |a: u64, b: u64| u128::from(a) * u128::from(b);
+    let (g1, g2, g3, g4) = (g[1] * 19, g[2] * 19, g[3] * 19, g[4] * 19);
+
+    let r = [
+        m(f[0], g[0]) + m(f[1], g4) + m(f[2], g3) + m(f[3], g2) + m(f[4], g1),
+        m(f[0], g[1]) + m(f[1], g[0]) + m(f[2], g4) + m(f[3], g3) + m(f[4], g2),
+        m(f[0], g[2]) + m(f[1], g[1]) + m(f[2], g[0]) + m(f[3], g4) + m(f[4], g3),
+        m(f[0], g[3]) + m(f[1], g[2]) + m(f[2], g[1]) + m(f[3], g[0]) + m(f[4], g4),
+        m(f[0], g[4]) + m(f[1], g[3]) + m(f[2], g[2]) + m(f[3], g[1]) + m(f[4], g[0]),
+    ];
+
+    let mut out = [0; 5];
+    let mut c = 0u128;
+    for i in 0..5 {
+        let t = r[i] + c;
+        out[i] = t as u64 & MASK;
+        c = t >> 51;
+    }
+    let t = u128::from(out[0]) + c * 19;
+    out[0] = t as u64 & MASK;
+    out[1] += (t >> 51) as u64;
+    out
+}
+
+/// z^(p - 2), the inverse of z. Every bit of p - 2 = 2^255 - 21 is set but bits 2 and 4.
+fn invert(z: &Fe) -> Fe {
+    let mut result = [1, 0, 0, 0, 0];
+    for bit in (0..255).rev() {
+        result = mul(&result, &result);
+        if bit != 2 && bit != 4 {
+            result = mul(&result, z);
+        }
+    }
+    result
+}
+
+fn cswap(swap: u64, a: &mut Fe, b: &mut Fe) {
+    let mask = 0u64.wrapping_sub(swap);
+    for i in 0..5 {
+        let t = mask & (a[i] ^ b[i]);
+        a[i] ^= t;
+        b[i] ^= t;
+    }
+}
+
+/// The X25519 function: `scalar` times the point with u-coordinate `u`.
+pub fn x25519(scalar: &[u8; 32], u: &[u8; 32]) -> [u8; 32] {
+    let mut k = *scalar;
+    k[0] &= 248;
+    k[31] &= 127;
+    k[31] |= 64;
+
+    let x1 = fe_from_bytes(u);
+    let (mut x2, mut z2, mut x3, mut z3) = ([1, 0, 0, 0, 0], [0; 5], x1, [1, 0, 0, 0, 0]);
+    let a24 = [121665, 0, 0, 0, 0];
+    let mut swap = 0;
+
+    for t in (0..255).rev() {
+        let bit = u64::from((k[t / 8] >> (t % 8)) & 1);
+        swap ^= bit;
+        cswap(swap, &mut x2, &mut x3);
+        cswap(swap, &mut z2, &mut z3);
+        swap = bit;
+
+        let a = add(&x2, &z2);
+        let aa = mul(&a, &a);
+        let b = sub(&x2, &z2);
+        let bb = mul(&b, &b);
+        let e = sub(&aa, &bb);
+        let c = add(&x3, &z3);
+        let d = sub(&x3, &z3);
+        let da = mul(&d, &a);
+        let cb = mul(&c, &b);
+        let sum = add(&da, &cb);
+        x3 = mul(&sum, &sum);
+        let diff = sub(&da, &cb);
+        z3 = mul(&x1, &mul(&diff, &diff));
+        x2 = mul(&aa, &bb);
+        z2 = mul(&e, &add(&aa, &mul(&a24, &e)));
+    }
+    cswap(swap, &mut x2, &mut x3);
+    cswap(swap, &mut z2, &mut z3);
+
+    fe_to_bytes(mul(&x2, &invert(&z2)))
+}
+
+/// The public key of a private one, both base64 encoded.
+pub fn public_key(private_key: &str) -> Result<String, String> {
+    let private_key: [u8; 32] = base64::decode(private_key)
+        .ok()
+        .and_then(|key| key.try_into().ok())
+        .ok_or_else(|| "a WireGuard key is 32 bytes of base64".to_string())?;
+
+    let mut base = [0; 32];
+    base[0] = 9;
+    Ok(base64::encode(x25519(&private_key, &base)))
+}
+
+/// A new random keypair, private key first.
+pub fn keypair() -> (String, String) {
+    let mut private_key: [u8; 32] = rand::random();
+    private_key[0] &= 248;
+    private_key[31] &= 127;
+    private_key[31] |= 64;
+
+    let private_key = base64::encode(private_key);
+    let public_key = public_key(&private_key).unwrap();
+    (private_key, public_key)
+}
@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Arc;
+use std::time::Duration;
+
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::PgConnection;
+use tonic::service::Interceptor;
+use tonic::{Code, Request};
+
+use tunnel_manager::auth::{AuthInterceptor, Caller, Tokens, UserId};
+use tunnel_manager::config::{self, Config, LogFormat};
+use tunnel_manager::storage::access::Access;
+
+fn load(args: &[&str], env: &[(&str, &str)]) -> Result<(Config, Vec<String>), config::ConfigError> {
+    let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+    let env: HashMap<String, String> = env.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    config::load::<Config>(&args, |var| env.get(var).cloned())
+}
+
+#[test]
+fn test_layering() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("server.toml");
+    fs::write(
+        &file,
+        r#"
+[listen]
+grpc_port = 6000
+http_port = 6001
+cors_allowed_origins = ["https://a.example.org"]
+
+[database]
+url = "postgres://file/db"
+max_connections = 8
+
+[auth]
+disabled = true
+
+[log]
+format = "compact"
+"#,
+    )
+    .unwrap();
+
+    let (config, rest) = load(
+        &["--config", file.to_str().unwrap(), "--listen-grpc-port=7000", "import-legacy", "--dry-run"],
+        &[
+            ("GRPC_PORT", "6500"),
+            ("TUNNEL_MANAGER_LISTEN_HTTP_PORT", "6501"),
+            ("DB_MAX_CONNECTION", "3"),
+            ("TUNNEL_MANAGER_DATABASE_MAX_CONNECTIONS", "4"),
+        ],
+    )
+    .unwrap();
+
+    // The flag beats the environment, which beats the file, which beats the defaults.
+    assert_eq!(config.listen.grpc_port, 7000);
+    assert_eq!(config.listen.http_port, 6501);
+    assert_eq!(config.listen.grpc_host, "[::1]");
+    assert_eq!(config.listen.cors_allowed_origins, vec!["https://a.example.org"]);
+    assert_eq!(config.database.url, "postgres://file/db");
+    assert_eq!(config.log.format, LogFormat::Compact);
+    // The old variable names still work, below the prefixed ones.
+    assert_eq!(config.database.max_connections, 4);
+    assert_eq!(rest, vec!["import-legacy", "--dry-run"]);
+}
+
+#[test]
+fn test_errors_are_collected() {
+    let err = load(
+        &["--listen-grpc-port", "many", "--nope", "1"],
+        &[("TUNNEL_MANAGER_LOG_LEVEL", "loud")],
+    )
+    .unwrap_err();
+
+    assert_eq!(
+        err.errors,
+        vec![
+            "--nope: unknown flag",
+            "TUNNEL_MANAGER_LOG_LEVEL (log.level): expected trace, debug, info, warn or error, got \"loud\"",
+            "--listen-grpc-port (listen.grpc_port): expected a port number, got \"many\"",
+        ]
+    );
+
+    let err = load(
+        &[
+            "--tls-cert",
+            "/nonexistent/cert.pem",
+            "--auth-token-secret",
+            "short",
+            "--render-psk-secret",
+            "short",
+            "--render-wireguard-network",
+            "10.254.0.0/31",
+        ],
+        &[("DATABASE_URL", "postgres://localhost/db"), ("GRPC_HOST", "localhost")],
+    )
+    .unwrap_err();
+    let errors = err.to_string();
+    assert!(errors.contains("listen.grpc_host: \"localhost\" is not an IP address"), "{}", errors);
+    assert!(errors.contains("tls.cert and tls.key must be set together"), "{}", errors);
+    assert!(errors.contains("tls.cert: /nonexistent/cert.pem"), "{}", errors);
+    assert!(errors.contains("the auth token secret must be at least 32 bytes"), "{}", errors);
+    assert!(errors.contains("the pre-shared key secret must be at least 32 bytes"), "{}", errors);
+    assert!(errors.contains("render.wireguard_network: a /31 network is too large or too small"), "{}", errors);
+
+    let err = load(&[], &[]).unwrap_err();
+    assert_eq!(
+        err.errors,
+        vec![
+            "database.url is required (or DATABASE_URL)",
+            "auth.token_secret or auth.token_secret_file is required, unless auth.disabled is set",
+        ]
+    );
+
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("server.toml");
+    fs::write(&file, "[database]\nurl = \"postgres://localhost/db\"\nmax_conections = 3\n").unwrap();
+    let err = load(&["--config", file.to_str().unwrap()], &[]).unwrap_err();
+    assert!(err.errors[0].contains("unknown field `max_conections`"), "{}", err);
+}
+
+#[test]
+fn test_token_secret_required() {
+    let database = ("DATABASE_URL", "postgres://localhost/db");
+
+    let (config, _) = load(&["--auth-token-secret", "0123456789abcdef0123456789abcdef"], &[database]).unwrap();
+    assert!(!config.auth.disabled);
+
+    // Running without one has to be asked for, and cannot be combined with one.
+    let (config, _) = load(&["--auth-disabled", "true"], &[database]).unwrap();
+    assert_eq!(config.auth.secret(), Ok(None));
+    let err = load(
+        &["--auth-disabled", "true"],
+        &[database, ("TUNNEL_MANAGER_AUTH_TOKEN_SECRET", "0123456789abcdef0123456789abcdef")],
+    )
+    .unwrap_err();
+    assert_eq!(err.errors, vec!["auth.disabled cannot be set with a token secret"]);
+}
+
+#[test]
+fn test_tokens() {
+    let tokens = Tokens::new(Some(b"0123456789abcdef0123456789abcdef"), Duration::from_secs(60));
+    let token = tokens.issue(42);
+    assert_eq!(tokens.verify(&token).unwrap(), Some(UserId(42)));
+
+    let other = Tokens::new(Some(b"fedcba9876543210fedcba9876543210"), Duration::from_secs(60));
+    assert_eq!(other.verify(&token).unwrap_err().code(), Code::Unauthenticated);
+    let forged = token.replacen("42.", "1.", 1);
+    assert_eq!(tokens.verify(&forged).unwrap_err().code(), Code::Unauthenticated);
+
+    let expired = Tokens::new(Some(b"0123456789abcdef0123456789abcdef"), Duration::ZERO);
+    assert_eq!(expired.verify(&expired.issue(42)).unwrap_err().message(), "Token expired");
+
+    // Without a secret, any token goes.
+    let unsigned = Tokens::new(None, Duration::from_secs(60));
+    assert_eq!(unsigned.verify("anything").unwrap(), None);
+    assert!(!unsigned.issue(42).is_empty());
+}
+
+#[tokio::test]
+async fn test_unsigned_tokens_are_not_admins() {
+    // Neither kind of caller needs the database.
+    let pool = Pool::builder().build_unchecked(ConnectionManager::<PgConnection>::new("postgres://127.0.0.1:1/none"));
+    let access = |tokens: Tokens| {
+        let mut request = Request::new(());
+        request.metadata_mut().insert("authorization", "anything".parse().unwrap());
+        let request = AuthInterceptor::new(Arc::new(tokens)).call(request).unwrap();
+        let caller = *request.extensions().get::<Caller>().unwrap();
+        let pool = pool.clone();
+        async move { (caller, Access::of(&pool, &request).await.unwrap()) }
+    };
+
+    let (caller, unsigned) = access(Tokens::new(None, Duration::from_secs(60))).await;
+    assert_eq!(caller, Caller::Anyone);
+    assert!(!unsigned.is_admin());
+    assert_eq!(unsigned.require_admin().unwrap_err().code(), Code::PermissionDenied);
+    assert_eq!(unsigned.check_owner(1).unwrap_err().code(), Code::PermissionDenied);
+
+    // Only when auth is disabled on purpose.
+    let (caller, disabled) = access(Tokens::disabled(Duration::from_secs(60))).await;
+    assert_eq!(caller, Caller::Developer);
+    assert!(disabled.is_admin());
+}
@@ -0,0 +1,134 @@
+use hyper::{Body, StatusCode};
+use prost::Message;
+use prost_types::Timestamp;
+use tonic::Code;
+
+use tunnel_manager::api::{TunnelResponse, TunnelUpdateRequest, FILE_DESCRIPTOR_SET};
+use tunnel_manager::gateway::json::Json;
+use tunnel_manager::gateway::transcode::{format_timestamp, parse_timestamp, Descriptors};
+use tunnel_manager::gateway::{find_route, http_status, openapi, read_body, ROUTES};
+
+#[test]
+fn test_json_round_trip() {
+    let text = r#"{"a":[1,-2.5e3,true,null],"b":"quote \" tab \t é 😀","c":{}}"#;
+    let json = Json::parse(text).unwrap();
+
+    assert_eq!(json.get("b").and_then(Json::as_str), Some("quote \" tab \t é 😀"));
+    assert_eq!(Json::parse(&json.to_string()), Ok(json));
+    assert!(Json::parse("{\"a\":1,}").is_err());
+    assert!(Json::parse("[1] 2").is_err());
+}
+
+#[test]
+fn test_transcode_request() {
+    let descriptors = Descriptors::decode(FILE_DESCRIPTOR_SET).unwrap();
+    let json = Json::parse(r#"{"ID":"50","cost":21,"dynamic_ip":true,"tunnelType":"GRE","hostname":null}"#).unwrap();
+
+    let bytes = descriptors.to_proto(".api.TunnelUpdateRequest", &json).unwrap();
+
+    assert_eq!(
+        TunnelUpdateRequest::decode(bytes.as_slice()).unwrap(),
+        TunnelUpdateRequest {
+            id: 50,
+            cost: Some(21),
+            dynamic_ip: Some(true),
+            tunnel_type: Some("GRE".to_string()),
+            ..Default::default()
+        }
+    );
+
+    let unknown = Json::parse(r#"{"colour":"red"}"#).unwrap();
+    assert!(descriptors.to_proto(".api.TunnelUpdateRequest", &unknown).is_err());
+
+    let overflow = Json::parse(r#"{"cost":4294967296}"#).unwrap();
+    assert!(descriptors.to_proto(".api.TunnelUpdateRequest", &overflow).is_err());
+}
+
+#[test]
+fn test_transcode_response() {
+    let descriptors = Descriptors::decode(FILE_DESCRIPTOR_SET).unwrap();
+    let tunnel = TunnelResponse {
+        id: 50,
+        router: 1,
+        ip: "192.0.2.1".to_string(),
+        cost: -1,
+        created_at: Some(Timestamp {
+            seconds: 1714564800,
+            nanos: 500_000_000,
+        }),
+        ..Default::default()
+    };
+
+    let json = descriptors
+        .to_json(".api.TunnelResponse", &tunnel.encode_to_vec())
+        .unwrap();
+
+    assert_eq!(json.get("ID"), Some(&Json::from(50)));
+    assert_eq!(json.get("IP"), Some(&Json::from("192.0.2.1")));
+    assert_eq!(json.get("cost"), Some(&Json::from(-1)));
+    assert_eq!(json.get("dynamicIp"), Some(&Json::Bool(false)));
+    assert_eq!(json.get("createdAt"), Some(&Json::from("2024-05-01T12:00:00.500Z")));
+    assert_eq!(json.get("updatedAt"), None);
+}
+
+#[test]
+fn test_timestamps() {
+    assert_eq!(format_timestamp(0, 0), "1970-01-01T00:00:00Z");
+    assert_eq!(format_timestamp(951782400, 0), "2000-02-29T00:00:00Z");
+    assert_eq!(format_timestamp(-1, 123_000), "1969-12-31T23:59:59.000123Z");
+
+    assert_eq!(parse_timestamp("2000-02-29T00:00:00Z"), Some((951782400, 0)));
+    assert_eq!(parse_timestamp("2000-02-29T02:30:00.25+02:30"), Some((951782400, 250_000_000)));
+    assert_eq!(parse_timestamp("2000-13-01T00:00:00Z"), None);
+    assert_eq!(parse_timestamp("yesterday"), None);
+}
+
+#[test]
+fn test_routes() {
+    let (route, vars) = find_route("PATCH", "/v1/tunnels/50").unwrap();
+    assert_eq!(route.rpc, "api.Tunnel/Update");
+    assert_eq!(vars, vec![("ID", "50".to_string())]);
+
+    let (route, vars) = find_route("GET", "/v1/permissions/3/members/").unwrap();
+    assert_eq!(route.rpc, "api.PermissionMembership/GetPermissionMembers");
+    assert_eq!(vars, vec![("permission", "3".to_string())]);
+
+    assert_eq!(find_route("PUT", "/v1/tunnels/50").unwrap_err(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(find_route("GET", "/v1/tunnels/50/extra").unwrap_err(), StatusCode::NOT_FOUND);
+
+    assert_eq!(http_status(Code::Unauthenticated), StatusCode::UNAUTHORIZED);
+    assert_eq!(http_status(Code::AlreadyExists), StatusCode::CONFLICT);
+}
+
+#[test]
+fn test_openapi_covers_every_route() {
+    let descriptors = Descriptors::decode(FILE_DESCRIPTOR_SET).unwrap();
+    let document = openapi::document(&descriptors, ROUTES);
+    let paths = document.get("paths").unwrap();
+
+    for route in ROUTES {
+        assert!(descriptors.method(route.rpc).is_some(), "{} is not an RPC", route.rpc);
+
+        let operation = paths
+            .get(route.path)
+            .and_then(|item| item.get(&route.method.to_lowercase()));
+        assert!(operation.is_some(), "{} {} is missing", route.method, route.path);
+    }
+
+    let schemas = document.get("components").and_then(|c| c.get("schemas")).unwrap();
+    assert!(schemas.get("api.TunnelResponse").is_some());
+}
+
+#[tokio::test]
+async fn test_read_body_limit() {
+    assert_eq!(read_body(Body::from("{\"ID\": 1}"), 16).await.unwrap(), "{\"ID\": 1}");
+
+    // Refused on its length alone, and when it turns out too long without one.
+    let (http, status) = read_body(Body::from(vec![b' '; 17]), 16).await.unwrap_err();
+    assert_eq!(http, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(status.code(), Code::ResourceExhausted);
+
+    let chunks: Vec<Result<_, std::io::Error>> = vec![Ok(vec![b' '; 10]), Ok(vec![b' '; 10])];
+    let (http, _) = read_body(Body::wrap_stream(futures_util::stream::iter(chunks)), 16).await.unwrap_err();
+    assert_eq!(http, StatusCode::PAYLOAD_TOO_LARGE);
+}
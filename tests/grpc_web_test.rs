@@ -0,0 +1,72 @@
+use std::convert::Infallible;
+
+use hyper::header::HeaderValue;
+use hyper::{Body, Request, Response, StatusCode};
+use tonic::body::{empty_body, BoxBody};
+use tower::{Layer, ServiceExt};
+
+use tunnel_manager::grpc_web::{Cors, GrpcWebLayer};
+
+/// Echoes the request body back and reports what content type the services would have seen.
+async fn echo(req: Request<Body>) -> Result<Response<BoxBody>, Infallible> {
+    let content_type = req.headers()["content-type"].clone();
+    let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+
+    let mut response = Response::new(empty_body());
+    response.headers_mut().insert("x-seen-content-type", content_type);
+    response
+        .headers_mut()
+        .insert("x-seen-body", HeaderValue::from_str(&format!("{:?}", body.as_ref())).unwrap());
+    Ok(response)
+}
+
+#[test]
+fn test_cors_origins() {
+    let cors = Cors::new("https://ui.example.org/, http://localhost:3000");
+
+    assert!(cors.allow_origin(&HeaderValue::from_static("https://ui.example.org")).is_some());
+    assert!(cors.allow_origin(&HeaderValue::from_static("http://localhost:3000")).is_some());
+    assert!(cors.allow_origin(&HeaderValue::from_static("https://evil.example.org")).is_none());
+    assert!(Cors::new("").allow_origin(&HeaderValue::from_static("http://a")).is_none());
+    assert_eq!(
+        Cors::new("*").allow_origin(&HeaderValue::from_static("http://a")),
+        Some(HeaderValue::from_static("*"))
+    );
+}
+
+#[tokio::test]
+async fn test_preflight() {
+    let service = GrpcWebLayer::new(Cors::new("https://ui.example.org")).layer(tower::service_fn(echo));
+
+    let preflight = |origin: &'static str| {
+        Request::options("/api.Tunnel/Get")
+            .header("origin", origin)
+            .header("access-control-request-method", "POST")
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let allowed = service.clone().oneshot(preflight("https://ui.example.org")).await.unwrap();
+    assert_eq!(allowed.status(), StatusCode::NO_CONTENT);
+    assert_eq!(allowed.headers()["access-control-allow-origin"], "https://ui.example.org");
+
+    let refused = service.oneshot(preflight("https://evil.example.org")).await.unwrap();
+    assert_eq!(refused.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_text_request_is_decoded() {
+    let service = GrpcWebLayer::new(Cors::default()).layer(tower::service_fn(echo));
+
+    // Two separately padded chunks: a frame header, then TunnelRequest { ID: 50 }.
+    let request = Request::post("/api.Tunnel/Get")
+        .header("content-type", "application/grpc-web-text")
+        .body(Body::from("AAAAAAI=CDI="))
+        .unwrap();
+
+    let response = service.oneshot(request).await.unwrap();
+
+    assert_eq!(response.headers()["content-type"], "application/grpc-web-text+proto");
+    assert_eq!(response.headers()["x-seen-content-type"], "application/grpc");
+    assert_eq!(response.headers()["x-seen-body"], "[0, 0, 0, 0, 2, 8, 50]");
+}
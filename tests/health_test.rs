@@ -0,0 +1,108 @@
+use std::time::Duration;
+
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::PgConnection;
+use prost::Message;
+use prost_types::FileDescriptorProto;
+use tonic::Code;
+
+use tunnel_manager::api::FILE_DESCRIPTOR_SET;
+use tunnel_manager::config::NotificationConfig;
+use tunnel_manager::grpc::health::v1::health_check_response::ServingStatus;
+use tunnel_manager::grpc::reflection::v1alpha::server_reflection_request::MessageRequest;
+use tunnel_manager::grpc::reflection::v1alpha::server_reflection_response::MessageResponse;
+use tunnel_manager::grpc::reflection::v1alpha::ServerReflectionRequest;
+use tunnel_manager::handlers::health::HealthService;
+use tunnel_manager::handlers::reflection::ReflectionService;
+use tunnel_manager::shutdown::Shutdown;
+use tunnel_manager::storage::changes::ChangeListener;
+use tunnel_manager::storage::mesh::Mesh;
+
+fn reflect(reflection: &ReflectionService, request: MessageRequest) -> MessageResponse {
+    let response = reflection.respond(ServerReflectionRequest {
+        host: String::new(),
+        message_request: Some(request),
+    });
+    response.message_response.unwrap()
+}
+
+fn file_names(response: MessageResponse) -> Vec<String> {
+    match response {
+        MessageResponse::FileDescriptorResponse(files) => files
+            .file_descriptor_proto
+            .iter()
+            .map(|bytes| FileDescriptorProto::decode(bytes.as_slice()).unwrap().name().to_string())
+            .collect(),
+        other => panic!("expected files, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_reflection() {
+    let reflection = ReflectionService::new(FILE_DESCRIPTOR_SET, &["api.Mesh", "grpc.health.v1.Health"]).unwrap();
+
+    match reflect(&reflection, MessageRequest::ListServices(String::new())) {
+        MessageResponse::ListServicesResponse(list) => assert_eq!(
+            list.service.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(),
+            vec!["api.Mesh", "grpc.health.v1.Health"]
+        ),
+        other => panic!("expected services, got {:?}", other),
+    }
+
+    // The file comes first, then everything it imports, transitively.
+    let files = file_names(reflect(&reflection, MessageRequest::FileContainingSymbol("api.Mesh".to_string())));
+    assert_eq!(files[0], "api/mesh.proto");
+    for import in ["api/agents.proto", "api/routers.proto", "api/tunnels.proto", "google/protobuf/empty.proto"] {
+        assert!(files.iter().any(|f| f == import), "{} missing from {:?}", import, files);
+    }
+
+    let files = file_names(reflect(
+        &reflection,
+        MessageRequest::FileContainingSymbol("grpc.health.v1.HealthCheckResponse.ServingStatus".to_string()),
+    ));
+    assert_eq!(files, vec!["grpc/health/v1/health.proto"]);
+    let files = file_names(reflect(&reflection, MessageRequest::FileContainingSymbol("api.Tunnel.Get".to_string())));
+    assert_eq!(files[0], "api/tunnels.proto");
+
+    match reflect(&reflection, MessageRequest::FileContainingSymbol("api.Nope".to_string())) {
+        MessageResponse::ErrorResponse(error) => assert_eq!(error.error_code, Code::NotFound as i32),
+        other => panic!("expected an error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_health_without_database() {
+    // Nothing listens on port 1, so neither the pool nor the listener can connect.
+    let url = "postgres://postgres@127.0.0.1:1/postgres";
+    let pool = Pool::builder()
+        .connection_timeout(Duration::from_secs(1))
+        .build_unchecked(ConnectionManager::<PgConnection>::new(url));
+    let listener = ChangeListener::spawn(url.to_string(), &NotificationConfig::default());
+    let shutdown = Shutdown::new();
+    let health = HealthService::new(pool, listener.clone(), shutdown.clone());
+
+    assert_eq!(health.status("").await, Some(ServingStatus::NotServing));
+    assert_eq!(health.status("api.Tunnel").await, Some(ServingStatus::NotServing));
+    assert_eq!(health.status("grpc.health.v1.Health").await, Some(ServingStatus::Serving));
+    assert_eq!(health.status("api.Nope").await, None);
+
+    shutdown.trigger();
+    shutdown.triggered().await;
+    assert_eq!(health.status("grpc.health.v1.Health").await, Some(ServingStatus::NotServing));
+    assert_eq!(health.status("api.Nope").await, None);
+
+    // Stops while waiting to retry the connection.
+    listener.stop();
+    assert!(!listener.is_connected());
+}
+
+#[tokio::test]
+async fn test_mesh_status_without_database() {
+    let pool = Pool::builder()
+        .connection_timeout(Duration::from_secs(1))
+        .build_unchecked(ConnectionManager::<PgConnection>::new("postgres://postgres@127.0.0.1:1/postgres"));
+
+    // What Mesh.Watch asks on every change, which used to panic once the pool timed out.
+    assert_eq!(Mesh::version(&pool).await.unwrap_err().code(), Code::Unavailable);
+    assert_eq!(Mesh::status(&pool).await.unwrap_err().code(), Code::Unavailable);
+}
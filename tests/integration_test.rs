@@ -1,28 +1,2140 @@
-use api::user_client::UserClient;
+use std::env;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 
-use crate::api::UserData;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::{Connection, PgConnection, RunQueryDsl};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use prost::Message;
+use prost_types::{FileDescriptorSet, Timestamp};
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::codec::ProstCodec;
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::transport::{Channel, Server};
+use tonic::{Code, Request};
 
-pub mod api {
-    tonic::include_proto!("api");
+use tunnel_manager::api::agent_client::AgentClient;
+use tunnel_manager::api::agent_request::IdUuidOrOwner;
+use tunnel_manager::api::auth_client::AuthClient;
+use tunnel_manager::api::mesh_client::MeshClient;
+use tunnel_manager::api::permission_membership_client::PermissionMembershipClient;
+use tunnel_manager::api::permission_membership_request::IdPermissionOrUserid;
+use tunnel_manager::api::router_client::RouterClient;
+use tunnel_manager::api::router_request::IdOrAgent;
+use tunnel_manager::api::tunnel_client::TunnelClient;
+use tunnel_manager::api::tunnel_request::IdOrRouter;
+use tunnel_manager::api::user_client::UserClient;
+use tunnel_manager::api::user_request::IdOrEmail;
+use tunnel_manager::api::{
+    AddressReport, MeshApplyRequest, AddressReportResponse, AgentData, ResolutionHistoryRequest, AgentDriversRequest, AgentRequest, LoginRequest, PushHistoryRequest, PushResult,
+    PermissionMembershipRequest, RouterAddRequest, RouterDriver, RouterListRequest, RouterRequest, RunningConfig, TunnelAddRequest, TunnelListRequest, TunnelRequest, TunnelUpdateRequest, RouterUpdateRequest, UserRequest,
+    FILE_DESCRIPTOR_SET,
+};
+use tunnel_manager::auth::Tokens;
+use tunnel_manager::config::{Config, NotificationConfig, RenderConfig, ResolverConfig};
+use tunnel_manager::grpc::health::v1::health_check_response::ServingStatus;
+use tunnel_manager::grpc::health::v1::health_client::HealthClient;
+use tunnel_manager::grpc::health::v1::HealthCheckRequest;
+use tunnel_manager::handlers::reflection::ReflectionService;
+use tunnel_manager::metrics::{Exporter, Metrics, MetricsLayer};
+use tunnel_manager::resolver::{Resolver, StaticResolver};
+use tunnel_manager::render;
+use tunnel_manager::server;
+use tunnel_manager::shutdown::Shutdown;
+use tunnel_manager::storage::access;
+use tunnel_manager::storage::changes::ChangeListener;
+use tunnel_manager::storage::drivers::RouterType;
+use tunnel_manager::wireguard::public_key;
+
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
+
+struct Started {
+    channel: Channel,
+    shutdown: Shutdown,
+    exporter: Exporter,
+    pool: Pool<ConnectionManager<PgConnection>>,
+}
+
+/// Serves every service the way the server binary does, on a free local port. `None` without a
+/// database to run against.
+async fn start() -> Option<Started> {
+    start_with(dev_config()).await
+}
+
+/// The defaults, with auth disabled so that any token may do anything.
+fn dev_config() -> Config {
+    let mut config = Config::default();
+    config.auth.disabled = true;
+    config
+}
+
+async fn start_with(config: Config) -> Option<Started> {
+    dotenvy::dotenv().ok();
+    let url = match env::var("DATABASE_URL") {
+        Ok(url) => url,
+        Err(_) => {
+            eprintln!("DATABASE_URL is not set, skipping");
+            return None;
+        }
+    };
+
+    Some(start_on(url, config).await)
+}
+
+async fn start_on(url: String, config: Config) -> Started {
+    let pool = Pool::builder()
+        .max_size(2)
+        .build(ConnectionManager::<PgConnection>::new(url.clone()))
+        .unwrap();
+    pool.get().unwrap().run_pending_migrations(MIGRATIONS).unwrap();
+    RouterType::register(&mut pool.get().unwrap()).unwrap();
+
+    let reflection = ReflectionService::new(FILE_DESCRIPTOR_SET, server::SERVICES).unwrap();
+    let listener = ChangeListener::spawn(url, &NotificationConfig::default());
+    let tokens = Arc::new(match config.auth.disabled {
+        true => Tokens::disabled(Duration::from_secs(60)),
+        false => Tokens::new(config.auth.secret().unwrap().as_deref(), Duration::from_secs(60)),
+    });
+    let shutdown = Shutdown::new();
+    let metrics = Metrics::new();
+    let exporter = Exporter::new(metrics.clone(), pool.clone());
+    let router = server::services(
+        &mut Server::builder().layer(MetricsLayer::new(metrics.clone())),
+        &pool,
+        &listener,
+        &reflection,
+        &tokens,
+        &config,
+        &metrics,
+        &shutdown,
+    );
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr: SocketAddr = listener.local_addr().unwrap();
+    let stop = shutdown.clone();
+    tokio::spawn(router.serve_with_incoming_shutdown(TcpListenerStream::new(listener), async move {
+        stop.triggered().await
+    }));
+
+    let channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    Started {
+        channel,
+        shutdown,
+        exporter,
+        pool,
+    }
+}
+
+/// A database of its own, for tests that replace the whole mesh and would otherwise delete what
+/// the tests running next to them add. Dropped along with it, even when the test fails.
+struct ScratchDatabase {
+    url: String,
+    name: String,
+}
+
+impl Drop for ScratchDatabase {
+    fn drop(&mut self) {
+        let url = env::var("DATABASE_URL").unwrap();
+        diesel::sql_query(format!("DROP DATABASE {} WITH (FORCE)", self.name))
+            .execute(&mut PgConnection::establish(&url).unwrap())
+            .unwrap();
+    }
+}
+
+/// `None` without a database to run against.
+fn scratch_database(name: &str) -> Option<ScratchDatabase> {
+    dotenvy::dotenv().ok();
+    let url = match env::var("DATABASE_URL") {
+        Ok(url) => url,
+        Err(_) => {
+            eprintln!("DATABASE_URL is not set, skipping");
+            return None;
+        }
+    };
+
+    let database = format!("tunnel_manager_{}_{}", name, rand::random::<u32>());
+    diesel::sql_query(format!("CREATE DATABASE {}", database))
+        .execute(&mut PgConnection::establish(&url).unwrap())
+        .unwrap();
+    let (server, _) = url.rsplit_once('/').unwrap();
+    Some(ScratchDatabase {
+        url: format!("{}/{}", server, database),
+        name: database,
+    })
+}
+
+fn authorized<T>(token: &str, message: T) -> Request<T> {
+    let mut request = Request::new(message);
+    request.metadata_mut().insert("authorization", token.parse().unwrap());
+    request
+}
+
+/// Calls `path` with an empty message and reports how it ended, whatever the response type.
+async fn call(channel: &Channel, path: &str, client_streaming: bool, token: Option<&str>) -> Code {
+    let mut grpc = tonic::client::Grpc::new(channel.clone());
+    grpc.ready().await.unwrap();
+    let path = PathAndQuery::try_from(path.to_string()).unwrap();
+    let codec = ProstCodec::<(), ()>::default();
+
+    let result = match client_streaming {
+        true => {
+            let mut request = Request::new(tokio_stream::empty::<()>());
+            if let Some(token) = token {
+                request.metadata_mut().insert("authorization", token.parse().unwrap());
+            }
+            grpc.streaming(request, path, codec).await.map(|_| ())
+        }
+        false => {
+            let mut request = Request::new(());
+            if let Some(token) = token {
+                request.metadata_mut().insert("authorization", token.parse().unwrap());
+            }
+            grpc.unary(request, path, codec).await.map(|_| ())
+        }
+    };
+
+    match result {
+        Ok(()) => Code::Ok,
+        Err(status) => status.code(),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_every_service_is_served() {
+    let channel = match start().await {
+        Some(started) => started.channel,
+        None => return,
+    };
+
+    let files = FileDescriptorSet::decode(FILE_DESCRIPTOR_SET).unwrap().file;
+    let services: Vec<_> = files
+        .iter()
+        .flat_map(|file| file.service.iter().map(move |service| (file.package(), service)))
+        .collect();
+
+    let mut names: Vec<_> = services
+        .iter()
+        .map(|(package, service)| format!("{}.{}", package, service.name()))
+        .collect();
+    let mut registered: Vec<_> = server::SERVICES.iter().map(|s| s.to_string()).collect();
+    names.sort();
+    registered.sort();
+    assert_eq!(names, registered, "build.rs and server::SERVICES disagree");
+
+    // The first method of every service only reads, or rejects the empty request.
+    for (package, service) in services {
+        let method = &service.method[0];
+        let path = format!("/{}.{}/{}", package, service.name(), method.name());
+
+        let code = call(&channel, &path, method.client_streaming(), Some("test")).await;
+        assert_ne!(code, Code::Unimplemented, "{} is not served", path);
+        assert_ne!(code, Code::Unauthenticated, "{} refused the token", path);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_auth() {
+    let channel = match start().await {
+        Some(started) => started.channel,
+        None => return,
+    };
+
+    assert_eq!(call(&channel, "/api.Agent/List", false, None).await, Code::Unauthenticated);
+    assert_eq!(call(&channel, "/grpc.health.v1.Health/Check", false, None).await, Code::Ok);
+
+    let mut auth = AuthClient::new(channel.clone());
+    let email = format!("integration-{}@example.org", rand::random::<u32>());
+    let login = LoginRequest {
+        email: email.clone(),
+        password: "correct horse".to_string(),
+    };
+
+    let registered = auth.register(login.clone()).await.unwrap().into_inner();
+    let logged_in = auth.login(login.clone()).await.unwrap().into_inner();
+    assert_eq!(logged_in.id, registered.id);
+    assert_eq!(logged_in.email, email);
+    assert!(!logged_in.token.is_empty());
+
+    let wrong = LoginRequest {
+        password: "wrong".to_string(),
+        ..login.clone()
+    };
+    assert_eq!(auth.login(wrong).await.unwrap_err().code(), Code::Unauthenticated);
+    assert_eq!(auth.register(login).await.unwrap_err().code(), Code::AlreadyExists);
+
+    let mut request = Request::new(UserRequest {
+        id_or_email: Some(IdOrEmail::Id(logged_in.id)),
+    });
+    request
+        .metadata_mut()
+        .insert("authorization", logged_in.token.parse().unwrap());
+    UserClient::new(channel).delete(request).await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_authorization() {
+    let mut config = Config::default();
+    config.auth.token_secret = Some("0123456789abcdef0123456789abcdef".to_string());
+    let Started { channel, pool, .. } = match start_with(config).await {
+        Some(started) => started,
+        None => return,
+    };
+
+    let auth = AuthClient::new(channel.clone());
+    let register = |name: &str| {
+        let login = LoginRequest {
+            email: format!("{}-{}@example.org", name, rand::random::<u32>()),
+            password: "correct horse".to_string(),
+        };
+        let mut auth = auth.clone();
+        async move { auth.register(login).await.unwrap().into_inner() }
+    };
+    let alice = register("alice").await;
+    let bob = register("bob").await;
+
+    // Only admins manage users and the mesh.
+    let mut users = UserClient::new(channel.clone());
+    let mut mesh = MeshClient::new(channel.clone());
+    let bob_user = UserRequest {
+        id_or_email: Some(IdOrEmail::Id(bob.id)),
+    };
+    assert_eq!(mesh.export(authorized(&alice.token, ())).await.unwrap_err().code(), Code::PermissionDenied);
+    assert_eq!(users.delete(authorized(&alice.token, bob_user.clone())).await.unwrap_err().code(), Code::PermissionDenied);
+    users.get(authorized(&bob.token, bob_user.clone())).await.unwrap();
+
+    // Members manage their own agents, routers and tunnels, and nobody else's.
+    let mut agents = AgentClient::new(channel.clone());
+    let agent_data = AgentData {
+        uuid: format!("alice-{}", rand::random::<u32>()),
+        owner: bob.id,
+        ..Default::default()
+    };
+    assert_eq!(agents.register(authorized(&alice.token, agent_data.clone())).await.unwrap_err().code(), Code::PermissionDenied);
+    let agent = agents
+        .register(authorized(&alice.token, AgentData { owner: alice.id, ..agent_data }))
+        .await
+        .unwrap()
+        .into_inner();
+    let mut routers = RouterClient::new(channel.clone());
+    let router = routers
+        .add(authorized(&alice.token, RouterAddRequest {
+            agent: agent.id.unwrap(),
+            conn_type: Some("SSH".to_string()),
+            router_type: Some("Cisco".to_string()),
+            ssh_password: Some("alice's password".to_string()),
+            ..Default::default()
+        }))
+        .await
+        .unwrap()
+        .into_inner()
+        .id
+        .unwrap();
+
+    let update = RouterUpdateRequest {
+        id: router,
+        ssh_username: Some("bob".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(routers.update(authorized(&bob.token, update)).await.unwrap_err().code(), Code::PermissionDenied);
+    let tunnel = TunnelAddRequest {
+        router,
+        ip: "192.0.2.1".to_string(),
+        hostname: "bob.example.org".to_string(),
+        description: "bob".to_string(),
+        source: "192.0.2.1".to_string(),
+        ..Default::default()
+    };
+    assert_eq!(TunnelClient::new(channel.clone()).add(authorized(&bob.token, tunnel)).await.unwrap_err().code(), Code::PermissionDenied);
+    let push = PushResult {
+        router,
+        config_hash: "forged".to_string(),
+        driver: "ssh-cisco".to_string(),
+        ..Default::default()
+    };
+    assert_eq!(routers.report_push(authorized(&bob.token, push)).await.unwrap_err().code(), Code::PermissionDenied);
+    let by_id = AgentRequest {
+        id_uuid_or_owner: Some(IdUuidOrOwner::Id(agent.id.unwrap())),
+    };
+    assert_eq!(agents.unregister(authorized(&bob.token, by_id.clone())).await.unwrap_err().code(), Code::PermissionDenied);
+
+    // Nor see them.
+    let by_router = RouterRequest {
+        id_or_agent: Some(IdOrAgent::Id(router)),
+        ..Default::default()
+    };
+    assert_eq!(routers.get(authorized(&bob.token, by_router.clone())).await.unwrap_err().code(), Code::PermissionDenied);
+    assert_eq!(routers.render(authorized(&bob.token, by_router.clone())).await.unwrap_err().code(), Code::PermissionDenied);
+    assert_eq!(agents.get_tree(authorized(&bob.token, by_id.clone())).await.unwrap_err().code(), Code::PermissionDenied);
+    assert_eq!(routers.sync_status(authorized(&bob.token, RouterRequest::default())).await.unwrap_err().code(), Code::PermissionDenied);
+    let listed = |token: &str| {
+        let mut routers = routers.clone();
+        let request = authorized(token, RouterListRequest {
+            agent: agent.id,
+            ..Default::default()
+        });
+        async move { routers.list(request).await.unwrap().into_inner().routers.len() }
+    };
+    assert_eq!(listed(&bob.token).await, 0);
+    assert_eq!(listed(&alice.token).await, 1);
+    routers.get(authorized(&alice.token, by_router)).await.unwrap();
+
+    // An admin may do anything.
+    assert!(access::grant_admin(&mut pool.get().unwrap(), &bob.email).unwrap());
+    assert!(!access::grant_admin(&mut pool.get().unwrap(), &bob.email).unwrap());
+    let export = mesh.export(authorized(&bob.token, ())).await.unwrap().into_inner();
+    assert!(!export.document.contains("alice's password"));
+    agents.unregister(authorized(&bob.token, by_id)).await.unwrap();
+
+    let alice_user = UserRequest {
+        id_or_email: Some(IdOrEmail::Id(alice.id)),
+    };
+    users.delete(authorized(&alice.token, alice_user)).await.unwrap();
+    PermissionMembershipClient::new(channel.clone())
+        .delete(authorized(&bob.token, PermissionMembershipRequest {
+            id_permission_or_userid: Some(IdPermissionOrUserid::UserId(bob.id)),
+        }))
+        .await
+        .unwrap();
+    users.delete(authorized(&bob.token, bob_user)).await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_shutdown_ends_watch_streams() {
+    let Started { channel, shutdown, .. } = match start().await {
+        Some(started) => started,
+        None => return,
+    };
+
+    let mut mesh = MeshClient::new(channel.clone())
+        .watch(authorized("test", ()))
+        .await
+        .unwrap()
+        .into_inner();
+    mesh.message().await.unwrap().unwrap();
+
+    let mut health = HealthClient::new(channel)
+        .watch(HealthCheckRequest { service: String::new() })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(health.message().await.unwrap().unwrap().status(), ServingStatus::Serving);
+
+    shutdown.trigger();
+
+    let ended = tokio::time::timeout(Duration::from_secs(5), async {
+        assert_eq!(mesh.message().await.unwrap_err().code(), Code::Unavailable);
+        assert_eq!(health.message().await.unwrap().unwrap().status(), ServingStatus::NotServing);
+        assert_eq!(health.message().await.unwrap_err().code(), Code::Unavailable);
+    });
+    ended.await.expect("watch streams were not ended");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_metrics() {
+    let Started { channel, exporter, .. } = match start().await {
+        Some(started) => started,
+        None => return,
+    };
+
+    let mut auth = AuthClient::new(channel.clone());
+    let user = auth
+        .register(LoginRequest {
+            email: format!("metrics-{}@example.org", rand::random::<u32>()),
+            password: "correct horse".to_string(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let token = user.token.as_str();
+
+    let mut agents = AgentClient::new(channel.clone());
+    let agent = AgentData {
+        uuid: format!("metrics-{}", rand::random::<u32>()),
+        owner: user.id,
+        ..Default::default()
+    };
+    let agent = agents.register(authorized(token, agent)).await.unwrap().into_inner();
+    let missing_uuid = AgentData {
+        owner: user.id,
+        ..Default::default()
+    };
+    assert_eq!(agents.register(authorized(token, missing_uuid)).await.unwrap_err().code(), Code::InvalidArgument);
+
+    let metrics = exporter.render().await;
+    for line in [
+        r#"grpc_server_handled_total{grpc_code="Ok",grpc_method="Register",grpc_service="api.Agent"} 1"#,
+        r#"grpc_server_handled_total{grpc_code="InvalidArgument",grpc_method="Register",grpc_service="api.Agent"} 1"#,
+        r#"tunnel_manager_db_pool_max_connections 2"#,
+    ] {
+        assert!(metrics.lines().any(|l| l == line), "{} missing from\n{}", line, metrics);
+    }
+    assert!(metrics.lines().any(|l| l.starts_with("tunnel_manager_agents ")), "{}", metrics);
+
+    agents
+        .unregister(authorized(token, AgentRequest {
+            id_uuid_or_owner: Some(IdUuidOrOwner::Id(agent.id.unwrap())),
+        }))
+        .await
+        .unwrap();
+    UserClient::new(channel)
+        .delete(authorized(token, UserRequest {
+            id_or_email: Some(IdOrEmail::Id(user.id)),
+        }))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_push_reports() {
+    let Started { channel, .. } = match start().await {
+        Some(started) => started,
+        None => return,
+    };
+
+    let mut auth = AuthClient::new(channel.clone());
+    let user = auth
+        .register(LoginRequest {
+            email: format!("pushes-{}@example.org", rand::random::<u32>()),
+            password: "correct horse".to_string(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let token = user.token.as_str();
+
+    let mut agents = AgentClient::new(channel.clone());
+    let agent = agents
+        .register(authorized(token, AgentData {
+            uuid: format!("pushes-{}", rand::random::<u32>()),
+            owner: user.id,
+            ..Default::default()
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+
+    let mut routers = RouterClient::new(channel.clone());
+    let router = routers
+        .add(authorized(token, RouterAddRequest {
+            agent: agent.id.unwrap(),
+            conn_type: Some("SSH".to_string()),
+            router_type: Some("Cisco".to_string()),
+            ..Default::default()
+        }))
+        .await
+        .unwrap()
+        .into_inner()
+        .id
+        .unwrap();
+    let by_router = RouterRequest {
+        id_or_agent: Some(IdOrAgent::Id(router)),
+        ..Default::default()
+    };
+
+    let desired = routers.render(authorized(token, by_router.clone())).await.unwrap().into_inner().config_hash;
+    let sync = &routers.sync_status(authorized(token, by_router.clone())).await.unwrap().into_inner().routers[0];
+    assert_eq!((sync.desired_hash.as_str(), sync.pushed_hash.as_str(), sync.behind), (desired.as_str(), "", true));
+    assert!(sync.last_push.is_none());
+
+    let now = std::time::SystemTime::now();
+    let failed = PushResult {
+        router,
+        config_hash: desired.clone(),
+        driver: "ssh-cisco".to_string(),
+        started_at: Some((now - Duration::from_secs(20)).into()),
+        finished_at: Some((now - Duration::from_secs(10)).into()),
+        success: false,
+        error_output: "% Invalid input detected at '^' marker.".to_string(),
+    };
+    routers.report_push(authorized(token, failed.clone())).await.unwrap();
+    let pushed = PushResult {
+        started_at: Some((now - Duration::from_secs(5)).into()),
+        finished_at: Some(now.into()),
+        success: true,
+        error_output: String::new(),
+        ..failed.clone()
+    };
+    let record = routers.report_push(authorized(token, pushed.clone())).await.unwrap().into_inner();
+    assert!(record.success && record.reported_at.is_some());
+
+    let sync = &routers.sync_status(authorized(token, by_router.clone())).await.unwrap().into_inner().routers[0];
+    assert_eq!((sync.pushed_hash.as_str(), sync.behind), (desired.as_str(), false));
+    assert_eq!(sync.last_push.as_ref().map(|p| p.id), Some(record.id));
+
+    let by_agent = RouterRequest {
+        id_or_agent: Some(IdOrAgent::Agent(agent.id.unwrap())),
+        ..Default::default()
+    };
+    assert_eq!(routers.sync_status(authorized(token, by_agent)).await.unwrap().into_inner().routers.len(), 1);
+
+    let history = routers
+        .push_history(authorized(token, PushHistoryRequest {
+            router,
+            page_size: 1,
+            ..Default::default()
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(history.pushes.len(), 1);
+    assert!(history.pushes[0].success);
+    let older = routers
+        .push_history(authorized(token, PushHistoryRequest {
+            router,
+            page_token: history.next_page_token,
+            ..Default::default()
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(older.pushes.len(), 1);
+    assert_eq!(older.pushes[0].error_output, failed.error_output);
+
+    let backwards = PushResult {
+        started_at: pushed.finished_at.clone(),
+        finished_at: pushed.started_at.clone(),
+        ..pushed.clone()
+    };
+    assert_eq!(routers.report_push(authorized(token, backwards)).await.unwrap_err().code(), Code::InvalidArgument);
+    let unknown = PushResult { router: i32::MAX, ..pushed };
+    assert_eq!(routers.report_push(authorized(token, unknown)).await.unwrap_err().code(), Code::NotFound);
+
+    routers.delete(authorized(token, by_router)).await.unwrap();
+    agents
+        .unregister(authorized(token, AgentRequest {
+            id_uuid_or_owner: Some(IdUuidOrOwner::Id(agent.id.unwrap())),
+        }))
+        .await
+        .unwrap();
+    UserClient::new(channel)
+        .delete(authorized(token, UserRequest {
+            id_or_email: Some(IdOrEmail::Id(user.id)),
+        }))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_drift() {
+    let mut config = dev_config();
+    config.drift.auto_remediate = true;
+    let Started { channel, .. } = match start_with(config).await {
+        Some(started) => started,
+        None => return,
+    };
+
+    let mut auth = AuthClient::new(channel.clone());
+    let user = auth
+        .register(LoginRequest {
+            email: format!("drift-{}@example.org", rand::random::<u32>()),
+            password: "correct horse".to_string(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let token = user.token.as_str();
+
+    let mut agents = AgentClient::new(channel.clone());
+    let agent = agents
+        .register(authorized(token, AgentData {
+            uuid: format!("drift-{}", rand::random::<u32>()),
+            owner: user.id,
+            ..Default::default()
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+
+    let mut routers = RouterClient::new(channel.clone());
+    let added = routers
+        .add(authorized(token, RouterAddRequest {
+            agent: agent.id.unwrap(),
+            conn_type: Some("SSH".to_string()),
+            router_type: Some("Cisco".to_string()),
+            ..Default::default()
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    let router = added.id.unwrap();
+    let by_agent = RouterRequest {
+        id_or_agent: Some(IdOrAgent::Agent(agent.id.unwrap())),
+        ..Default::default()
+    };
+
+    let never_read = &routers.get_drift(authorized(token, by_agent.clone())).await.unwrap().into_inner().routers[0];
+    assert!(!never_read.drifted && never_read.read_at.is_none());
+
+    // A tunnel the manager configured once and no longer renders, and one set up by hand.
+    let running = RunningConfig {
+        router,
+        config: "interface Tunnel60\n description HECnet: gone\n!\ninterface Tunnel7\n description lab\n!\n".to_string(),
+        read_at: Some(std::time::SystemTime::now().into()),
+    };
+    let report = routers.report_readback(authorized(token, running.clone())).await.unwrap().into_inner();
+    assert!(report.drifted && report.remediate);
+    assert_eq!(report.readback_interval_secs, Config::default().drift.readback_interval_secs);
+    assert_eq!(report.interfaces.len(), 1);
+    assert_eq!((report.interfaces[0].interface.as_str(), report.interfaces[0].kind.as_str()), ("Tunnel60", "unexpected"));
+
+    // Remediation pushes the config again, like Router.Repush.
+    let by_router = RouterRequest {
+        id_or_agent: Some(IdOrAgent::Id(router)),
+        ..Default::default()
+    };
+    let fetched = &routers.get(authorized(token, by_router.clone())).await.unwrap().into_inner().routers[0];
+    assert_ne!(fetched.updated_at, added.updated_at);
+
+    let drift = &routers.get_drift(authorized(token, by_agent)).await.unwrap().into_inner().routers[0];
+    assert!(drift.drifted && drift.read_at.is_some());
+
+    let fixed = RunningConfig {
+        config: "interface Tunnel7\n description lab\n!\n".to_string(),
+        ..running.clone()
+    };
+    let report = routers.report_readback(authorized(token, fixed)).await.unwrap().into_inner();
+    assert!(!report.drifted && !report.remediate && report.interfaces.is_empty());
+
+    let unread = RunningConfig {
+        read_at: None,
+        ..running.clone()
+    };
+    assert_eq!(routers.report_readback(authorized(token, unread)).await.unwrap_err().code(), Code::InvalidArgument);
+    let unknown = RunningConfig {
+        router: i32::MAX,
+        ..running
+    };
+    assert_eq!(routers.report_readback(authorized(token, unknown)).await.unwrap_err().code(), Code::NotFound);
+
+    routers.delete(authorized(token, by_router)).await.unwrap();
+    agents
+        .unregister(authorized(token, AgentRequest {
+            id_uuid_or_owner: Some(IdUuidOrOwner::Id(agent.id.unwrap())),
+        }))
+        .await
+        .unwrap();
+    UserClient::new(channel)
+        .delete(authorized(token, UserRequest {
+            id_or_email: Some(IdOrEmail::Id(user.id)),
+        }))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_dry_run() {
+    let Started { channel, .. } = match start().await {
+        Some(started) => started,
+        None => return,
+    };
+
+    let mut auth = AuthClient::new(channel.clone());
+    let user = auth
+        .register(LoginRequest {
+            email: format!("dry-run-{}@example.org", rand::random::<u32>()),
+            password: "correct horse".to_string(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let token = user.token.as_str();
+
+    let mut agents = AgentClient::new(channel.clone());
+    let agent = agents
+        .register(authorized(token, AgentData {
+            uuid: format!("dry-run-{}", rand::random::<u32>()),
+            owner: user.id,
+            ..Default::default()
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+
+    let mut routers = RouterClient::new(channel.clone());
+    let mut router_ids = Vec::new();
+    for _ in 0..2 {
+        let router = routers
+            .add(authorized(token, RouterAddRequest {
+                agent: agent.id.unwrap(),
+                conn_type: Some("SSH".to_string()),
+                router_type: Some("Cisco".to_string()),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        router_ids.push(router.id.unwrap());
+    }
+
+    let mut tunnels = TunnelClient::new(channel.clone());
+    let tunnel = |router: i32, last_octet: u32, dry_run: bool| TunnelAddRequest {
+        router,
+        ip: format!("198.51.100.{}", last_octet),
+        hostname: format!("dry-run-{}.example.org", last_octet),
+        description: "dry run".to_string(),
+        source: "GigabitEthernet0/0".to_string(),
+        topology_type: Some("mesh".to_string()),
+        dry_run,
+        ..Default::default()
+    };
+    let first = tunnels.add(authorized(token, tunnel(router_ids[0], 1, false))).await.unwrap().into_inner();
+    assert!(first.config_diffs.is_empty());
+
+    // Other tests share the database, so routers besides these may be touched too.
+    let preview = tunnels.add(authorized(token, tunnel(router_ids[1], 2, true))).await.unwrap().into_inner();
+    let touched: Vec<i32> = preview.config_diffs.iter().map(|d| d.router).collect();
+    assert!(router_ids.iter().all(|id| touched.contains(id)), "{:?} not all in {:?}", router_ids, touched);
+    let diff = &preview.config_diffs.iter().find(|d| d.router == router_ids[0]).unwrap().diff;
+    assert!(diff.contains(&format!("+interface Tunnel{}\n", preview.id)), "{}", diff);
+
+    // Nothing was kept.
+    let by_router = |router: i32| TunnelRequest {
+        id_or_router: Some(IdOrRouter::Router(router)),
+        ..Default::default()
+    };
+    assert!(tunnels.get(authorized(token, by_router(router_ids[1]))).await.unwrap().into_inner().tunnels.is_empty());
+
+    let second = tunnels.add(authorized(token, tunnel(router_ids[1], 2, false))).await.unwrap().into_inner();
+    let update = tunnels
+        .update(authorized(token, TunnelUpdateRequest {
+            id: second.id,
+            cost: Some(42),
+            dry_run: true,
+            ..Default::default()
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(update.config_diffs.len(), 1);
+    assert!(update.config_diffs[0].diff.contains("+ decnet cost 42\n"));
+    let delete = tunnels
+        .delete(authorized(token, TunnelRequest {
+            id_or_router: Some(IdOrRouter::Id(second.id)),
+            dry_run: true,
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    let touched: Vec<i32> = delete.config_diffs.iter().map(|d| d.router).collect();
+    assert!(router_ids.iter().all(|id| touched.contains(id)), "{:?} not all in {:?}", router_ids, touched);
+    assert_eq!(tunnels.get(authorized(token, by_router(router_ids[1]))).await.unwrap().into_inner().tunnels.len(), 1);
+
+    let converted = routers
+        .update(authorized(token, RouterUpdateRequest {
+            id: router_ids[0],
+            router_type: Some("PyDECNet".to_string()),
+            dry_run: true,
+            ..Default::default()
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(converted.router_type.as_deref(), Some("PyDECNet"));
+    assert_eq!(converted.config_diffs.len(), 1);
+    assert!(converted.config_diffs[0].diff.contains(&format!("+circuit gre-{} GRE", second.id)));
+    let fetched = &routers
+        .get(authorized(token, RouterRequest {
+            id_or_agent: Some(IdOrAgent::Id(router_ids[0])),
+            ..Default::default()
+        }))
+        .await
+        .unwrap()
+        .into_inner()
+        .routers[0];
+    assert_eq!(fetched.router_type.as_deref(), Some("Cisco"));
+
+    for router in &router_ids {
+        tunnels.delete(authorized(token, by_router(*router))).await.unwrap();
+        routers
+            .delete(authorized(token, RouterRequest {
+                id_or_agent: Some(IdOrAgent::Id(*router)),
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+    }
+    agents
+        .unregister(authorized(token, AgentRequest {
+            id_uuid_or_owner: Some(IdUuidOrOwner::Id(agent.id.unwrap())),
+        }))
+        .await
+        .unwrap();
+    UserClient::new(channel)
+        .delete(authorized(token, UserRequest {
+            id_or_email: Some(IdOrEmail::Id(user.id)),
+        }))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_stale_peers() {
+    let Started { channel, .. } = match start().await {
+        Some(started) => started,
+        None => return,
+    };
+
+    let mut auth = AuthClient::new(channel.clone());
+    let user = auth
+        .register(LoginRequest {
+            email: format!("stale-{}@example.org", rand::random::<u32>()),
+            password: "correct horse".to_string(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let token = user.token.as_str();
+
+    let mut agents = AgentClient::new(channel.clone());
+    let mut routers = RouterClient::new(channel.clone());
+    let mut agent_ids = Vec::new();
+    let mut router_ids = Vec::new();
+    for _ in 0..2 {
+        let agent = agents
+            .register(authorized(token, AgentData {
+                uuid: format!("stale-{}", rand::random::<u32>()),
+                owner: user.id,
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let router = routers
+            .add(authorized(token, RouterAddRequest {
+                agent: agent.id.unwrap(),
+                conn_type: Some("SSH".to_string()),
+                router_type: Some("Cisco".to_string()),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        agent_ids.push(agent.id.unwrap());
+        router_ids.push(router.id.unwrap());
+    }
+
+    let mut tunnels = TunnelClient::new(channel.clone());
+    let tunnel = |router: i32, last_octet: u32| TunnelAddRequest {
+        router,
+        ip: format!("203.0.113.{}", last_octet),
+        hostname: format!("stale-{}.example.org", last_octet),
+        description: "stale peers".to_string(),
+        source: "GigabitEthernet0/0".to_string(),
+        topology_type: Some("mesh".to_string()),
+        ..Default::default()
+    };
+    tunnels.add(authorized(token, tunnel(router_ids[0], 1))).await.unwrap();
+    let peer = tunnels.add(authorized(token, tunnel(router_ids[1], 2))).await.unwrap().into_inner();
+
+    let by_router = RouterRequest {
+        id_or_agent: Some(IdOrAgent::Id(router_ids[0])),
+        ..Default::default()
+    };
+    let renderer = routers.clone();
+    let rendered = || {
+        let mut routers = renderer.clone();
+        let request = authorized(token, by_router.clone());
+        async move { routers.render(request).await.unwrap().into_inner() }
+    };
+    let now = std::time::SystemTime::now();
+    let pushed = |config_hash: String| PushResult {
+        router: router_ids[0],
+        config_hash,
+        driver: "ssh-cisco".to_string(),
+        started_at: Some(now.into()),
+        finished_at: Some(now.into()),
+        success: true,
+        error_output: String::new(),
+    };
+    let removal = format!("no interface Tunnel{}\n", peer.id);
+
+    // Deleting a tunnel the router was never pushed leaves nothing to remove.
+    let config = rendered().await;
+    assert!(config.config.contains(&format!("interface Tunnel{}\n", peer.id)));
+    routers.report_push(authorized(token, pushed(config.config_hash))).await.unwrap();
+
+    tunnels
+        .delete(authorized(token, TunnelRequest {
+            id_or_router: Some(IdOrRouter::Id(peer.id)),
+            dry_run: false,
+        }))
+        .await
+        .unwrap();
+    let config = rendered().await;
+    assert!(config.config.contains(&removal), "{}", config.config);
+
+    // Once the removal is pushed the peer is forgotten.
+    routers.report_push(authorized(token, pushed(config.config_hash))).await.unwrap();
+    assert!(!rendered().await.config.contains(&removal));
+
+    // Unregistering an agent takes its routers and tunnels along and cleans up after them too.
+    let peer = tunnels.add(authorized(token, tunnel(router_ids[1], 3))).await.unwrap().into_inner();
+    routers.report_push(authorized(token, pushed(rendered().await.config_hash))).await.unwrap();
+    let unregister = |agent: i32| {
+        authorized(token, AgentRequest {
+            id_uuid_or_owner: Some(IdUuidOrOwner::Id(agent)),
+        })
+    };
+    agents.unregister(unregister(agent_ids[1])).await.unwrap();
+    assert!(rendered().await.config.contains(&format!("no interface Tunnel{}\n", peer.id)));
+
+    agents.unregister(unregister(agent_ids[0])).await.unwrap();
+    UserClient::new(channel)
+        .delete(authorized(token, UserRequest {
+            id_or_email: Some(IdOrEmail::Id(user.id)),
+        }))
+        .await
+        .unwrap();
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn test_api() -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = UserClient::connect("http://[::1]:50051").await?;
+async fn test_apply_stale_peers() {
+    // Applying replaces the whole mesh, so this runs on a database of its own.
+    let database = match scratch_database("apply") {
+        Some(database) => database,
+        None => return,
+    };
+    let Started { channel, .. } = start_on(database.url.clone(), dev_config()).await;
+
+    let user = AuthClient::new(channel.clone())
+        .register(LoginRequest {
+            email: "apply@example.org".to_string(),
+            password: "correct horse".to_string(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let token = user.token.as_str();
+
+    // Three Cisco routers on one agent, each with the tunnels listed for it.
+    let document = |layout: &[(i32, &[i32])]| {
+        let mut document = "[[agents]]\nuuid = \"apply\"\nowner = \"apply@example.org\"\n".to_string();
+        for (router, tunnels) in layout {
+            document += &format!("\n[[agents.routers]]\nid = {}\nrouter_type = \"Cisco\"\nconn_type = \"SSH\"\n", router);
+            for tunnel in *tunnels {
+                document += &format!(
+                    "\n[[agents.routers.tunnels]]\nid = {0}\nip = \"198.51.100.{0}\"\nhostname = \"apply-{0}.example.org\"\n\
+                     description = \"apply\"\nsource = \"GigabitEthernet0/0\"\ntopology_type = \"mesh\"\n",
+                    tunnel
+                );
+            }
+        }
+        document
+    };
+    let mesh = MeshClient::new(channel.clone());
+    let apply = |layout: &[(i32, &[i32])]| {
+        let request = authorized(token, MeshApplyRequest {
+            document: document(layout),
+            ..Default::default()
+        });
+        let mut mesh = mesh.clone();
+        async move { mesh.apply(request).await.unwrap().into_inner() }
+    };
+
+    let routers = RouterClient::new(channel.clone());
+    let by_router = |router: i32| {
+        authorized(token, RouterRequest {
+            id_or_agent: Some(IdOrAgent::Id(router)),
+            ..Default::default()
+        })
+    };
+    // Renders every router and reports it pushed, so the server knows which peers each has.
+    let push_all = || {
+        let mut routers = routers.clone();
+        async move {
+            let now = std::time::SystemTime::now();
+            let mut updated = Vec::new();
+            for router in [1, 2, 3] {
+                let config = routers.render(by_router(router)).await.unwrap().into_inner();
+                let push = PushResult {
+                    router,
+                    config_hash: config.config_hash,
+                    driver: "ssh-cisco".to_string(),
+                    started_at: Some(now.into()),
+                    finished_at: Some(now.into()),
+                    success: true,
+                    error_output: String::new(),
+                };
+                routers.report_push(authorized(token, push)).await.unwrap();
+                let router = routers.get(by_router(router)).await.unwrap().into_inner().routers.remove(0);
+                updated.push(std::time::SystemTime::try_from(router.updated_at.unwrap()).unwrap());
+            }
+            updated
+        }
+    };
+    let rendered = |router: i32| {
+        let mut routers = routers.clone();
+        async move { routers.render(by_router(router)).await.unwrap().into_inner().config }
+    };
+    let changed_since = |before: Vec<std::time::SystemTime>| {
+        let mut routers = routers.clone();
+        async move {
+            let mut changed = Vec::new();
+            for (router, before) in [1, 2, 3].into_iter().zip(before) {
+                let after = routers.get(by_router(router)).await.unwrap().into_inner().routers.remove(0).updated_at;
+                if std::time::SystemTime::try_from(after.unwrap()).unwrap() > before {
+                    changed.push(router);
+                }
+            }
+            changed
+        }
+    };
+
+    apply(&[(1, &[51]), (2, &[52]), (3, &[53])]).await;
+    let pushed = push_all().await;
+
+    // Removing a tunnel changes its router and the routers that have an interface to it.
+    let plan = apply(&[(1, &[51]), (2, &[52]), (3, &[])]).await;
+    assert_eq!((plan.changes[0].action.as_str(), plan.changes[0].key.as_str()), ("delete", "53"));
+    assert_eq!(changed_since(pushed).await, vec![1, 2, 3]);
+    let config = rendered(1).await;
+    assert!(config.contains("no interface Tunnel53\n"), "{}", config);
+    let config = rendered(3).await;
+    assert!(config.contains("no interface Tunnel51\n"), "{}", config);
+
+    // Moving one changes the router it leaves, as well as those that have an interface to it.
+    let pushed = push_all().await;
+    apply(&[(1, &[51]), (2, &[]), (3, &[52])]).await;
+    assert_eq!(changed_since(pushed).await, vec![1, 2]);
+    let config = rendered(2).await;
+    assert!(config.contains("no interface Tunnel51\n"), "{}", config);
+}
+
+#[tokio::test]
+async fn test_dynamic_ip() {
+    let Started { channel, .. } = match start().await {
+        Some(started) => started,
+        None => return,
+    };
+
+    let mut auth = AuthClient::new(channel.clone());
+    let user = auth
+        .register(LoginRequest {
+            email: format!("dynamic-{}@example.org", rand::random::<u32>()),
+            password: "correct horse".to_string(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let token = user.token.as_str();
+
+    let mut agents = AgentClient::new(channel.clone());
+    let agent = agents
+        .register(authorized(token, AgentData {
+            uuid: format!("dynamic-{}", rand::random::<u32>()),
+            owner: user.id,
+            ..Default::default()
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+
+    let mut routers = RouterClient::new(channel.clone());
+    let mut router_ids = Vec::new();
+    for _ in 0..3 {
+        let router = routers
+            .add(authorized(token, RouterAddRequest {
+                agent: agent.id.unwrap(),
+                conn_type: Some("SSH".to_string()),
+                router_type: Some("Cisco".to_string()),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        router_ids.push(router.id.unwrap());
+    }
+
+    let mut tunnels = TunnelClient::new(channel.clone());
+    let tunnel = |router: i32, ip: &str, ip_class: i32, dynamic_ip: bool| TunnelAddRequest {
+        router,
+        ip: ip.to_string(),
+        dynamic_ip: Some(dynamic_ip),
+        ip_class: Some(ip_class),
+        hostname: format!("dynamic-{}.example.org", router),
+        description: "dynamic ip".to_string(),
+        source: "GigabitEthernet0/0".to_string(),
+        topology_type: Some("mesh".to_string()),
+        ..Default::default()
+    };
+    let dynamic = tunnels
+        .add(authorized(token, tunnel(router_ids[0], "198.18.0.1", 4, true)))
+        .await
+        .unwrap()
+        .into_inner();
+    let fixed = tunnels
+        .add(authorized(token, tunnel(router_ids[1], "198.18.0.2", 4, false)))
+        .await
+        .unwrap()
+        .into_inner();
+    tunnels
+        .add(authorized(token, tunnel(router_ids[2], "2001:db8::3", 6, false)))
+        .await
+        .unwrap();
+
+    let rendered = |router: i32| {
+        let mut routers = routers.clone();
+        let request = authorized(token, RouterRequest {
+            id_or_agent: Some(IdOrAgent::Id(router)),
+            ..Default::default()
+        });
+        async move { routers.render(request).await.unwrap().into_inner().config }
+    };
+    assert!(rendered(router_ids[1]).await.contains(&format!(" tunnel destination dynamic-{}.example.org\n", router_ids[0])));
+
+    let report = |tunnel: i32, ip: &str| {
+        authorized(token, AddressReport {
+            tunnel,
+            ip: ip.to_string(),
+        })
+    };
+    let reported = tunnels.report_address(report(dynamic.id, "198.18.0.99")).await.unwrap().into_inner();
+    assert!(reported.changed);
+    assert!(reported.tunnel.unwrap().ip_reported_at.is_some());
+    assert!(reported.peers.contains(&router_ids[1]), "{:?}", reported.peers);
+    assert!(!reported.peers.contains(&router_ids[0]) && !reported.peers.contains(&router_ids[2]));
+    assert!(rendered(router_ids[1]).await.contains(" tunnel destination 198.18.0.99\n"));
+
+    let again = tunnels.report_address(report(dynamic.id, "198.18.0.99")).await.unwrap().into_inner();
+    assert!(!again.changed && again.peers.is_empty());
+
+    // Without an address the server uses the one it sees; clients cannot claim another one.
+    let mut seen = report(dynamic.id, "");
+    seen.metadata_mut().insert("x-forwarded-for", "192.0.2.1".parse().unwrap());
+    let seen = tunnels.report_address(seen).await.unwrap().into_inner();
+    assert_eq!(seen.tunnel.unwrap().ip, "127.0.0.1");
+
+    let code = |result: Result<tonic::Response<AddressReportResponse>, tonic::Status>| result.unwrap_err().code();
+    assert_eq!(code(tunnels.report_address(report(fixed.id, "198.18.0.98")).await), Code::FailedPrecondition);
+    assert_eq!(code(tunnels.report_address(report(dynamic.id, "2001:db8::1")).await), Code::InvalidArgument);
+    assert_eq!(code(tunnels.report_address(report(dynamic.id, "not an address")).await), Code::InvalidArgument);
+    assert_eq!(code(tunnels.report_address(report(i32::MAX, "198.18.0.98")).await), Code::NotFound);
 
-    let user1 = UserData {
-        id: None,
-        email: "test@example.com".to_string(),
+    agents
+        .unregister(authorized(token, AgentRequest {
+            id_uuid_or_owner: Some(IdUuidOrOwner::Id(agent.id.unwrap())),
+        }))
+        .await
+        .unwrap();
+    UserClient::new(channel)
+        .delete(authorized(token, UserRequest {
+            id_or_email: Some(IdOrEmail::Id(user.id)),
+        }))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_resolver() {
+    let Started { channel, pool, .. } = match start().await {
+        Some(started) => started,
+        None => return,
+    };
+
+    let mut auth = AuthClient::new(channel.clone());
+    let user = auth
+        .register(LoginRequest {
+            email: format!("resolver-{}@example.org", rand::random::<u32>()),
+            password: "correct horse".to_string(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let token = user.token.as_str();
+
+    let mut agents = AgentClient::new(channel.clone());
+    let agent = agents
+        .register(authorized(token, AgentData {
+            uuid: format!("resolver-{}", rand::random::<u32>()),
+            owner: user.id,
+            ..Default::default()
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+
+    let mut routers = RouterClient::new(channel.clone());
+    let mut router_ids = Vec::new();
+    for _ in 0..2 {
+        let router = routers
+            .add(authorized(token, RouterAddRequest {
+                agent: agent.id.unwrap(),
+                conn_type: Some("SSH".to_string()),
+                router_type: Some("Cisco".to_string()),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        router_ids.push(router.id.unwrap());
+    }
+
+    let hostname = format!("resolver-{}.example.org", rand::random::<u32>());
+    let mut tunnels = TunnelClient::new(channel.clone());
+    let tunnel = |router: i32, ip: &str, hostname: &str, dynamic_ip: bool| TunnelAddRequest {
+        router,
+        ip: ip.to_string(),
+        dynamic_ip: Some(dynamic_ip),
+        hostname: hostname.to_string(),
+        description: "resolver".to_string(),
+        source: "GigabitEthernet0/0".to_string(),
+        topology_type: Some("mesh".to_string()),
+        ..Default::default()
+    };
+    let dynamic = tunnels
+        .add(authorized(token, tunnel(router_ids[0], "198.19.0.1", &hostname, true)))
+        .await
+        .unwrap()
+        .into_inner();
+    tunnels
+        .add(authorized(token, tunnel(router_ids[1], "198.19.0.2", "resolver-peer.example.org", false)))
+        .await
+        .unwrap();
+
+    let dns = Arc::new(StaticResolver::new(Duration::from_secs(300)));
+    // A fresh resolver has every hostname due, instead of waiting out the TTL.
+    let resolve = || async { Resolver::new(pool.clone(), dns.clone(), &ResolverConfig::default()).pass().await };
+    let rendered = |router: i32| {
+        let mut routers = routers.clone();
+        let request = authorized(token, RouterRequest {
+            id_or_agent: Some(IdOrAgent::Id(router)),
+            ..Default::default()
+        });
+        async move { routers.render(request).await.unwrap().into_inner().config }
+    };
+    let history = || {
+        let mut tunnels = tunnels.clone();
+        let request = authorized(token, ResolutionHistoryRequest {
+            tunnel: dynamic.id,
+            ..Default::default()
+        });
+        async move { tunnels.resolution_history(request).await.unwrap().into_inner().resolutions }
+    };
+
+    resolve().await;
+    let failed = history().await;
+    assert_eq!(failed.len(), 1);
+    assert_eq!((failed[0].ip.as_str(), failed[0].error.as_str()), ("", "no such host"));
+    assert!(rendered(router_ids[1]).await.contains(&format!(" tunnel destination {}\n", hostname)));
+
+    dns.set(&hostname, vec!["198.19.0.5".parse().unwrap()]);
+    resolve().await;
+    let resolved = history().await;
+    assert_eq!((resolved.len(), resolved[0].ip.as_str(), resolved[0].ttl_secs), (2, "198.19.0.5", 300));
+    assert!(rendered(router_ids[1]).await.contains(" tunnel destination 198.19.0.5\n"));
+    let fetched = &tunnels
+        .clone()
+        .get(authorized(token, TunnelRequest {
+            id_or_router: Some(IdOrRouter::Id(dynamic.id)),
+            ..Default::default()
+        }))
+        .await
+        .unwrap()
+        .into_inner()
+        .tunnels[0];
+    assert!(fetched.ip_resolved_at.is_some());
+
+    // The same answer, or one that still has the current address, changes nothing.
+    dns.set(&hostname, vec!["198.19.0.6".parse().unwrap(), "198.19.0.5".parse().unwrap()]);
+    resolve().await;
+    assert_eq!(history().await.len(), 2);
+
+    dns.set(&hostname, vec!["198.19.0.9".parse().unwrap()]);
+    resolve().await;
+    assert_eq!(history().await[0].ip, "198.19.0.9");
+    assert!(rendered(router_ids[1]).await.contains(" tunnel destination 198.19.0.9\n"));
+
+    agents
+        .unregister(authorized(token, AgentRequest {
+            id_uuid_or_owner: Some(IdUuidOrOwner::Id(agent.id.unwrap())),
+        }))
+        .await
+        .unwrap();
+    UserClient::new(channel)
+        .delete(authorized(token, UserRequest {
+            id_or_email: Some(IdOrEmail::Id(user.id)),
+        }))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_ipsec_keys() {
+    let config = Config {
+        render: RenderConfig {
+            psk_secret: Some("an ipsec test secret, long enough".to_string()),
+            ..RenderConfig::default()
+        },
+        ..dev_config()
+    };
+    let Started { channel, .. } = match start_with(config).await {
+        Some(started) => started,
+        None => return,
     };
 
-    let add_response = client.add(user1.clone()).await;
-    assert!(!add_response.is_err());
+    let mut auth = AuthClient::new(channel.clone());
+    let user = auth
+        .register(LoginRequest {
+            email: format!("ipsec-{}@example.org", rand::random::<u32>()),
+            password: "correct horse".to_string(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let token = user.token.as_str();
+
+    let mut agents = AgentClient::new(channel.clone());
+    let agent = agents
+        .register(authorized(token, AgentData {
+            uuid: format!("ipsec-{}", rand::random::<u32>()),
+            owner: user.id,
+            ..Default::default()
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+
+    let mut routers = RouterClient::new(channel.clone());
+    let mut router_ids = Vec::new();
+    for router_type in ["Cisco", "Cisco", "PyDECNet"] {
+        let router = routers
+            .add(authorized(token, RouterAddRequest {
+                agent: agent.id.unwrap(),
+                conn_type: Some("SSH".to_string()),
+                router_type: Some(router_type.to_string()),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        router_ids.push(router.id.unwrap());
+    }
+
+    let mut tunnels = TunnelClient::new(channel.clone());
+    let tunnel = |router: i32, tunnel_type: &str| TunnelAddRequest {
+        router,
+        ip: format!("198.18.1.{}", router % 250),
+        hostname: format!("ipsec-{}.example.org", router),
+        description: "ipsec".to_string(),
+        source: "GigabitEthernet0/0".to_string(),
+        tunnel_type: Some(tunnel_type.to_string()),
+        topology_type: Some("mesh".to_string()),
+        ..Default::default()
+    };
+    let first = tunnels.add(authorized(token, tunnel(router_ids[0], "IPSec"))).await.unwrap().into_inner();
+    tunnels.add(authorized(token, tunnel(router_ids[1], "IPSec"))).await.unwrap();
+    let gre = tunnels.add(authorized(token, tunnel(router_ids[2], "GRE"))).await.unwrap().into_inner();
+
+    let key = |router: i32| {
+        let mut routers = routers.clone();
+        let request = authorized(token, RouterRequest {
+            id_or_agent: Some(IdOrAgent::Id(router)),
+            ..Default::default()
+        });
+        async move {
+            let config = routers.render(request).await.unwrap().into_inner().config;
+            let line = config.lines().find(|l| l.trim_start().starts_with("pre-shared-key ")).unwrap();
+            line.trim_start()["pre-shared-key ".len()..].to_string()
+        }
+    };
+
+    // Both ends get the same key, which stays the same until it is rotated.
+    let psk = key(router_ids[0]).await;
+    assert_eq!(psk.len(), 40);
+    assert_eq!(key(router_ids[1]).await, psk);
+    assert_eq!(key(router_ids[0]).await, psk);
+
+    let rotated = tunnels
+        .rotate_keys(authorized(token, TunnelRequest {
+            id_or_router: Some(IdOrRouter::Id(first.id)),
+            ..Default::default()
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(rotated.rotated, 1);
+    assert_eq!(rotated.routers, router_ids[..2].to_vec());
+    let rotated_psk = key(router_ids[1]).await;
+    assert_ne!(rotated_psk, psk);
+    assert_eq!(key(router_ids[0]).await, rotated_psk);
+
+    let missing = tunnels
+        .rotate_keys(authorized(token, TunnelRequest {
+            id_or_router: Some(IdOrRouter::Id(i32::MAX)),
+            ..Default::default()
+        }))
+        .await;
+    assert_eq!(missing.unwrap_err().code(), Code::NotFound);
+
+    // PyDECnet routers cannot take part in IPSec peerings.
+    let added = tunnels.add(authorized(token, tunnel(router_ids[2], "IPSec"))).await;
+    assert_eq!(added.unwrap_err().code(), Code::FailedPrecondition);
+    let updated = tunnels
+        .update(authorized(token, TunnelUpdateRequest {
+            id: gre.id,
+            tunnel_type: Some("IPSec".to_string()),
+            ..Default::default()
+        }))
+        .await;
+    assert_eq!(updated.unwrap_err().code(), Code::FailedPrecondition);
+    let moved = tunnels
+        .update(authorized(token, TunnelUpdateRequest {
+            id: first.id,
+            router: Some(router_ids[2]),
+            ..Default::default()
+        }))
+        .await;
+    assert_eq!(moved.unwrap_err().code(), Code::FailedPrecondition);
+    let converted = routers
+        .update(authorized(token, RouterUpdateRequest {
+            id: router_ids[0],
+            router_type: Some("PyDECNet".to_string()),
+            ..Default::default()
+        }))
+        .await;
+    assert_eq!(converted.unwrap_err().code(), Code::FailedPrecondition);
+
+    agents
+        .unregister(authorized(token, AgentRequest {
+            id_uuid_or_owner: Some(IdUuidOrOwner::Id(agent.id.unwrap())),
+        }))
+        .await
+        .unwrap();
+    UserClient::new(channel)
+        .delete(authorized(token, UserRequest {
+            id_or_email: Some(IdOrEmail::Id(user.id)),
+        }))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_wireguard() {
+    let config = Config {
+        render: RenderConfig {
+            psk_secret: Some("a wireguard test secret, long enough".to_string()),
+            ..RenderConfig::default()
+        },
+        ..dev_config()
+    };
+    let Started { channel, .. } = match start_with(config).await {
+        Some(started) => started,
+        None => return,
+    };
+
+    let mut auth = AuthClient::new(channel.clone());
+    let user = auth
+        .register(LoginRequest {
+            email: format!("wireguard-{}@example.org", rand::random::<u32>()),
+            password: "correct horse".to_string(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let token = user.token.as_str();
+
+    let mut agents = AgentClient::new(channel.clone());
+    let agent = agents
+        .register(authorized(token, AgentData {
+            uuid: format!("wireguard-{}", rand::random::<u32>()),
+            owner: user.id,
+            ..Default::default()
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+
+    let mut routers = RouterClient::new(channel.clone());
+    let mut router_ids = Vec::new();
+    for router_type in ["PyDECNet", "PyDECNet", "Cisco"] {
+        let router = routers
+            .add(authorized(token, RouterAddRequest {
+                agent: agent.id.unwrap(),
+                conn_type: Some("SSH".to_string()),
+                router_type: Some(router_type.to_string()),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        router_ids.push(router.id.unwrap());
+    }
 
-    let add_response = client.add(user1).await;
-    assert!(add_response.is_err());
+    let mut tunnels = TunnelClient::new(channel.clone());
+    let tunnel = |router: i32| TunnelAddRequest {
+        router,
+        ip: format!("198.18.2.{}", router % 250),
+        hostname: format!("wireguard-{}.example.org", router),
+        description: "wireguard".to_string(),
+        source: "eth0".to_string(),
+        tunnel_type: Some("WireGuard".to_string()),
+        topology_type: Some("mesh".to_string()),
+        ..Default::default()
+    };
+    let first = tunnels.add(authorized(token, tunnel(router_ids[0]))).await.unwrap().into_inner();
+    let second = tunnels.add(authorized(token, tunnel(router_ids[1]))).await.unwrap().into_inner();
+
+    let wg_quick = |router: i32, tunnel: i32| {
+        let mut routers = routers.clone();
+        let request = authorized(token, RouterRequest {
+            id_or_agent: Some(IdOrAgent::Id(router)),
+            ..Default::default()
+        });
+        async move {
+            let config = routers.render(request).await.unwrap().into_inner().config;
+            let (_, mut configs) = render::wg_quick_configs(&config);
+            configs.remove(&format!("wg{}", tunnel)).unwrap()
+        }
+    };
+    let value = |config: &str, key: &str| {
+        config
+            .lines()
+            .find_map(|line| line.strip_prefix(&format!("{} = ", key)))
+            .unwrap()
+            .to_string()
+    };
+
+    // Each end knows the other's public key and address, and they stay the same.
+    let one = wg_quick(router_ids[0], first.id).await;
+    let two = wg_quick(router_ids[1], second.id).await;
+    assert_eq!(public_key(&value(&one, "PrivateKey")).unwrap(), value(&two, "PublicKey"));
+    assert_eq!(public_key(&value(&two, "PrivateKey")).unwrap(), value(&one, "PublicKey"));
+    assert_eq!(value(&one, "Address").replace("/32", ""), value(&two, "AllowedIPs").replace("/32", ""));
+    assert_ne!(value(&one, "Address"), value(&two, "Address"));
+    assert_eq!(value(&two, "Endpoint"), format!("{}:{}", first.ip, value(&one, "ListenPort")));
+    assert_eq!(wg_quick(router_ids[0], first.id).await, one);
+
+    // IOS has no WireGuard.
+    let added = tunnels.add(authorized(token, tunnel(router_ids[2]))).await;
+    assert_eq!(added.unwrap_err().code(), Code::FailedPrecondition);
+    let converted = routers
+        .update(authorized(token, RouterUpdateRequest {
+            id: router_ids[0],
+            router_type: Some("Cisco".to_string()),
+            ..Default::default()
+        }))
+        .await;
+    assert_eq!(converted.unwrap_err().code(), Code::FailedPrecondition);
+
+    agents
+        .unregister(authorized(token, AgentRequest {
+            id_uuid_or_owner: Some(IdUuidOrOwner::Id(agent.id.unwrap())),
+        }))
+        .await
+        .unwrap();
+    UserClient::new(channel)
+        .delete(authorized(token, UserRequest {
+            id_or_email: Some(IdOrEmail::Id(user.id)),
+        }))
+        .await
+        .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_router_drivers() {
+    let Started { channel, .. } = match start().await {
+        Some(started) => started,
+        None => return,
+    };
+
+    let mut auth = AuthClient::new(channel.clone());
+    let user = auth
+        .register(LoginRequest {
+            email: format!("drivers-{}@example.org", rand::random::<u32>()),
+            password: "correct horse".to_string(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let token = user.token.as_str();
+
+    let mut routers = RouterClient::new(channel.clone());
+    let types = routers.list_types(authorized(token, ())).await.unwrap().into_inner();
+    let mikrotik = types.router_types.iter().find(|t| t.name == "MikroTik").unwrap();
+    assert!(mikrotik.renderable);
+    assert_eq!(mikrotik.conn_types, vec!["SSH"]);
+    assert!(types.router_types.iter().any(|t| t.name == "Cisco" && t.renderable));
+    for conn_type in ["SNMP", "SSH"] {
+        assert!(types.conn_types.iter().any(|c| c == conn_type), "{:?}", types.conn_types);
+    }
+
+    let uuid = format!("drivers-{}", rand::random::<u32>());
+    let mut agents = AgentClient::new(channel.clone());
+    let agent = agents
+        .register(authorized(token, AgentData {
+            uuid: uuid.clone(),
+            owner: user.id,
+            ..Default::default()
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    let agent_id = agent.id.unwrap();
+    let router = |router_type: &str, conn_type: &str| RouterAddRequest {
+        agent: agent_id,
+        router_type: Some(router_type.to_string()),
+        conn_type: Some(conn_type.to_string()),
+        ..Default::default()
+    };
+
+    for (router_type, conn_type) in [("Juniper", "SSH"), ("MikroTik", "Telnet")] {
+        let err = routers.add(authorized(token, router(router_type, conn_type))).await.unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument, "{}", err.message());
+    }
+
+    // An agent that never said which drivers it has can have routers of any known type.
+    let mikrotik = routers
+        .add(authorized(token, router("MikroTik", "SSH")))
+        .await
+        .unwrap()
+        .into_inner();
+    let mikrotik_id = mikrotik.id.unwrap();
+
+    let driver = |router_type: &str, conn_type: &str| RouterDriver {
+        router_type: router_type.to_string(),
+        conn_type: conn_type.to_string(),
+    };
+    let drivers = AgentDriversRequest {
+        uuid: uuid.clone(),
+        drivers: vec![driver("MikroTik", "SSH"), driver("Cisco", "SSH")],
+    };
+    agents.set_drivers(authorized(token, drivers.clone())).await.unwrap();
+
+    let get = AgentRequest {
+        id_uuid_or_owner: Some(IdUuidOrOwner::Id(agent_id)),
+    };
+    let fetched = &agents.get(authorized(token, get)).await.unwrap().into_inner().agents[0];
+    assert_eq!(fetched.drivers, vec![driver("Cisco", "SSH"), driver("MikroTik", "SSH")]);
+
+    for (router_type, conn_type) in [("PyDECNet", "SSH"), ("Cisco", "SNMP")] {
+        let err = routers.add(authorized(token, router(router_type, conn_type))).await.unwrap_err();
+        assert_eq!(err.code(), Code::FailedPrecondition, "{}", err.message());
+    }
+    let cisco = routers
+        .add(authorized(token, router("Cisco", "SSH")))
+        .await
+        .unwrap()
+        .into_inner();
+    let update = RouterUpdateRequest {
+        id: cisco.id.unwrap(),
+        conn_type: Some("SNMP".to_string()),
+        ..Default::default()
+    };
+    let err = routers.update(authorized(token, update.clone())).await.unwrap_err();
+    assert_eq!(err.code(), Code::FailedPrecondition, "{}", err.message());
+
+    let unknown = AgentDriversRequest {
+        uuid: "no-such-agent".to_string(),
+        ..drivers.clone()
+    };
+    assert_eq!(agents.set_drivers(authorized(token, unknown)).await.unwrap_err().code(), Code::NotFound);
+
+    // RouterOS gets its peerings as GRE interfaces.
+    let mut tunnels = TunnelClient::new(channel.clone());
+    for (router_id, ip) in [(mikrotik_id, "198.18.3.1"), (cisco.id.unwrap(), "198.18.3.2")] {
+        tunnels
+            .add(authorized(token, TunnelAddRequest {
+                router: router_id,
+                ip: ip.to_string(),
+                hostname: format!("drivers-{}.example.org", router_id),
+                description: "drivers".to_string(),
+                source: ip.to_string(),
+                topology_type: Some("mesh".to_string()),
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+    }
+    let request = RouterRequest {
+        id_or_agent: Some(IdOrAgent::Id(mikrotik_id)),
+        dry_run: false,
+    };
+    let config = routers.render(authorized(token, request)).await.unwrap().into_inner();
+    assert_eq!(config.router_type, "MikroTik");
+    assert!(config.config.contains("/interface gre\n"), "{}", config.config);
+    assert!(config.config.contains("remote-address=198.18.3.2 local-address=198.18.3.1"), "{}", config.config);
+
+    // Setting no drivers lifts the restriction again.
+    agents
+        .set_drivers(authorized(token, AgentDriversRequest { drivers: vec![], ..drivers }))
+        .await
+        .unwrap();
+    routers.update(authorized(token, update)).await.unwrap();
+
+    agents
+        .unregister(authorized(token, AgentRequest {
+            id_uuid_or_owner: Some(IdUuidOrOwner::Id(agent_id)),
+        }))
+        .await
+        .unwrap();
+    UserClient::new(channel)
+        .delete(authorized(token, UserRequest {
+            id_or_email: Some(IdOrEmail::Id(user.id)),
+        }))
+        .await
+        .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_pagination() {
+    let Started { channel, .. } = match start().await {
+        Some(started) => started,
+        None => return,
+    };
+
+    let user = AuthClient::new(channel.clone())
+        .register(LoginRequest {
+            email: format!("pages-{}@example.org", rand::random::<u32>()),
+            password: "correct horse".to_string(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let token = user.token.as_str();
+
+    let mut agents = AgentClient::new(channel.clone());
+    let agent = agents
+        .register(authorized(token, AgentData {
+            uuid: format!("pages-{}", rand::random::<u32>()),
+            owner: user.id,
+            ..Default::default()
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    let router = RouterClient::new(channel.clone())
+        .add(authorized(token, RouterAddRequest {
+            agent: agent.id.unwrap(),
+            conn_type: Some("SSH".to_string()),
+            router_type: Some("Cisco".to_string()),
+            ..Default::default()
+        }))
+        .await
+        .unwrap()
+        .into_inner()
+        .id
+        .unwrap();
+
+    // Three of the five share a cost, so ordering by cost puts page boundaries inside a tie.
+    let mut tunnels = TunnelClient::new(channel.clone());
+    let mut added = Vec::new();
+    for (i, cost) in [20, 10, 20, 30, 20].into_iter().enumerate() {
+        let ip = format!("198.18.5.{}", i + 1);
+        let tunnel = tunnels
+            .add(authorized(token, TunnelAddRequest {
+                router,
+                ip: ip.clone(),
+                hostname: format!("pages-{}.example.org", i),
+                description: "pages".to_string(),
+                source: ip,
+                cost: Some(cost),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        added.push((tunnel.cost, tunnel.id));
+    }
+
+    let list = |order_by: &str, page_size: i32, page_token: String| {
+        let mut tunnels = tunnels.clone();
+        let request = authorized(token, TunnelListRequest {
+            router: Some(router),
+            order_by: order_by.to_string(),
+            page_size,
+            page_token,
+            ..Default::default()
+        });
+        async move { tunnels.list(request).await }
+    };
+    let pages = |order_by: &'static str, page_size: i32| async move {
+        let (mut pages, mut page_token) = (Vec::new(), String::new());
+        loop {
+            let page = list(order_by, page_size, page_token).await.unwrap().into_inner();
+            pages.push(page.tunnels.iter().map(|t| t.id).collect::<Vec<_>>());
+            if page.next_page_token.is_empty() {
+                return pages;
+            }
+            page_token = page.next_page_token;
+        }
+    };
+
+    let mut by_id: Vec<i32> = added.iter().map(|(_, id)| *id).collect();
+    by_id.sort();
+    assert_eq!(pages("id", 2).await, vec![by_id[..2].to_vec(), by_id[2..4].to_vec(), by_id[4..].to_vec()]);
+    // A last page that is exactly full says so, rather than pointing at an empty one.
+    assert_eq!(pages("id", 5).await, vec![by_id.clone()]);
+
+    added.sort();
+    let by_cost: Vec<i32> = added.iter().map(|(_, id)| *id).collect();
+    assert_eq!(pages("cost", 2).await.concat(), by_cost);
+    assert_eq!(pages("cost", 1).await.concat(), by_cost);
+
+    added.reverse();
+    let by_cost_desc: Vec<i32> = added.iter().map(|(_, id)| *id).collect();
+    assert_eq!(pages("cost desc", 2).await.concat(), by_cost_desc);
+    assert_eq!(pages("cost DESC", 3).await.concat(), by_cost_desc);
+
+    // A token only makes sense for the order it was issued for.
+    let token_by_cost = list("cost", 2, String::new()).await.unwrap().into_inner().next_page_token;
+    let err = list("hostname", 2, token_by_cost.clone()).await.unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument, "{}", err.message());
+    let err = list("cost desc", 2, token_by_cost.clone()).await.unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument, "{}", err.message());
+    assert_eq!(list("cost asc", 2, token_by_cost).await.unwrap().into_inner().tunnels.len(), 2);
+    for (order_by, page_size, page_token) in [("ip", 2, ""), ("cost sideways", 2, ""), ("cost", -1, ""), ("cost", 2, "zz")] {
+        let err = list(order_by, page_size, page_token.to_string()).await.unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument, "{}", err.message());
+    }
+
+    agents
+        .unregister(authorized(token, AgentRequest {
+            id_uuid_or_owner: agent.id.map(IdUuidOrOwner::Id),
+        }))
+        .await
+        .unwrap();
+    UserClient::new(channel)
+        .delete(authorized(token, UserRequest {
+            id_or_email: Some(IdOrEmail::Id(user.id)),
+        }))
+        .await
+        .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_updated_since() {
+    let Started { channel, .. } = match start().await {
+        Some(started) => started,
+        None => return,
+    };
+
+    let user = AuthClient::new(channel.clone())
+        .register(LoginRequest {
+            email: format!("since-{}@example.org", rand::random::<u32>()),
+            password: "correct horse".to_string(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let token = user.token.as_str();
+
+    let mut agents = AgentClient::new(channel.clone());
+    let agent = agents
+        .register(authorized(token, AgentData {
+            uuid: format!("since-{}", rand::random::<u32>()),
+            owner: user.id,
+            ..Default::default()
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    let mut routers = RouterClient::new(channel.clone());
+    let router = routers
+        .add(authorized(token, RouterAddRequest {
+            agent: agent.id.unwrap(),
+            conn_type: Some("SSH".to_string()),
+            router_type: Some("Cisco".to_string()),
+            ..Default::default()
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+
+    let mut tunnels = TunnelClient::new(channel.clone());
+    let mut added = Vec::new();
+    for i in 1..=2 {
+        let ip = format!("198.18.6.{}", i);
+        let tunnel = tunnels
+            .add(authorized(token, TunnelAddRequest {
+                router: router.id.unwrap(),
+                ip: ip.clone(),
+                hostname: format!("since-{}.example.org", i),
+                description: "since".to_string(),
+                source: ip,
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        added.push(tunnel);
+    }
+    let (first, second) = (&added[0], &added[1]);
+    let at = |time: &Option<Timestamp>| time.clone().map(|t| (t.seconds, t.nanos)).unwrap();
+    assert_eq!(first.updated_at, first.created_at);
+    assert!(at(&second.updated_at) > at(&first.updated_at));
+
+    // Only updates move updated_at; created_at stays put.
+    let updated = tunnels
+        .update(authorized(token, TunnelUpdateRequest {
+            id: first.id,
+            description: Some("since, updated".to_string()),
+            ..Default::default()
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(updated.created_at, first.created_at);
+    assert!(at(&updated.updated_at) > at(&second.updated_at));
+
+    let since = |updated_since: Option<Timestamp>| {
+        let mut tunnels = tunnels.clone();
+        let request = authorized(token, TunnelListRequest {
+            router: router.id,
+            updated_since,
+            ..Default::default()
+        });
+        async move { tunnels.list(request).await.unwrap().into_inner().tunnels.iter().map(|t| t.id).collect::<Vec<_>>() }
+    };
+    assert_eq!(since(None).await, vec![first.id, second.id]);
+    assert_eq!(since(first.updated_at.clone()).await, vec![first.id, second.id]);
+    assert_eq!(since(second.updated_at.clone()).await, vec![first.id, second.id]);
+    assert_eq!(since(updated.updated_at.clone()).await, vec![first.id]);
+    let later = Timestamp {
+        seconds: at(&updated.updated_at).0 + 3600,
+        nanos: 0,
+    };
+    assert_eq!(since(Some(later.clone())).await, Vec::<i32>::new());
+
+    // Routers filter the same way.
+    let listed = |updated_since: Timestamp| {
+        let mut routers = routers.clone();
+        let request = authorized(token, RouterListRequest {
+            agent: agent.id,
+            updated_since: Some(updated_since),
+            ..Default::default()
+        });
+        async move { routers.list(request).await.unwrap().into_inner().routers.len() }
+    };
+    assert_eq!(listed(router.updated_at.clone().unwrap()).await, 1);
+    assert_eq!(listed(later).await, 0);
+
+    agents
+        .unregister(authorized(token, AgentRequest {
+            id_uuid_or_owner: agent.id.map(IdUuidOrOwner::Id),
+        }))
+        .await
+        .unwrap();
+    UserClient::new(channel)
+        .delete(authorized(token, UserRequest {
+            id_or_email: Some(IdOrEmail::Id(user.id)),
+        }))
+        .await
+        .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_get_by_foreign_key() {
+    let Started { channel, .. } = match start().await {
+        Some(started) => started,
+        None => return,
+    };
+
+    let user = AuthClient::new(channel.clone())
+        .register(LoginRequest {
+            email: format!("tree-{}@example.org", rand::random::<u32>()),
+            password: "correct horse".to_string(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let token = user.token.as_str();
+
+    let mut agents = AgentClient::new(channel.clone());
+    let agent = agents
+        .register(authorized(token, AgentData {
+            uuid: format!("tree-{}", rand::random::<u32>()),
+            owner: user.id,
+            ..Default::default()
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    let by_agent = AgentRequest {
+        id_uuid_or_owner: agent.id.map(IdUuidOrOwner::Id),
+    };
+
+    // Two routers, with three tunnels and none.
+    let mut routers = RouterClient::new(channel.clone());
+    let mut router_ids = Vec::new();
+    for _ in 0..2 {
+        let router = routers
+            .add(authorized(token, RouterAddRequest {
+                agent: agent.id.unwrap(),
+                conn_type: Some("SSH".to_string()),
+                router_type: Some("Cisco".to_string()),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        router_ids.push(router.id.unwrap());
+    }
+    let mut tunnels = TunnelClient::new(channel.clone());
+    let mut tunnel_ids = Vec::new();
+    for i in 1..=3 {
+        let ip = format!("198.18.7.{}", i);
+        let tunnel = tunnels
+            .add(authorized(token, TunnelAddRequest {
+                router: router_ids[0],
+                ip: ip.clone(),
+                hostname: format!("tree-{}.example.org", i),
+                description: "tree".to_string(),
+                source: ip,
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        tunnel_ids.push(tunnel.id);
+    }
+
+    let of_router = |router: i32| {
+        let mut tunnels = tunnels.clone();
+        let request = authorized(token, TunnelRequest {
+            id_or_router: Some(IdOrRouter::Router(router)),
+            ..Default::default()
+        });
+        async move { tunnels.get(request).await.unwrap().into_inner().tunnels.iter().map(|t| t.id).collect::<Vec<_>>() }
+    };
+    assert_eq!(of_router(router_ids[0]).await, tunnel_ids);
+    assert_eq!(of_router(router_ids[1]).await, Vec::<i32>::new());
+
+    let by_id = TunnelRequest {
+        id_or_router: Some(IdOrRouter::Id(tunnel_ids[1])),
+        ..Default::default()
+    };
+    let tunnel = tunnels.get(authorized(token, by_id)).await.unwrap().into_inner().tunnels;
+    assert_eq!(tunnel.iter().map(|t| t.id).collect::<Vec<_>>(), vec![tunnel_ids[1]]);
+
+    let request = RouterRequest {
+        id_or_agent: agent.id.map(IdOrAgent::Agent),
+        ..Default::default()
+    };
+    let of_agent = routers.get(authorized(token, request)).await.unwrap().into_inner().routers;
+    assert_eq!(of_agent.iter().map(|r| r.id.unwrap()).collect::<Vec<_>>(), router_ids);
 
-    // let list_response = client.list(()).await?;
-    // assert_eq!(list_response.into_inner().users[0].id, add_response.into_inner().id);
+    // The tree holds all of it in one response.
+    let trees = agents.get_tree(authorized(token, by_agent.clone())).await.unwrap().into_inner().agents;
+    assert_eq!(trees.len(), 1);
+    assert_eq!(trees[0].agent.as_ref().and_then(|a| a.id), agent.id);
+    let tree: Vec<(i32, Vec<i32>)> = trees[0]
+        .routers
+        .iter()
+        .map(|r| (r.router.as_ref().unwrap().id.unwrap(), r.tunnels.iter().map(|t| t.id).collect()))
+        .collect();
+    assert_eq!(tree, vec![(router_ids[0], tunnel_ids.clone()), (router_ids[1], Vec::new())]);
 
-    Ok(())
+    agents.unregister(authorized(token, by_agent)).await.unwrap();
+    UserClient::new(channel)
+        .delete(authorized(token, UserRequest {
+            id_or_email: Some(IdOrEmail::Id(user.id)),
+        }))
+        .await
+        .unwrap();
 }
@@ -0,0 +1,113 @@
+use tunnel_manager::legacy::LegacyData;
+
+const COPY_DUMP: &str = "\
+--
+-- PostgreSQL database dump
+--
+
+COPY public.routers (id, owner, type, method, community) FROM stdin;
+200\tOld@Example.org\tcisco\tsnmp\tpublic
+201\t\\N\tcisco\tsnmp\tx
+202\tnew@example.org\tjuniper\tsnmp\tx
+\\.
+
+COPY public.tunnels (id, router_id, ip, dynamic, hostname, description, source, cost, topology) FROM stdin;
+300\t200\t192.0.2.1\tf\tpeer.example.com\tto\\\\peer\t198.51.100.1\t\\N\tHUB
+10\t200\t192.0.2.2\tf\th\td\ts\t1\tmesh
+301\t999\t192.0.2.3\tf\th\td\ts\t1\tmesh
+302\t200\t192.0.2.4\tf\th\td\\n no shutdown\ts\t1\tmesh
+\\.
+";
+
+const INSERT_DUMP: &str = "\
+CREATE TABLE public.routers (
+    id integer NOT NULL,
+    owner character varying,
+    router_type character varying(10),
+    CONSTRAINT type_check CHECK (router_type IN ('cisco', 'pydecnet'))
+);
+
+INSERT INTO public.routers VALUES (200, 'a@example.org', 'PyDECnet');
+INSERT INTO public.tunnels (id, router, ip, hostname, description, source) VALUES
+    (50, 200, '192.0.2.1', NULL, 'it''s; here', '198.51.100.1'),
+    (51, 200, '192.0.2.2', 'h', E'd', 's');
+";
+
+#[test]
+fn maps_copy_dump() {
+    let data = LegacyData::from_dump(COPY_DUMP, "routers", "tunnels").unwrap();
+    assert_eq!(data.routers.len(), 3);
+    assert_eq!(data.tunnels.len(), 4);
+
+    let import = data.map();
+    assert_eq!(import.owners, vec!["old@example.org"]);
+    assert_eq!(import.document.agents.len(), 1);
+
+    let agent = &import.document.agents[0];
+    assert_eq!(agent.uuid, "legacy-old@example.org");
+    assert_eq!(agent.routers.len(), 1);
+
+    let router = &agent.routers[0];
+    assert_eq!(router.router_type.as_deref(), Some("Cisco"));
+    assert_eq!(router.conn_type.as_deref(), Some("SNMP"));
+    assert_eq!(router.snmp_community.as_deref(), Some("public"));
+    assert_eq!(router.tunnels.len(), 1);
+
+    let tunnel = &router.tunnels[0];
+    assert_eq!(tunnel.description, "to\\peer");
+    assert_eq!(tunnel.cost, 10);
+    assert_eq!(tunnel.topology_type, "hub");
+
+    assert_eq!(
+        import.unmapped,
+        vec![
+            "router 201: no owner",
+            "router 202: unknown router type juniper",
+            "tunnel 10: tunnel index 10 is below 50",
+            "tunnel 301: router 999 was not imported",
+            "tunnel 302: description \"d\\n no shutdown\" contains control characters",
+        ]
+    );
+}
+
+#[test]
+fn maps_insert_dump() {
+    let data = LegacyData::from_dump(INSERT_DUMP, "routers", "tunnels").unwrap();
+    let import = data.map();
+
+    assert!(import.unmapped.is_empty(), "{:?}", import.unmapped);
+
+    let router = &import.document.agents[0].routers[0];
+    assert_eq!(router.router_type.as_deref(), Some("PyDECNet"));
+    assert_eq!(router.tunnels.len(), 2);
+    assert_eq!(router.tunnels[0].hostname, "192.0.2.1");
+    assert_eq!(router.tunnels[0].description, "it's; here");
+    assert_eq!(router.tunnels[1].description, "d");
+}
+
+#[test]
+fn parses_awkward_statements() {
+    let dump = "\
+INSERT INTO tunnels (id, \"straße\", \"values\") VALUES (50, 'groß', 'v');
+INSERT INTO tunnels (id, \"VALUES\", description) values (51, 'x', 'values (1)');
+";
+    let data = LegacyData::from_dump(dump, "routers", "tunnels").unwrap();
+
+    assert_eq!(data.tunnels.len(), 2);
+    assert_eq!(data.tunnels[0]["straße"].as_deref(), Some("groß"));
+    assert_eq!(data.tunnels[0]["values"].as_deref(), Some("v"));
+    assert_eq!(data.tunnels[1]["values"].as_deref(), Some("x"));
+    assert_eq!(data.tunnels[1]["description"].as_deref(), Some("values (1)"));
+}
+
+#[test]
+fn rejects_malformed_statements() {
+    for dump in [
+        "INSERT INTO tunnels ) (id VALUES (1);\n",
+        "INSERT INTO tunnels (id, \"values\") (1);\n",
+        "COPY tunnels ) id ( FROM stdin;\n\\.\n",
+        "CREATE TABLE tunnels ) id integer (;\n",
+    ] {
+        assert!(LegacyData::from_dump(dump, "routers", "tunnels").is_err(), "{}", dump);
+    }
+}
@@ -0,0 +1,103 @@
+use tunnel_manager::mesh::{MeshAgent, MeshDocument, MeshRouter, MeshTunnel};
+
+fn sample() -> MeshDocument {
+    MeshDocument {
+        agents: vec![MeshAgent {
+            uuid: "4c1d2c1e-agent".to_string(),
+            description: "Tunnel agent \"one\"".to_string(),
+            owner: "test@example.com".to_string(),
+            routers: vec![MeshRouter {
+                id: 1,
+                router_type: Some("Cisco".to_string()),
+                conn_type: Some("SNMP".to_string()),
+                snmp_community: None,
+                ssh_username: Some("tunnels".to_string()),
+                ssh_password: None,
+                tunnels: vec![MeshTunnel {
+                    id: 50,
+                    version: 0,
+                    ip: "192.0.2.1".to_string(),
+                    dynamic_ip: false,
+                    ip_class: 4,
+                    hostname: "peer.example.com".to_string(),
+                    description: "to peer".to_string(),
+                    source: "198.51.100.1".to_string(),
+                    cost: 10,
+                    tunnel_type: "GRE".to_string(),
+                    topology_type: "mesh".to_string(),
+                }],
+            }],
+        }],
+    }
+}
+
+#[test]
+fn test_mesh_round_trip() {
+    let document = sample();
+
+    assert_eq!(MeshDocument::from_toml(&document.to_toml()), Ok(document));
+}
+
+#[test]
+fn test_mesh_leaves_out_credentials() {
+    let mut document = sample();
+    let router = &mut document.agents[0].routers[0];
+    router.snmp_community = Some("private".to_string());
+    router.ssh_password = Some("hunter2".to_string());
+
+    let toml = document.to_toml();
+    assert!(!toml.contains("private") && !toml.contains("hunter2"), "{}", toml);
+    assert_eq!(MeshDocument::from_toml(&toml), Ok(sample()));
+}
+
+#[test]
+fn test_mesh_defaults() {
+    let document = MeshDocument::from_toml(
+        r#"
+        [[agents]]
+        uuid = "a"
+        owner = "test@example.com"
+
+        [[agents.routers]]
+        id = 1
+
+        [[agents.routers.tunnels]]
+        id = 50
+        ip = "192.0.2.1"
+        hostname = "peer.example.com"
+        description = "to peer"
+        source = "198.51.100.1"
+        "#,
+    )
+    .unwrap();
+
+    let tunnel = &document.agents[0].routers[0].tunnels[0];
+    assert_eq!(tunnel.ip_class, 4);
+    assert_eq!(tunnel.cost, 10);
+    assert_eq!(tunnel.tunnel_type, "GRE");
+    assert_eq!(tunnel.topology_type, "mesh");
+}
+
+#[test]
+fn test_mesh_rejects_duplicates() {
+    let mut document = sample();
+    let router = document.agents[0].routers[0].clone();
+    document.agents[0].routers.push(router);
+
+    assert!(MeshDocument::from_toml(&document.to_toml()).is_err());
+}
+
+#[test]
+fn test_mesh_rejects_unsafe_fields() {
+    let mut document = sample();
+    document.agents[0].routers[0].tunnels[0].description = "to peer\n no shutdown".to_string();
+    assert!(MeshDocument::from_toml(&document.to_toml()).is_err());
+
+    let mut document = sample();
+    document.agents[0].routers[0].tunnels[0].hostname = "peer.example.com shutdown".to_string();
+    assert!(MeshDocument::from_toml(&document.to_toml()).is_err());
+
+    let mut document = sample();
+    document.agents[0].routers[0].tunnels[0].ip = "peer.example.com".to_string();
+    assert!(MeshDocument::from_toml(&document.to_toml()).is_err());
+}
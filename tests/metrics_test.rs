@@ -0,0 +1,41 @@
+use std::time::{Duration, SystemTime};
+
+use tunnel_manager::api::PushResult;
+use tunnel_manager::metrics::AgentMetrics;
+
+#[test]
+fn test_agent_push_metrics() {
+    let metrics = AgentMetrics::new();
+    let started = SystemTime::now();
+    let push = |driver: &str, seconds: u64, success: bool| PushResult {
+        router: 1,
+        driver: driver.to_string(),
+        started_at: Some(started.into()),
+        finished_at: Some((started + Duration::from_secs(seconds)).into()),
+        success,
+        ..Default::default()
+    };
+
+    metrics.observe_push(&push("MikroTik/SSH", 3, true));
+    metrics.observe_push(&push("MikroTik/SSH", 20, false));
+    metrics.observe_push(&push("Cisco/SNMP", 1, true));
+    // Finished before it started: counted, but not timed.
+    metrics.observe_push(&PushResult {
+        finished_at: Some((started - Duration::from_secs(5)).into()),
+        ..push("Cisco/SNMP", 0, false)
+    });
+
+    let text = metrics.render();
+    for line in [
+        r#"tunnel_manager_agent_push_attempts_total{driver="MikroTik/SSH"} 2"#,
+        r#"tunnel_manager_agent_push_attempts_total{driver="Cisco/SNMP"} 2"#,
+        r#"tunnel_manager_agent_push_failures_total{driver="MikroTik/SSH"} 1"#,
+        r#"tunnel_manager_agent_push_failures_total{driver="Cisco/SNMP"} 1"#,
+        r#"tunnel_manager_agent_push_duration_seconds_count{driver="MikroTik/SSH"} 2"#,
+        r#"tunnel_manager_agent_push_duration_seconds_sum{driver="MikroTik/SSH"} 23"#,
+        r#"tunnel_manager_agent_push_duration_seconds_bucket{driver="MikroTik/SSH",le="5"} 1"#,
+        r#"tunnel_manager_agent_push_duration_seconds_count{driver="Cisco/SNMP"} 1"#,
+    ] {
+        assert!(text.lines().any(|l| l == line), "{} missing from\n{}", line, text);
+    }
+}
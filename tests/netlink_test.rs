@@ -0,0 +1,208 @@
+use std::io;
+use std::process::Command;
+use std::thread;
+use std::time::SystemTime;
+
+use tunnel_manager::netlink::{desired, Applied, GreDriver, GreInterface, GreKind, GreSettings};
+use tunnel_manager::storage::routers::Router;
+use tunnel_manager::storage::tunnels::Tunnel;
+
+fn router(id: i32) -> Router {
+    Router {
+        id,
+        agent: 1,
+        snmp_community: None,
+        ssh_username: None,
+        ssh_password: None,
+        conn_type: Some("SSH".to_string()),
+        router_type: Some("PyDECNet".to_string()),
+        created_at: SystemTime::UNIX_EPOCH,
+        updated_at: SystemTime::UNIX_EPOCH,
+    }
+}
+
+fn tunnel(id: i32, router: i32, ip: &str) -> Tunnel {
+    Tunnel {
+        id,
+        version: 0,
+        router,
+        ip: ip.to_string(),
+        dynamic_ip: false,
+        ip_class: if ip.contains(':') { 6 } else { 4 },
+        hostname: format!("host{}.example.com", id),
+        description: format!("peer {}", id),
+        source: "eth0".to_string(),
+        cost: 10,
+        tunnel_type: "GRE".to_string(),
+        topology_type: "mesh".to_string(),
+        created_at: SystemTime::UNIX_EPOCH,
+        updated_at: SystemTime::UNIX_EPOCH,
+        ip_reported_at: None,
+        ip_resolved_at: None,
+    }
+}
+
+#[test]
+fn test_desired() {
+    let mut local = tunnel(10, 1, "192.0.2.10");
+    local.source = "192.0.2.10".to_string();
+    let mut unresolved = tunnel(12, 3, "");
+    unresolved.dynamic_ip = true;
+    let mut wireguard = tunnel(14, 5, "192.0.2.14");
+    wireguard.tunnel_type = "WireGuard".to_string();
+    let tunnels = vec![
+        local,
+        tunnel(11, 2, "192.0.2.11"),
+        unresolved,
+        tunnel(13, 1, "2001:db8::13"),
+        tunnel(15, 4, "2001:db8::15"),
+        wireguard,
+    ];
+
+    let settings = GreSettings::default();
+    assert_eq!(
+        desired(&router(1), &tunnels, &settings),
+        vec![
+            GreInterface {
+                name: "hecnet11".to_string(),
+                kind: GreKind::Gre,
+                local: Some("192.0.2.10".parse().unwrap()),
+                remote: "192.0.2.11".parse().unwrap(),
+                ttl: 64,
+                mtu: 1400,
+            },
+            GreInterface {
+                name: "hecnet15".to_string(),
+                kind: GreKind::Ip6Gre,
+                local: None,
+                remote: "2001:db8::15".parse().unwrap(),
+                ttl: 64,
+                mtu: 1400,
+            },
+        ]
+    );
+
+    let settings = GreSettings { prefix: "hn".to_string(), tap: true, ..GreSettings::default() };
+    let kinds: Vec<_> = desired(&router(1), &tunnels, &settings)
+        .into_iter()
+        .map(|i| (i.name, i.kind))
+        .collect();
+    assert_eq!(kinds, vec![("hn11".to_string(), GreKind::GreTap), ("hn15".to_string(), GreKind::Ip6Gre)]);
+}
+
+/// Runs `test` in a network namespace of its own, on a thread of its own as namespaces are per
+/// thread. Skipped without the privileges to create one.
+fn in_namespace(test: impl FnOnce() + Send + 'static) {
+    thread::spawn(move || {
+        if unsafe { libc::unshare(libc::CLONE_NEWNET) } != 0 {
+            eprintln!("skipped: cannot create a network namespace: {}", io::Error::last_os_error());
+            return;
+        }
+        test();
+    })
+    .join()
+    .unwrap();
+}
+
+#[test]
+fn test_driver() {
+    in_namespace(|| {
+        let mut driver = GreDriver::new("hn").unwrap();
+        let links = driver.links().unwrap();
+        assert!(links.iter().any(|l| l.name == "lo"), "{:?}", links);
+        assert_eq!(driver.apply(&[]).unwrap(), Applied::default());
+
+        let mut interface = GreInterface {
+            name: "tun11".to_string(),
+            kind: GreKind::Gre,
+            local: None,
+            remote: "192.0.2.11".parse().unwrap(),
+            ttl: 64,
+            mtu: 1400,
+        };
+        assert_eq!(driver.apply(&[interface.clone()]).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        interface.name = "hn12345678901234".to_string();
+        assert_eq!(driver.apply(&[interface]).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        // Anything named with the prefix that is not desired goes, whatever its type.
+        if Command::new("ip").args(["link", "add", "hn99", "type", "bridge"]).status().is_ok_and(|s| s.success()) {
+            let applied = driver.apply(&[]).unwrap();
+            assert!(applied.removed.contains(&"hn99".to_string()), "{:?}", applied);
+            assert!(driver.managed().unwrap().is_empty());
+        }
+    });
+}
+
+#[test]
+fn test_gre_interfaces() {
+    in_namespace(|| {
+        let mut driver = GreDriver::new("hn").unwrap();
+        let mut interfaces = vec![
+            GreInterface {
+                name: "hn11".to_string(),
+                kind: GreKind::Gre,
+                local: Some("192.0.2.10".parse().unwrap()),
+                remote: "192.0.2.11".parse().unwrap(),
+                ttl: 64,
+                mtu: 1400,
+            },
+            GreInterface {
+                name: "hn12".to_string(),
+                kind: GreKind::GreTap,
+                local: None,
+                remote: "192.0.2.12".parse().unwrap(),
+                ttl: 32,
+                mtu: 1400,
+            },
+            GreInterface {
+                name: "hn13".to_string(),
+                kind: GreKind::Ip6Gre,
+                local: None,
+                remote: "2001:db8::13".parse().unwrap(),
+                ttl: 64,
+                mtu: 1400,
+            },
+        ];
+
+        let applied = match driver.apply(&interfaces) {
+            Err(err) if err.raw_os_error() == Some(libc::EOPNOTSUPP) => {
+                eprintln!("skipped: the kernel has no GRE support");
+                return;
+            }
+            result => result.unwrap(),
+        };
+        assert_eq!(applied.created, vec!["hn11", "hn12", "hn13"]);
+
+        let managed = driver.managed().unwrap();
+        for interface in &interfaces {
+            let link = managed.iter().find(|l| l.name == interface.name).unwrap();
+            assert_eq!(link.kind.as_deref(), Some(interface.kind.name()));
+            assert_eq!((link.local, link.remote), (interface.local, Some(interface.remote)));
+            assert_eq!((link.ttl, link.mtu, link.up), (Some(interface.ttl), interface.mtu, true));
+        }
+        assert_eq!(driver.apply(&interfaces).unwrap(), Applied::default());
+
+        // Changes are made in place, another kind replaces the interface, and dropped ones go.
+        interfaces[0].mtu = 1300;
+        interfaces[0].ttl = 16;
+        interfaces[1].kind = GreKind::Gre;
+        interfaces.pop();
+        let applied = driver.apply(&interfaces).unwrap();
+        assert_eq!(
+            applied,
+            Applied {
+                created: vec![],
+                updated: vec!["hn11".to_string(), "hn12".to_string()],
+                removed: vec!["hn13".to_string()],
+            }
+        );
+        let managed = driver.managed().unwrap();
+        assert_eq!(managed.len(), 2);
+        let hn11 = managed.iter().find(|l| l.name == "hn11").unwrap();
+        assert_eq!((hn11.mtu, hn11.ttl), (1300, Some(16)));
+        let hn12 = managed.iter().find(|l| l.name == "hn12").unwrap();
+        assert_eq!(hn12.kind.as_deref(), Some("gre"));
+
+        assert_eq!(driver.apply(&[]).unwrap().removed.len(), 2);
+    });
+}
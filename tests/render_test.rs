@@ -0,0 +1,469 @@
+use std::time::SystemTime;
+
+use tunnel_manager::config::RenderConfig;
+use tunnel_manager::render::{
+    check_endpoint, config_hash, configured_peers, drift, links, managed_config, render, renderer, supports, unified_diff, wg_quick_configs,
+    Keys, Psks, WireGuardEndpoint, RENDERERS,
+};
+use tunnel_manager::storage::routers::Router;
+use tunnel_manager::storage::tunnels::Tunnel;
+
+fn router(id: i32, router_type: &str) -> Router {
+    Router {
+        id,
+        agent: 1,
+        snmp_community: None,
+        ssh_username: None,
+        ssh_password: None,
+        conn_type: Some("SSH".to_string()),
+        router_type: Some(router_type.to_string()),
+        created_at: SystemTime::UNIX_EPOCH,
+        updated_at: SystemTime::UNIX_EPOCH,
+    }
+}
+
+fn tunnel(id: i32, router: i32, topology_type: &str) -> Tunnel {
+    Tunnel {
+        id,
+        version: 0,
+        router,
+        ip: format!("192.0.2.{}", id),
+        dynamic_ip: false,
+        ip_class: 4,
+        hostname: format!("host{}.example.com", id),
+        description: format!("peer {}", id),
+        source: "GigabitEthernet0/0".to_string(),
+        cost: 10,
+        tunnel_type: "GRE".to_string(),
+        topology_type: topology_type.to_string(),
+        created_at: SystemTime::UNIX_EPOCH,
+        updated_at: SystemTime::UNIX_EPOCH,
+        ip_reported_at: None,
+        ip_resolved_at: None,
+    }
+}
+
+fn peer_ids(router_id: i32, tunnels: &[Tunnel]) -> Vec<i32> {
+    links(&router(router_id, "Cisco"), tunnels)
+        .iter()
+        .map(|(_, peer)| peer.id)
+        .collect()
+}
+
+#[test]
+fn test_topologies() {
+    let mut v6 = tunnel(55, 5, "mesh");
+    v6.ip_class = 6;
+
+    let tunnels = vec![
+        tunnel(50, 1, "hub"),
+        tunnel(51, 2, "mesh"),
+        tunnel(52, 3, "mesh"),
+        tunnel(53, 4, "spoke"),
+        tunnel(54, 4, "spoke"),
+        v6,
+    ];
+
+    assert_eq!(peer_ids(1, &tunnels), vec![51, 52, 53, 54]);
+    assert_eq!(peer_ids(2, &tunnels), vec![50, 52]);
+    assert_eq!(peer_ids(4, &tunnels), vec![50]);
+    assert_eq!(peer_ids(5, &tunnels), Vec::<i32>::new());
+}
+
+#[test]
+fn test_render_cisco() {
+    let mut dynamic = tunnel(51, 2, "mesh");
+    dynamic.dynamic_ip = true;
+    let tunnels = vec![tunnel(50, 1, "mesh"), dynamic];
+
+    let config = render(&router(1, "Cisco"), &tunnels, &[], &Keys::default(), &RenderConfig::default()).unwrap();
+
+    assert!(config.contains(
+        "interface Tunnel51\n description HECnet: host51.example.com (peer 51)\n no ip address\n decnet cost 10\n \
+         tunnel source GigabitEthernet0/0\n tunnel destination host51.example.com\n tunnel mode gre ip\n!\n"
+    ));
+    assert!(!config.contains("Tunnel50"));
+
+    // Once its agent reports the address it is used instead.
+    let mut reported = tunnel(51, 2, "mesh");
+    reported.dynamic_ip = true;
+    reported.ip_reported_at = Some(SystemTime::UNIX_EPOCH);
+    let config = render(&router(1, "Cisco"), &[tunnel(50, 1, "mesh"), reported], &[], &Keys::default(), &RenderConfig::default()).unwrap();
+    assert!(config.contains(" tunnel destination 192.0.2.51\n"));
+}
+
+#[test]
+fn test_render_pydecnet() {
+    let mut ipsec = tunnel(52, 3, "mesh");
+    ipsec.tunnel_type = "IPSec".to_string();
+    let mut local_ipsec = tunnel(53, 1, "mesh");
+    local_ipsec.tunnel_type = "IPSec".to_string();
+    let tunnels = vec![tunnel(50, 1, "mesh"), tunnel(51, 2, "mesh"), ipsec, local_ipsec];
+
+    let config = render(&router(1, "PyDECNet"), &tunnels, &[], &Keys::default(), &RenderConfig::default()).unwrap();
+
+    assert!(config.contains("circuit gre-51 GRE 192.0.2.51 --source GigabitEthernet0/0 --cost 10\n"));
+    assert!(config.contains("# Tunnel 52 to host52.example.com skipped"));
+
+    let mut untyped = router(1, "Cisco");
+    untyped.router_type = None;
+    assert!(render(&untyped, &tunnels, &[], &Keys::default(), &RenderConfig::default()).is_err());
+}
+
+#[test]
+fn test_render_defaults() {
+    let mut ipsec = tunnel(51, 2, "mesh");
+    ipsec.tunnel_type = "IPSec".to_string();
+    let mut local = tunnel(50, 1, "mesh");
+    local.tunnel_type = "IPSec".to_string();
+    let defaults = RenderConfig {
+        ipsec_profile: "DECNET-VPN".to_string(),
+        keepalive_secs: Some(10),
+        ..RenderConfig::default()
+    };
+    let keys = Keys {
+        psks: Psks::from([((50, 51), "s3cr3t".to_string())]),
+        ..Keys::default()
+    };
+
+    let config = render(&router(1, "Cisco"), &[local, ipsec], &[], &keys, &defaults).unwrap();
+
+    assert!(config.contains(
+        " tunnel mode gre ip\n keepalive 10 3\n tunnel protection ipsec profile DECNET-VPN-Tunnel51\n"
+    ));
+}
+
+#[test]
+fn test_render_ipsec() {
+    let ipsec = |id, router_id| Tunnel {
+        tunnel_type: "IPSec".to_string(),
+        ..tunnel(id, router_id, "mesh")
+    };
+    let tunnels = vec![ipsec(50, 1), ipsec(51, 2), tunnel(52, 3, "mesh")];
+    let psks = Keys {
+        psks: Psks::from([((50, 51), "s3cr3t".to_string())]),
+        ..Keys::default()
+    };
+
+    // Both ends render the same key, under names of their own.
+    let config = render(&router(1, "Cisco"), &tunnels, &[], &psks, &RenderConfig::default()).unwrap();
+    assert!(config.contains(
+        "\
+crypto ikev2 keyring HECNET-Tunnel51
+ peer tunnel-51
+  address 192.0.2.51
+  pre-shared-key s3cr3t
+ !
+!
+crypto ikev2 profile HECNET-Tunnel51
+ match identity remote address 192.0.2.51 255.255.255.255
+ authentication remote pre-share
+ authentication local pre-share
+ keyring local HECNET-Tunnel51
+!
+crypto ipsec profile HECNET-Tunnel51
+ set ikev2-profile HECNET-Tunnel51
+!
+interface Tunnel51
+"
+    ));
+    assert!(config.contains(" tunnel protection ipsec profile HECNET-Tunnel51\n"));
+    assert!(!config.contains("Tunnel52"), "GRE and IPSec endpoints do not peer:\n{}", config);
+
+    let other_end = render(&router(2, "Cisco"), &tunnels, &[], &psks, &RenderConfig::default()).unwrap();
+    assert!(other_end.contains("crypto ikev2 keyring HECNET-Tunnel50\n peer tunnel-50\n  address 192.0.2.50\n  pre-shared-key s3cr3t\n"));
+
+    // IPv6 peers, and dynamic ones only known by hostname.
+    let v6 = |id, router_id| Tunnel {
+        ip: format!("2001:db8::{}", id),
+        ip_class: 6,
+        ..ipsec(id, router_id)
+    };
+    let dynamic = Tunnel {
+        dynamic_ip: true,
+        ..v6(51, 2)
+    };
+    let config = render(&router(1, "Cisco"), &[v6(50, 1), v6(51, 2)], &[], &psks, &RenderConfig::default()).unwrap();
+    assert!(config.contains("  address 2001:db8::51/128\n"));
+    assert!(config.contains(" match identity remote address ipv6 2001:db8::51/128\n"));
+    let config = render(&router(1, "Cisco"), &[v6(50, 1), dynamic], &[], &psks, &RenderConfig::default()).unwrap();
+    assert!(config.contains("  identity fqdn host51.example.com\n"));
+    assert!(config.contains(" match identity remote fqdn host51.example.com\n"));
+
+    let err = render(&router(1, "Cisco"), &tunnels, &[], &Keys::default(), &RenderConfig::default()).unwrap_err();
+    assert_eq!(err, "no usable pre-shared key for tunnels 50 and 51 (is render.psk_secret set?)");
+}
+
+#[test]
+fn test_render_wireguard() {
+    let wireguard = |id, router_id| Tunnel {
+        tunnel_type: "WireGuard".to_string(),
+        ..tunnel(id, router_id, "mesh")
+    };
+    let local = Tunnel {
+        dynamic_ip: true,
+        ip_reported_at: Some(SystemTime::UNIX_EPOCH),
+        ..wireguard(50, 1)
+    };
+    let unresolved = Tunnel {
+        dynamic_ip: true,
+        ..wireguard(52, 3)
+    };
+    let tunnels = vec![local, wireguard(51, 2), unresolved, tunnel(53, 1, "mesh"), tunnel(54, 4, "mesh")];
+    let endpoint = |id: i32, port| WireGuardEndpoint {
+        private_key: Some(format!("private{}", id)),
+        public_key: format!("public{}", id),
+        address: format!("10.254.0.{}", id - 49).parse().unwrap(),
+        listen_port: port,
+    };
+    let keys = Keys {
+        wireguard: [(50, endpoint(50, 51820)), (51, endpoint(51, 51820)), (52, endpoint(52, 51821))].into(),
+        ..Keys::default()
+    };
+
+    let config = render(&router(1, "PyDECNet"), &tunnels, &[], &keys, &RenderConfig::default()).unwrap();
+    assert_eq!(
+        config,
+        "\
+# HECnet tunnels for router 1, rendered by the tunnel manager.
+circuit gre-51 GRE 10.254.0.2 --source 10.254.0.1 --cost 10
+circuit gre-52 GRE 10.254.0.3 --source 10.254.0.1 --cost 10
+circuit gre-54 GRE 192.0.2.54 --source GigabitEthernet0/0 --cost 10
+# wg-quick wg50
+[Interface]
+PrivateKey = private50
+Address = 10.254.0.1/32
+ListenPort = 51820
+
+[Peer]
+# host51.example.com (tunnel 51)
+PublicKey = public51
+Endpoint = 192.0.2.51:51820
+AllowedIPs = 10.254.0.2/32
+PersistentKeepalive = 25
+
+[Peer]
+# host52.example.com (tunnel 52)
+PublicKey = public52
+Endpoint = host52.example.com:51821
+AllowedIPs = 10.254.0.3/32
+PersistentKeepalive = 25
+"
+    );
+    assert_eq!(configured_peers("PyDECNet", &config), vec![51, 52, 54]);
+
+    let (pydecnet, wg_quick) = wg_quick_configs(&config);
+    assert!(pydecnet.ends_with("--cost 10\n") && !pydecnet.contains("[Interface]"));
+    assert_eq!(wg_quick.keys().collect::<Vec<_>>(), vec!["wg50"]);
+    assert!(wg_quick["wg50"].starts_with("[Interface]\nPrivateKey = private50\n"));
+
+    // The other end sends to the port this end listens on.
+    let other_end = render(&router(2, "PyDECNet"), &tunnels, &[], &keys, &RenderConfig::default()).unwrap();
+    assert!(other_end.contains("ListenPort = 51820\n\n[Peer]\n# host50.example.com (tunnel 50)\nPublicKey = public50\nEndpoint = 192.0.2.50:51820\n"));
+    assert!(!other_end.contains("PersistentKeepalive"));
+
+    let err = render(&router(1, "PyDECNet"), &tunnels, &[], &Keys::default(), &RenderConfig::default()).unwrap_err();
+    assert_eq!(err, "no WireGuard keys for tunnel 50 (is render.psk_secret set?)");
+    let cisco = render(&router(1, "Cisco"), &tunnels, &[], &keys, &RenderConfig::default()).unwrap();
+    assert!(cisco.contains("! Tunnel 51 to host51.example.com skipped: IOS does not support WireGuard.\n"));
+    assert!(!cisco.contains("interface Tunnel51"));
+}
+
+#[test]
+fn test_render_mikrotik() {
+    let mut local = tunnel(50, 1, "mesh");
+    local.source = "192.0.2.50".to_string();
+    let mut dynamic = tunnel(53, 4, "mesh");
+    dynamic.dynamic_ip = true;
+    let tunnels = vec![local, tunnel(51, 2, "mesh"), dynamic];
+    let defaults = RenderConfig {
+        keepalive_secs: Some(10),
+        ..RenderConfig::default()
+    };
+
+    let config = render(&router(1, "MikroTik"), &tunnels, &[52], &Keys::default(), &defaults).unwrap();
+    assert_eq!(
+        config,
+        "\
+# HECnet tunnels for router 1, rendered by the tunnel manager.
+/interface gre remove [find name=Tunnel52]
+/interface gre6 remove [find name=Tunnel52]
+/interface gre
+:if ([:len [find name=Tunnel51]] = 0) do={ add name=Tunnel51 remote-address=192.0.2.51 }
+set [find name=Tunnel51] comment=\"HECnet: host51.example.com (peer 51)\" remote-address=192.0.2.51 local-address=192.0.2.50 keepalive=10s,3
+# Tunnel 53 to host53.example.com skipped: its address is not known yet.
+"
+    );
+    assert_eq!(configured_peers("MikroTik", &config), vec![51]);
+
+    // IPSec peerings use RouterOS's own IPSec for GRE.
+    let ipsec: Vec<Tunnel> = [tunnel(50, 1, "mesh"), tunnel(51, 2, "mesh")]
+        .into_iter()
+        .map(|t| Tunnel {
+            tunnel_type: "IPSec".to_string(),
+            ..t
+        })
+        .collect();
+    let keys = Keys {
+        psks: Psks::from([((50, 51), "s3 \"cr$t".to_string())]),
+        ..Keys::default()
+    };
+    let config = render(&router(1, "MikroTik"), &ipsec, &[], &keys, &defaults).unwrap();
+    assert!(config.contains(" keepalive=10s,3 ipsec-secret=\"s3 \\\"cr\\$t\"\n"), "{}", config);
+
+    // An export lists the interfaces with its own parameter order, wrapping long lines.
+    let running = "\
+# 2026-10-19 12:00:00 by RouterOS 7.16
+/interface bridge
+add name=bridge1
+/interface gre
+add comment=\"HECnet: host51.example.com (peer 51)\" keepalive=10s,3 local-address=192.0.2.50 name=Tunnel51 \\
+    remote-address=192.0.2.51
+add comment=\"HECnet: host60.example.com (peer 60)\" name=Tunnel60 remote-address=192.0.2.60
+add comment=lab name=Tunnel7 remote-address=192.0.2.7
+";
+    let desired = render(&router(1, "MikroTik"), &tunnels, &[], &Keys::default(), &defaults).unwrap();
+    let found: Vec<(String, String)> = drift("MikroTik", &desired, running)
+        .into_iter()
+        .map(|d| (d.interface, d.kind))
+        .collect();
+    assert_eq!(found, vec![("Tunnel60".to_string(), "unexpected".to_string())]);
+    assert!(drift("MikroTik", &desired, &managed_config("MikroTik", &desired)).is_empty());
+}
+
+#[test]
+fn test_renderers() {
+    let names: Vec<&str> = RENDERERS.iter().map(|r| r.router_type()).collect();
+    assert_eq!(names, vec!["Cisco", "PyDECNet", "MikroTik"]);
+    assert_eq!(renderer("MikroTik").unwrap().description(), "MikroTik RouterOS");
+    assert!(renderer("Juniper").is_none());
+
+    assert!(supports("MikroTik", "IPSec") && !supports("MikroTik", "WireGuard"));
+    assert!(!supports("Cisco", "WireGuard") && !supports("PyDECNet", "IPSec"));
+    assert!(supports("Juniper", "WireGuard"));
+
+    let tunnels = vec![tunnel(50, 1, "mesh"), tunnel(51, 2, "mesh")];
+    let err = render(&router(1, "Juniper"), &tunnels, &[], &Keys::default(), &RenderConfig::default()).unwrap_err();
+    assert_eq!(err, "cannot render configuration for router type Juniper");
+}
+
+#[test]
+fn test_config_hash() {
+    let tunnels = vec![tunnel(50, 1, "mesh"), tunnel(51, 2, "mesh")];
+    let config = render(&router(1, "Cisco"), &tunnels, &[], &Keys::default(), &RenderConfig::default()).unwrap();
+
+    assert_eq!(config_hash(""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    let again = render(&router(1, "Cisco"), &tunnels, &[], &Keys::default(), &RenderConfig::default()).unwrap();
+    assert_eq!(config_hash(&config), config_hash(&again));
+    assert_ne!(config_hash(&config), config_hash(&config.replace("cost 10", "cost 11")));
+}
+
+#[test]
+fn test_drift() {
+    let tunnels = vec![tunnel(50, 1, "mesh"), tunnel(51, 2, "mesh"), tunnel(52, 3, "mesh")];
+    let desired = render(&router(1, "Cisco"), &tunnels, &[], &Keys::default(), &RenderConfig::default()).unwrap();
+
+    // IOS reorders lines and adds its own around the managed blocks.
+    let running = "\
+hostname gw
+!
+interface GigabitEthernet0/0
+ ip address dhcp
+!
+interface Tunnel7
+ description lab
+!
+interface Tunnel51
+ description HECnet: host51.example.com (peer 51)
+ no ip address
+ decnet cost 10
+ tunnel mode gre ip
+ tunnel source GigabitEthernet0/0
+ tunnel destination 192.0.2.51
+!
+interface Tunnel52
+ description HECnet: host52.example.com (peer 52)
+ no ip address
+ decnet cost 20
+ tunnel source GigabitEthernet0/0
+ tunnel destination 192.0.2.52
+ tunnel mode gre ip
+interface Tunnel60
+ description HECnet: host60.example.com (peer 60)
+end
+";
+
+    assert!(drift("Cisco", &desired, &desired).is_empty());
+    let found: Vec<(String, String)> = drift("Cisco", &desired, running)
+        .into_iter()
+        .map(|d| (d.interface, d.kind))
+        .collect();
+    assert_eq!(found, vec![
+        ("Tunnel52".to_string(), "changed".to_string()),
+        ("Tunnel60".to_string(), "unexpected".to_string()),
+    ]);
+
+    let kept = managed_config("Cisco", running);
+    assert!(!kept.contains("hostname") && !kept.contains("dhcp"));
+    assert!(drift("Cisco", &desired, &kept).iter().any(|d| d.interface == "Tunnel52"));
+
+    let pydecnet = render(&router(1, "PyDECNet"), &tunnels, &[], &Keys::default(), &RenderConfig::default()).unwrap();
+    let missing = drift("PyDECNet", &pydecnet, "circuit gre-51 GRE 192.0.2.51  --source GigabitEthernet0/0 --cost 10\n");
+    assert_eq!(missing.len(), 1);
+    assert_eq!((missing[0].interface.as_str(), missing[0].kind.as_str()), ("gre-52", "missing"));
+}
+
+#[test]
+fn test_unified_diff() {
+    let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\nk\nl\n";
+    let new = "a\nB\nc\nd\ne\nf\ng\nh\ni\nj\nk\nl\nm\n";
+
+    assert_eq!(unified_diff("a/x", "b/x", old, old), "");
+    assert_eq!(
+        unified_diff("a/x", "b/x", old, new),
+        "--- a/x\n+++ b/x\n@@ -1,5 +1,5 @@\n a\n-b\n+B\n c\n d\n e\n@@ -10,3 +10,4 @@\n j\n k\n l\n+m\n"
+    );
+    assert_eq!(unified_diff("a/x", "b/x", "", "new\n"), "--- a/x\n+++ b/x\n@@ -0,0 +1,1 @@\n+new\n");
+}
+
+#[test]
+fn test_render_removed_peers() {
+    let tunnels = vec![tunnel(50, 1, "mesh"), tunnel(51, 2, "mesh")];
+
+    let config = render(&router(1, "Cisco"), &tunnels, &[53, 51, 52], &Keys::default(), &RenderConfig::default()).unwrap();
+    assert!(config.contains("!\nno interface Tunnel52\n!\nno interface Tunnel53\n!\n"), "{}", config);
+    assert!(!config.contains("no interface Tunnel51"));
+    assert_eq!(configured_peers("Cisco", &config), vec![51]);
+
+    let config = render(&router(1, "PyDECNet"), &tunnels, &[52], &Keys::default(), &RenderConfig::default()).unwrap();
+    assert!(config.contains("# Circuit gre-52 removed"));
+    assert_eq!(configured_peers("PyDECNet", &config), vec![51]);
+}
+
+#[test]
+fn test_check_endpoint() {
+    assert!(check_endpoint("192.0.2.1", false, "host.example.com.", "peer \"one\"", "Gi0/0").is_ok());
+    assert!(check_endpoint("2001:db8::1", false, "2001:db8::1", "", "198.51.100.1").is_ok());
+    assert!(check_endpoint("", true, "host.example.com", "peer", "Gi0/0").is_ok());
+
+    assert!(check_endpoint("", false, "host.example.com", "peer", "Gi0/0").is_err());
+    assert!(check_endpoint("192.0.2.1\n shutdown", false, "host.example.com", "peer", "Gi0/0").is_err());
+    assert!(check_endpoint("192.0.2.1", false, "host.example.com\n shutdown", "peer", "Gi0/0").is_err());
+    assert!(check_endpoint("192.0.2.1", false, "-host.example.com", "peer", "Gi0/0").is_err());
+    assert!(check_endpoint("192.0.2.1", false, "host..example.com", "peer", "Gi0/0").is_err());
+    assert!(check_endpoint("192.0.2.1", false, &"a".repeat(64), "peer", "Gi0/0").is_err());
+    assert!(check_endpoint("192.0.2.1", false, "host.example.com", "peer\r\nshutdown", "Gi0/0").is_err());
+    assert!(check_endpoint("192.0.2.1", false, "host.example.com", "peer", "Gi0/0\n shutdown").is_err());
+}
+
+#[test]
+fn test_render_refuses_unchecked_peers() {
+    let mut peer = tunnel(51, 2, "mesh");
+    peer.description = "peer\n no shutdown".to_string();
+    let tunnels = vec![tunnel(50, 1, "mesh"), peer];
+
+    for router_type in ["Cisco", "PyDECnet", "MikroTik"] {
+        let err = render(&router(1, router_type), &tunnels, &[], &Keys::default(), &RenderConfig::default()).unwrap_err();
+        assert!(err.contains("tunnel 51"), "{}", err);
+    }
+}
@@ -0,0 +1,109 @@
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::thread;
+use std::time::Duration;
+
+use tunnel_manager::resolver::{DnsResolver, Resolution, Resolve, StaticResolver};
+
+/// A DNS server on a local port that answers one query with `answers` (type, TTL, data) and
+/// the given rcode.
+fn serve_once(rcode: u8, answers: Vec<(u16, u32, Vec<u8>)>) -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let mut buf = [0; 512];
+        let (len, client) = socket.recv_from(&mut buf).unwrap();
+        let query = &buf[..len];
+
+        let mut answer = Vec::new();
+        answer.extend_from_slice(&query[..2]);
+        answer.extend_from_slice(&[0x81, 0x80 | rcode, 0, 1]);
+        answer.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+        answer.extend_from_slice(&[0, 0, 0, 0]);
+        answer.extend_from_slice(&query[12..]);
+        for (rtype, ttl, data) in answers {
+            // A pointer to the name in the question.
+            answer.extend_from_slice(&[0xc0, 12]);
+            answer.extend_from_slice(&rtype.to_be_bytes());
+            answer.extend_from_slice(&1u16.to_be_bytes());
+            answer.extend_from_slice(&ttl.to_be_bytes());
+            answer.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            answer.extend_from_slice(&data);
+        }
+        socket.send_to(&answer, client).unwrap();
+    });
+
+    addr
+}
+
+#[test]
+fn test_dns_resolver() {
+    let server = serve_once(0, vec![
+        (5, 600, vec![3, b'c', b'd', b'n', 0]),
+        (1, 300, vec![192, 0, 2, 7]),
+        (1, 120, vec![192, 0, 2, 8]),
+    ]);
+    let resolver = DnsResolver::new(vec![server], Duration::from_secs(2));
+    assert_eq!(
+        resolver.resolve("member.example.org", false),
+        Ok(Resolution {
+            addresses: vec!["192.0.2.7".parse().unwrap(), "192.0.2.8".parse().unwrap()],
+            ttl: Duration::from_secs(120),
+        })
+    );
+
+    let mut v6 = vec![0; 16];
+    v6[..4].copy_from_slice(&[0x20, 0x01, 0x0d, 0xb8]);
+    v6[15] = 1;
+    let server = serve_once(0, vec![(28, 60, v6)]);
+    let resolution = DnsResolver::new(vec![server], Duration::from_secs(2))
+        .resolve("member.example.org.", true)
+        .unwrap();
+    assert_eq!(resolution.addresses, vec!["2001:db8::1".parse::<IpAddr>().unwrap()]);
+
+    let server = serve_once(3, vec![]);
+    let err = DnsResolver::new(vec![server], Duration::from_secs(2))
+        .resolve("gone.example.org", false)
+        .unwrap_err();
+    assert!(err.ends_with("no such host"), "{}", err);
+
+    let server = serve_once(0, vec![]);
+    let err = DnsResolver::new(vec![server], Duration::from_secs(2))
+        .resolve("member.example.org", false)
+        .unwrap_err();
+    assert!(err.ends_with("no IPv4 address"), "{}", err);
+
+    // A server that never answers is given up on, and the next one asked.
+    let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let server = serve_once(0, vec![(1, 30, vec![192, 0, 2, 9])]);
+    let resolver = DnsResolver::new(vec![silent.local_addr().unwrap(), server], Duration::from_millis(200));
+    assert_eq!(
+        resolver.resolve("member.example.org", false).unwrap().addresses,
+        vec!["192.0.2.9".parse::<IpAddr>().unwrap()]
+    );
+
+    assert!(DnsResolver::new(vec![], Duration::from_secs(1)).resolve("member.example.org", false).is_err());
+    assert!(DnsResolver::new(vec![server], Duration::from_secs(1)).resolve("bad..name", false).is_err());
+}
+
+#[test]
+fn test_static_resolver() {
+    let resolver = StaticResolver::new(Duration::from_secs(30));
+    resolver.set("member.example.org", vec!["192.0.2.1".parse().unwrap(), "2001:db8::1".parse().unwrap()]);
+
+    assert_eq!(
+        resolver.resolve("member.example.org", false),
+        Ok(Resolution {
+            addresses: vec!["192.0.2.1".parse().unwrap()],
+            ttl: Duration::from_secs(30),
+        })
+    );
+    assert_eq!(
+        resolver.resolve("member.example.org", true).unwrap().addresses,
+        vec!["2001:db8::1".parse::<IpAddr>().unwrap()]
+    );
+    assert!(resolver.resolve("other.example.org", false).is_err());
+
+    resolver.set("member.example.org", vec![]);
+    assert!(resolver.resolve("member.example.org", false).is_err());
+}
@@ -0,0 +1,44 @@
+use tunnel_manager::wireguard::{keypair, public_key, x25519};
+
+fn hex(s: &str) -> [u8; 32] {
+    let bytes: Vec<u8> = (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+        .collect();
+    bytes.try_into().unwrap()
+}
+
+#[test]
+fn test_x25519() {
+    // RFC 7748, section 5.2.
+    assert_eq!(
+        x25519(
+            &hex("a546e36bf0527c9d3b16154b82465edd62144c0ac1fc5a18506a2244ba449ac4"),
+            &hex("e6db6867583030db3594c1a424b15f7c726624ec26b3353b10a903a6d0ab1c4c"),
+        ),
+        hex("c3da55379de9c6908e94ea4df28d084f32eccf03491c71f754b4075577a28552")
+    );
+
+    // RFC 7748, section 6.1: both sides agree on the shared secret.
+    let alice = hex("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a");
+    let bob = hex("5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb");
+    let alice_public = hex("8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a");
+    let bob_public = hex("de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f");
+    assert_eq!(public_key(&base64::encode(alice)).unwrap(), base64::encode(alice_public));
+    assert_eq!(public_key(&base64::encode(bob)).unwrap(), base64::encode(bob_public));
+
+    let shared = hex("4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742");
+    assert_eq!(x25519(&alice, &bob_public), shared);
+    assert_eq!(x25519(&bob, &alice_public), shared);
+}
+
+#[test]
+fn test_keypair() {
+    let (private_key, public) = keypair();
+    assert_eq!(base64::decode(&private_key).unwrap().len(), 32);
+    assert_eq!(public_key(&private_key).unwrap(), public);
+    assert_ne!(keypair().0, private_key);
+
+    assert!(public_key("not base64!").is_err());
+    assert!(public_key(&base64::encode([1u8; 16])).is_err());
+}